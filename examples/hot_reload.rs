@@ -0,0 +1,86 @@
+//! Serving queries from a mapped dictionary while new versions of it are
+//! deployed underneath.
+//!
+//! A deploy writes the new dictionary next to the old one and renames it
+//! into place. A reloader thread polls `is_stale`, maps the new file with
+//! `reopen`, and swaps it into an `ArcSwap`. Readers load the current
+//! dictionary for each query; one that loaded the old dictionary keeps it
+//! mapped until its query is done.
+//!
+//!     cargo run --example hot_reload --features memmap2
+
+extern crate arc_swap;
+extern crate mars_trie;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use arc_swap::ArcSwap;
+use mars_trie::{Config, Keyset, LoudsTrie, MappedTrie};
+
+/// Writes a dictionary of `keys` to a new file beside `path`, then renames
+/// it over `path`, so a reader never sees a partly written file.
+fn deploy(path: &Path, keys: &[&str]) {
+    let mut keyset = Keyset::new();
+    for key in keys {
+        keyset.push(key.as_bytes());
+    }
+    let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+    let next = path.with_extension("new");
+    trie.save(&next).unwrap();
+    fs::rename(&next, path).unwrap();
+}
+
+fn main() {
+    let path = env::temp_dir().join(format!("hot-reload-{}.dic",
+                                            process::id()));
+    deploy(&path, &["apple", "banana"]);
+
+    let current = Arc::new(ArcSwap::from_pointee(
+        MappedTrie::open(&path).unwrap()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reloader = {
+        let current = current.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let stale = current.load().is_stale();
+                match stale {
+                    Ok(true) => match current.load().reopen() {
+                        Ok(trie) => {
+                            println!("reloaded {} bytes", trie.mapped_len());
+                            current.store(Arc::new(trie));
+                        },
+                        // Likely caught between a removal and a rename;
+                        // keep serving the old one and try again.
+                        Err(e) => println!("reload failed: {}", e),
+                    },
+                    Ok(false) => {},
+                    Err(e) => println!("cannot check {:?}: {}", path, e),
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+
+    let found = |key: &str| {
+        current.load().trie().lookup(key.as_bytes()).is_some()
+    };
+    println!("cherry before deploy: {}", found("cherry"));
+    deploy(&current.load().path().to_path_buf(),
+           &["apple", "banana", "cherry"]);
+    while !found("cherry") {
+        thread::sleep(Duration::from_millis(1));
+    }
+    println!("cherry after deploy: {}", found("cherry"));
+
+    done.store(true, Ordering::Relaxed);
+    reloader.join().unwrap();
+    fs::remove_file(current.load().path()).unwrap();
+}
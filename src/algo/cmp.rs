@@ -0,0 +1,125 @@
+//! The orders keys and tail entries are sorted in.
+//!
+//! The builder sorts with `sort::sort`, which compares `Label`s a byte at a
+//! time, while `Ord` on keys and the tail's suffix sharing look at whole
+//! slices. They have to agree: if they do not, keys that should share a
+//! node, or strings that should share tail bytes, silently stop doing so.
+//! So every byte order is defined here, once, and the `Label` impls read
+//! bytes through `forward_at` and `reverse_at`.
+
+use std::cmp::Ordering;
+use sort::Label;
+
+/// The byte `i` places from the start of `s`, as `forward` visits them.
+pub fn forward_at(s: &[u8], i: usize) -> u8 {
+    s[i]
+}
+
+/// The byte `i` places from the end of `s`, as `reverse` visits them.
+pub fn reverse_at(s: &[u8], i: usize) -> u8 {
+    s[s.len() - i - 1]
+}
+
+/// Bytes from the first; a key sorts right before the keys it is a prefix
+/// of. The order of `Key`, and of the first trie.
+pub fn forward(l: &[u8], r: &[u8]) -> Ordering {
+    l.cmp(r)
+}
+
+/// Bytes from the last; a string sorts right before the strings it is a
+/// suffix of. The order of `ReverseKey` and of tail entries, which is what
+/// lets the tail store a string that ends another one only once.
+pub fn reverse(l: &[u8], r: &[u8]) -> Ordering {
+    l.iter().rev().cmp(r.iter().rev())
+}
+
+/// Compares two units known to be equal before `depth`. This is the order
+/// `sort::sort` leaves units in, so it is `forward` or `reverse` of their
+/// slices, depending on which way their `Label` impl reads them.
+pub fn labels<T: Label>(lhs: &T, rhs: &T, depth: usize) -> Ordering {
+    for i in depth..lhs.label_len() {
+        if i == rhs.label_len() {
+            return Ordering::Greater;
+        }
+        match lhs.label_at(i).cmp(&rhs.label_at(i)) {
+            Ordering::Equal => {},
+            x => return x,
+        }
+    }
+    lhs.label_len().cmp(&rhs.label_len())
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use entry::Entry;
+    use key::{Key, ReverseKey};
+    use quickcheck as qc;
+    use std::cmp::Ordering::{Equal, Greater, Less};
+    use super::{forward, labels, reverse};
+
+    #[test]
+    fn forward_order() {
+        let _ = env_logger::init();
+        assert_eq!(forward(b"", b""), Equal);
+        assert_eq!(forward(b"", b"a"), Less);
+        assert_eq!(forward(b"ab", b"abc"), Less);
+        assert_eq!(forward(b"abc", b"ab"), Greater);
+        assert_eq!(forward(b"abc", b"abd"), Less);
+        assert_eq!(forward(b"b", b"abc"), Greater);
+        assert_eq!(forward(b"\xFF", b"\x00\x00"), Greater);
+    }
+
+    #[test]
+    fn reverse_order() {
+        let _ = env_logger::init();
+        assert_eq!(reverse(b"", b""), Equal);
+        assert_eq!(reverse(b"", b"a"), Less);
+        assert_eq!(reverse(b"bc", b"abc"), Less);
+        assert_eq!(reverse(b"abc", b"bc"), Greater);
+        assert_eq!(reverse(b"cba", b"dba"), Less);
+        assert_eq!(reverse(b"ab", b"ba"), Greater);
+        assert_eq!(reverse(b"ab", b"ab"), Equal);
+        // A prefix is not a suffix, so nothing puts them together.
+        assert_eq!(reverse(b"ab", b"abc"), Less);
+        assert_eq!(reverse(b"abd", b"abc"), Greater);
+    }
+
+    #[test]
+    fn reverse_puts_suffixes_before_their_strings() {
+        let _ = env_logger::init();
+        let mut v: Vec<&[u8]> = vec![b"xbc", b"c", b"abc", b"bc", b"b"];
+        v.sort_by(|l, r| reverse(l, r));
+        let expected: Vec<&[u8]> = vec![b"b", b"c", b"bc", b"abc", b"xbc"];
+        assert_eq!(v, expected);
+    }
+
+    /// Compares at a depth where the units are known to be equal, as
+    /// multi-key quicksort does, and from the start, as `Ord` does.
+    fn labels_prop(l: Vec<u8>, r: Vec<u8>) -> bool {
+        let shared = l.iter().zip(&r).take_while(|&(a, b)| a == b).count();
+        let rshared = l.iter().rev().zip(r.iter().rev())
+                      .take_while(|&(a, b)| a == b).count();
+        let (k, rk) = (|s| Key::new(s), |s| ReverseKey::new(s));
+        labels(&k(&l), &k(&r), shared) == forward(&l, &r)
+        && k(&l).cmp(&k(&r)) == forward(&l, &r)
+        && labels(&rk(&l), &rk(&r), rshared) == reverse(&l, &r)
+        && rk(&l).cmp(&rk(&r)) == reverse(&l, &r)
+        && labels(&Entry::new(&l, 0), &Entry::new(&r, 1), 0) == reverse(&l, &r)
+    }
+
+    /// `sort::sort` and `Ord` agree with the slice orders for every type
+    /// that gets sorted.
+    #[test]
+    fn labels_agree_with_slice_orders() {
+        let _ = env_logger::init();
+        qc::quickcheck(labels_prop as fn(Vec<u8>, Vec<u8>) -> bool);
+        for &(l, r) in &[(&b""[..], &b""[..]), (b"a", b""), (b"ab", b"abc"),
+                         (b"bc", b"abc"), (b"abd", b"abc")] {
+            assert!(labels_prop(l.to_vec(), r.to_vec()));
+            assert!(labels_prop(r.to_vec(), l.to_vec()));
+        }
+    }
+}
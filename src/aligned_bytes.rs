@@ -0,0 +1,158 @@
+//! An owned byte buffer that starts on an 8-byte boundary, for dictionaries
+//! to map from.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Bytes kept in u64 words, so that they start 8-byte aligned. A
+/// dictionary in one maps with every vector borrowed in place; one in a
+/// buffer that is not aligned, as an `include_bytes!` array may not be,
+/// does not map.
+///
+/// ```
+/// use mars_trie::{AlignedBytes, Config, LoudsTrie};
+///
+/// let trie = LoudsTrie::build_from(vec!["a", "ab"], &Config::new()).unwrap();
+/// let mut bytes = Vec::new();
+/// trie.write_to(&mut bytes).unwrap();
+/// let buf = AlignedBytes::from_slice(&bytes);
+/// let view = LoudsTrie::map_from_slice(&buf).unwrap();
+/// assert_eq!(view.lookup(b"ab"), trie.lookup(b"ab"));
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct AlignedBytes {
+    words_: Vec<u64>,
+    len_: usize,
+}
+
+impl AlignedBytes {
+    pub fn new() -> AlignedBytes {
+        AlignedBytes { words_: Vec::new(), len_: 0 }
+    }
+
+    /// `len` zero bytes.
+    pub fn zeroed(len: usize) -> AlignedBytes {
+        AlignedBytes { words_: vec![0; words_for(len)], len_: len }
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> AlignedBytes {
+        let mut out = AlignedBytes::zeroed(bytes.len());
+        out.copy_from_slice(bytes);
+        out
+    }
+
+    /// Reads `r` to the end, growing the buffer as `Vec` would.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: io::Read>(r: &mut R) -> io::Result<AlignedBytes> {
+        let mut out = AlignedBytes::new();
+        loop {
+            if out.len_ == out.words_.len() * 8 {
+                let words = std::cmp::max(2 * out.words_.len(), 1024);
+                out.words_.resize(words, 0);
+            }
+            let len = out.len_;
+            let spare = &mut out.all_bytes_mut()[len..];
+            match r.read(spare) {
+                Ok(0) => break,
+                Ok(n) => out.len_ += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        out.words_.truncate(words_for(out.len_));
+        out.words_.shrink_to_fit();
+        Ok(out)
+    }
+
+    /// Reads the file at `path`, as `LoudsTrie::save` wrote it, ready for
+    /// `LoudsTrie::map_from_slice`.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<AlignedBytes> {
+        AlignedBytes::read_from(&mut File::open(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len_
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len_ == 0
+    }
+
+    fn all_bytes_mut(&mut self) -> &mut [u8] {
+        // The words are initialized, and any bytes are valid u64s.
+        unsafe {
+            std::slice::from_raw_parts_mut(self.words_.as_mut_ptr() as *mut u8,
+                                           self.words_.len() * 8)
+        }
+    }
+}
+
+fn words_for(len: usize) -> usize {
+    len / 8 + if len % 8 != 0 { 1 } else { 0 }
+}
+
+impl Deref for AlignedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // `len_` is never past the end of the words.
+        unsafe {
+            std::slice::from_raw_parts(self.words_.as_ptr() as *const u8,
+                                       self.len_)
+        }
+    }
+}
+
+impl DerefMut for AlignedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len_;
+        &mut self.all_bytes_mut()[..len]
+    }
+}
+
+impl std::fmt::Debug for AlignedBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AlignedBytes({} bytes)", self.len_)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use super::AlignedBytes;
+
+    #[test]
+    fn holds_the_bytes_aligned() {
+        let _ = env_logger::init();
+        fn prop(bytes: Vec<u8>) -> bool {
+            let aligned = AlignedBytes::from_slice(&bytes);
+            aligned.as_ptr() as usize % 8 == 0 && &aligned[..] == &bytes[..]
+            && aligned.len() == bytes.len()
+        }
+        qc::quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn reads_to_the_end() {
+        let _ = env_logger::init();
+        let bytes: Vec<u8> = (0..20000u32).map(|i| (i * 7) as u8).collect();
+        for &len in &[0, 1, 8, 8191, 8192, 8193, 20000] {
+            let read = AlignedBytes::read_from(&mut &bytes[..len]).unwrap();
+            assert_eq!(&read[..], &bytes[..len]);
+            assert_eq!(read, AlignedBytes::from_slice(&bytes[..len]));
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! A global allocator for the tests that counts allocations per thread, so
+//! a test can check that some code makes none, or only small ones, or how
+//! much memory it holds at once.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAlloc;
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    static BYTES: Cell<usize> = Cell::new(0);
+    // Signed, since a thread can free what another one allocated.
+    static LIVE: Cell<isize> = Cell::new(0);
+    static PEAK: Cell<isize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // try_with, since the thread may be tearing its locals down.
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        let _ = BYTES.try_with(|n| n.set(n.get() + layout.size()));
+        let _ = LIVE.try_with(|n| {
+            n.set(n.get() + layout.size() as isize);
+            let _ = PEAK.try_with(|p| if n.get() > p.get() {
+                p.set(n.get())
+            });
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|n| n.set(n.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// The number of allocations `f` makes on this thread.
+pub fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(|n| n.get());
+    f();
+    ALLOCATIONS.with(|n| n.get()) - before
+}
+
+/// The number of bytes `f` allocates on this thread, not counting frees.
+pub fn bytes_allocated<F: FnOnce()>(f: F) -> usize {
+    let before = BYTES.with(|n| n.get());
+    f();
+    BYTES.with(|n| n.get()) - before
+}
+
+/// The most bytes `f` has allocated on this thread and not yet freed at any
+/// one time.
+pub fn peak_bytes<F: FnOnce()>(f: F) -> usize {
+    let before = LIVE.with(|n| n.get());
+    PEAK.with(|p| p.set(before));
+    f();
+    (PEAK.with(|p| p.get()) - before) as usize
+}
@@ -0,0 +1,212 @@
+//! Writing a file so that it is either all there or not changed at all.
+//!
+//! A process that dies while writing a dictionary in place leaves a
+//! truncated file that will not load. Instead, the dictionary is written to
+//! a temporary file in the same directory, flushed to disk, and renamed
+//! over the target. On Unix the rename is atomic: a reader opening the path
+//! sees the old file or the new one. On Windows `std::fs::rename` replaces
+//! the target too, but without that guarantee, so a crash mid-rename may
+//! leave the target missing, though never truncated; the complete new file
+//! is then still in the directory under its temporary name.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How `LoudsTrie::save_with` and `save_libmarisa_with` write their file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WriteOptions {
+    atomic_: bool,
+}
+
+impl WriteOptions {
+    pub fn new() -> WriteOptions {
+        WriteOptions { atomic_: true }
+    }
+
+    /// Whether to write a temporary file and rename it over the target,
+    /// rather than writing the target in place. On by default; turn it off
+    /// to write to a path that cannot be renamed over, such as a device.
+    pub fn atomic(&self) -> bool {
+        self.atomic_
+    }
+    pub fn set_atomic(&mut self, atomic: bool) {
+        self.atomic_ = atomic;
+    }
+    pub fn with_atomic(mut self, atomic: bool) -> WriteOptions {
+        self.set_atomic(atomic);
+        self
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions::new()
+    }
+}
+
+/// Tries at a fresh temporary name before giving up.
+const MAX_TEMP_ATTEMPTS: usize = 16;
+
+/// Creates `path` with `write`, as `options` say. If `write` or anything
+/// after it fails, an atomic write removes its temporary file and leaves
+/// `path` as it was.
+pub fn write_file<F>(path: &Path, options: &WriteOptions, write: F)
+  -> io::Result<()>
+  where F: FnOnce(&mut io::BufWriter<&File>) -> io::Result<()> {
+    if !options.atomic() {
+        let file = File::create(path)?;
+        let mut w = io::BufWriter::new(&file);
+        write(&mut w)?;
+        return w.flush();
+    }
+
+    let (file, temp_path) = create_temp_beside(path)?;
+    let written = write_and_sync(&file, write)
+        .and_then(|()| fs::rename(&temp_path, path));
+    drop(file);
+    match written {
+        Ok(()) => {
+            sync_dir_of(path);
+            Ok(())
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        },
+    }
+}
+
+fn write_and_sync<F>(file: &File, write: F) -> io::Result<()>
+  where F: FnOnce(&mut io::BufWriter<&File>) -> io::Result<()> {
+    let mut w = io::BufWriter::new(file);
+    write(&mut w)?;
+    w.flush()?;
+    file.sync_all()
+}
+
+/// A new file in the directory of `path`, named after it with a suffix no
+/// other writer, in this process or another, is using.
+fn create_temp_beside(path: &Path) -> io::Result<(File, PathBuf)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "path has no file name")),
+    };
+    let mut last_err = None;
+    for _ in 0..MAX_TEMP_ATTEMPTS {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+                                     .map(|d| d.subsec_nanos()).unwrap_or(0);
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}-{}-{:08x}.tmp", name, process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed), nanos));
+        match OpenOptions::new().write(true).create_new(true)
+                                .open(&temp_path) {
+            Ok(file) => return Ok((file, temp_path)),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(e);
+                }
+                last_err = Some(e);
+            },
+        }
+    }
+    Err(last_err.unwrap())
+}
+
+/// Makes the rename itself durable where that is possible. Failing to is
+/// not an error: the file is complete either way.
+#[cfg(unix)]
+fn sync_dir_of(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir_of(_: &Path) {}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use super::{WriteOptions, write_file};
+
+    /// A fresh, empty directory for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mars-trie-{}-{}",
+                                               process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn entries(dir: &PathBuf) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| {
+            e.unwrap().file_name().to_string_lossy().into_owned()
+        }).collect();
+        names.sort();
+        names
+    }
+
+    /// Writes a lot, so some of it reaches the file, then fails.
+    fn fail_midway<W: Write>(w: &mut W) -> io::Result<()> {
+        w.write_all(&vec![b'x'; 1 << 16])?;
+        Err(io::Error::new(io::ErrorKind::Other, "disk on fire"))
+    }
+
+    #[test]
+    fn failed_write_leaves_target_alone() {
+        let _ = env_logger::init();
+        let dir = temp_dir("atomic-fail");
+        let path = dir.join("dict");
+        let options = WriteOptions::new();
+        assert!(options.atomic());
+
+        // Nothing there before: nothing there after.
+        let err = write_file(&path, &options, |w| fail_midway(w))
+                  .unwrap_err();
+        assert_eq!(err.to_string(), "disk on fire");
+        assert!(entries(&dir).is_empty());
+
+        write_file(&path, &options, |w| w.write_all(b"old")).unwrap();
+        assert!(write_file(&path, &options, |w| fail_midway(w)).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(entries(&dir), vec!["dict".to_string()]);
+
+        write_file(&path, &options, |w| w.write_all(b"new")).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(entries(&dir), vec!["dict".to_string()]);
+
+        // In place, a failure leaves what was written so far.
+        let in_place = options.with_atomic(false);
+        assert!(write_file(&path, &in_place, |w| fail_midway(w)).is_err());
+        assert_eq!(fs::read(&path).unwrap().len(), 1 << 16);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory_is_an_error() {
+        let _ = env_logger::init();
+        let dir = temp_dir("atomic-missing");
+        let path = dir.join("no such dir").join("dict");
+        assert!(write_file(&path, &WriteOptions::new(), |w| w.write_all(b"x"))
+                .is_err());
+        assert!(entries(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use std;
+use error::MarisaError;
 
 #[cfg(target_pointer_width = "32")]
 pub const WORD_SIZE: usize = 32;
@@ -8,3 +9,68 @@ pub const WORD_SIZE: usize = 64;
 
 pub const INVALID_KEY_ID: u32 = std::u32::MAX;
 
+/// Narrows `x` to u32, or fails with a SizeError naming `what`.
+pub fn checked_u32(x: usize, what: &'static str) -> Result<u32, MarisaError> {
+    if x <= std::u32::MAX as usize {
+        Ok(x as u32)
+    } else {
+        Err(MarisaError::SizeError { what: what,
+                                     limit: std::u32::MAX as u64,
+                                     actual: x as u64 })
+    }
+}
+
+/// Widens a length read from a dictionary to usize, or fails with a
+/// SizeError when the dictionary cannot be addressed on this platform.
+pub fn checked_usize(x: u64) -> Result<usize, MarisaError> {
+    checked_len(x, std::usize::MAX as u64)
+}
+
+fn checked_len(x: u64, max: u64) -> Result<usize, MarisaError> {
+    if x <= max {
+        Ok(x as usize)
+    } else {
+        Err(MarisaError::SizeError { what: "dictionary for this platform",
+                                     limit: max, actual: x })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use std;
+    use error::MarisaError;
+    use super::{checked_len, checked_u32, checked_usize};
+
+    #[test]
+    fn checked_u32_bounds() {
+        assert_eq!(checked_u32(0, "x"), Ok(0));
+        assert_eq!(checked_u32(std::u32::MAX as usize, "x"),
+                   Ok(std::u32::MAX));
+        if std::mem::size_of::<usize>() > 4 {
+            let too_big = std::u32::MAX as usize + 1;
+            assert_eq!(checked_u32(too_big, "keys"),
+                       Err(MarisaError::SizeError {
+                           what: "keys", limit: std::u32::MAX as u64,
+                           actual: too_big as u64 }));
+        }
+    }
+
+    #[test]
+    fn checked_usize_bounds() {
+        assert_eq!(checked_usize(0), Ok(0));
+        assert_eq!(checked_usize(std::usize::MAX as u64), Ok(std::usize::MAX));
+        // What a 32-bit platform sees for a 5 GiB section.
+        let five_gib = 5u64 << 30;
+        let max32 = std::u32::MAX as u64;
+        assert_eq!(checked_len(max32, max32), Ok(std::u32::MAX as usize));
+        let err = checked_len(five_gib, max32).unwrap_err();
+        assert_eq!(err, MarisaError::SizeError {
+            what: "dictionary for this platform", limit: max32,
+            actual: five_gib });
+        assert_eq!(err.to_string(), "MARISA_SIZE_ERROR: dictionary for this \
+                                     platform is too large (5368709120, the \
+                                     limit is 4294967295)");
+    }
+}
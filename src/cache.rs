@@ -1,6 +1,10 @@
 use std;
+use std::io;
+use vector::pod_vec::Pod;
+use vector::util::{u64_from_le, write_u32};
 
 #[derive(Copy, Clone, Debug)]
+#[repr(C)]
 struct Union {
     /// link: u32 or weight: f32
     bits_: u32,
@@ -11,20 +15,23 @@ impl Union {
         Union { bits_: 0 }
     }
     fn get_weight(&self) -> f32 {
-        unsafe { std::mem::transmute(self.bits_) }
+        f32::from_bits(self.bits_)
     }
     fn get_link(&self) -> u32 {
         self.bits_
     }
     fn set_weight(&mut self, weight: f32) {
-        self.bits_ = unsafe { std::mem::transmute(weight) };
+        self.bits_ = weight.to_bits();
     }
     fn set_link(&mut self, link: u32) {
         self.bits_ = link
     }
 }
 
+/// Laid out as the three u32s `write` writes, so a cache can be mapped in
+/// place.
 #[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct Cache {
     parent_: u32,
     child_: u32,
@@ -78,5 +85,24 @@ impl Cache {
     pub fn weight(&self) -> f32 {
         self.union_.get_weight()
     }
+
+    /// Writes the parent, the child and then the link or weight bits, each
+    /// as a little-endian u32.
+    pub fn write<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, self.parent_)?;
+        write_u32(w, self.child_)?;
+        write_u32(w, self.union_.bits_)
+    }
 }
 
+unsafe impl Pod for Cache {
+    fn from_le(bytes: &[u8]) -> Cache {
+        let word = |i: usize| u64_from_le(&bytes[4 * i..4 * i + 4]) as u32;
+        Cache { parent_: word(0), child_: word(1),
+                union_: Union { bits_: word(2) } }
+    }
+
+    fn write_le<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write(w)
+    }
+}
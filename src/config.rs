@@ -1,3 +1,10 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use std::fmt;
+use std::str::FromStr;
+use error::MarisaError;
+#[cfg(any(test, feature = "quickcheck"))]
 use quickcheck as qc;
 
 /// Min/max values, flags and masks for dictionary settings are defined below.
@@ -7,7 +14,7 @@ use quickcheck as qc;
 
 /// A dictionary consists of 3 tries by default. Usually more tries make a
 /// dictionary space-efficient but time-inefficient.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct NumTries { num_: u32 }
 pub const MIN_NUM_TRIES: u32 = 0x00001;
 pub const MAX_NUM_TRIES: u32 = 0x0007F;
@@ -25,6 +32,7 @@ impl Default for NumTries {
         NumTries::new(3)
     }
 }
+#[cfg(any(test, feature = "quickcheck"))]
 impl qc::Arbitrary for NumTries {
     fn arbitrary<G: qc::Gen>(g: &mut G) -> NumTries {
         // This is slow when using the full range...
@@ -33,8 +41,7 @@ impl qc::Arbitrary for NumTries {
     }
     fn shrink(&self) -> Box<Iterator<Item=Self>> {
         let fewer = self.get() / 2;
-        let fewer = NumTries::new(fewer);
-        if fewer.get() > 0 { qc::single_shrinker(fewer) }
+        if fewer > 0 { qc::single_shrinker(NumTries::new(fewer)) }
             else { qc::empty_shrinker() }
     }
 }
@@ -42,7 +49,7 @@ impl qc::Arbitrary for NumTries {
 /// This library uses a cache technique to accelerate search functions. The
 /// following enumerated type gives a list of available cache size options. A
 /// larger cache enables faster search but takes a more space.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum CacheLevel {
     Huge   = 0x00080,
     Large  = 0x00100,
@@ -55,9 +62,21 @@ impl Default for CacheLevel {
         CacheLevel::Normal
     }
 }
+#[cfg(any(test, feature = "quickcheck"))]
+impl qc::Arbitrary for CacheLevel {
+    fn arbitrary<G: qc::Gen>(g: &mut G) -> CacheLevel {
+        *g.choose(&[CacheLevel::Huge, CacheLevel::Large, CacheLevel::Normal,
+                   CacheLevel::Small, CacheLevel::Tiny]).unwrap()
+    }
+    fn shrink(&self) -> Box<Iterator<Item=Self>> {
+        if *self == CacheLevel::default() { qc::empty_shrinker() }
+            else { qc::single_shrinker(CacheLevel::default()) }
+    }
+}
 
-/// This library provides 2 kinds of TAIL implementations.
-#[derive(Copy, Clone, Debug)]
+/// This library provides 2 kinds of TAIL implementations, and a mode that
+/// picks one of them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum TailMode {
     /// Merge last labels as zero-terminated strings. Available if and only if
     /// last labels do not contain a null character.
@@ -71,12 +90,28 @@ pub enum TailMode {
     /// characters. So, TailMode::Binary requires a larger space if the average
     /// length of labels is greater than 8.
     Binary = 0x02000,
+
+    /// Measures both for the last labels and uses the smaller, Text on a tie
+    /// and Binary if Text is not available. A dictionary built this way
+    /// records the mode it ended up with.
+    Auto = 0x03000,
 }
 impl Default for TailMode {
     fn default() -> TailMode {
         TailMode::Text
     }
 }
+#[cfg(any(test, feature = "quickcheck"))]
+impl qc::Arbitrary for TailMode {
+    fn arbitrary<G: qc::Gen>(g: &mut G) -> TailMode {
+        *g.choose(&[TailMode::Text, TailMode::Binary, TailMode::Auto])
+            .unwrap()
+    }
+    fn shrink(&self) -> Box<Iterator<Item=Self>> {
+        if *self == TailMode::default() { qc::empty_shrinker() }
+            else { qc::single_shrinker(TailMode::default()) }
+    }
+}
 
 /// The arrangement of nodes affects the time cost of matching and the order of
 /// predictive search.
@@ -95,20 +130,41 @@ impl Default for NodeOrder {
         NodeOrder::Weight
     }
 }
+#[cfg(any(test, feature = "quickcheck"))]
+impl qc::Arbitrary for NodeOrder {
+    fn arbitrary<G: qc::Gen>(g: &mut G) -> NodeOrder {
+        *g.choose(&[NodeOrder::Label, NodeOrder::Weight]).unwrap()
+    }
+    fn shrink(&self) -> Box<Iterator<Item=Self>> {
+        if *self == NodeOrder::default() { qc::empty_shrinker() }
+            else { qc::single_shrinker(NodeOrder::default()) }
+    }
+}
+
+/// Keep the weight of each key in the dictionary, so it can be retrieved by
+/// key id after the build. Not part of marisa's flags.
+const WEIGHTS_FLAG: u32 = 0x100000;
+
+/// Leave out the select1 table of the terminal flags, which only `id_lookup`
+/// uses. Negative so that 0 keeps meaning the defaults. Not part of marisa's
+/// flags.
+const NO_REVERSE_LOOKUP_FLAG: u32 = 0x200000;
 
 /// Config masks
 const NUM_TRIES_MASK   : u32 = 0x0007F;
 const CACHE_LEVEL_MASK : u32 = 0x00F80;
 const TAIL_MODE_MASK   : u32 = 0x0F000;
 const NODE_ORDER_MASK  : u32 = 0xF0000;
-const CONFIG_MASK      : u32 = 0xFFFFF;
+const CONFIG_MASK      : u32 = 0x3FFFFF;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Config {
     num_tries_: NumTries,
     cache_level_: CacheLevel,
     tail_mode_: TailMode,
     node_order_: NodeOrder,
+    weights_: bool,
+    reverse_lookup_: bool,
 }
 
 impl Config {
@@ -118,24 +174,37 @@ impl Config {
             cache_level_: Default::default(),
             tail_mode_: Default::default(),
             node_order_: Default::default(),
+            weights_: false,
+            reverse_lookup_: true,
         }
     }
 
-    pub fn parse(config_flags: u32) -> Config {
-        assert!((config_flags & !CONFIG_MASK) == 0, "MARISA_CODE_ERROR");
+    /// The config `config_flags` describe, as `flags` produces them, or a
+    /// CodeError naming the first field that holds no defined value.
+    pub fn parse(config_flags: u32) -> Result<Config, MarisaError> {
+        if (config_flags & !CONFIG_MASK) != 0 {
+            return Err(MarisaError::CodeError {
+                what: "config flags", code: config_flags & !CONFIG_MASK });
+        }
 
         let mut out = Config::new();
         out.parse_num_tries(config_flags);
-        out.parse_cache_level(config_flags);
-        out.parse_tail_mode(config_flags);
-        out.parse_node_order(config_flags);
-        out
+        out.parse_cache_level(config_flags)?;
+        out.parse_tail_mode(config_flags)?;
+        out.parse_node_order(config_flags)?;
+        out.weights_ = (config_flags & WEIGHTS_FLAG) != 0;
+        out.reverse_lookup_ = (config_flags & NO_REVERSE_LOOKUP_FLAG) == 0;
+        Ok(out)
     }
 
+    /// All of the config in one word, which `parse` turns back into it.
     pub fn flags(&self) -> u32 {
         self.num_tries_.get()
+        | (self.cache_level_ as u32)
         | (self.tail_mode_ as u32)
         | (self.node_order_ as u32)
+        | if self.weights_ { WEIGHTS_FLAG } else { 0 }
+        | if self.reverse_lookup_ { 0 } else { NO_REVERSE_LOOKUP_FLAG }
     }
 
     pub fn with_num_tries(mut self, num_tries: NumTries) -> Config {
@@ -154,6 +223,14 @@ impl Config {
         self.set_node_order(node_order);
         self
     }
+    pub fn with_weights(mut self, weights: bool) -> Config {
+        self.set_weights(weights);
+        self
+    }
+    pub fn with_reverse_lookup(mut self, reverse_lookup: bool) -> Config {
+        self.set_reverse_lookup(reverse_lookup);
+        self
+    }
 
     pub fn set_num_tries(&mut self, num_tries: NumTries) {
         self.num_tries_ = num_tries;
@@ -167,6 +244,12 @@ impl Config {
     pub fn set_node_order(&mut self, node_order: NodeOrder) {
         self.node_order_ = node_order;
     }
+    pub fn set_weights(&mut self, weights: bool) {
+        self.weights_ = weights;
+    }
+    pub fn set_reverse_lookup(&mut self, reverse_lookup: bool) {
+        self.reverse_lookup_ = reverse_lookup;
+    }
 
     pub fn num_tries(&self) -> NumTries {
         self.num_tries_
@@ -180,6 +263,15 @@ impl Config {
     pub fn node_order(&self) -> NodeOrder {
         self.node_order_
     }
+    /// Whether key weights are kept in the dictionary.
+    pub fn weights(&self) -> bool {
+        self.weights_
+    }
+    /// Whether `id_lookup` works on the dictionary. Turning it off saves the
+    /// select1 table of the terminal flags, 4 bytes per 512 keys.
+    pub fn reverse_lookup(&self) -> bool {
+        self.reverse_lookup_
+    }
 
     pub fn clear(&mut self) {
         *self = Config::new();
@@ -192,7 +284,8 @@ impl Config {
         }
     }
 
-    fn parse_cache_level(&mut self, config_flags: u32) {
+    fn parse_cache_level(&mut self, config_flags: u32)
+      -> Result<(), MarisaError> {
         self.cache_level_ = match config_flags & CACHE_LEVEL_MASK {
             0 => Default::default(),
             x if x == CacheLevel::Huge as u32 => CacheLevel::Huge,
@@ -200,26 +293,309 @@ impl Config {
             x if x == CacheLevel::Normal as u32 => CacheLevel::Normal,
             x if x == CacheLevel::Small as u32 => CacheLevel::Small,
             x if x == CacheLevel::Tiny as u32 => CacheLevel::Tiny,
-            _ => panic!("MARISA_CODE_ERROR: undefined cache level"),
-        }
+            x => return Err(MarisaError::CodeError { what: "cache level",
+                                                     code: x }),
+        };
+        Ok(())
     }
 
-    fn parse_tail_mode(&mut self, config_flags: u32) {
+    fn parse_tail_mode(&mut self, config_flags: u32)
+      -> Result<(), MarisaError> {
         self.tail_mode_ = match config_flags & TAIL_MODE_MASK {
             0 => Default::default(),
             x if x == TailMode::Text as u32 => TailMode::Text,
             x if x == TailMode::Binary as u32 => TailMode::Binary,
-            _ => panic!("MARISA_CODE_ERROR: undefined tail mode"),
-        }
+            x if x == TailMode::Auto as u32 => TailMode::Auto,
+            x => return Err(MarisaError::CodeError { what: "tail mode",
+                                                     code: x }),
+        };
+        Ok(())
     }
 
-    fn parse_node_order(&mut self, config_flags: u32) {
+    fn parse_node_order(&mut self, config_flags: u32)
+      -> Result<(), MarisaError> {
         self.node_order_ = match config_flags & NODE_ORDER_MASK {
             0 => Default::default(),
             x if x == NodeOrder::Label as u32 => NodeOrder::Label,
             x if x == NodeOrder::Weight as u32 => NodeOrder::Weight,
-            _ => panic!("MARISA_CODE_ERROR: undefined node order"),
+            x => return Err(MarisaError::CodeError { what: "node order",
+                                                     code: x }),
+        };
+        Ok(())
+    }
+}
+
+/// The names the textual config syntax uses for each value.
+const CACHE_LEVEL_NAMES: [(CacheLevel, &'static str); 5] = [
+    (CacheLevel::Huge, "huge"), (CacheLevel::Large, "large"),
+    (CacheLevel::Normal, "normal"), (CacheLevel::Small, "small"),
+    (CacheLevel::Tiny, "tiny")];
+const TAIL_MODE_NAMES: [(TailMode, &'static str); 3] = [
+    (TailMode::Text, "text"), (TailMode::Binary, "binary"),
+    (TailMode::Auto, "auto")];
+const NODE_ORDER_NAMES: [(NodeOrder, &'static str); 2] = [
+    (NodeOrder::Label, "label"), (NodeOrder::Weight, "weight")];
+
+fn name_of<T: PartialEq>(names: &[(T, &'static str)], x: T) -> &'static str {
+    names.iter().find(|&&(ref y, _)| *y == x).unwrap().1
+}
+
+fn value_named<T: Copy>(names: &[(T, &'static str)], field: &str,
+                        value: &str) -> Result<T, ParseConfigError> {
+    match names.iter().find(|&&(_, name)| name.eq_ignore_ascii_case(value)) {
+        Some(&(x, _)) => Ok(x),
+        None => {
+            let expected: Vec<&str> = names.iter().map(|&(_, n)| n).collect();
+            Err(ParseConfigError::new(format!(
+                "{} must be one of {}, not \"{}\"", field,
+                expected.join(", "), value)))
+        },
+    }
+}
+
+fn parse_bool(field: &str, value: &str) -> Result<bool, ParseConfigError> {
+    match &value.to_ascii_lowercase()[..] {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(ParseConfigError::new(format!(
+            "{} must be true or false, not \"{}\"", field, value))),
+    }
+}
+
+/// Why a string could not be read as a `Config`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseConfigError {
+    message_: String,
+}
+
+impl ParseConfigError {
+    fn new(message: String) -> ParseConfigError {
+        ParseConfigError { message_: message }
+    }
+}
+
+impl fmt::Display for ParseConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid config: {}", self.message_)
+    }
+}
+
+impl std::error::Error for ParseConfigError {
+    fn description(&self) -> &str {
+        &self.message_
+    }
+}
+
+/// Reads a config written as comma-separated fields, such as
+/// `"tries=3,cache=normal,tail=text,order=weight"`, as `Display` writes
+/// it. Fields left out keep their defaults. The other fields are
+/// `weights` and `reverse-lookup`, each true or false. A flags word, in
+/// decimal or as hex starting with `0x`, is read as by `Config::parse`.
+impl FromStr for Config {
+    type Err = ParseConfigError;
+
+    fn from_str(s: &str) -> Result<Config, ParseConfigError> {
+        let s = s.trim();
+        let flags = if s.starts_with("0x") || s.starts_with("0X") {
+            Some(u32::from_str_radix(&s[2..], 16))
+        } else if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            Some(s.parse::<u32>())
+        } else {
+            None
+        };
+        if let Some(flags) = flags {
+            let flags = flags.map_err(|_| ParseConfigError::new(format!(
+                "\"{}\" is not a flags word", s)))?;
+            return Config::parse(flags)
+                .map_err(|e| ParseConfigError::new(e.to_string()));
+        }
+
+        let mut out = Config::new();
+        let mut seen: Vec<&str> = Vec::new();
+        for field in s.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            let (name, value) = match field.find('=') {
+                Some(i) => (field[..i].trim(), field[i + 1..].trim()),
+                None => return Err(ParseConfigError::new(format!(
+                    "\"{}\" is not of the form name=value", field))),
+            };
+            if seen.contains(&name) {
+                return Err(ParseConfigError::new(format!(
+                    "{} is given more than once", name)));
+            }
+            seen.push(name);
+            match name {
+                "tries" => {
+                    let num = value.parse::<u32>().ok().filter(|&n| {
+                        n >= MIN_NUM_TRIES && n <= MAX_NUM_TRIES
+                    }).ok_or_else(|| ParseConfigError::new(format!(
+                        "tries must be from {} to {}, not \"{}\"",
+                        MIN_NUM_TRIES, MAX_NUM_TRIES, value)))?;
+                    out.set_num_tries(NumTries::new(num));
+                },
+                "cache" => out.set_cache_level(
+                    value_named(&CACHE_LEVEL_NAMES, name, value)?),
+                "tail" => out.set_tail_mode(
+                    value_named(&TAIL_MODE_NAMES, name, value)?),
+                "order" => out.set_node_order(
+                    value_named(&NODE_ORDER_NAMES, name, value)?),
+                "weights" => out.set_weights(parse_bool(name, value)?),
+                "reverse-lookup" =>
+                    out.set_reverse_lookup(parse_bool(name, value)?),
+                _ => return Err(ParseConfigError::new(format!(
+                    "unknown field \"{}\"", name))),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Writes the config as `FromStr` reads it: the four fields libmarisa
+/// has, then `weights` and `reverse-lookup` if they are not the defaults.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tries={},cache={},tail={},order={}", self.num_tries_.get(),
+               name_of(&CACHE_LEVEL_NAMES, self.cache_level_),
+               name_of(&TAIL_MODE_NAMES, self.tail_mode_),
+               name_of(&NODE_ORDER_NAMES, self.node_order_))?;
+        if self.weights_ {
+            write!(f, ",weights=true")?;
         }
+        if !self.reverse_lookup_ {
+            write!(f, ",reverse-lookup=false")?;
+        }
+        Ok(())
     }
 }
 
+#[cfg(any(test, feature = "quickcheck"))]
+impl qc::Arbitrary for Config {
+    fn arbitrary<G: qc::Gen>(g: &mut G) -> Config {
+        Config::new().with_num_tries(qc::Arbitrary::arbitrary(g))
+                     .with_cache_level(qc::Arbitrary::arbitrary(g))
+                     .with_tail_mode(qc::Arbitrary::arbitrary(g))
+                     .with_node_order(qc::Arbitrary::arbitrary(g))
+                     .with_weights(qc::Arbitrary::arbitrary(g))
+                     .with_reverse_lookup(qc::Arbitrary::arbitrary(g))
+    }
+    /// Shrinks one field at a time towards its default.
+    fn shrink(&self) -> Box<Iterator<Item=Self>> {
+        let c = *self;
+        let mut out: Vec<Config> = Vec::new();
+        out.extend(c.num_tries_.shrink().map(|n| c.with_num_tries(n)));
+        out.extend(c.cache_level_.shrink().map(|l| c.with_cache_level(l)));
+        out.extend(c.tail_mode_.shrink().map(|m| c.with_tail_mode(m)));
+        out.extend(c.node_order_.shrink().map(|o| c.with_node_order(o)));
+        if c.weights_ {
+            out.push(c.with_weights(false));
+        }
+        if !c.reverse_lookup_ {
+            out.push(c.with_reverse_lookup(true));
+        }
+        Box::new(out.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use error::MarisaError;
+    use super::{CACHE_LEVEL_MASK, CacheLevel, Config, MAX_NUM_TRIES,
+                NODE_ORDER_MASK, NodeOrder, NUM_TRIES_MASK, NumTries,
+                TAIL_MODE_MASK, TailMode};
+
+    #[test]
+    fn flags_round_trip_qc() {
+        let _ = env_logger::init();
+        fn prop(config: Config) -> bool {
+            Config::parse(config.flags()) == Ok(config)
+        }
+        qc::quickcheck(prop as fn(Config) -> bool);
+    }
+
+    #[test]
+    fn text_round_trip_qc() {
+        let _ = env_logger::init();
+        fn prop(config: Config) -> bool {
+            config.to_string().parse::<Config>() == Ok(config)
+        }
+        qc::quickcheck(prop as fn(Config) -> bool);
+    }
+
+    #[test]
+    fn text_syntax() {
+        let _ = env_logger::init();
+        let parse = |s: &str| s.parse::<Config>();
+        assert_eq!(parse(""), Ok(Config::new()));
+        assert_eq!(Config::new().to_string(),
+                   "tries=3,cache=normal,tail=text,order=weight");
+        let config = Config::new().with_num_tries(NumTries::new(5))
+                                  .with_cache_level(CacheLevel::Tiny)
+                                  .with_weights(true);
+        assert_eq!(parse(" cache = TINY, tries=5 ,weights=yes"), Ok(config));
+        assert_eq!(config.to_string(),
+                   "tries=5,cache=tiny,tail=text,order=weight,weights=true");
+        assert_eq!(parse("reverse-lookup=off").unwrap().reverse_lookup(),
+                   false);
+        // The flags word form.
+        assert_eq!(parse(&format!("{:#x}", config.flags())), Ok(config));
+        assert_eq!(parse(&config.flags().to_string()), Ok(config));
+        assert_eq!(parse("0x10203").unwrap().node_order(), NodeOrder::Label);
+        assert_eq!(parse("tail=auto").unwrap().tail_mode(), TailMode::Auto);
+
+        let message = |s: &str| parse(s).unwrap_err().to_string();
+        assert_eq!(message("tries=0"),
+                   "invalid config: tries must be from 1 to 127, not \"0\"");
+        assert_eq!(message("tail=zip"),
+                   "invalid config: tail must be one of text, binary, auto, \
+                    not \"zip\"");
+        assert_eq!(message("colour=blue"),
+                   "invalid config: unknown field \"colour\"");
+        assert_eq!(message("tries"),
+                   "invalid config: \"tries\" is not of the form name=value");
+        assert_eq!(message("tries=2,tries=3"),
+                   "invalid config: tries is given more than once");
+        assert_eq!(message("weights=2"),
+                   "invalid config: weights must be true or false, not \"2\"");
+        assert_eq!(message("0x4000"),
+                   "invalid config: MARISA_CODE_ERROR: undefined tail mode \
+                    (0x4000)");
+        assert_eq!(message("0xfffffffff"),
+                   "invalid config: \"0xfffffffff\" is not a flags word");
+    }
+
+    #[test]
+    fn every_combination_round_trips() {
+        let _ = env_logger::init();
+        let mut num_valid = 0;
+        // A few num_tries for every value of every other field, zero (the
+        // default) included.
+        for high in 0..(1 << 15) {
+            for &num_tries in &[0, 1, 2, MAX_NUM_TRIES] {
+                let flags = (high << 7) | num_tries;
+                if let Ok(config) = Config::parse(flags) {
+                    num_valid += 1;
+                    assert_eq!(Config::parse(config.flags()), Ok(config));
+                    // With no field left to its default, exactly the same.
+                    let explicit = [NUM_TRIES_MASK, CACHE_LEVEL_MASK,
+                                    TAIL_MODE_MASK, NODE_ORDER_MASK];
+                    if explicit.iter().all(|&mask| flags & mask != 0) {
+                        assert_eq!(config.flags(), flags);
+                    }
+                }
+            }
+        }
+        // 6 cache levels, 4 tail modes, 3 node orders and 4 boolean pairs.
+        assert_eq!(num_valid, 4 * 6 * 4 * 3 * 4);
+
+        let huge = Config::new().with_cache_level(CacheLevel::Huge)
+                                .with_num_tries(NumTries::new(5));
+        assert_eq!(Config::parse(huge.flags()).unwrap().cache_level(),
+                   CacheLevel::Huge);
+        assert_eq!(Config::parse(0x00180).unwrap_err(),
+                   MarisaError::CodeError { what: "cache level",
+                                            code: 0x00180 });
+    }
+}
@@ -0,0 +1,122 @@
+//! CRC-32 (IEEE 802.3, as in zlib and PNG), to check that a dictionary
+//! file is the one that was written.
+
+use std::io;
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+#[derive(Clone)]
+pub struct Crc32 {
+    table_: [u32; 256],
+    crc_: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut x = i as u32;
+            for _ in 0..8 {
+                x = if x & 1 != 0 { (x >> 1) ^ POLYNOMIAL } else { x >> 1 };
+            }
+            *entry = x;
+        }
+        Crc32 { table_: table, crc_: !0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.crc_;
+        for &b in bytes {
+            crc = self.table_[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        self.crc_ = crc;
+    }
+
+    /// The CRC of everything passed to `update` so far.
+    pub fn value(&self) -> u32 {
+        !self.crc_
+    }
+}
+
+/// A reader or writer that hashes and counts the bytes passing through.
+pub struct Hashed<T> {
+    inner_: T,
+    crc_: Crc32,
+    len_: u64,
+}
+
+impl<T> Hashed<T> {
+    pub fn new(inner: T) -> Hashed<T> {
+        Hashed { inner_: inner, crc_: Crc32::new(), len_: 0 }
+    }
+
+    pub fn crc(&self) -> &Crc32 {
+        &self.crc_
+    }
+
+    pub fn num_bytes(&self) -> u64 {
+        self.len_
+    }
+}
+
+impl<R: io::Read> io::Read for Hashed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner_.read(buf)?;
+        self.crc_.update(&buf[..n]);
+        self.len_ += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: io::Write> io::Write for Hashed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner_.write(buf)?;
+        self.crc_.update(&buf[..n]);
+        self.len_ += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner_.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use std::io::{Read, Write};
+    use super::{Crc32, Hashed};
+
+    #[test]
+    fn known_values() {
+        let _ = env_logger::init();
+        let crc = |bytes: &[u8]| {
+            let mut c = Crc32::new();
+            c.update(bytes);
+            c.value()
+        };
+        assert_eq!(crc(b""), 0);
+        assert_eq!(crc(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc(b"The quick brown fox jumps over the lazy dog"),
+                   0x414F_A339);
+        let mut split = Crc32::new();
+        split.update(b"1234");
+        split.update(b"56789");
+        assert_eq!(split.value(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn hashed_reader_and_writer() {
+        let _ = env_logger::init();
+        let mut w = Hashed::new(Vec::new());
+        w.write_all(b"123456789").unwrap();
+        assert_eq!((w.num_bytes(), w.crc().value()), (9, 0xCBF4_3926));
+
+        let mut r = Hashed::new(&b"123456789"[..]);
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!((r.num_bytes(), r.crc().value()), (9, 0xCBF4_3926));
+    }
+}
@@ -1,6 +1,8 @@
-use std;
 use std::cmp::Ordering;
+use algo::cmp;
 use iter_util::common_count_eq;
+use key::{Bytes, Reverse};
+use sort::Label;
 
 pub struct Entry<'a> {
     slice_: &'a [u8],
@@ -11,8 +13,9 @@ impl<'a> Entry<'a> {
     pub fn new(slice: &'a [u8], id: u32) -> Entry<'a> {
         Entry { slice_: slice, id_: id }
     }
+    /// Number of bytes shared at the end of both entries.
     pub fn common_count<'b>(&'a self, rhs: &Entry<'b>) -> usize {
-        common_count_eq(self.slice_.iter(), rhs.slice_.iter())
+        common_count_eq(self.iter(), rhs.iter())
     }
     pub fn len(&self) -> usize {
         self.slice_.len()
@@ -20,8 +23,9 @@ impl<'a> Entry<'a> {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
-    pub fn iter(&self) -> std::iter::Rev<std::slice::Iter<'a, u8> > {
-        self.slice_.iter().rev()
+    /// The bytes from the last, the order the tail groups entries in.
+    pub fn iter(&self) -> Bytes<'a, Reverse> {
+        Bytes::new(self.slice_)
     }
     pub fn set_slice(&mut self, slice: &'a [u8]) {
         self.slice_ = slice;
@@ -35,15 +39,22 @@ impl<'a> Entry<'a> {
 }
 
 impl<'a> IntoIterator for &'a Entry<'a> {
-    type Item = &'a u8;
-    type IntoIter = std::iter::Rev<std::slice::Iter<'a, u8> >;
+    type Item = u8;
+    type IntoIter = Bytes<'a, Reverse>;
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-pub fn cmp_slice(l: &Entry, r: &Entry) -> Ordering {
-    l.slice_.cmp(&r.slice_)
+// Entries are visited from the end, so sorting them groups together labels
+// that are suffixes of each other, which the tail then stores once.
+impl<'a> Label for Entry<'a> {
+    fn label_len(&self) -> usize {
+        self.len()
+    }
+    fn label_at(&self, i: usize) -> u8 {
+        cmp::reverse_at(self.slice_, i)
+    }
 }
 
 pub fn cmp_id(l: &Entry, r: &Entry) -> Ordering {
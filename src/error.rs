@@ -1,3 +1,10 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+#[cfg(feature = "std")]
+use std;
+use std::fmt;
+use std::io;
+
 // Similar error codes were used by exceptions in the C++ 'marisa-trie'. Here
 // they can be repurposed as returned error codes.
 pub enum ErrorCode {
@@ -30,3 +37,143 @@ pub enum ErrorCode {
     Format,
 }
 
+
+/// An error from building, loading or using a dictionary.
+#[derive(Debug)]
+pub enum MarisaError {
+    /// A size has exceeded a library limitation. `what` names the quantity,
+    /// `actual` is how large it would have been and `limit` how large it
+    /// can be.
+    SizeError { what: &'static str, limit: u64, actual: u64 },
+    /// The key at `index` in its Keyset cannot be built, for `reason`.
+    /// `excerpt` is the start of the key, as `KeyDisplay` shows it.
+    InvalidKey { index: u64, reason: &'static str, excerpt: String },
+    /// `index` was out of range for `what`, of which there are `len`.
+    BoundError { what: &'static str, index: u64, len: u64 },
+    /// `code` does not name any `what`, such as an undefined tail mode in
+    /// config flags.
+    CodeError { what: &'static str, code: u32 },
+    /// Serialized input was truncated or malformed. `what` names the part
+    /// that could not be read.
+    FormatError { what: &'static str },
+    /// A dictionary was written in format version `found`, but this
+    /// version of the crate reads versions up to `supported` only.
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The dictionary was not built for the operation; `what` says why.
+    StateError { what: &'static str },
+    /// Key `id` was read back as a string, but only its first `valid_up_to`
+    /// bytes are UTF-8.
+    Utf8Error { id: u64, valid_up_to: u64 },
+    /// Reading a dictionary failed for a reason other than its contents.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+/// `Io` errors are equal if their kinds are, as `io::Error` itself cannot
+/// be compared.
+impl PartialEq for MarisaError {
+    fn eq(&self, other: &MarisaError) -> bool {
+        use self::MarisaError::*;
+        match (self, other) {
+            (&SizeError { what: a, limit: b, actual: c },
+             &SizeError { what: d, limit: e, actual: f }) =>
+                (a, b, c) == (d, e, f),
+            (&InvalidKey { index: a, reason: b, excerpt: ref c },
+             &InvalidKey { index: d, reason: e, excerpt: ref f }) =>
+                (a, b, c) == (d, e, f),
+            (&BoundError { what: a, index: b, len: c },
+             &BoundError { what: d, index: e, len: f }) =>
+                (a, b, c) == (d, e, f),
+            (&CodeError { what: a, code: b },
+             &CodeError { what: c, code: d }) => (a, b) == (c, d),
+            (&FormatError { what: a }, &FormatError { what: b }) => a == b,
+            (&UnsupportedVersion { found: a, supported: b },
+             &UnsupportedVersion { found: c, supported: d }) =>
+                (a, b) == (c, d),
+            (&StateError { what: a }, &StateError { what: b }) => a == b,
+            (&Utf8Error { id: a, valid_up_to: b },
+             &Utf8Error { id: c, valid_up_to: d }) => (a, b) == (c, d),
+            #[cfg(feature = "std")]
+            (&Io(ref a), &Io(ref b)) => a.kind() == b.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MarisaError {}
+
+/// Unwraps a MarisaError carried by an `io::Error`, such as the ones the
+/// readers return for malformed input. Running out of input is a
+/// FormatError; anything else is `Io`.
+#[cfg(feature = "std")]
+impl From<io::Error> for MarisaError {
+    fn from(e: io::Error) -> MarisaError {
+        if e.get_ref().map_or(false, |inner| inner.is::<MarisaError>()) {
+            let inner = e.into_inner().unwrap();
+            return *inner.downcast::<MarisaError>().unwrap();
+        }
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof =>
+                MarisaError::FormatError { what: "truncated input" },
+            _ => MarisaError::Io(e),
+        }
+    }
+}
+
+/// Without std, input only comes from byte slices, so anything but a
+/// MarisaError is running out of input.
+#[cfg(not(feature = "std"))]
+impl From<io::Error> for MarisaError {
+    fn from(e: io::Error) -> MarisaError {
+        e.into_marisa_error().unwrap_or(
+            MarisaError::FormatError { what: "truncated input" })
+    }
+}
+
+impl fmt::Display for MarisaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MarisaError::SizeError { what, limit, actual } =>
+                write!(f, "MARISA_SIZE_ERROR: {} is too large ({}, the limit \
+                           is {})", what, actual, limit),
+            MarisaError::InvalidKey { index, reason, ref excerpt } =>
+                write!(f, "MARISA_RANGE_ERROR: key {} \"{}\" {}", index,
+                       excerpt, reason),
+            MarisaError::BoundError { what, index, len } =>
+                write!(f, "MARISA_BOUND_ERROR: {} {} is out of range (there \
+                           are {})", what, index, len),
+            MarisaError::CodeError { what, code } =>
+                write!(f, "MARISA_CODE_ERROR: undefined {} ({:#x})", what,
+                       code),
+            MarisaError::FormatError { what } =>
+                write!(f, "MARISA_FORMAT_ERROR: bad {}", what),
+            MarisaError::UnsupportedVersion { found, supported } =>
+                write!(f, "MARISA_FORMAT_ERROR: format version {} is not \
+                           supported ({} is)", found, supported),
+            MarisaError::StateError { what } =>
+                write!(f, "MARISA_STATE_ERROR: {}", what),
+            MarisaError::Utf8Error { id, valid_up_to } =>
+                write!(f, "MARISA_UTF8_ERROR: key {} is not UTF-8 past byte \
+                           {}", id, valid_up_to),
+            #[cfg(feature = "std")]
+            MarisaError::Io(ref e) => write!(f, "MARISA_IO_ERROR: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MarisaError {
+    fn description(&self) -> &str {
+        match *self {
+            MarisaError::SizeError { .. } => "MARISA_SIZE_ERROR",
+            MarisaError::InvalidKey { .. } => "MARISA_RANGE_ERROR",
+            MarisaError::BoundError { .. } => "MARISA_BOUND_ERROR",
+            MarisaError::CodeError { .. } => "MARISA_CODE_ERROR",
+            MarisaError::FormatError { .. }
+            | MarisaError::UnsupportedVersion { .. } => "MARISA_FORMAT_ERROR",
+            MarisaError::StateError { .. } => "MARISA_STATE_ERROR",
+            MarisaError::Utf8Error { .. } => "MARISA_UTF8_ERROR",
+            MarisaError::Io(_) => "MARISA_IO_ERROR",
+        }
+    }
+}
@@ -1,41 +1,243 @@
+//! The start of a serialized dictionary: marisa's magic string, the format
+//! version, the version of the id assignment the dictionary was built with,
+//! its config flags, and the length and checksum of what follows.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::io;
+use crc32::Crc32;
+use error::MarisaError;
+use vector::util::{invalid_data, map_bytes, read_bytes, read_u32, read_u64,
+                   u64_from_le, write_u32, write_u64};
+
 const HEADER_SIZE: usize = 16;
 
-struct Header;
+const MAGIC: &'static [u8; HEADER_SIZE] = b"We love Marisa.\0";
+
+/// The version of the native layout `write` produces. Version 3 pads every
+/// vector to 8 bytes, starts the payload with a table of where each one
+/// starts, and stores the bit vectors' indexes, so that a mapped
+/// dictionary borrows its parts rather than copying them.
+pub const FORMAT_VERSION: u32 = 3;
 
-const header: &'static str = "We love Marisa.";
+/// The oldest version `read` accepts. Version 1, with no fields past the id
+/// algorithm version, had no way to tell a damaged file from a good one.
+pub const MIN_FORMAT_VERSION: u32 = 2;
+
+/// The format version, id algorithm version, flags and payload length.
+const FIELDS_SIZE: usize = 4 + 4 + 4 + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    version_: u32,
+    id_algorithm_version_: u32,
+    flags_: u32,
+    payload_len_: u64,
+    checksum_: u32,
+}
 
 impl Header {
+    /// A header for a payload of `payload_len` bytes, with the CRC-32
+    /// `payload` has accumulated over them.
+    pub fn new(id_algorithm_version: u32, flags: u32, payload_len: u64,
+               payload: &Crc32) -> Header {
+        let mut header = Header {
+            version_: FORMAT_VERSION,
+            id_algorithm_version_: id_algorithm_version,
+            flags_: flags,
+            payload_len_: payload_len,
+            checksum_: 0,
+        };
+        header.checksum_ = header.checksum_for(payload);
+        header
+    }
+
+    /// The format version of the payload, `FORMAT_VERSION` unless read
+    /// from an older dictionary.
+    pub fn version(&self) -> u32 {
+        self.version_
+    }
 
-    fn new() -> Header {
-        Header
+    pub fn id_algorithm_version(&self) -> u32 {
+        self.id_algorithm_version_
     }
 
-/*
-  void map(Mapper &mapper) {
-    const char *ptr;
-    mapper.map(&ptr, HEADER_SIZE);
-    MARISA_THROW_IF(!test_header(ptr), MARISA_FORMAT_ERROR);
-  }
-  void read(Reader &reader) {
-    char buf[HEADER_SIZE];
-    reader.read(buf, HEADER_SIZE);
-    MARISA_THROW_IF(!test_header(buf), MARISA_FORMAT_ERROR);
-  }
-  void write(Writer &writer) const {
-    writer.write(get_header(), HEADER_SIZE);
-  }
-*/
+    /// The config flags and cache level of the dictionary.
+    pub fn flags(&self) -> u32 {
+        self.flags_
+    }
 
-    fn io_size() -> usize {
-        HEADER_SIZE
+    pub fn payload_len(&self) -> u64 {
+        self.payload_len_
     }
 
-    fn get_header() -> &'static str {
-        header
+    /// Whether the payload hashed into `payload` is the one this header was
+    /// written for. The checksum covers the header's fields too, so a
+    /// damaged flag or length is caught as well.
+    pub fn checks(&self, payload: &Crc32) -> bool {
+        self.checksum_for(payload) == self.checksum_
+    }
+
+    fn checksum_for(&self, payload: &Crc32) -> u32 {
+        let mut crc = payload.clone();
+        crc.update(&self.fields());
+        crc.value()
+    }
+
+    fn fields(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FIELDS_SIZE);
+        write_u32(&mut bytes, self.version_).unwrap();
+        write_u32(&mut bytes, self.id_algorithm_version_).unwrap();
+        write_u32(&mut bytes, self.flags_).unwrap();
+        write_u64(&mut bytes, self.payload_len_).unwrap();
+        bytes
+    }
+
+    /// The number of bytes `write` produces.
+    pub fn io_size() -> usize {
+        HEADER_SIZE + FIELDS_SIZE + 4
     }
 
-    fn test_header<'a>(x: &'a str) -> bool {
-        header == x
+    pub fn write<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&self.fields())?;
+        write_u32(w, self.checksum_)
+    }
+
+    /// Reads a header, checking the magic string and then the format
+    /// version before anything else.
+    pub fn read<R: io::Read>(r: &mut R) -> io::Result<Header> {
+        if &read_bytes(r, HEADER_SIZE)?[..] != &MAGIC[..] {
+            return Err(invalid_data("header"));
+        }
+        let version = read_u32(r)?;
+        check_version(version)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Header {
+            version_: version,
+            id_algorithm_version_: read_u32(r)?,
+            flags_: read_u32(r)?,
+            payload_len_: read_u64(r)?,
+            checksum_: read_u32(r)?,
+        })
+    }
+
+    /// libmarisa's header is the magic string alone.
+    pub fn write_libmarisa<W: io::Write>(w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)
+    }
+
+    pub fn read_libmarisa<R: io::Read>(r: &mut R) -> io::Result<()> {
+        if &read_bytes(r, HEADER_SIZE)?[..] != &MAGIC[..] {
+            return Err(invalid_data("header"));
+        }
+        Ok(())
+    }
+
+    /// Reads a Header written by `write` from the start of `buf`, returning
+    /// it and the number of bytes it took up.
+    pub fn map(buf: &[u8]) -> Result<(Header, usize), MarisaError> {
+        let mut pos = 0;
+        if map_bytes(buf, &mut pos, HEADER_SIZE, "header")? != &MAGIC[..] {
+            return Err(MarisaError::FormatError { what: "header" });
+        }
+        let u32_at = |pos: &mut usize| {
+            map_bytes(buf, pos, 4, "header").map(|b| u64_from_le(b) as u32)
+        };
+        let version = u32_at(&mut pos)?;
+        check_version(version)?;
+        let id_algorithm_version = u32_at(&mut pos)?;
+        let flags = u32_at(&mut pos)?;
+        let payload_len = u64_from_le(map_bytes(buf, &mut pos, 8, "header")?);
+        let checksum = u32_at(&mut pos)?;
+        Ok((Header {
+            version_: version,
+            id_algorithm_version_: id_algorithm_version,
+            flags_: flags,
+            payload_len_: payload_len,
+            checksum_: checksum,
+        }, pos))
     }
 }
 
+fn check_version(found: u32) -> Result<(), MarisaError> {
+    if MIN_FORMAT_VERSION <= found && found <= FORMAT_VERSION {
+        Ok(())
+    } else {
+        Err(MarisaError::UnsupportedVersion { found: found,
+                                              supported: FORMAT_VERSION })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use crc32::Crc32;
+    use error::MarisaError;
+    use super::{FORMAT_VERSION, Header};
+
+    #[test]
+    fn io_round_trip() {
+        let _ = env_logger::init();
+        let mut payload = Crc32::new();
+        payload.update(b"payload");
+        let header = Header::new(7, 0x1234, 7, &payload);
+        assert!(header.checks(&payload));
+        assert!(!header.checks(&Crc32::new()));
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), Header::io_size());
+        assert_eq!(Header::read(&mut &bytes[..]).unwrap(), header);
+        assert_eq!(Header::map(&bytes), Ok((header, bytes.len())));
+        for len in 0..bytes.len() {
+            assert!(Header::read(&mut &bytes[..len]).is_err());
+            assert!(Header::map(&bytes[..len]).is_err());
+        }
+        // Every field is covered by the checksum.
+        for i in 16..bytes.len() {
+            let mut bad = bytes.clone();
+            bad[i] ^= 0x10;
+            if let Ok(read) = Header::read(&mut &bad[..]) {
+                assert!(!read.checks(&payload), "byte {}", i);
+            }
+        }
+        bytes[3] = b'L';
+        assert!(Header::read(&mut &bytes[..]).is_err());
+        assert!(Header::map(&bytes).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn older_and_newer_versions() {
+        let _ = env_logger::init();
+        let mut bytes = Vec::new();
+        Header::new(1, 0, 0, &Crc32::new()).write(&mut bytes).unwrap();
+        bytes[16] = FORMAT_VERSION as u8 + 1;
+        let expected = MarisaError::UnsupportedVersion {
+            found: FORMAT_VERSION + 1, supported: FORMAT_VERSION };
+        assert_eq!(Header::map(&bytes).unwrap_err(), expected);
+        let err = Header::read(&mut &bytes[..]).unwrap_err();
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref()),
+                   Some(&expected));
+
+        // Version 2 is still read, and its checksum covers its own version.
+        let mut payload = Crc32::new();
+        payload.update(b"payload");
+        let mut old = Header::new(1, 0, 7, &payload);
+        old.version_ = 2;
+        old.checksum_ = old.checksum_for(&payload);
+        bytes.clear();
+        old.write(&mut bytes).unwrap();
+        assert_eq!(bytes[16], 2);
+        let read = Header::read(&mut &bytes[..]).unwrap();
+        assert_eq!(read.version(), 2);
+        assert!(read.checks(&payload));
+        assert_eq!(Header::map(&bytes), Ok((read, bytes.len())));
+        bytes[16] = 1;
+        assert_eq!(Header::map(&bytes).unwrap_err(),
+                   MarisaError::UnsupportedVersion {
+                       found: 1, supported: FORMAT_VERSION });
+    }
+}
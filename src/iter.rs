@@ -0,0 +1,9 @@
+//! The iterators returned by `LoudsTrie`'s searches, named so that they can
+//! be stored in struct fields and used in signatures. Each borrows the trie
+//! for `'a` and the query for `'b`, and keeps returning None once done.
+//! `PredictiveCursor` also borrows its `SearchState` for `'s`, and
+//! `Intersection` and `Difference` borrow both tries for `'a`.
+
+pub use louds_trie::{CommonPrefixIter, Difference, Intersection,
+                     LossyPredictiveIter, PredictiveCursor, PredictiveIter,
+                     StrPredictiveIter, WeightedPredictiveIter};
@@ -1,11 +1,11 @@
 pub fn common_count<L: Iterator, R: Iterator, F: Fn(L::Item, R::Item) -> bool>(
   l: L, r: R, f: F) -> usize {
     let mut c = 0;
-    for ((i, li), ri) in l.enumerate().zip(r) {
-        c = i;
+    for (li, ri) in l.zip(r) {
         if !f(li, ri) {
-            return c;
+            break;
         }
+        c += 1;
     }
     c
 }
@@ -24,6 +24,8 @@ mod test {
         let l = [4, 5, 6];
         let r = [4, 5, 7, 8];
         assert!(2 == common_count(l.iter(), r.iter(), |a, b| a == b));
+        assert!(3 == common_count(l.iter(), l.iter(), |a, b| a == b));
+        assert!(0 == common_count(l.iter(), [].iter(), |a, b| a == b));
     }
 }
 
@@ -1,36 +1,89 @@
 use std;
+use std::marker::PhantomData;
+use algo::cmp;
 use entry::Entry;
+use iter_util::common_count_eq;
+use sort::Label;
 
+/// What a Union last held. Only tracked in debug builds, to catch reading
+/// a terminal as a weight or the other way around.
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Variant {
+    Unset,
+    Weight,
+    Terminal,
+}
+
+/// A key's weight until the builder has placed it, and its terminal node
+/// after.
 #[derive(Copy, Clone, Debug)]
 struct Union {
     // weight or terminal
     bits_: u32,
+    #[cfg(debug_assertions)]
+    variant_: Variant,
 }
 
 impl Union {
+    #[cfg(debug_assertions)]
+    fn new() -> Union {
+        Union { bits_: 0, variant_: Variant::Unset }
+    }
+    #[cfg(not(debug_assertions))]
     fn new() -> Union {
         Union { bits_: 0 }
     }
 
+    #[cfg(debug_assertions)]
+    fn expect(&self, variant: Variant) {
+        assert!(self.variant_ == variant || self.variant_ == Variant::Unset,
+                "MARISA_STATE_ERROR: read {:?} from a key holding {:?}",
+                variant, self.variant_);
+    }
+    #[cfg(debug_assertions)]
+    fn hold(&mut self, variant: Variant) {
+        self.variant_ = variant;
+    }
+    #[cfg(not(debug_assertions))]
+    fn expect(&self, _: Variant) {}
+    #[cfg(not(debug_assertions))]
+    fn hold(&mut self, _: Variant) {}
+
     fn get_weight(&self) -> f32 {
-        unsafe { std::mem::transmute(self.bits_) }
+        self.expect(Variant::Weight);
+        f32::from_bits(self.bits_)
     }
     fn get_terminal(&self) -> usize {
+        self.expect(Variant::Terminal);
         self.bits_ as usize
     }
 
     fn set_weight(&mut self, weight: f32) {
-        self.bits_ = unsafe { std::mem::transmute(weight) };
+        self.bits_ = weight.to_bits();
+        self.hold(Variant::Weight);
     }
     fn set_terminal(&mut self, terminal: usize) {
         assert!(terminal <= std::u32::MAX as usize, "MARISA_SIZE_ERROR");
         self.bits_ = terminal as u32;
+        self.hold(Variant::Terminal);
     }
 }
 
 pub trait IKey<'a> {
+    /// Which end `at` and `iter` read from.
+    type Direction: Direction;
+
     // Could replace this with Index trait
     fn at(&self, i: usize) -> u8;
+    /// The bytes as `at` reads them, without an index per byte.
+    fn iter(&self) -> Bytes<'a, Self::Direction> {
+        Bytes::new(self.get_slice())
+    }
+    /// The number of bytes both keys read before they differ.
+    fn common_prefix_len(&self, other: &Self) -> usize where Self: Sized {
+        common_count_eq(self.iter(), other.iter())
+    }
 
     fn subslice(&mut self, pos: usize, length: usize);
 
@@ -47,107 +100,114 @@ pub trait IKey<'a> {
     fn len(&self) -> usize;
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Key<'a> {
-    slice_: &'a[u8],
-    union_: Union,
-    id_: u32,
+/// Which end of the slice a key is read from. `at` and `subslice` count
+/// positions from that end, and `cmp` compares bytes in that order, which
+/// is the order the trie built from the keys visits them.
+pub trait Direction: Copy + Clone + std::fmt::Debug {
+    fn at(s: &[u8], i: usize) -> u8;
+    /// The `length` bytes starting `pos` bytes from this end of `s`.
+    fn subslice(s: &[u8], pos: usize, length: usize) -> &[u8];
+    fn cmp(l: &[u8], r: &[u8]) -> std::cmp::Ordering;
+    /// The next byte from this end of `iter`.
+    fn next<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8>;
+    /// The next byte from the other end of `iter`.
+    fn next_back<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8>;
 }
 
-impl<'a> Key<'a> {
-    pub fn new(slice: &'a[u8]) -> Key<'a> {
-        Key { slice_: slice, union_: Union::new(), id_: 0 }
-    }
-    pub fn with_weight(&self, weight: f32) -> Self {
-        let mut out = *self;
-        out.set_weight(weight);
-        out
-    }
-    pub fn from_key<T: IKey<'a>>(key: &T) -> Self {
-        Self::new(key.get_slice()).with_weight(key.get_weight())
-    }
-}
+/// Read from the start of the slice, as the first trie is built.
+#[derive(Copy, Clone, Debug)]
+pub struct Forward;
 
-impl<'a> From<&'a[u8]> for Key<'a> {
-    fn from(slice: &'a[u8]) -> Key<'a> {
-        Key::new(slice)
-    }
-}
+/// Read from the end of the slice, as the next tries and the tail are.
+#[derive(Copy, Clone, Debug)]
+pub struct Reverse;
 
-impl<'a> IKey<'a> for Key<'a> {
-    fn at(&self, i: usize) -> u8 {
-        self.slice_[i]
+impl Direction for Forward {
+    fn at(s: &[u8], i: usize) -> u8 {
+        cmp::forward_at(s, i)
     }
-    fn subslice(&mut self, pos: usize, length: usize) {
-        assert!(length <= self.slice_.len(), "MARISA_BOUND_ERROR");
-        assert!(pos <= self.slice_.len() - length, "MARISA_BOUND_ERROR");
-        self.slice_ = &self.slice_[pos..pos+length];
+    fn subslice(s: &[u8], pos: usize, length: usize) -> &[u8] {
+        &s[pos..pos + length]
     }
-    fn set_slice(&mut self, slice: &'a[u8]) {
-        assert!(slice.len() <= std::u32::MAX as usize, "MARISA_SIZE_ERROR");
-        self.slice_ = slice;
+    fn cmp(l: &[u8], r: &[u8]) -> std::cmp::Ordering {
+        cmp::forward(l, r)
     }
-    fn set_weight(&mut self, weight: f32) {
-        self.union_.set_weight(weight);
+    fn next<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8> {
+        iter.next()
     }
-    fn set_terminal(&mut self, terminal: usize) {
-        self.union_.set_terminal(terminal);
+    fn next_back<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8> {
+        iter.next_back()
     }
-    fn set_id(&mut self, id: usize) {
-        assert!(id <= std::u32::MAX as usize, "MARISA_SIZE_ERROR");
-        self.id_ = id as u32;
+}
+
+impl Direction for Reverse {
+    fn at(s: &[u8], i: usize) -> u8 {
+        cmp::reverse_at(s, i)
     }
-    fn get_slice(&self) -> &'a[u8] {
-        self.slice_
+    fn subslice(s: &[u8], pos: usize, length: usize) -> &[u8] {
+        let end = s.len() - pos;
+        &s[end - length..end]
     }
-    fn get_weight(&self) -> f32 {
-        self.union_.get_weight()
+    fn cmp(l: &[u8], r: &[u8]) -> std::cmp::Ordering {
+        cmp::reverse(l, r)
     }
-    fn get_terminal(&self) -> usize {
-        self.union_.get_terminal()
+    fn next<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8> {
+        iter.next_back()
     }
-    fn get_id(&self) -> usize {
-        self.id_ as usize
-    }
-    fn len(&self) -> usize {
-        self.get_slice().len()
+    fn next_back<'a>(iter: &mut std::slice::Iter<'a, u8>) -> Option<&'a u8> {
+        iter.next()
     }
 }
 
-impl<'a> PartialEq for Key<'a> {
-    fn eq(&self, rhs: &Self) -> bool {
-        self.get_slice() == rhs.get_slice()
-    }
+/// The bytes of a slice in the order `D` reads them, so `next` yields what
+/// `at(0)`, `at(1)`, ... would. Reversing it reads them the other way.
+#[derive(Clone, Debug)]
+pub struct Bytes<'a, D: Direction> {
+    iter_: std::slice::Iter<'a, u8>,
+    direction_: PhantomData<D>,
 }
 
-impl<'a> Eq for Key<'a> {}
+impl<'a, D: Direction> Bytes<'a, D> {
+    pub fn new(slice: &'a [u8]) -> Bytes<'a, D> {
+        Bytes { iter_: slice.iter(), direction_: PhantomData }
+    }
+}
 
-impl<'a> PartialOrd for Key<'a> {
-    fn partial_cmp(&self, rhs: &Self) -> Option<std::cmp::Ordering> {
-        self.get_slice().partial_cmp(rhs.get_slice())
+impl<'a, D: Direction> Iterator for Bytes<'a, D> {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        D::next(&mut self.iter_).cloned()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter_.size_hint()
     }
 }
 
-impl<'a> Ord for Key<'a> {
-    fn cmp(&self, rhs: &Self) -> std::cmp::Ordering {
-        self.get_slice().cmp(rhs.get_slice())
+impl<'a, D: Direction> DoubleEndedIterator for Bytes<'a, D> {
+    fn next_back(&mut self) -> Option<u8> {
+        D::next_back(&mut self.iter_).cloned()
     }
 }
 
-/// Just like Key, except we index and subslice from the end of the slice
+impl<'a, D: Direction> ExactSizeIterator for Bytes<'a, D> {}
+
 #[derive(Copy, Clone, Debug)]
-pub struct ReverseKey<'a> {
+pub struct GenericKey<'a, D: Direction> {
     slice_: &'a[u8],
     union_: Union,
     id_: u32,
+    direction_: PhantomData<D>,
 }
 
-// FIXME: Reduce amount of identical code between Key and ReverseKey. Only
-//        at() and subslice() are different at all!
+pub type Key<'a> = GenericKey<'a, Forward>;
+
+/// Just like Key, except we index and subslice from the end of the slice
+pub type ReverseKey<'a> = GenericKey<'a, Reverse>;
 
-impl<'a> ReverseKey<'a> {
-    pub fn new(slice: &'a[u8]) -> ReverseKey<'a> {
-        ReverseKey { slice_: slice, union_: Union::new(), id_: 0 }
+impl<'a, D: Direction> GenericKey<'a, D> {
+    pub fn new(slice: &'a[u8]) -> GenericKey<'a, D> {
+        GenericKey { slice_: slice, union_: Union::new(), id_: 0,
+                     direction_: PhantomData }
     }
     pub fn with_weight(&self, weight: f32) -> Self {
         let mut out = *self;
@@ -159,22 +219,22 @@ impl<'a> ReverseKey<'a> {
     }
 }
 
-impl<'a> From<&'a[u8]> for ReverseKey<'a> {
-    fn from(slice: &'a[u8]) -> ReverseKey<'a> {
-        ReverseKey::new(slice)
+impl<'a, D: Direction> From<&'a[u8]> for GenericKey<'a, D> {
+    fn from(slice: &'a[u8]) -> GenericKey<'a, D> {
+        GenericKey::new(slice)
     }
 }
 
-impl<'a> IKey<'a> for ReverseKey<'a> {
+impl<'a, D: Direction> IKey<'a> for GenericKey<'a, D> {
+    type Direction = D;
+
     fn at(&self, i: usize) -> u8 {
-        self.slice_[self.slice_.len() - i - 1]
+        D::at(self.slice_, i)
     }
     fn subslice(&mut self, pos: usize, length: usize) {
         assert!(length <= self.slice_.len(), "MARISA_BOUND_ERROR");
         assert!(pos <= self.slice_.len() - length, "MARISA_BOUND_ERROR");
-        let new_end = self.slice_.len() - pos;
-        let new_begin = new_end - length;
-        self.slice_ = &self.slice_[new_begin..new_end];
+        self.slice_ = D::subslice(self.slice_, pos, length);
     }
     fn set_slice(&mut self, slice: &'a[u8]) {
         assert!(slice.len() <= std::u32::MAX as usize, "MARISA_SIZE_ERROR");
@@ -207,23 +267,171 @@ impl<'a> IKey<'a> for ReverseKey<'a> {
     }
 }
 
-impl<'a> PartialEq for ReverseKey<'a> {
+impl<'a, D: Direction> Label for GenericKey<'a, D> {
+    fn label_len(&self) -> usize {
+        self.len()
+    }
+    fn label_at(&self, i: usize) -> u8 {
+        self.at(i)
+    }
+}
+
+impl<'a, D: Direction> PartialEq for GenericKey<'a, D> {
     fn eq(&self, rhs: &Self) -> bool {
         self.get_slice() == rhs.get_slice()
     }
 }
 
-impl<'a> Eq for ReverseKey<'a> {}
+impl<'a, D: Direction> Eq for GenericKey<'a, D> {}
 
-impl<'a> PartialOrd for ReverseKey<'a> {
+// Ordered by the bytes as they are visited, so from the end of the slice
+// for a ReverseKey. build_current_trie relies on this to group keys by their
+// shared prefix.
+impl<'a, D: Direction> PartialOrd for GenericKey<'a, D> {
     fn partial_cmp(&self, rhs: &Self) -> Option<std::cmp::Ordering> {
-        self.get_slice().partial_cmp(rhs.get_slice())
+        Some(self.cmp(rhs))
     }
 }
 
-impl<'a> Ord for ReverseKey<'a> {
+impl<'a, D: Direction> Ord for GenericKey<'a, D> {
     fn cmp(&self, rhs: &Self) -> std::cmp::Ordering {
-        self.get_slice().cmp(rhs.get_slice())
+        D::cmp(self.get_slice(), rhs.get_slice())
     }
 }
 
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use entry::Entry;
+    use super::{IKey, Key, ReverseKey};
+
+    fn reverse_at_prop(s: Vec<u8>) -> bool {
+        let key = ReverseKey::new(&s);
+        (0..s.len()).all(|i| key.at(i) == s[s.len() - 1 - i])
+    }
+
+    #[test]
+    fn reverse_at_reads_from_the_end() {
+        let _ = env_logger::init();
+        qc::quickcheck(reverse_at_prop as fn(Vec<u8>) -> bool);
+    }
+
+    fn iter_prop(s: Vec<u8>, t: Vec<u8>) -> bool {
+        let (key, reverse) = (Key::new(&s), ReverseKey::new(&s));
+        key.iter().eq((0..s.len()).map(|i| key.at(i)))
+        && reverse.iter().eq((0..s.len()).map(|i| reverse.at(i)))
+        && key.iter().rev().eq(reverse.iter())
+        && reverse.iter().rev().eq(s.iter().cloned())
+        && key.iter().len() == s.len()
+        && Entry::new(&s, 0).iter().eq(reverse.iter())
+        && key.common_prefix_len(&Key::new(&t))
+           == (0..s.len()).take_while(|&i| i < t.len() && s[i] == t[i]).count()
+        && reverse.common_prefix_len(&ReverseKey::new(&t))
+           == Entry::new(&s, 0).common_count(&Entry::new(&t, 1))
+    }
+
+    #[test]
+    fn iter_agrees_with_at() {
+        let _ = env_logger::init();
+        qc::quickcheck(iter_prop as fn(Vec<u8>, Vec<u8>) -> bool);
+        assert!(iter_prop(b"abcd".to_vec(), b"abxd".to_vec()));
+        assert!(iter_prop(b"abcd".to_vec(), b"xbcd".to_vec()));
+        let mut bytes = ReverseKey::new(b"abcd").iter();
+        assert_eq!((bytes.next(), bytes.next_back()), (Some(b'd'), Some(b'a')));
+        assert_eq!(bytes.len(), 2);
+        assert_eq!(bytes.collect::<Vec<u8>>(), b"cb");
+    }
+
+    /// Subslicing either key leaves the bytes it would have read next.
+    fn subslice_prop(s: Vec<u8>, pos: usize, length: usize) -> bool {
+        let length = if s.is_empty() { 0 } else { length % (s.len() + 1) };
+        let pos = pos % (s.len() - length + 1);
+        let mut key = Key::new(&s);
+        let mut reverse = ReverseKey::new(&s);
+        key.subslice(pos, length);
+        reverse.subslice(pos, length);
+        (0..length).all(|i| key.at(i) == Key::new(&s).at(pos + i)
+                            && reverse.at(i) == ReverseKey::new(&s).at(pos + i))
+    }
+
+    #[test]
+    fn subslice_keeps_the_direction() {
+        let _ = env_logger::init();
+        qc::quickcheck(subslice_prop as fn(Vec<u8>, usize, usize) -> bool);
+    }
+
+    fn reverse_sort_prop(v: Vec<Vec<u8>>) -> bool {
+        let mut keys: Vec<ReverseKey> = v.iter().map(|s| ReverseKey::new(s))
+                                         .collect();
+        keys.sort();
+        let mut reversed: Vec<Vec<u8>> = v.iter().map(|s| {
+            s.iter().rev().cloned().collect()
+        }).collect();
+        reversed.sort();
+        keys.iter().zip(&reversed).all(|(k, r)| {
+            k.get_slice().iter().rev().eq(r.iter())
+        })
+    }
+
+    /// ReverseKeys sort in the order the reverse tries visit their bytes,
+    /// which is not the order of their slices.
+    #[test]
+    fn reverse_keys_sort_by_reversed_slices() {
+        let _ = env_logger::init();
+        qc::quickcheck(reverse_sort_prop as fn(Vec<Vec<u8>>) -> bool);
+        let v = vec![b"ab".to_vec(), b"ba".to_vec(), b"b".to_vec()];
+        assert!(reverse_sort_prop(v));
+        let mut keys = vec![ReverseKey::new(b"ab"), ReverseKey::new(b"ba")];
+        keys.sort();
+        assert_eq!(keys[0].get_slice(), b"ba");
+    }
+
+    /// Every f32 bit pattern, NaN payloads included, reads back as written.
+    fn weight_bits_prop(bits: u32) -> bool {
+        let weight = f32::from_bits(bits);
+        let key = Key::new(b"k").with_weight(weight);
+        let reverse = ReverseKey::from_key(&key);
+        key.get_weight().to_bits() == bits
+        && reverse.get_weight().to_bits() == bits
+        && Key::from_key(&reverse).get_weight().to_bits() == bits
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let _ = env_logger::init();
+        qc::quickcheck(weight_bits_prop as fn(u32) -> bool);
+        for &bits in &[0, 0x8000_0000, 0x7F80_0000, 0xFF80_0000, 0x7FC0_0001,
+                       0xFFFF_FFFF, 1] {
+            assert!(weight_bits_prop(bits), "{:#x}", bits);
+        }
+    }
+
+    #[test]
+    fn terminal_replaces_weight() {
+        let _ = env_logger::init();
+        let mut key = Key::new(b"k").with_weight(2.5);
+        key.set_terminal(7);
+        assert_eq!(key.get_terminal(), 7);
+        key.set_weight(0.5);
+        assert_eq!(key.get_weight(), 0.5);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "MARISA_STATE_ERROR")]
+    fn weight_of_placed_key_panics() {
+        let mut key = ReverseKey::new(b"k").with_weight(2.5);
+        key.set_terminal(7);
+        key.get_weight();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "MARISA_STATE_ERROR")]
+    fn terminal_of_unplaced_key_panics() {
+        Key::new(b"k").with_weight(2.5).get_terminal();
+    }
+}
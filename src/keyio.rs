@@ -0,0 +1,194 @@
+//! Reading keys from text files, one key per line.
+//!
+//! Key files written on Windows end their lines with "\r\n" and often start
+//! with a UTF-8 byte order mark. Taken literally, every key would end in a
+//! '\r' and the first would start with EF BB BF, and lookups of the clean
+//! keys would miss. The readers here strip both and count what they
+//! stripped, so the tools can report it.
+
+use std::ascii;
+use std::cmp::min;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use keyset::Keyset;
+
+/// The UTF-8 encoding of U+FEFF, as written at the start of a text file.
+pub const UTF8_BOM: &'static [u8] = b"\xEF\xBB\xBF";
+
+/// `line` without its line ending: a trailing "\n", "\r\n" or "\r".
+pub fn normalize_line(line: &[u8]) -> &[u8] {
+    let line = if line.ends_with(b"\n") { &line[..line.len() - 1] }
+               else { line };
+    if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line }
+}
+
+/// `line` without a leading byte order mark.
+pub fn strip_bom(line: &[u8]) -> &[u8] {
+    if line.starts_with(UTF8_BOM) { &line[UTF8_BOM.len()..] } else { line }
+}
+
+/// How many bytes of a key `KeyDisplay` shows by default.
+pub const KEY_EXCERPT_LEN: usize = 32;
+
+/// Shows the start of a key in messages: printable ASCII as it is, other
+/// bytes escaped as `\xNN`, and `...` if the key goes on past `max_len`
+/// bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyDisplay<'a> {
+    bytes_: &'a [u8],
+    max_len_: usize,
+}
+
+impl<'a> KeyDisplay<'a> {
+    pub fn new(bytes: &'a [u8]) -> KeyDisplay<'a> {
+        KeyDisplay { bytes_: bytes, max_len_: KEY_EXCERPT_LEN }
+    }
+    pub fn with_max_len(mut self, max_len: usize) -> KeyDisplay<'a> {
+        self.max_len_ = max_len;
+        self
+    }
+}
+
+impl<'a> fmt::Display for KeyDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown = &self.bytes_[..min(self.bytes_.len(), self.max_len_)];
+        for &b in shown {
+            for c in ascii::escape_default(b) {
+                write!(f, "{}", c as char)?;
+            }
+        }
+        if shown.len() < self.bytes_.len() {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of the Windows text file artifacts found in, or stripped from,
+/// a set of keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineArtifacts {
+    /// Keys ending in '\r'.
+    pub crs: usize,
+    /// Keys starting with a byte order mark.
+    pub boms: usize,
+}
+
+impl LineArtifacts {
+    pub fn is_empty(&self) -> bool {
+        self.crs == 0 && self.boms == 0
+    }
+}
+
+impl fmt::Display for LineArtifacts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} trailing carriage return{}, {} byte order mark{}",
+               self.crs, if self.crs == 1 { "" } else { "s" },
+               self.boms, if self.boms == 1 { "" } else { "s" })
+    }
+}
+
+/// Pushes each line of `r` into `keyset`, without its "\n". A byte order
+/// mark at the start of the first line is always stripped, with a warning;
+/// a '\r' before the "\n" is stripped if `strip_cr` is set, as it should
+/// be for text. Returns what was stripped.
+#[cfg(feature = "std")]
+pub fn read_keys<R: io::BufRead>(r: R, keyset: &mut Keyset, strip_cr: bool)
+  -> io::Result<LineArtifacts> {
+    let mut out = LineArtifacts::default();
+    for (i, line) in r.split(b'\n').enumerate() {
+        let line = line?;
+        let mut key = &line[..];
+        if i == 0 && key.starts_with(UTF8_BOM) {
+            warn!("stripped a byte order mark from the first key");
+            key = strip_bom(key);
+            out.boms += 1;
+        }
+        if strip_cr && key.ends_with(b"\r") {
+            key = normalize_line(key);
+            out.crs += 1;
+        }
+        keyset.push(key);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use config::Config;
+    use keyset::Keyset;
+    use louds_trie::LoudsTrie;
+    #[cfg(feature = "std")]
+    use super::read_keys;
+    use super::{KeyDisplay, LineArtifacts, normalize_line, strip_bom};
+
+    const CLEAN: &'static [u8] =
+        include_bytes!("../tests/data/keys_clean.txt");
+    const WINDOWS: &'static [u8] =
+        include_bytes!("../tests/data/keys_crlf_bom.txt");
+
+    #[test]
+    fn line_endings_and_bom() {
+        let _ = env_logger::init();
+        assert_eq!(normalize_line(b"key\r\n"), b"key");
+        assert_eq!(normalize_line(b"key\n"), b"key");
+        assert_eq!(normalize_line(b"key\r"), b"key");
+        assert_eq!(normalize_line(b"key"), b"key");
+        assert_eq!(normalize_line(b"k\ry\r\r\n"), b"k\ry\r");
+        assert_eq!(normalize_line(b""), b"");
+        assert_eq!(strip_bom(b"\xEF\xBB\xBFkey"), b"key");
+        assert_eq!(strip_bom(b"\xEF\xBBkey"), b"\xEF\xBBkey");
+    }
+
+    #[test]
+    fn key_display_escapes_and_truncates() {
+        let _ = env_logger::init();
+        let show = |key: &[u8], max_len| {
+            KeyDisplay::new(key).with_max_len(max_len).to_string()
+        };
+        assert_eq!(show(b"plain key", 32), "plain key");
+        assert_eq!(show(b"\0\r\n\xEF\"", 32), "\\x00\\r\\n\\xef\\\"");
+        assert_eq!(show(b"abcdef", 6), "abcdef");
+        assert_eq!(show(b"abcdef", 3), "abc...");
+        assert_eq!(show(b"", 0), "");
+        assert_eq!(KeyDisplay::new(&[b'a'; 40]).to_string().len(), 35);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn windows_file_builds_like_clean_file() {
+        let _ = env_logger::init();
+        let mut clean = Keyset::new();
+        assert!(read_keys(CLEAN, &mut clean, true).unwrap().is_empty());
+        let mut windows = Keyset::new();
+        let stripped = read_keys(WINDOWS, &mut windows, true).unwrap();
+        let num_lines = clean.len();
+        assert_eq!(stripped, LineArtifacts { crs: num_lines, boms: 1 });
+        assert_eq!(stripped.to_string(),
+                   format!("{} trailing carriage returns, 1 byte order mark",
+                           num_lines));
+        assert!(windows.validate().is_empty());
+
+        let bytes = |keyset: &mut Keyset| {
+            let trie = LoudsTrie::build(keyset, &Config::new()).unwrap();
+            let mut out = Vec::new();
+            trie.write_to(&mut out).unwrap();
+            out
+        };
+        assert_eq!(bytes(&mut windows), bytes(&mut clean));
+
+        // With the '\r's kept, none of the clean keys is found.
+        let mut literal = Keyset::new();
+        let kept = read_keys(WINDOWS, &mut literal, false).unwrap();
+        assert_eq!(kept, LineArtifacts { crs: 0, boms: 1 });
+        assert_eq!(literal.validate(),
+                   LineArtifacts { crs: num_lines, boms: 0 });
+        let trie = LoudsTrie::build(&mut literal, &Config::new()).unwrap();
+        assert!(clean.iter().all(|key| trie.lookup(key).is_none()));
+    }
+}
@@ -0,0 +1,488 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use std::iter::FromIterator;
+use base::INVALID_KEY_ID;
+use error::MarisaError;
+use keyio::{KeyDisplay, LineArtifacts, UTF8_BOM};
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck as qc;
+
+/// Short keys are packed into blocks of this size, so pushing many keys does
+/// not cost one allocation per key.
+const BASE_BLOCK_SIZE: usize = 4096;
+
+/// Keys longer than this get a block of their own instead of wasting the
+/// rest of a base block.
+const EXTRA_BLOCK_SIZE: usize = 1024;
+
+#[derive(Copy, Clone, Debug)]
+struct KeyRef {
+    block_: u32,
+    offset_: u32,
+    length_: u32,
+    weight_: f32,
+    id_: u32,
+}
+
+/// A set of keys to build a dictionary from.
+///
+/// Key bytes are copied into pooled blocks owned by the Keyset. Once a
+/// dictionary has been built from it, each key exposes the id it was
+/// assigned.
+#[derive(Clone, Debug)]
+pub struct Keyset {
+    blocks_: Vec<Vec<u8>>,
+    base_block_: Option<usize>,
+    keys_: Vec<KeyRef>,
+    total_length_: usize,
+}
+
+impl Keyset {
+    pub fn new() -> Keyset {
+        Keyset { blocks_: Vec::new(), base_block_: None, keys_: Vec::new(),
+                 total_length_: 0 }
+    }
+
+    /// Adds a key with the default weight of 1.0.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.push_weighted(bytes, 1.0);
+    }
+
+    /// Adds a key with the given weight. Weights of duplicate keys are
+    /// summed when building. Panics with the error `try_push_weighted`
+    /// would return.
+    pub fn push_weighted(&mut self, bytes: &[u8], weight: f32) {
+        if let Err(e) = self.try_push_weighted(bytes, weight) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Adds a key with the given weight, or fails with an InvalidKey error
+    /// naming the index it would have had, leaving the keyset as it was.
+    /// Keys must fit a u32 length, and weights must not be negative or NaN,
+    /// as neither sums or orders nodes sensibly.
+    pub fn try_push_weighted(&mut self, bytes: &[u8], weight: f32)
+      -> Result<(), MarisaError> {
+        self.try_push_(bytes, weight, std::u32::MAX as usize)
+    }
+
+    fn try_push_(&mut self, bytes: &[u8], weight: f32, max_len: usize)
+      -> Result<(), MarisaError> {
+        let reason = if !(weight >= 0.0) {
+            "has a negative or NaN weight"
+        } else if bytes.len() > max_len {
+            "is too long"
+        } else {
+            self.push_(bytes, weight);
+            return Ok(());
+        };
+        Err(MarisaError::InvalidKey {
+            index: self.len() as u64, reason: reason,
+            excerpt: KeyDisplay::new(bytes).to_string() })
+    }
+
+    fn push_(&mut self, bytes: &[u8], weight: f32) {
+        let block = if bytes.len() > EXTRA_BLOCK_SIZE {
+            self.blocks_.push(Vec::with_capacity(bytes.len()));
+            self.blocks_.len() - 1
+        } else {
+            match self.base_block_ {
+                Some(block) if self.blocks_[block].capacity()
+                               - self.blocks_[block].len() >= bytes.len()
+                    => block,
+                _ => {
+                    self.blocks_.push(Vec::with_capacity(BASE_BLOCK_SIZE));
+                    self.base_block_ = Some(self.blocks_.len() - 1);
+                    self.blocks_.len() - 1
+                }
+            }
+        };
+        assert!(block <= std::u32::MAX as usize, "MARISA_SIZE_ERROR");
+
+        let offset = self.blocks_[block].len();
+        self.blocks_[block].extend_from_slice(bytes);
+        self.keys_.push(KeyRef { block_: block as u32, offset_: offset as u32,
+                                 length_: bytes.len() as u32,
+                                 weight_: weight, id_: INVALID_KEY_ID });
+        self.total_length_ += bytes.len();
+    }
+
+    /// The bytes of the `i`th pushed key.
+    pub fn key(&self, i: usize) -> &[u8] {
+        let k = &self.keys_[i];
+        let begin = k.offset_ as usize;
+        &self.blocks_[k.block_ as usize][begin..begin + k.length_ as usize]
+    }
+
+    pub fn weight(&self, i: usize) -> f32 {
+        self.keys_[i].weight_
+    }
+
+    /// The id assigned to the `i`th pushed key by the last build, if any.
+    pub fn id(&self, i: usize) -> Option<usize> {
+        match self.keys_[i].id_ {
+            INVALID_KEY_ID => None,
+            id => Some(id as usize),
+        }
+    }
+
+    pub fn set_id(&mut self, i: usize, id: usize) {
+        assert!(id < INVALID_KEY_ID as usize, "MARISA_SIZE_ERROR");
+        self.keys_[i].id_ = id as u32;
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a> {
+        Iter { keyset_: self, pos_: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys_.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keys_.is_empty()
+    }
+    /// Sum of the lengths of all keys.
+    pub fn total_length(&self) -> usize {
+        self.total_length_
+    }
+
+    /// Counts the keys that look like lines of a Windows text file read
+    /// literally: keys ending in '\r', and keys starting with a byte order
+    /// mark. Such keys are built as they are, but clean queries miss them.
+    pub fn validate(&self) -> LineArtifacts {
+        let mut out = LineArtifacts::default();
+        for key in self.iter() {
+            if key.ends_with(b"\r") {
+                out.crs += 1;
+            }
+            if key.starts_with(UTF8_BOM) {
+                out.boms += 1;
+            }
+        }
+        out
+    }
+
+    pub fn clear(&mut self) {
+        *self = Keyset::new();
+    }
+}
+
+impl Default for Keyset {
+    fn default() -> Keyset {
+        Keyset::new()
+    }
+}
+
+/// A key, or a `(key, weight)` pair, as `LoudsTrie::build_from_sorted`
+/// takes them one at a time. A key alone weighs 1, as with `Keyset::push`.
+pub trait KeyItem {
+    fn key_bytes(&self) -> &[u8];
+    fn key_weight(&self) -> f32;
+}
+
+/// Keys can be collected from the usual byte and string types, alone or
+/// paired with a weight. Each type gets its own impls, since a blanket impl
+/// over `AsRef<[u8]>` would overlap with the one for pairs.
+macro_rules! impl_extend {
+    ($([$($gen:tt)*] $key:ty;)*) => { $(
+        impl<'a, $($gen)*> Extend<$key> for Keyset {
+            fn extend<I: IntoIterator<Item = $key>>(&mut self, keys: I) {
+                for key in keys {
+                    self.push(AsRef::<[u8]>::as_ref(&key));
+                }
+            }
+        }
+
+        impl<'a, $($gen)*> Extend<($key, f32)> for Keyset {
+            fn extend<I>(&mut self, keys: I)
+              where I: IntoIterator<Item = ($key, f32)> {
+                for (key, weight) in keys {
+                    self.push_weighted(AsRef::<[u8]>::as_ref(&key), weight);
+                }
+            }
+        }
+
+        impl<'a, $($gen)*> KeyItem for $key {
+            fn key_bytes(&self) -> &[u8] {
+                AsRef::<[u8]>::as_ref(self)
+            }
+            fn key_weight(&self) -> f32 {
+                1.0
+            }
+        }
+
+        impl<'a, $($gen)*> KeyItem for ($key, f32) {
+            fn key_bytes(&self) -> &[u8] {
+                AsRef::<[u8]>::as_ref(&self.0)
+            }
+            fn key_weight(&self) -> f32 {
+                self.1
+            }
+        }
+
+        impl<'a, $($gen)*> FromIterator<$key> for Keyset {
+            fn from_iter<I: IntoIterator<Item = $key>>(keys: I) -> Keyset {
+                let mut keyset = Keyset::new();
+                keyset.extend(keys);
+                keyset
+            }
+        }
+
+        impl<'a, $($gen)*> FromIterator<($key, f32)> for Keyset {
+            fn from_iter<I>(keys: I) -> Keyset
+              where I: IntoIterator<Item = ($key, f32)> {
+                let mut keyset = Keyset::new();
+                keyset.extend(keys);
+                keyset
+            }
+        }
+    )* }
+}
+
+impl_extend! {
+    [] &'a str;
+    [] String;
+    [] &'a String;
+    [] &'a [u8];
+    [] Vec<u8>;
+    [] &'a Vec<u8>;
+    [const N: usize] &'a [u8; N];
+}
+
+/// Keysets shaped like the ones that break tries: keys sharing long stems,
+/// keys that are prefixes of other keys, duplicates, the empty key, and
+/// bytes 0x00 and 0xFF. Weights are small and positive.
+#[cfg(any(test, feature = "quickcheck"))]
+impl qc::Arbitrary for Keyset {
+    fn arbitrary<G: qc::Gen>(g: &mut G) -> Keyset {
+        fn byte<G: qc::Gen>(g: &mut G) -> u8 {
+            match g.gen_range(0, 8) {
+                0 => 0x00,
+                1 => 0xFF,
+                2 => g.gen(),
+                n => b'a' + n as u8 - 3,
+            }
+        }
+        fn bytes<G: qc::Gen>(g: &mut G, max_len: usize) -> Vec<u8> {
+            let len = g.gen_range(0, max_len + 1);
+            (0..len).map(|_| byte(g)).collect()
+        }
+        let size = std::cmp::max(g.size(), 1);
+        let stems: Vec<Vec<u8>> = (0..g.gen_range(1, 4))
+            .map(|_| bytes(g, size)).collect();
+        let mut keys: Vec<(Vec<u8>, f32)> = Vec::new();
+        for _ in 0..g.gen_range(0, size + 1) {
+            let key = match g.gen_range(0, 10) {
+                0 if !keys.is_empty() => {
+                    g.choose(&keys).unwrap().0.clone()
+                },
+                1 if !keys.is_empty() => {
+                    let key = &g.choose(&keys).unwrap().0;
+                    key[..g.gen_range(0, key.len() + 1)].to_vec()
+                },
+                2 => Vec::new(),
+                3 => bytes(g, size),
+                _ => {
+                    let mut key = g.choose(&stems).unwrap().clone();
+                    key.extend(bytes(g, 4));
+                    key
+                },
+            };
+            keys.push((key, *g.choose(&[1.0, 0.5, 2.0, 10.0]).unwrap()));
+        }
+        keys.into_iter().collect()
+    }
+
+    /// Drops keys, then cuts the same byte position out of every key and
+    /// resets weights. A cut keeps prefixes prefixes and duplicates
+    /// duplicates.
+    fn shrink(&self) -> Box<Iterator<Item=Self>> {
+        let keys: Vec<(Vec<u8>, f32)> = (0..self.len())
+            .map(|i| (self.key(i).to_vec(), self.weight(i))).collect();
+        let mut out: Vec<Keyset> = Vec::new();
+        let mut chunk = keys.len();
+        while chunk > 0 {
+            let mut start = 0;
+            while start < keys.len() {
+                let end = std::cmp::min(start + chunk, keys.len());
+                out.push(keys[..start].iter().chain(keys[end..].iter())
+                         .map(|&(ref key, weight)| (key, weight)).collect());
+                start = end;
+            }
+            chunk /= 2;
+        }
+        let max_len = keys.iter().map(|k| k.0.len()).max().unwrap_or(0);
+        for pos in 0..max_len {
+            out.push(keys.iter().map(|&(ref key, weight)| {
+                let mut key = key.clone();
+                if pos < key.len() {
+                    key.remove(pos);
+                }
+                (key, weight)
+            }).collect());
+        }
+        if keys.iter().any(|k| k.1 != 1.0) {
+            out.push(keys.iter().map(|k| &k.0).collect());
+        }
+        Box::new(out.into_iter())
+    }
+}
+
+/// Iterator over the bytes of each key in a Keyset, in push order.
+pub struct Iter<'a> {
+    keyset_: &'a Keyset,
+    pos_: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.pos_ < self.keyset_.len() {
+            self.pos_ += 1;
+            Some(self.keyset_.key(self.pos_ - 1))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.keyset_.len() - self.pos_;
+        (n, Some(n))
+    }
+}
+
+impl<'a> IntoIterator for &'a Keyset {
+    type Item = &'a [u8];
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use config::Config;
+    use error::MarisaError;
+    use louds_trie::LoudsTrie;
+    use quickcheck as qc;
+    use std;
+    use std::iter::FromIterator;
+    use std::panic;
+    use super::{Keyset, BASE_BLOCK_SIZE};
+
+    #[test]
+    fn push_many_short_keys() {
+        let mut keyset = Keyset::new();
+        for i in 0..1000000 {
+            keyset.push(format!("{}", i).as_bytes());
+        }
+        assert_eq!(keyset.len(), 1000000);
+        assert_eq!(keyset.key(123456), b"123456");
+        // Short keys share blocks; at most one block was started per key
+        // that did not fit in the remainder of the previous block.
+        let max_blocks = keyset.total_length() / (BASE_BLOCK_SIZE - 8) + 1;
+        assert!(keyset.blocks_.len() <= max_blocks);
+    }
+
+    #[test]
+    fn long_keys_get_own_block() {
+        let mut keyset = Keyset::new();
+        let long = vec![b'x'; 5000];
+        keyset.push(b"a");
+        keyset.push(&long);
+        keyset.push(b"b");
+        assert_eq!(keyset.blocks_.len(), 2);
+        assert_eq!(keyset.key(0), b"a");
+        assert_eq!(keyset.key(1), &long[..]);
+        assert_eq!(keyset.key(2), b"b");
+        assert_eq!(keyset.total_length(), 5002);
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let mut keyset: Keyset = vec!["a", "bc"].into_iter().collect();
+        keyset.extend(vec![(b"de".to_vec(), 2.5)]);
+        keyset.extend(vec![b"f"]);
+        let keys: Vec<&[u8]> = keyset.iter().collect();
+        assert_eq!(keys, vec![&b"a"[..], b"bc", b"de", b"f"]);
+        assert_eq!(keyset.weight(0), 1.0);
+        assert_eq!(keyset.weight(2), 2.5);
+        assert_eq!(Keyset::from_iter(Vec::<String>::new()).len(), 0);
+    }
+
+    #[test]
+    fn negative_and_nan_weights_are_rejected() {
+        for &w in &[0.0, -0.0, 0.5, std::f32::INFINITY] {
+            Keyset::new().push_weighted(b"a", w);
+        }
+        for &w in &[-1.0, std::f32::NEG_INFINITY, std::f32::NAN,
+                    -std::f32::MIN_POSITIVE] {
+            let pushed = panic::catch_unwind(|| {
+                Keyset::new().push_weighted(b"a", w);
+            });
+            assert!(pushed.is_err(), "weight {}", w);
+        }
+    }
+
+    #[test]
+    fn invalid_keys_are_named() {
+        let mut keyset: Keyset = vec!["a", "b"].into_iter().collect();
+        let err = keyset.try_push_weighted(b"tab\there\xff", -1.0)
+                        .unwrap_err();
+        assert_eq!(err, MarisaError::InvalidKey {
+            index: 2, reason: "has a negative or NaN weight",
+            excerpt: "tab\\there\\xff".to_string() });
+        assert_eq!(err.to_string(), "MARISA_RANGE_ERROR: key 2 \
+                                     \"tab\\there\\xff\" has a negative \
+                                     or NaN weight");
+        assert_eq!(keyset.len(), 2);
+
+        // A long key is cut short in the excerpt.
+        let long = vec![b'x'; 100];
+        assert!(keyset.try_push_(&long, 1.0, 100).is_ok());
+        match keyset.try_push_(&long[..99], 1.0, 98).unwrap_err() {
+            MarisaError::InvalidKey { index, reason, excerpt } => {
+                assert_eq!((index, reason), (3, "is too long"));
+                assert_eq!(excerpt, format!("{}...", "x".repeat(32)));
+            },
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(keyset.len(), 3);
+        let pushed = panic::catch_unwind(|| {
+            Keyset::new().push_weighted(b"k", std::f32::NAN);
+        });
+        let message = pushed.unwrap_err();
+        assert_eq!(message.downcast_ref::<String>().unwrap(),
+                   "MARISA_RANGE_ERROR: key 0 \"k\" has a negative or NaN \
+                    weight");
+    }
+
+    fn ids_prop(v: Vec<Vec<u8>>) -> bool {
+        let mut keyset = Keyset::new();
+        for k in &v {
+            keyset.push(k);
+        }
+        if keyset.iter().zip(v.iter()).any(|(a, b)| a != &b[..]) {
+            return false;
+        }
+        if (0..keyset.len()).any(|i| keyset.id(i).is_some()) {
+            return false;
+        }
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        (0..keyset.len()).all(|i| {
+            match keyset.id(i) {
+                Some(id) => trie.id_lookup(id).unwrap() == keyset.key(i),
+                None => false,
+            }
+        })
+    }
+
+    #[test]
+    fn ids_qc() {
+        qc::quickcheck(ids_prop as fn(Vec<Vec<u8>>) -> bool);
+    }
+}
@@ -1,8 +1,32 @@
+// Without std, the crate needs only `alloc`. The `std` module below stands
+// in for the parts of std that the rest of the crate names.
+#![cfg_attr(not(feature = "std"), no_std)]
+// Without std nothing writes dictionaries, so the writers go unused, and
+// the tests of files and streams are left out.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+#![cfg_attr(all(test, not(feature = "std")), allow(unused_imports))]
+
+#[cfg(not(feature = "std"))]
+#[cfg_attr(not(test), macro_use)]
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use] extern crate std as host_std;
+#[cfg(not(feature = "std"))]
+#[path = "std_shim/mod.rs"]
+mod std;
+
 #[macro_use] extern crate log;
-#[macro_use] extern crate fallthrough;
+#[cfg(any(test, feature = "quickcheck"))]
 extern crate quickcheck;
-extern crate rand;
+#[cfg(all(feature = "std", feature = "memmap2"))]
+extern crate memmap2;
+#[cfg(all(feature = "std", feature = "rayon"))]
+extern crate rayon;
 
+mod aligned_bytes;
+mod algo;
+#[cfg(feature = "std")]
+mod atomic_write;
 mod base;
 mod config;
 mod error;
@@ -10,12 +34,40 @@ mod iter_util;
 mod vector;
 
 mod cache;
+mod crc32;
 mod entry;
 mod header;
 mod key;
+mod keyset;
 mod louds_trie;
 mod range;
+mod sort;
+
+pub mod iter;
+pub mod keyio;
 
 #[cfg(test)]
 extern crate env_logger;
+#[cfg(test)]
+extern crate rand;
+#[cfg(test)]
+mod alloc_counter;
+
 
+pub use aligned_bytes::AlignedBytes;
+#[cfg(feature = "std")]
+pub use atomic_write::WriteOptions;
+pub use config::{CacheLevel, Config, MAX_NUM_TRIES, MIN_NUM_TRIES, NodeOrder,
+                 NumTries, ParseConfigError, TailMode};
+pub use error::MarisaError;
+pub use keyset::{KeyItem, Keyset};
+pub use louds_trie::{ID_ALGORITHM_VERSION, LevelStats, LongKeys, LoudsTrie,
+                     LoudsTrieView, MIN_LINK_SHARE, Nav, SearchOrder,
+                     SearchState, SectionSizes, SizeEstimate,
+                     suggest_num_tries, TrieBuilder, TrieStats};
+#[cfg(feature = "std")]
+pub use louds_trie::CacheRetune;
+#[cfg(all(feature = "std", feature = "memmap2"))]
+pub use louds_trie::MappedTrie;
+#[cfg(feature = "query-cache")]
+pub use louds_trie::{CacheCapacity, CachedTrie, QueryCache};
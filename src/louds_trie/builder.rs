@@ -0,0 +1,126 @@
+//! Collecting keys for a dictionary with a config other than the default,
+//! which `FromIterator` has no way to take.
+
+use std::iter::FromIterator;
+use config::Config;
+use error::MarisaError;
+use keyset::Keyset;
+use super::LoudsTrie;
+
+/// Gathers keys, or `(key, weight)` pairs, of any type a Keyset can be
+/// collected from, and builds them under one config:
+///
+/// ```
+/// use mars_trie::{Config, NodeOrder, TrieBuilder};
+///
+/// let config = Config::new().with_node_order(NodeOrder::Label);
+/// let trie = TrieBuilder::new(config).extend(vec!["b", "a", "b"])
+///                                    .extend(vec![("c", 2.0)])
+///                                    .build().unwrap();
+/// assert_eq!(trie.len(), 3);
+/// ```
+#[derive(Clone, Debug)]
+pub struct TrieBuilder {
+    config_: Config,
+    keyset_: Keyset,
+}
+
+impl TrieBuilder {
+    pub fn new(config: Config) -> TrieBuilder {
+        TrieBuilder { config_: config, keyset_: Keyset::new() }
+    }
+
+    /// Adds `keys`. Duplicates are merged when building, as by
+    /// `LoudsTrie::build`: they share one id and their weights are summed.
+    pub fn extend<I>(mut self, keys: I) -> TrieBuilder
+      where I: IntoIterator, Keyset: Extend<I::Item> {
+        self.keyset_.extend(keys);
+        self
+    }
+
+    pub fn push(&mut self, key: &[u8]) {
+        self.keyset_.push(key);
+    }
+    pub fn push_weighted(&mut self, key: &[u8], weight: f32) {
+        self.keyset_.push_weighted(key, weight);
+    }
+
+    /// The number of keys added so far, duplicates included.
+    pub fn len(&self) -> usize {
+        self.keyset_.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keyset_.is_empty()
+    }
+
+    pub fn build(mut self) -> Result<LoudsTrie, MarisaError> {
+        LoudsTrie::build(&mut self.keyset_, &self.config_)
+    }
+}
+
+impl<K> FromIterator<K> for TrieBuilder where Keyset: FromIterator<K> {
+    /// Keys to be built with the default config.
+    fn from_iter<I: IntoIterator<Item = K>>(keys: I) -> TrieBuilder {
+        TrieBuilder { config_: Config::new(),
+                      keyset_: keys.into_iter().collect() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use std::collections::BTreeSet;
+    use config::{Config, NodeOrder, NumTries};
+    use louds_trie::LoudsTrie;
+    use super::TrieBuilder;
+
+    fn words() -> BTreeSet<String> {
+        (0..2000u64).map(|i| {
+            format!("{:x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (i % 50))
+        }).collect()
+    }
+
+    #[test]
+    fn enumeration_matches_the_set() {
+        let _ = env_logger::init();
+        let set = words();
+        let expected: Vec<Vec<u8>> = set.iter().map(|w| w.as_bytes().to_vec())
+                                        .collect();
+        for &num_tries in &[1, 3] {
+            let config = Config::new().with_node_order(NodeOrder::Label)
+                                      .with_num_tries(NumTries::new(num_tries));
+            // Every word twice: duplicates are merged.
+            let trie = TrieBuilder::new(config)
+                .extend(set.iter().map(|w| w.as_bytes()))
+                .extend(set.iter().cloned())
+                .build().unwrap();
+            let keys: Vec<Vec<u8>> = trie.iter().map(|(_, k)| k).collect();
+            assert_eq!(keys, expected);
+        }
+
+        let trie: LoudsTrie = set.iter().map(|w| w.as_bytes()).collect();
+        let mut keys: Vec<Vec<u8>> = trie.iter().map(|(_, k)| k).collect();
+        keys.sort();
+        assert_eq!(keys, expected);
+        let collected: TrieBuilder = set.iter().collect();
+        assert_eq!(collected.len(), set.len());
+        assert_eq!(collected.build().unwrap().len(), set.len());
+    }
+
+    #[test]
+    fn weights_of_duplicates_are_summed() {
+        let _ = env_logger::init();
+        let mut builder = TrieBuilder::new(Config::new().with_weights(true))
+            .extend(vec![(b"a".to_vec(), 1.5), (b"b".to_vec(), 1.0)]);
+        builder.push_weighted(b"a", 2.0);
+        builder.push(b"c");
+        assert_eq!(builder.len(), 4);
+        let trie = builder.build().unwrap();
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.lookup_with_weight(b"a").unwrap().1, Some(3.5));
+        assert_eq!(trie.lookup_with_weight(b"c").unwrap().1, Some(1.0));
+        assert!(TrieBuilder::new(Config::new()).is_empty());
+    }
+}
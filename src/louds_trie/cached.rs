@@ -0,0 +1,425 @@
+//! Memoizing queries, for workloads that ask the same few over and over.
+//!
+//! A `QueryCache` maps query bytes to the results of `lookup` and
+//! `common_prefix_search` for one dictionary, and forgets the least recently
+//! used queries once it is full. It is split into shards, each behind its
+//! own lock, so threads asking different queries rarely wait on each other.
+//! The cache records the `build_id` of the dictionary it was filled from,
+//! and `CachedTrie` refuses to pair it with any other.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use error::MarisaError;
+use super::LoudsTrie;
+
+/// Shards per cache. Each gets an equal part of the capacity.
+const NUM_SHARDS: usize = 16;
+
+/// What a cached query is charged for besides its bytes and results: the
+/// list node, and the map entry with its own copy of the query.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// How much a `QueryCache` holds before it starts forgetting queries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheCapacity {
+    /// At most this many distinct queries.
+    Entries(usize),
+    /// At most about this many bytes of queries and results.
+    Bytes(usize),
+}
+
+/// The results cached for one query, each filled in when first asked.
+#[derive(Clone, Debug, Default)]
+struct Results {
+    lookup_: Option<Option<usize>>,
+    prefixes_: Option<Vec<(usize, usize)>>,
+}
+
+impl Results {
+    fn num_bytes(&self, query: &[u8]) -> usize {
+        ENTRY_OVERHEAD + 2 * query.len()
+        + self.prefixes_.as_ref().map_or(0, |p| {
+            p.len() * mem::size_of::<(usize, usize)>()
+        })
+    }
+}
+
+const NIL: usize = !0;
+
+struct Node {
+    query_: Vec<u8>,
+    results_: Results,
+    prev_: usize,
+    next_: usize,
+}
+
+/// A least recently used list over a slab of nodes, with a map from query
+/// to node. `head_` is the most recently used.
+struct Lru {
+    map_: HashMap<Vec<u8>, usize>,
+    nodes_: Vec<Node>,
+    free_: Vec<usize>,
+    head_: usize,
+    tail_: usize,
+    used_: usize,
+    capacity_: usize,
+    by_bytes_: bool,
+}
+
+impl Lru {
+    fn new(capacity: usize, by_bytes: bool) -> Lru {
+        Lru { map_: HashMap::new(), nodes_: Vec::new(), free_: Vec::new(),
+              head_: NIL, tail_: NIL, used_: 0, capacity_: capacity,
+              by_bytes_: by_bytes }
+    }
+
+    fn cost(&self, i: usize) -> usize {
+        if self.by_bytes_ {
+            let node = &self.nodes_[i];
+            node.results_.num_bytes(&node.query_)
+        } else {
+            1
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes_[i].prev_, self.nodes_[i].next_);
+        if prev == NIL { self.head_ = next; }
+        else { self.nodes_[prev].next_ = next; }
+        if next == NIL { self.tail_ = prev; }
+        else { self.nodes_[next].prev_ = prev; }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes_[i].prev_ = NIL;
+        self.nodes_[i].next_ = self.head_;
+        if self.head_ != NIL {
+            self.nodes_[self.head_].prev_ = i;
+        } else {
+            self.tail_ = i;
+        }
+        self.head_ = i;
+    }
+
+    /// The results cached for `query`, marked as just used.
+    fn get(&mut self, query: &[u8]) -> Option<&Results> {
+        let i = match self.map_.get(query) {
+            Some(&i) => i,
+            None => return None,
+        };
+        self.unlink(i);
+        self.push_front(i);
+        Some(&self.nodes_[i].results_)
+    }
+
+    /// Merges `update` into the results for `query`, then forgets the least
+    /// recently used queries until the cache fits; `query` itself goes too
+    /// if it alone is over capacity.
+    fn update<F: FnOnce(&mut Results)>(&mut self, query: &[u8], update: F) {
+        let i = match self.map_.get(query) {
+            Some(&i) => {
+                self.used_ -= self.cost(i);
+                self.unlink(i);
+                i
+            },
+            None => {
+                let node = Node { query_: query.to_vec(),
+                                  results_: Results::default(),
+                                  prev_: NIL, next_: NIL };
+                let i = match self.free_.pop() {
+                    Some(i) => { self.nodes_[i] = node; i },
+                    None => { self.nodes_.push(node); self.nodes_.len() - 1 },
+                };
+                self.map_.insert(query.to_vec(), i);
+                i
+            },
+        };
+        update(&mut self.nodes_[i].results_);
+        self.used_ += self.cost(i);
+        self.push_front(i);
+        while self.used_ > self.capacity_ && self.tail_ != NIL {
+            let last = self.tail_;
+            self.used_ -= self.cost(last);
+            self.unlink(last);
+            let query = mem::replace(&mut self.nodes_[last].query_, Vec::new());
+            self.map_.remove(&query);
+            self.nodes_[last].results_ = Results::default();
+            self.free_.push(last);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map_.len()
+    }
+}
+
+/// Results of queries against one dictionary, shared between threads.
+pub struct QueryCache {
+    build_id_: u64,
+    shards_: Vec<Mutex<Lru>>,
+    hits_: AtomicU64,
+    misses_: AtomicU64,
+}
+
+impl QueryCache {
+    /// An empty cache for the dictionary with `build_id`.
+    pub fn new(build_id: u64, capacity: CacheCapacity) -> QueryCache {
+        let (total, by_bytes) = match capacity {
+            CacheCapacity::Entries(n) => (n, false),
+            CacheCapacity::Bytes(n) => (n, true),
+        };
+        let shards = (0..NUM_SHARDS).map(|i| {
+            // The remainder goes to the first shards.
+            let share = total / NUM_SHARDS
+                        + if i < total % NUM_SHARDS { 1 } else { 0 };
+            Mutex::new(Lru::new(share, by_bytes))
+        }).collect();
+        QueryCache { build_id_: build_id, shards_: shards,
+                     hits_: AtomicU64::new(0), misses_: AtomicU64::new(0) }
+    }
+
+    /// The `LoudsTrie::build_id` of the dictionary the results are from.
+    pub fn build_id(&self) -> u64 {
+        self.build_id_
+    }
+    /// Queries answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits_.load(Ordering::Relaxed)
+    }
+    /// Queries that had to go to the dictionary.
+    pub fn misses(&self) -> u64 {
+        self.misses_.load(Ordering::Relaxed)
+    }
+    /// The number of distinct queries cached.
+    pub fn len(&self) -> usize {
+        self.shards_.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, query: &[u8]) -> &Mutex<Lru> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(query);
+        &self.shards_[hasher.finish() as usize % NUM_SHARDS]
+    }
+
+    /// The result `get` picks out of the cached results for `query`, or
+    /// else `compute`'s, which `put` stores.
+    fn get_or_compute<T, G, C, P>(&self, query: &[u8], get: G, compute: C,
+                                  put: P) -> T
+      where G: Fn(&Results) -> Option<T>, C: FnOnce() -> T,
+            P: FnOnce(&mut Results, &T) {
+        let shard = self.shard(query);
+        if let Some(found) = shard.lock().unwrap().get(query).and_then(get) {
+            self.hits_.fetch_add(1, Ordering::Relaxed);
+            return found;
+        }
+        self.misses_.fetch_add(1, Ordering::Relaxed);
+        // Not under the lock: other threads may go on meanwhile, and at
+        // worst compute the same result.
+        let out = compute();
+        shard.lock().unwrap().update(query, |results| put(results, &out));
+        out
+    }
+}
+
+/// A dictionary whose `lookup` and `common_prefix_search` results are
+/// remembered in a `QueryCache`. Clones share the dictionary and the cache.
+#[derive(Clone)]
+pub struct CachedTrie {
+    trie_: Arc<LoudsTrie>,
+    cache_: Arc<QueryCache>,
+}
+
+impl CachedTrie {
+    /// `trie` with a new, empty cache.
+    pub fn new(trie: Arc<LoudsTrie>, capacity: CacheCapacity) -> CachedTrie {
+        let cache = QueryCache::new(trie.build_id(), capacity);
+        CachedTrie { trie_: trie, cache_: Arc::new(cache) }
+    }
+
+    /// `trie` with a cache filled earlier, which fails with a StateError
+    /// unless the cache was filled from a dictionary with the same
+    /// `build_id`.
+    pub fn with_cache(trie: Arc<LoudsTrie>, cache: Arc<QueryCache>)
+      -> Result<CachedTrie, MarisaError> {
+        if trie.build_id() != cache.build_id() {
+            return Err(MarisaError::StateError {
+                what: "the query cache was filled from another dictionary" });
+        }
+        Ok(CachedTrie { trie_: trie, cache_: cache })
+    }
+
+    pub fn trie(&self) -> &Arc<LoudsTrie> {
+        &self.trie_
+    }
+    pub fn cache(&self) -> &Arc<QueryCache> {
+        &self.cache_
+    }
+
+    /// `LoudsTrie::lookup`, remembered.
+    pub fn lookup(&self, query: &[u8]) -> Option<usize> {
+        let trie = &self.trie_;
+        self.cache_.get_or_compute(query, |r| r.lookup_,
+                                   || trie.lookup(query),
+                                   |r, &found| r.lookup_ = Some(found))
+    }
+
+    /// The items of `LoudsTrie::common_prefix_search`, remembered.
+    pub fn common_prefix_search(&self, query: &[u8]) -> Vec<(usize, usize)> {
+        let trie = &self.trie_;
+        self.cache_.get_or_compute(
+            query, |r| r.prefixes_.clone(),
+            || trie.common_prefix_search(query).collect(),
+            |r, found| r.prefixes_ = Some(found.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use std::sync::Arc;
+    use std::thread;
+    use config::Config;
+    use error::MarisaError;
+    use louds_trie::LoudsTrie;
+    use super::{CacheCapacity, CachedTrie, Lru, NUM_SHARDS, QueryCache};
+
+    fn numbers(n: usize) -> Arc<LoudsTrie> {
+        let keys: Vec<String> = (0..n).map(|i| (i * 3).to_string()).collect();
+        Arc::new(LoudsTrie::build_from(keys, &Config::new()).unwrap())
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let _ = env_logger::init();
+        let mut lru = Lru::new(3, false);
+        for q in &[b"a", b"b", b"c"] {
+            lru.update(*q, |r| r.lookup_ = Some(None));
+        }
+        assert!(lru.get(b"a").is_some());
+        lru.update(b"d", |r| r.lookup_ = Some(Some(4)));
+        assert_eq!(lru.len(), 3);
+        assert!(lru.get(b"b").is_none());
+        assert!(lru.get(b"a").is_some());
+        assert_eq!(lru.get(b"d").unwrap().lookup_, Some(Some(4)));
+        // Updating an entry keeps one entry for it.
+        lru.update(b"d", |r| r.prefixes_ = Some(vec![(1, 2)]));
+        assert_eq!(lru.len(), 3);
+        assert_eq!(lru.get(b"d").unwrap().lookup_, Some(Some(4)));
+
+        // By bytes, a query too big for the whole cache is not kept.
+        let mut lru = Lru::new(150, true);
+        lru.update(b"small", |r| r.lookup_ = Some(None));
+        lru.update(&[b'x'; 100], |r| r.lookup_ = Some(None));
+        assert_eq!(lru.len(), 0);
+        lru.update(b"small", |r| r.lookup_ = Some(None));
+        lru.update(b"other", |r| r.prefixes_ = Some(vec![(0, 0); 3]));
+        assert_eq!(lru.len(), 1);
+        assert!(lru.get(b"other").is_some());
+    }
+
+    #[test]
+    fn eviction_at_capacity() {
+        let _ = env_logger::init();
+        let trie = CachedTrie::new(numbers(1000),
+                                   CacheCapacity::Entries(NUM_SHARDS * 4));
+        for i in 0..1000 {
+            trie.lookup((i * 3).to_string().as_bytes());
+        }
+        assert!(trie.cache().len() <= NUM_SHARDS * 4);
+        assert_eq!((trie.cache().hits(), trie.cache().misses()), (0, 1000));
+        // The most recent query is still there.
+        assert_eq!(trie.lookup(b"2997"), trie.trie().lookup(b"2997"));
+        assert_eq!(trie.cache().hits(), 1);
+
+        let empty = CachedTrie::new(numbers(10), CacheCapacity::Bytes(0));
+        assert_eq!(empty.lookup(b"3"), empty.trie().lookup(b"3"));
+        assert!(empty.cache().is_empty());
+    }
+
+    #[test]
+    fn same_results_as_the_trie() {
+        let _ = env_logger::init();
+        let trie = numbers(3000);
+        for &capacity in &[CacheCapacity::Entries(100),
+                           CacheCapacity::Bytes(20000)] {
+            let cached = CachedTrie::new(trie.clone(), capacity);
+            for i in 0..20000usize {
+                // A few hot queries among many cold ones.
+                let query = if i % 4 == 0 { i % 37 }
+                            else { i.wrapping_mul(7919) % 9500 };
+                let query = query.to_string();
+                let query = query.as_bytes();
+                if i % 2 == 0 {
+                    assert_eq!(cached.lookup(query), trie.lookup(query));
+                } else {
+                    assert_eq!(cached.common_prefix_search(query),
+                               trie.common_prefix_search(query)
+                                   .collect::<Vec<_>>());
+                }
+            }
+            let cache = cached.cache();
+            assert_eq!(cache.hits() + cache.misses(), 20000);
+            assert!(cache.hits() > 0 && cache.misses() > 0);
+        }
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let _ = env_logger::init();
+        let cached = CachedTrie::new(numbers(2000),
+                                     CacheCapacity::Entries(256));
+        let threads: Vec<_> = (0..4).map(|t| {
+            let cached = cached.clone();
+            thread::spawn(move || {
+                for i in 0..5000usize {
+                    let query = ((i * (t + 1)) % 700).to_string();
+                    let query = query.as_bytes();
+                    assert_eq!(cached.lookup(query),
+                               cached.trie().lookup(query));
+                    assert_eq!(cached.common_prefix_search(query),
+                               cached.trie().common_prefix_search(query)
+                                     .collect::<Vec<_>>());
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let cache = cached.cache();
+        assert_eq!(cache.hits() + cache.misses(), 4 * 5000 * 2);
+        assert!(cache.len() <= 256);
+    }
+
+    #[test]
+    fn other_dictionary_is_rejected() {
+        let _ = env_logger::init();
+        let trie = numbers(100);
+        let cached = CachedTrie::new(trie.clone(), CacheCapacity::Entries(8));
+        cached.lookup(b"3");
+
+        // A copy read back from its bytes is the same dictionary.
+        let mut bytes = Vec::new();
+        trie.write_to(&mut bytes).unwrap();
+        let copy = Arc::new(LoudsTrie::read_from(&mut &bytes[..]).unwrap());
+        let reused = CachedTrie::with_cache(copy, cached.cache().clone())
+                     .unwrap();
+        assert_eq!(reused.lookup(b"3"), trie.lookup(b"3"));
+        assert_eq!(reused.cache().hits(), 1);
+
+        let other = numbers(101);
+        assert_ne!(other.build_id(), trie.build_id());
+        match CachedTrie::with_cache(other, cached.cache().clone()) {
+            Err(MarisaError::StateError { .. }) => {},
+            _ => panic!("a cache of another dictionary was accepted"),
+        }
+        let fresh = QueryCache::new(trie.build_id(), CacheCapacity::Entries(1));
+        assert!(CachedTrie::with_cache(trie, Arc::new(fresh)).is_ok());
+    }
+}
@@ -0,0 +1,191 @@
+//! Drawing the nested tries with Graphviz, to see what the builder made.
+
+use std::io;
+use std::io::Write;
+use super::LoudsTrieView;
+
+/// `bytes` as the inside of a DOT string: printable ASCII as it is, other
+/// bytes as `\xNN`, with quotes and backslashes escaped for DOT.
+fn dot_escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b if b >= 0x20 && b < 0x7F => out.push(b as char),
+            _ => out.push_str(&format!("\\\\x{:02X}", b)),
+        }
+    }
+    out
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// Writes the tries as a Graphviz DOT graph, one cluster per trie. Each
+    /// node shows its id and the label of the edge into it: a byte, or the
+    /// whole label behind a link. Keys end at double circles. A
+    /// link into the next trie is also drawn as a dashed edge to the node
+    /// its label is restored from. With `max_nodes`, only that many nodes
+    /// are drawn, in breadth-first order from the first trie down, and an
+    /// ellipsis node stands for the rest of each trie.
+    pub fn dump_dot<W: io::Write>(&self, w: &mut W, max_nodes: Option<usize>)
+      -> io::Result<()> {
+        let mut budget = max_nodes.unwrap_or(!0);
+        let mut tries: Vec<(&LoudsTrieView, usize)> = Vec::new();
+        let mut trie = Some(self);
+        while let Some(t) = trie {
+            let shown = ::std::cmp::min(budget, t.num_nodes());
+            budget -= shown;
+            tries.push((t, shown));
+            trie = t.next_trie_.as_ref().map(|next| &**next);
+        }
+
+        let mut w = io::BufWriter::new(w);
+        writeln!(w, "digraph trie {{")?;
+        writeln!(w, "  node [shape=circle];")?;
+        let mut links = Vec::new();
+        for (k, &(t, shown)) in tries.iter().enumerate() {
+            writeln!(w, "  subgraph cluster_{} {{", k)?;
+            writeln!(w, "    label=\"trie {}\";", k)?;
+            if shown > 0 {
+                let prefix = if k == 0 { &self.common_prefix_[..] }
+                             else { &[][..] };
+                writeln!(w, "    t{}_0 [label=\"0\\n{}\"];", k,
+                         dot_escape(prefix))?;
+            }
+            // Reading the LOUDS bits after the super-root's "10": a 1 is
+            // the next child of the current parent, a 0 moves on to the
+            // next parent.
+            let (mut parent, mut child) = (0, 1);
+            let mut last_cut_parent = None;
+            for pos in 2..t.louds_.len() {
+                if !t.louds_.at(pos) {
+                    parent += 1;
+                    continue;
+                }
+                if child >= shown {
+                    if parent < shown && last_cut_parent != Some(parent) {
+                        writeln!(w, "    t{}_{} -> t{}_more;", k, parent, k)?;
+                        last_cut_parent = Some(parent);
+                    }
+                    child += 1;
+                    continue;
+                }
+                let mut label = Vec::new();
+                if t.link_flags_.at(child) {
+                    let linked = t.get_linked_node_id(child);
+                    t.restore(linked, &mut label);
+                    if t.next_trie_.is_some() {
+                        links.push((k, child, linked));
+                    }
+                } else {
+                    label.push(t.bases_[child]);
+                }
+                // Only the first trie has terminal flags; the nodes of the
+                // others end link labels, and the dashed edges show which.
+                let shape = if k == 0 && t.terminal_flags_.at(child) {
+                    " shape=doublecircle"
+                } else {
+                    ""
+                };
+                writeln!(w, "    t{}_{} [label=\"{}\\n{}\"{}];", k, child,
+                         child, dot_escape(&label), shape)?;
+                writeln!(w, "    t{}_{} -> t{}_{};", k, parent, k, child)?;
+                child += 1;
+            }
+            if last_cut_parent.is_some() {
+                writeln!(w, "    t{}_more [label=\"...\" shape=plaintext];",
+                         k)?;
+            }
+            writeln!(w, "  }}")?;
+        }
+        for (k, from, to) in links {
+            if to < tries[k + 1].1 {
+                writeln!(w, "  t{}_{} -> t{}_{} [style=dashed];", k, from,
+                         k + 1, to)?;
+            }
+        }
+        writeln!(w, "}}")?;
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use config::{Config, NodeOrder, NumTries, TailMode};
+    use louds_trie::LoudsTrie;
+    use super::dot_escape;
+
+    fn dot(trie: &LoudsTrie, max_nodes: Option<usize>) -> String {
+        let mut bytes = Vec::new();
+        trie.dump_dot(&mut bytes, max_nodes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(dot_escape(b"ab c"), "ab c");
+        assert_eq!(dot_escape(b"\"\\"), "\\\"\\\\");
+        assert_eq!(dot_escape(b"\n\xFF"), "\\\\x0A\\\\xFF");
+    }
+
+    #[test]
+    fn golden_dump() {
+        let _ = env_logger::init();
+        let config = Config::new().with_num_tries(NumTries::new(2))
+                                  .with_node_order(NodeOrder::Label)
+                                  .with_tail_mode(TailMode::Binary);
+        let trie = LoudsTrie::build_from(vec!["an", "and", "band", "bee\n"],
+                                         &config).unwrap();
+        let expected = "\
+digraph trie {
+  node [shape=circle];
+  subgraph cluster_0 {
+    label=\"trie 0\";
+    t0_0 [label=\"0\\n\"];
+    t0_1 [label=\"1\\nan\" shape=doublecircle];
+    t0_0 -> t0_1;
+    t0_2 [label=\"2\\nb\"];
+    t0_0 -> t0_2;
+    t0_3 [label=\"3\\nd\" shape=doublecircle];
+    t0_1 -> t0_3;
+    t0_4 [label=\"4\\nand\" shape=doublecircle];
+    t0_2 -> t0_4;
+    t0_5 [label=\"5\\nee\\\\x0A\" shape=doublecircle];
+    t0_2 -> t0_5;
+  }
+  subgraph cluster_1 {
+    label=\"trie 1\";
+    t1_0 [label=\"0\\n\"];
+    t1_1 [label=\"1\\nee\\\\x0A\"];
+    t1_0 -> t1_1;
+    t1_2 [label=\"2\\nand\"];
+    t1_0 -> t1_2;
+    t1_3 [label=\"3\\nan\"];
+    t1_0 -> t1_3;
+  }
+  t0_1 -> t1_3 [style=dashed];
+  t0_4 -> t1_2 [style=dashed];
+  t0_5 -> t1_1 [style=dashed];
+}
+";
+        assert_eq!(dot(&trie, None), expected);
+    }
+
+    #[test]
+    fn cap_adds_ellipsis() {
+        let _ = env_logger::init();
+        let keys: Vec<String> = (0..500).map(|i| i.to_string()).collect();
+        let trie = LoudsTrie::build_from(keys.iter(), &Config::new())
+                   .unwrap();
+        let full = dot(&trie, None);
+        assert!(!full.contains("..."));
+        let capped = dot(&trie, Some(5));
+        assert_eq!(capped.matches(" [label=").count(), 5 + 1);
+        assert!(capped.contains("t0_0 -> t0_more;"));
+        assert!(capped.contains("t0_more [label=\"...\" shape=plaintext];"));
+        assert!(capped.len() < full.len() / 10);
+        let none = dot(&trie, Some(0));
+        assert!(!none.contains("->"));
+    }
+}
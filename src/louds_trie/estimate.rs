@@ -0,0 +1,348 @@
+//! Estimating the size of a dictionary without building it.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+
+use cache::Cache;
+use config::Config;
+use entry::Entry;
+use key::{IKey, Key, ReverseKey};
+use keyset::Keyset;
+use sort;
+use sort::Label;
+use vector::bit_vec::BitVec;
+use vector::flat_vec::FlatVec;
+use super::{cache_len, common_prefix_len, LoudsTrie, MIN_COMMON_PREFIX};
+use super::tail::Tail;
+
+/// Bounds on the `total_size` of a dictionary that has not been built; see
+/// `LoudsTrie::estimate_size`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SizeEstimate {
+    low_: usize,
+    high_: usize,
+    expected_: usize,
+}
+
+impl SizeEstimate {
+    /// The dictionary takes at least this many bytes.
+    pub fn low(&self) -> usize {
+        self.low_
+    }
+    /// The dictionary takes at most this many bytes.
+    pub fn high(&self) -> usize {
+        self.high_
+    }
+    /// The best guess at how many bytes the dictionary takes.
+    pub fn expected(&self) -> usize {
+        self.expected_
+    }
+}
+
+/// What goes into one trie.
+struct TrieCounts {
+    /// Keys the trie is built from, duplicates included. Sizes the cache.
+    num_keys: usize,
+    /// Distinct keys, which are terminal nodes.
+    num_distinct: usize,
+    /// Nodes, the root included.
+    num_nodes: usize,
+    /// Links to the next trie or the tail.
+    num_links: usize,
+    /// Total length of the labels behind those links.
+    link_bytes: usize,
+}
+
+/// Counts the trie `build_current_trie` makes from `keys`, and replaces
+/// `keys` with the labels it passes on, one per link.
+///
+/// Once sorted, each key adds a node wherever a later key branches off from
+/// it, and one where it ends, below the prefix it shares with the key
+/// before it. The branch points are the running minima of the shared prefix
+/// lengths of the keys that follow, kept on a stack.
+fn count_trie<'a, T>(keys: &mut Vec<T>) -> TrieCounts
+  where T: IKey<'a> + Label + From<&'a [u8]> {
+    let num_keys = keys.len();
+    sort::sort(keys);
+
+    // Each distinct key, with the length of the prefix it shares with the
+    // one before it.
+    let mut distinct: Vec<(usize, usize)> = Vec::new();
+    for i in 0..keys.len() {
+        let lcp = if i == 0 { 0 }
+                  else { keys[i - 1].common_prefix_len(&keys[i]) };
+        if i == 0 || lcp < keys[i].len() {
+            distinct.push((i, lcp));
+        }
+    }
+
+    let mut next_keys: Vec<T> = Vec::new();
+    let mut num_nodes = 1;
+    let mut link_bytes = 0;
+    // Depths at which the keys seen so far branch off from the current
+    // one, deepest last.
+    let mut branches: Vec<usize> = Vec::new();
+    let mut points: Vec<usize> = Vec::new();
+    for &(i, lcp) in distinct.iter().rev() {
+        points.clear();
+        while branches.last().is_some_and(|&depth| depth > lcp) {
+            points.push(branches.pop().unwrap());
+        }
+        if branches.last() != Some(&lcp) {
+            branches.push(lcp);
+        }
+        points.reverse();
+        let len = keys[i].len();
+        if len > *points.last().unwrap_or(&lcp) {
+            points.push(len);
+        }
+
+        let mut depth = lcp;
+        for &point in &points {
+            num_nodes += 1;
+            if point - depth > 1 {
+                let mut label = T::from(keys[i].get_slice());
+                label.subslice(depth, point - depth);
+                next_keys.push(label);
+                link_bytes += point - depth;
+            }
+            depth = point;
+        }
+    }
+
+    let num_distinct = distinct.len();
+    *keys = next_keys;
+    TrieCounts { num_keys, num_distinct, num_nodes, num_links: keys.len(),
+                 link_bytes }
+}
+
+/// Size of trie `trie_id` made of `counts`, when the largest of its links
+/// is `max_link`.
+fn trie_size(counts: &TrieCounts, max_link: usize, config: &Config,
+             trie_id: usize) -> usize {
+    let n = counts.num_nodes;
+    let max_extra = std::cmp::min(max_link / 256, u32::MAX as usize);
+    let mut size = BitVec::total_size_for(2 * n + 2, n,
+                                          Some((trie_id == 1, true)))
+                   + BitVec::total_size_for(n, counts.num_links,
+                                            Some((false, false)))
+                   + n
+                   + FlatVec::total_size_for(counts.num_links,
+                                             max_extra as u32)
+                   + cache_len(config, trie_id, counts.num_keys)
+                     * std::mem::size_of::<Cache>();
+    if trie_id == 1 {
+        let index = Some((false, config.reverse_lookup()));
+        size += BitVec::total_size_for(n + 1, counts.num_distinct, index);
+    }
+    size
+}
+
+/// `len` and `total_size` of the tail built from `keys`.
+fn tail_size<'a, T: IKey<'a>>(keys: &[T], config: &Config)
+  -> (usize, usize) {
+    let mut entries: Vec<Entry> = keys.iter()
+        .map(|key| Entry::new(key.get_slice(), 0)).collect();
+    Tail::size_for(&mut entries, config.tail_mode())
+}
+
+impl LoudsTrie {
+    /// Estimates the `total_size` of the dictionary `build` would make from
+    /// `keyset` and `config`, without building it.
+    ///
+    /// The expected size counts the nodes of every trie and the bytes of
+    /// the tail, which takes a sort per trie like a build does, but none of
+    /// the dictionary's memory. The bounds only need the first trie: `low`
+    /// is what it takes on its own, and `high` assumes the labels it links
+    /// to share nothing further down.
+    pub fn estimate_size(keyset: &Keyset, config: &Config) -> SizeEstimate {
+        let prefix_len = match common_prefix_len(keyset) {
+            len if len >= MIN_COMMON_PREFIX => len,
+            _ => 0,
+        };
+        let mut keys: Vec<Key> = keyset.iter()
+            .map(|k| Key::new(&k[prefix_len..])).collect();
+        let first = count_trie(&mut keys);
+
+        let mut fixed = prefix_len;
+        if config.weights() {
+            fixed += first.num_distinct * std::mem::size_of::<f32>();
+        }
+        let num_tries = config.num_tries().get() as usize;
+
+        // The tries after the first and the tail, if the labels passed on
+        // were as long and as many as they can be.
+        let bytes = first.link_bytes;
+        let mut rest: Vec<TrieCounts> = Vec::new();
+        let mut num_labels = first.num_links;
+        while num_labels != 0 && rest.len() + 1 < num_tries {
+            let num_nodes = 1 + std::cmp::min(bytes, 2 * num_labels);
+            let num_links = std::cmp::min(num_nodes - 1, bytes / 2);
+            rest.push(TrieCounts { num_keys: num_labels, num_distinct: 0,
+                                   num_nodes, num_links,
+                                   link_bytes: bytes });
+            num_labels = num_links;
+        }
+        let (tail_len, tail_total) = if num_labels == 0 {
+            (0, 0)
+        } else {
+            let binary = bytes + BitVec::total_size_for(bytes, 0, None);
+            (bytes + num_labels, std::cmp::max(bytes + num_labels, binary))
+        };
+        let mut high = fixed + tail_total;
+        for (i, counts) in rest.iter().enumerate() {
+            let max_link = rest.get(i + 1).map_or(tail_len,
+                                                  |next| next.num_nodes);
+            high += trie_size(counts, max_link, config, i + 2);
+        }
+        let max_link = rest.first().map_or(tail_len, |next| next.num_nodes);
+        high += trie_size(&first, max_link, config, 1);
+
+        let low = fixed + trie_size(&first, 0, config, 1);
+
+        // The tries as they will be built.
+        let mut tries = vec![first];
+        let mut tail = (0, 0);
+        if !keys.is_empty() {
+            if num_tries == 1 {
+                tail = tail_size(&keys, config);
+            } else {
+                let mut keys: Vec<ReverseKey> = keys.iter()
+                    .map(ReverseKey::from_key).collect();
+                while !keys.is_empty() {
+                    tries.push(count_trie(&mut keys));
+                    if tries.len() == num_tries {
+                        tail = tail_size(&keys, config);
+                        break;
+                    }
+                }
+            }
+        }
+        let mut expected = fixed + tail.1;
+        for (i, counts) in tries.iter().enumerate() {
+            let max_link = tries.get(i + 1).map_or(tail.0,
+                                                   |next| next.num_nodes)
+                           .saturating_sub(1);
+            expected += trie_size(counts, max_link, config, i + 1);
+        }
+
+        SizeEstimate { low_: low, high_: high, expected_: expected }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use config::{Config, NumTries, TailMode};
+    use keyset::Keyset;
+    use louds_trie::LoudsTrie;
+
+    fn check(keys: &[Vec<u8>], config: &Config) -> bool {
+        let mut keyset = Keyset::new();
+        for k in keys {
+            keyset.push(k);
+        }
+        let estimate = LoudsTrie::estimate_size(&keyset, config);
+        let actual = LoudsTrie::build(&mut keyset, config).unwrap()
+                     .total_size();
+        let ok = estimate.low() <= actual && actual <= estimate.high();
+        if !ok {
+            debug!("{:?} {:?}: actual {}", config, estimate, actual);
+        }
+        ok
+    }
+
+    fn estimate_prop(keys: Vec<Vec<u8>>, num_tries: NumTries, binary: bool)
+      -> bool {
+        let mode = if binary { TailMode::Binary } else { TailMode::Text };
+        let config = Config::new().with_num_tries(num_tries)
+                                  .with_tail_mode(mode);
+        check(&keys, &config)
+    }
+
+    #[test]
+    fn estimate_bounds_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(estimate_prop as fn(Vec<Vec<u8>>, NumTries, bool)
+                                       -> bool);
+    }
+
+    /// Word-like keys from a fixed generator, with duplicates and shared
+    /// suffixes.
+    fn word_keys(n: usize) -> Vec<Vec<u8>> {
+        let mut x: u64 = 12345;
+        (0..n).map(|_| {
+            x = x.wrapping_mul(6364136223846793005)
+                 .wrapping_add(1442695040888963407);
+            let len = 2 + (x >> 60) as usize % 10;
+            let mut k: Vec<u8> = (0..len)
+                .map(|i| b"etaoinsrhl"[((x >> (4 * i)) % 10) as usize])
+                .collect();
+            if x.is_multiple_of(3) {
+                k.extend(b"ing");
+            }
+            k
+        }).collect()
+    }
+
+    fn datasets() -> Vec<(&'static str, Vec<Vec<u8>>)> {
+        let urls = (0..5000).map(|i| {
+            format!("http://www.example.{}/{}/index-{}.html",
+                    ["com", "org", "net"][i % 3], i % 89, i * 7).into_bytes()
+        }).collect();
+        let numbers = (0..20000u64).map(|i| {
+            format!("{}", i.wrapping_mul(2654435761) % 1000003).into_bytes()
+        }).collect();
+        let prefixed = (0..3000).map(|i| {
+            format!("/a/rather/long/directory/name/shared/by/all/{}.txt",
+                    i * 31).into_bytes()
+        }).collect();
+        let binary = (0..3000u32).map(|i| {
+            vec![(i % 7) as u8, 0, (i >> 3) as u8, (i % 5) as u8, 0,
+                 (i * 13) as u8]
+        }).collect();
+        vec![("words", word_keys(20000)), ("urls", urls),
+             ("numbers", numbers), ("prefixed", prefixed),
+             ("binary", binary), ("empty", Vec::new())]
+    }
+
+    #[test]
+    fn estimate_matches_builds() {
+        let _ = env_logger::init();
+        for (name, keys) in datasets() {
+            for &num_tries in &[1, 2, 3, 5] {
+                for &mode in &[TailMode::Text, TailMode::Binary] {
+                    for &weights in &[false, true] {
+                        let config = Config::new()
+                            .with_num_tries(NumTries::new(num_tries))
+                            .with_tail_mode(mode)
+                            .with_weights(weights)
+                            .with_reverse_lookup(!weights);
+                        let mut keyset = Keyset::new();
+                        for k in &keys {
+                            keyset.push(k);
+                        }
+                        let estimate = LoudsTrie::estimate_size(&keyset,
+                                                                &config);
+                        let actual = LoudsTrie::build(&mut keyset, &config)
+                                     .unwrap().total_size();
+                        debug!("{} {:?}: {:?}, actual {}", name, config,
+                               estimate, actual);
+                        assert!(estimate.low() <= actual);
+                        assert!(actual <= estimate.high());
+                        let diff = if actual > estimate.expected() {
+                            actual - estimate.expected()
+                        } else {
+                            estimate.expected() - actual
+                        };
+                        assert!(diff * 4 <= estimate.expected());
+                    }
+                }
+            }
+        }
+    }
+}
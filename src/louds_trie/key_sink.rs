@@ -0,0 +1,87 @@
+//! Where restored key bytes go. Restoring appends bytes one at a time, or a
+//! tail string at once, so the same code can fill a Vec, a caller's fixed
+//! buffer, or just count.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+
+/// Takes the bytes of a key as they are restored.
+pub trait KeySink {
+    fn push(&mut self, c: u8);
+
+    fn push_slice(&mut self, bytes: &[u8]) {
+        for &c in bytes {
+            self.push(c);
+        }
+    }
+}
+
+impl KeySink for Vec<u8> {
+    fn push(&mut self, c: u8) {
+        Vec::push(self, c);
+    }
+    fn push_slice(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Counts the bytes and keeps none.
+#[derive(Debug, Default)]
+pub struct KeyLen(pub usize);
+
+impl KeySink for KeyLen {
+    fn push(&mut self, _: u8) {
+        self.0 += 1;
+    }
+    fn push_slice(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+}
+
+/// Writes the bytes into a slice from `pos` on, dropping whatever falls
+/// past its end.
+#[derive(Debug)]
+pub struct SliceSink<'a> {
+    pub buf: &'a mut [u8],
+    pub pos: usize,
+}
+
+impl<'a> KeySink for SliceSink<'a> {
+    fn push(&mut self, c: u8) {
+        if self.pos < self.buf.len() {
+            self.buf[self.pos] = c;
+        }
+        self.pos += 1;
+    }
+}
+
+/// Appends the bytes to a Vec until it is `limit` bytes long, and notes
+/// whether any were left over.
+#[derive(Debug)]
+pub struct LimitSink<'a> {
+    pub buf: &'a mut Vec<u8>,
+    pub limit: usize,
+    pub overflowed: bool,
+}
+
+impl<'a> KeySink for LimitSink<'a> {
+    fn push(&mut self, c: u8) {
+        if self.buf.len() < self.limit {
+            self.buf.push(c);
+        } else {
+            self.overflowed = true;
+        }
+    }
+}
+
+/// Keeps the first byte only.
+#[derive(Debug, Default)]
+pub struct FirstByte(pub Option<u8>);
+
+impl KeySink for FirstByte {
+    fn push(&mut self, c: u8) {
+        if self.0.is_none() {
+            self.0 = Some(c);
+        }
+    }
+}
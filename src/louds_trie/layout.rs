@@ -0,0 +1,189 @@
+//! The layouts a dictionary can be written or read in. They hold the same
+//! parts in the same order, and differ in how each part is encoded: the
+//! native one as `vector::io` lays vectors out.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use std::borrow::Cow;
+use std::io;
+use cache::Cache;
+use header::FORMAT_VERSION;
+use louds_trie::mapper::Source;
+use louds_trie::tail::Tail;
+use vector::bit_vec::BitVec;
+use vector::flat_vec::FlatVec;
+use vector::io::SerializableVec;
+use vector::pod_vec::{Pod, PodVec};
+use vector::util::{invalid_data, read_bytes, read_len, read_libmarisa_vec,
+                   read_u32, write_u32, write_u64};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// This crate's own, from `LoudsTrie::write_to`: lengths and counts are
+    /// u64s, every vector is padded to 8 bytes, and bit vectors include
+    /// their rank index and select tables, so that all of it can be mapped
+    /// in place.
+    Native,
+    /// Format version 2 of the native layout, which is only read: nothing
+    /// is padded, and bit vectors leave out their index.
+    NativeV2,
+    /// libmarisa 0.2's, from `LoudsTrie::write_libmarisa`: vectors are
+    /// padded to 8 bytes, counts are u32s, and bit vectors include their
+    /// rank index and select tables.
+    Libmarisa,
+}
+
+/// Writers only write the current native layout.
+const V2_IS_READ_ONLY: &'static str = "format version 2 is only read";
+
+/// Reads a version 2 vector of `T`, whose length counts values rather than
+/// bytes.
+fn read_v2_values<R: io::Read, T: Pod>(r: &mut R, what: &'static str)
+  -> io::Result<Vec<T>> {
+    let size = std::mem::size_of::<T>();
+    let num_bytes = read_len(r)?.checked_mul(size)
+                                .ok_or(invalid_data(what))?;
+    Ok(read_bytes(r, num_bytes)?.chunks(size).map(T::from_le).collect())
+}
+
+/// Reads a libmarisa vector of `T`.
+fn read_libmarisa_values<R: io::Read, T: Pod>(r: &mut R, what: &'static str)
+  -> io::Result<Vec<T>> {
+    let size = std::mem::size_of::<T>();
+    Ok(read_libmarisa_vec(r, size, what)?.chunks(size).map(T::from_le)
+                                         .collect())
+}
+
+impl Layout {
+    /// The native layout of a dictionary whose header gives `version`, which
+    /// `Header` has checked is one that can be read.
+    pub fn native(version: u32) -> Layout {
+        if version == FORMAT_VERSION {
+            Layout::Native
+        } else {
+            Layout::NativeV2
+        }
+    }
+
+    pub fn write_bit_vec<W: io::Write>(self, w: &mut W, bv: &BitVec)
+      -> io::Result<()> {
+        match self {
+            Layout::Native => bv.write_to(w),
+            Layout::NativeV2 => unreachable!("{}", V2_IS_READ_ONLY),
+            Layout::Libmarisa => bv.write_libmarisa(w),
+        }
+    }
+
+    pub fn read_bit_vec<'m, S: Source<'m>>(self, r: &mut S)
+      -> io::Result<BitVec<'m>> {
+        match self {
+            Layout::Native => r.read_vec(),
+            Layout::NativeV2 => BitVec::read_v2(r),
+            Layout::Libmarisa => BitVec::read_libmarisa(r),
+        }
+    }
+
+    pub fn write_bytes<W: io::Write>(self, w: &mut W, bytes: &[u8])
+      -> io::Result<()> {
+        self.write_values(w, bytes)
+    }
+
+    pub fn read_bytes<'m, S: Source<'m>>(self, r: &mut S)
+      -> io::Result<PodVec<'m, u8>> {
+        self.read_values(r, "bytes")
+    }
+
+    pub fn write_flat_vec<W: io::Write>(self, w: &mut W, fv: &FlatVec)
+      -> io::Result<()> {
+        match self {
+            Layout::Native => fv.write_to(w),
+            Layout::NativeV2 => unreachable!("{}", V2_IS_READ_ONLY),
+            Layout::Libmarisa => fv.write_libmarisa(w),
+        }
+    }
+
+    pub fn read_flat_vec<'m, S: Source<'m>>(self, r: &mut S)
+      -> io::Result<FlatVec<'m>> {
+        match self {
+            Layout::Native => r.read_vec(),
+            Layout::NativeV2 => FlatVec::read_v2(r),
+            Layout::Libmarisa => FlatVec::read_libmarisa(r),
+        }
+    }
+
+    pub fn write_tail<W: io::Write>(self, w: &mut W, tail: &Tail)
+      -> io::Result<()> {
+        match self {
+            Layout::Native => tail.write_to(w),
+            Layout::NativeV2 => unreachable!("{}", V2_IS_READ_ONLY),
+            Layout::Libmarisa => tail.write_libmarisa(w),
+        }
+    }
+
+    pub fn read_tail<'m, S: Source<'m>>(self, r: &mut S)
+      -> io::Result<Tail<'m>> {
+        match self {
+            Layout::Native => r.read_vec(),
+            Layout::NativeV2 => Tail::read_v2(r),
+            Layout::Libmarisa => Tail::read_libmarisa(r),
+        }
+    }
+
+    /// Each item is the parent, the child and then the link or weight bits,
+    /// as little-endian u32s.
+    pub fn write_cache<W: io::Write>(self, w: &mut W, cache: &[Cache])
+      -> io::Result<()> {
+        self.write_values(w, cache)
+    }
+
+    pub fn read_cache<'m, S: Source<'m>>(self, r: &mut S)
+      -> io::Result<PodVec<'m, Cache>> {
+        self.read_values(r, "cache")
+    }
+
+    /// A vector of values. The current native layout and libmarisa's lay
+    /// vectors out alike.
+    pub fn write_values<W: io::Write, T: Pod>(self, w: &mut W, values: &[T])
+      -> io::Result<()> {
+        match self {
+            Layout::Native | Layout::Libmarisa => {
+                PodVec(Cow::Borrowed(values)).write_to(w)
+            },
+            Layout::NativeV2 => unreachable!("{}", V2_IS_READ_ONLY),
+        }
+    }
+
+    pub fn read_values<'m, S: Source<'m>, T: Pod>(self, r: &mut S,
+                                                  what: &'static str)
+      -> io::Result<PodVec<'m, T>> {
+        match self {
+            Layout::Native => r.read_vec(),
+            Layout::NativeV2 => Ok(read_v2_values(r, what)?.into()),
+            Layout::Libmarisa => Ok(read_libmarisa_values(r, what)?.into()),
+        }
+    }
+
+    /// A count or flags word, which must fit in a u32 either way.
+    pub fn write_u32<W: io::Write>(self, w: &mut W, x: u32)
+      -> io::Result<()> {
+        match self {
+            Layout::Native => write_u64(w, x as u64),
+            Layout::NativeV2 => unreachable!("{}", V2_IS_READ_ONLY),
+            Layout::Libmarisa => write_u32(w, x),
+        }
+    }
+
+    pub fn read_u32<R: io::Read>(self, r: &mut R) -> io::Result<u32> {
+        match self {
+            Layout::Native | Layout::NativeV2 => {
+                let x = read_len(r)?;
+                if x > std::u32::MAX as usize {
+                    return Err(invalid_data("u32 field"));
+                }
+                Ok(x as u32)
+            },
+            Layout::Libmarisa => read_u32(r),
+        }
+    }
+}
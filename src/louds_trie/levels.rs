@@ -0,0 +1,145 @@
+//! How much each level of nesting is used, to tune `NumTries`.
+//!
+//! An edge of a trie is labelled either with one byte, stored in the node's
+//! base, or with a longer label, stored as a link into the next trie or the
+//! tail. The next trie is built from the link labels alone, so a level whose
+//! edges are almost all single bytes gives the one below it little to do.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use config::{MAX_NUM_TRIES, NumTries};
+use super::LoudsTrieView;
+
+/// Below this share of link edges, another level of nesting is not worth
+/// its overhead: it would only hold the labels of a few long edges, which
+/// the tail stores as well.
+pub const MIN_LINK_SHARE: f64 = 0.05;
+
+/// The edges of one level of a dictionary's nested tries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LevelStats {
+    num_nodes_: usize,
+    num_link_edges_: usize,
+    num_byte_edges_: usize,
+}
+
+impl LevelStats {
+    pub fn new(num_nodes: usize, num_link_edges: usize) -> LevelStats {
+        let num_edges = num_nodes.saturating_sub(1);
+        assert!(num_link_edges <= num_edges, "MARISA_BOUND_ERROR");
+        LevelStats { num_nodes_: num_nodes, num_link_edges_: num_link_edges,
+                     num_byte_edges_: num_edges - num_link_edges }
+    }
+
+    /// Nodes, the root included.
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes_
+    }
+    /// Edges labelled with more than one byte, through a link.
+    pub fn num_link_edges(&self) -> usize {
+        self.num_link_edges_
+    }
+    /// Edges labelled with a single byte.
+    pub fn num_byte_edges(&self) -> usize {
+        self.num_byte_edges_
+    }
+    /// The share of edges that are links, 0 for a trie with no edges.
+    pub fn link_share(&self) -> f64 {
+        match self.num_link_edges_ + self.num_byte_edges_ {
+            0 => 0.0,
+            n => self.num_link_edges_ as f64 / n as f64,
+        }
+    }
+}
+
+/// The number of tries to build keys like those behind `stats` with: one,
+/// and one more below each level, starting from the first, whose share of
+/// link edges is at least `MIN_LINK_SHARE`. When every level in `stats`
+/// has enough links, this is one more than the dictionary was built with.
+pub fn suggest_num_tries(stats: &[LevelStats]) -> NumTries {
+    let num = 1 + stats.iter().take_while(|level| {
+        level.link_share() >= MIN_LINK_SHARE
+    }).count();
+    NumTries::new(::std::cmp::min(num, MAX_NUM_TRIES as usize) as u32)
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// The edges of each trie, from the first; the links of the last lead
+    /// into the tail.
+    pub fn level_stats(&self) -> Vec<LevelStats> {
+        let mut out = Vec::new();
+        let mut trie = Some(self);
+        while let Some(t) = trie {
+            out.push(LevelStats::new(t.num_nodes(), t.link_flags_.num_1s()));
+            trie = t.next_trie_.as_ref().map(|next| &**next);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use config::{Config, MAX_NUM_TRIES, NumTries};
+    use keyset::Keyset;
+    use louds_trie::LoudsTrie;
+    use super::{LevelStats, suggest_num_tries};
+
+    fn build(keys: &[String], num_tries: u32) -> LoudsTrie {
+        let mut keyset = Keyset::new();
+        for key in keys {
+            keyset.push(key.as_bytes());
+        }
+        let config = Config::new().with_num_tries(NumTries::new(num_tries));
+        LoudsTrie::build(&mut keyset, &config).unwrap()
+    }
+
+    #[test]
+    fn suggestion_follows_link_shares() {
+        let _ = env_logger::init();
+        let level = |share: usize| LevelStats::new(101, share);
+        assert_eq!(suggest_num_tries(&[]).get(), 1);
+        assert_eq!(suggest_num_tries(&[level(4)]).get(), 1);
+        assert_eq!(suggest_num_tries(&[level(5)]).get(), 2);
+        assert_eq!(suggest_num_tries(&[level(50), level(30), level(4)]).get(),
+                   3);
+        // Only the levels down to the first with few links count.
+        assert_eq!(suggest_num_tries(&[level(50), level(0), level(50)]).get(),
+                   2);
+        assert_eq!(suggest_num_tries(&[LevelStats::new(1, 0)]).get(), 1);
+        let many = vec![level(100); MAX_NUM_TRIES as usize + 5];
+        assert_eq!(suggest_num_tries(&many).get(), MAX_NUM_TRIES);
+    }
+
+    #[test]
+    fn levels_of_built_tries() {
+        let _ = env_logger::init();
+        // Dense numbers branch on every byte: almost no links.
+        let numbers: Vec<String> = (0..10000).map(|i| i.to_string())
+                                             .collect();
+        let trie = build(&numbers, 3);
+        let levels = trie.level_stats();
+        assert!(levels.len() >= 1);
+        assert_eq!(levels[0].num_nodes(), trie.num_nodes());
+        assert_eq!(levels[0].num_link_edges() + levels[0].num_byte_edges(),
+                   trie.num_nodes() - 1);
+        assert_eq!(suggest_num_tries(&levels).get(), 1);
+
+        // Long keys that share little: most edges of the first trie are
+        // links, and so are many of the second.
+        let urls: Vec<String> = (0..2000u64).map(|i| {
+            format!("http://{:x}.example/{:x}/index.html",
+                    i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40, i)
+        }).collect();
+        let one = build(&urls, 1).level_stats();
+        assert_eq!(one.len(), 1);
+        assert!(one[0].link_share() >= 0.5, "{:?}", one);
+        assert_eq!(suggest_num_tries(&one).get(), 2);
+        let levels = build(&urls, 3).level_stats();
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0], one[0]);
+        assert!(suggest_num_tries(&levels).get() >= 2);
+    }
+}
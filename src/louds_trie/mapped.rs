@@ -0,0 +1,203 @@
+//! Dictionaries queried straight out of a memory-mapped file.
+//!
+//! A mapping sees the file as it is, not as it was when mapped: if the file is
+//! truncated, queries that touch the lost pages die with SIGBUS, and if it is
+//! written in place, they read a mix of the two dictionaries. Nothing on this
+//! side of the mapping can prevent that, so deploy new dictionaries by writing
+//! them to a new file in the same directory and renaming it over the old one,
+//! as `LoudsTrie::save` does by default. The rename leaves the mapped file
+//! untouched until the last mapping of it goes away, and a process that polls
+//! `is_stale` and calls `reopen` picks up the new file, swapping it in with
+//! something like `ArcSwap` so queries in flight finish on the old one. See
+//! `examples/hot_reload.rs`.
+
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::SystemTime;
+use memmap2::Mmap;
+use error::MarisaError;
+use super::{LoudsTrie, LoudsTrieView};
+
+/// What identifies the file a path named when it was mapped. A rename
+/// over the path changes the inode; a write in place or a truncation
+/// changes the length or the modification time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FileIdentity {
+    dev_: u64,
+    ino_: u64,
+    len_: u64,
+    modified_: Option<SystemTime>,
+}
+
+impl FileIdentity {
+    #[cfg(unix)]
+    fn of(metadata: &Metadata) -> FileIdentity {
+        use std::os::unix::fs::MetadataExt;
+        FileIdentity {
+            dev_: metadata.dev(),
+            ino_: metadata.ino(),
+            len_: metadata.len(),
+            modified_: metadata.modified().ok(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(metadata: &Metadata) -> FileIdentity {
+        FileIdentity {
+            dev_: 0,
+            ino_: 0,
+            len_: metadata.len(),
+            modified_: metadata.modified().ok(),
+        }
+    }
+}
+
+/// A dictionary written by `LoudsTrie::save`, mapped from its file as by
+/// `LoudsTrie::map_from_slice`. The mapping is page-aligned, so every part
+/// is borrowed from it, unless the file is of format version 2.
+pub struct MappedTrie {
+    // Borrows from `mmap_`, so it is declared, and dropped, first.
+    trie_: LoudsTrieView<'static>,
+    mmap_: Mmap,
+    path_: PathBuf,
+    identity_: FileIdentity,
+}
+
+/// Fails to compile if a mapped dictionary stops being shareable between
+/// threads, as `LoudsTrie` is.
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<MappedTrie>();
+}
+
+impl MappedTrie {
+    /// Maps the file at `path`, checking its header and checksum.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedTrie, MarisaError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        // From the open file, so it describes what is mapped even if the
+        // path is renamed over in between.
+        let identity = FileIdentity::of(&file.metadata()?);
+        let mmap = unsafe { Mmap::map(&file)? };
+        // The mapping does not move when `mmap` does, and `trie_` is dropped
+        // before it, so the view never outlives the bytes.
+        let buf: &'static [u8] = unsafe {
+            slice::from_raw_parts(mmap.as_ptr(), mmap.len())
+        };
+        let trie = LoudsTrie::map_from_slice(buf)?;
+        Ok(MappedTrie {
+            trie_: trie,
+            mmap_: mmap,
+            path_: path.to_path_buf(),
+            identity_: identity,
+        })
+    }
+
+    /// The mapped dictionary.
+    pub fn trie<'a>(&'a self) -> &'a LoudsTrieView<'a> {
+        &self.trie_
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path_
+    }
+
+    /// The number of bytes mapped.
+    pub fn mapped_len(&self) -> usize {
+        self.mmap_.len()
+    }
+
+    /// Whether the path no longer names the file as it was mapped: it was
+    /// renamed over, removed, truncated, or written to. A stale mapping of
+    /// a renamed-over or removed file is still safe to query; one of a file
+    /// changed in place is not, and should be replaced as soon as possible.
+    pub fn is_stale(&self) -> io::Result<bool> {
+        match Path::metadata(&self.path_) {
+            Ok(metadata) => Ok(FileIdentity::of(&metadata) != self.identity_),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Maps whatever file the path names now. `self` is left as it was, so
+    /// it can keep answering queries until the caller swaps in the result.
+    pub fn reopen(&self) -> Result<MappedTrie, MarisaError> {
+        MappedTrie::open(&self.path_)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use std::env;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+    use std::process;
+    use config::Config;
+    use keyset::Keyset;
+    use louds_trie::LoudsTrie;
+    use super::MappedTrie;
+
+    fn trie_of(keys: &[&str]) -> LoudsTrie {
+        let mut keyset = Keyset::new();
+        for key in keys {
+            keyset.push(key.as_bytes());
+        }
+        LoudsTrie::build(&mut keyset, &Config::new()).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("mars-trie-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn rename_then_reopen() {
+        let _ = env_logger::init();
+        let path = temp_path("rename.dic");
+        let next = temp_path("rename.dic.new");
+        trie_of(&["apple", "banana"]).save(&path).unwrap();
+
+        let old = MappedTrie::open(&path).unwrap();
+        assert_eq!(old.path(), path.as_path());
+        assert!(old.mapped_len() > 0);
+        assert!(old.trie().lookup(b"apple").is_some());
+        assert_eq!(old.trie().is_mapped(), cfg!(target_endian = "little"));
+        assert!(!old.is_stale().unwrap());
+
+        trie_of(&["cherry", "date", "elder"]).save(&next).unwrap();
+        fs::rename(&next, &path).unwrap();
+        assert!(old.is_stale().unwrap());
+        // The old file lives on for as long as it is mapped.
+        assert!(old.trie().lookup(b"apple").is_some());
+        assert_eq!(old.trie().len(), 2);
+
+        let new = old.reopen().unwrap();
+        assert!(!new.is_stale().unwrap());
+        assert_eq!(new.trie().len(), 3);
+        assert!(new.trie().lookup(b"apple").is_none());
+        assert!(new.trie().lookup(b"cherry").is_some());
+
+        fs::remove_file(&path).unwrap();
+        assert!(new.is_stale().unwrap());
+        assert!(new.reopen().is_err());
+    }
+
+    #[test]
+    fn truncation_is_stale() {
+        let _ = env_logger::init();
+        let path = temp_path("truncate.dic");
+        trie_of(&["apple", "banana"]).save(&path).unwrap();
+        let mapped = MappedTrie::open(&path).unwrap();
+        // Not queried after this: its pages are gone.
+        OpenOptions::new().write(true).open(&path).unwrap()
+                          .set_len(8).unwrap();
+        assert!(mapped.is_stale().unwrap());
+        assert!(mapped.reopen().is_err());
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+//! Where `LoudsTrieView::read_` takes a dictionary's parts from. Like
+//! marisa's Reader and Mapper, both sources go through the same parsing
+//! and checks, and differ only in whether native vectors are copied or
+//! borrowed. Both find each native vector by the section table.
+
+use std::io;
+use louds_trie::section_table::{SectionTable, skip_padding};
+use vector::io::SerializableVec;
+
+pub trait Source<'m>: io::Read {
+    /// Reads the section table at the start of a native payload of
+    /// `payload_len` bytes, which `read_vec` then goes by.
+    fn read_section_table(&mut self, payload_len: u64) -> io::Result<()>;
+
+    /// Reads a vector in the native layout from the start of the next
+    /// section.
+    fn read_vec<T: SerializableVec<'m>>(&mut self) -> io::Result<T>;
+
+    /// Whether every section in the table has been read.
+    fn sections_done(&self) -> bool;
+}
+
+/// A stream. Every part is copied out of it.
+pub struct Reader<'r, R: 'r> {
+    r_: &'r mut R,
+    /// The number of bytes read so far.
+    pos_: u64,
+    sections_: SectionTable,
+}
+
+impl<'r, R: io::Read> Reader<'r, R> {
+    pub fn new(r: &'r mut R) -> Reader<'r, R> {
+        Reader { r_: r, pos_: 0, sections_: SectionTable::default() }
+    }
+}
+
+impl<'r, R: io::Read> io::Read for Reader<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r_.read(buf)?;
+        self.pos_ += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'r, 'm, R: io::Read> Source<'m> for Reader<'r, R> {
+    fn read_section_table(&mut self, payload_len: u64) -> io::Result<()> {
+        self.sections_ = SectionTable::read(self, payload_len)?;
+        Ok(())
+    }
+
+    fn read_vec<T: SerializableVec<'m>>(&mut self) -> io::Result<T> {
+        let padding = self.sections_.next(self.pos_)?;
+        skip_padding(self, padding)?;
+        T::read_from(self)
+    }
+
+    fn sections_done(&self) -> bool {
+        self.sections_.is_done()
+    }
+}
+
+/// A mapped payload. Native vectors are borrowed from it, and must be
+/// aligned for their values; everything else is read a byte at a time.
+pub struct Mapper<'m> {
+    buf_: &'m [u8],
+    pos_: usize,
+    sections_: SectionTable,
+}
+
+impl<'m> Mapper<'m> {
+    pub fn new(buf: &'m [u8]) -> Mapper<'m> {
+        Mapper { buf_: buf, pos_: 0, sections_: SectionTable::default() }
+    }
+
+    /// Whether all of the payload has been read.
+    pub fn is_empty(&self) -> bool {
+        self.pos_ == self.buf_.len()
+    }
+}
+
+impl<'m> io::Read for Mapper<'m> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = io::Read::read(&mut &self.buf_[self.pos_..], buf)?;
+        self.pos_ += n;
+        Ok(n)
+    }
+}
+
+impl<'m> Source<'m> for Mapper<'m> {
+    fn read_section_table(&mut self, payload_len: u64) -> io::Result<()> {
+        self.sections_ = SectionTable::read(self, payload_len)?;
+        Ok(())
+    }
+
+    fn read_vec<T: SerializableVec<'m>>(&mut self) -> io::Result<T> {
+        let padding = self.sections_.next(self.pos_ as u64)?;
+        skip_padding(self, padding)?;
+        T::map_at(self.buf_, &mut self.pos_)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn sections_done(&self) -> bool {
+        self.sections_.is_done()
+    }
+}
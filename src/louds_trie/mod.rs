@@ -1,32 +1,169 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 use std;
 use std::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::fs::File;
+use std::io;
+use std::iter::FromIterator;
+#[cfg(feature = "std")]
+use std::path::Path;
 
+#[cfg(feature = "std")]
+use atomic_write::{WriteOptions, write_file};
+use base::{checked_u32, checked_usize};
 use cache::Cache;
 use config::Config;
+use crc32::{Crc32, Hashed};
+use config::MAX_NUM_TRIES;
 use config::CacheLevel;
 use config::NodeOrder;
 use config::TailMode;
 use entry::Entry;
+use error::MarisaError;
+use header::Header;
 use range::Range;
 use range::WeightedRange;
+use sort;
+use sort::Label;
 use key::IKey;
 use key::Key;
 use key::ReverseKey;
+use keyset::{KeyItem, Keyset};
+use louds_trie::key_sink::{FirstByte, KeyLen, KeySink, LimitSink,
+                            SliceSink};
+use louds_trie::layout::Layout;
+#[cfg(feature = "std")]
+use louds_trie::mapper::Reader;
+use louds_trie::mapper::{Mapper, Source};
+use louds_trie::section_table::SectionTable;
 use louds_trie::tail::Tail;
 use vector::bit_vec::BitVec;
 use vector::flat_vec::FlatVec;
+use vector::intrinsic::prefetch;
+use vector::io::SerializableVec;
+use vector::pod_vec::PodVec;
+use vector::util::{invalid_data, map_bytes, slice_total_size};
 
+mod builder;
+#[cfg(feature = "query-cache")]
+mod cached;
+#[cfg(feature = "std")]
+mod dot;
+mod estimate;
+mod key_sink;
+mod layout;
+mod levels;
+#[cfg(all(feature = "std", feature = "memmap2"))]
+mod mapped;
+mod mapper;
 pub mod nav;
+#[cfg(all(feature = "std", feature = "rayon"))]
+mod par;
+mod rebuild;
+#[cfg(feature = "std")]
+mod retune;
+mod section_table;
+mod set_ops;
+mod sorted;
+mod stats;
 mod tail;
+mod utf8;
+mod verify;
+
+pub use self::builder::TrieBuilder;
+#[cfg(feature = "query-cache")]
+pub use self::cached::{CacheCapacity, CachedTrie, QueryCache};
+pub use self::estimate::SizeEstimate;
+pub use self::levels::{LevelStats, MIN_LINK_SHARE, suggest_num_tries};
+pub use self::nav::Nav;
+pub use self::set_ops::{Difference, Intersection};
+#[cfg(all(feature = "std", feature = "memmap2"))]
+pub use self::mapped::MappedTrie;
+#[cfg(feature = "std")]
+pub use self::retune::CacheRetune;
+pub use self::stats::{SectionSizes, TrieStats};
+pub use self::utf8::{LossyPredictiveIter, StrPredictiveIter};
 
 pub const INVALID_EXTRA: u32 = std::u32::MAX >> 8;
 
+/// Version of the rules by which `build` assigns key ids. Building the same
+/// keyset with the same config gives the same ids as long as this stays the
+/// same, so a change means ids stored elsewhere must be regenerated. Bump it,
+/// and regenerate tests/data/id_golden.txt, whenever ids change.
+pub const ID_ALGORITHM_VERSION: u32 = 1;
+
+/// If every key starts with the same bytes, and there are at least this many
+/// of them, they are stored once instead of as the label of the root's only
+/// child. Matching a label goes byte by byte through the next trie or tail,
+/// while the stripped prefix is compared as a slice.
+const MIN_COMMON_PREFIX: usize = 32;
+
+/// How many queries `lookup_batch` walks down together: enough that fetches
+/// overlap, few enough that the group's state stays on the stack.
+const BATCH_WIDTH: usize = 16;
+
 #[derive(Clone, Copy, Debug)]
 pub struct LoudsPos(u32);
 
 #[derive(Clone, Copy, Debug)]
 pub struct NodeID(u32);
 
+/// Whether queries cross-check their results; see `LoudsTrie::set_paranoia`.
+/// Zero-sized in release builds, where the checks are compiled out.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy, Debug, Default)]
+struct Paranoia(bool);
+
+#[cfg(not(debug_assertions))]
+#[derive(Clone, Copy, Debug, Default)]
+struct Paranoia;
+
+impl Paranoia {
+    #[cfg(debug_assertions)]
+    fn is_on(&self) -> bool { self.0 }
+    #[cfg(debug_assertions)]
+    fn set(&mut self, on: bool) { self.0 = on; }
+
+    #[cfg(not(debug_assertions))]
+    fn is_on(&self) -> bool { false }
+    #[cfg(not(debug_assertions))]
+    fn set(&mut self, _: bool) {}
+}
+
+/// Fails to compile if a part of the trie stops being shareable between
+/// threads, e.g. a cache behind a `RefCell`.
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<LoudsTrie>();
+    is_send_sync::<LoudsTrieView<'static>>();
+    is_send_sync::<SearchState>();
+}
+
+/// The order `PredictiveIter::order` has a predictive search visit keys
+/// in, instead of depth first in node order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SearchOrder {
+    /// Ascending key id. The subtree is walked a level at a time, and each
+    /// key is restored upwards from its last node.
+    Id,
+    /// Ascending byte order. Free for a dictionary built with
+    /// `NodeOrder::Label`; otherwise each node's children are sorted by
+    /// the first byte of their labels.
+    Lex,
+}
+
+/// What `LoudsTrie::for_each_key_into` does with a key longer than its
+/// buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LongKeys {
+    /// Report the first `buf.len()` bytes, with the truncation flag set.
+    Truncate,
+    /// Leave the key out.
+    Skip,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct LinkID(u32);
 
@@ -46,6 +183,7 @@ pub const INVALID_LINK_ID: LinkID = LinkID(std::u32::MAX);
 ///
 /// Example (from Jacobson):
 ///
+/// ```text
 ///  Tree:              With degrees:
 ///
 ///                           10  <-- super-root
@@ -66,6 +204,7 @@ pub const INVALID_LINK_ID: LinkID = LinkID(std::u32::MAX);
 ///  Degree bit sequences, concatenated in level order:
 ///
 ///  10 1110 110 0 10 10 10 0 0 0
+/// ```
 ///
 /// Nodes are represented by the index of their corresponding '1' bit.
 /// Traversal operations are as follows:
@@ -90,24 +229,33 @@ pub const INVALID_LINK_ID: LinkID = LinkID(std::u32::MAX);
 ///
 /// 'louds_pos' variables refer to bit indexes in 'louds_'
 ///
+/// A `LoudsTrie` owns all of its parts. One from `LoudsTrie::map_from_slice`
+/// borrows them for `'m` from the buffer it was mapped from, and otherwise
+/// works the same, so code that takes a `&LoudsTrieView` accepts both.
+///
+/// Nothing changes after the build: queries take `&self` and keep their
+/// scratch space in a `SearchState` or iterator of the caller's, so one
+/// dictionary can be shared by any number of threads behind an `Arc`.
 #[derive(Debug)]
-pub struct LoudsTrie {
+pub struct LoudsTrieView<'m> {
     /// The tree structure
-    louds_: BitVec,
+    louds_: BitVec<'m>,
 
     /// Bit vector of terminal-ness per node id. Indexed by node. Can be used to
     /// retrieve NodeID from user-facing word ID:
     ///
-    ///     let node_id = NodeID(self.terminal_flags_.select1(id));
-    ///
-    terminal_flags_: BitVec,
+    /// ```ignore
+    /// let node_id = NodeID(self.terminal_flags_.select1(id));
+    /// ```
+    terminal_flags_: BitVec<'m>,
 
     /// Per node, does this node have a link to another trie? Indexed by node.
     /// LinkIDs are assigned sequentially to nodes that have links, so
     ///
-    ///     let link_id = LinkID(self.link_flags_.rank1(node_id))
-    ///
-    link_flags_: BitVec,
+    /// ```ignore
+    /// let link_id = LinkID(self.link_flags_.rank1(node_id))
+    /// ```
+    link_flags_: BitVec<'m>,
 
     /// Base characters, limited to one per node. Indexed by node. Present if
     /// link_flags_[node_id] is false.
@@ -115,87 +263,120 @@ pub struct LoudsTrie {
     /// If link_flags[node_id] is true, bases_[node_id] contains the low byte of
     /// the connected NodeID (returned by `get_linked_node_id`). This NodeID
     /// points into the next trie if there is one, or into the Tail.
-    bases_: Vec<u8>,
+    bases_: PodVec<'m, u8>,
 
     /// Upper bits of the connected node_id, indexed by link_id(?)
-    extras_: FlatVec,
+    extras_: FlatVec<'m>,
 
     /// Tail strings, accessed by NodeID returned from `get_linked_node_id`.
-    tail_: Tail,
+    tail_: Tail<'m>,
 
     /// Next trie
-    next_trie_: Option<Box<LoudsTrie> >,
+    next_trie_: Option<Box<LoudsTrieView<'m>> >,
 
-    cache_: Vec<Cache>,
+    cache_: PodVec<'m, Cache>,
     cache_mask_: usize,
     num_l1_nodes_: usize,
 
     config_: Config,
 //    mapper_: Mapper,
+
+    /// Bytes every key starts with, stripped from the keys before they were
+    /// added to the trie. Only ever set on the root trie.
+    common_prefix_: PodVec<'m, u8>,
+
+    /// Summed weight of each key, indexed by key id. Empty unless the
+    /// dictionary was built with `Config::with_weights(true)`.
+    weights_: PodVec<'m, f32>,
+
+    /// `ID_ALGORITHM_VERSION` when the dictionary was built. Only set on the
+    /// root trie.
+    id_algorithm_version_: u32,
+
+    paranoia_: Paranoia,
+}
+
+/// How much `read_` checks of a dictionary, past what keeps queries from
+/// indexing out of bounds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Checks {
+    /// Only what takes constant time a part: lengths, and that the indexes
+    /// queries need are there.
+    Lengths,
+    /// Also that parents come before their children, that links lead
+    /// somewhere, and that the cache agrees with the trie.
+    All,
 }
 
+/// A dictionary that owns all of its parts.
+pub type LoudsTrie = LoudsTrieView<'static>;
+
 trait CallBuildNextTrie {
-    fn build_next_trie(&mut self, louds_trie: &mut LoudsTrie,
-                       terminals: &mut Vec<u32>, config: &mut Config,
-                       trie_id: usize);
+    fn build_next_trie<'m>(&mut self, louds_trie: &mut LoudsTrieView<'m>,
+                           terminals: &mut Vec<u64>, config: &Config,
+                           trie_id: usize) -> Result<(), MarisaError>;
 }
 
 impl<'a> CallBuildNextTrie for Vec<Key<'a>> {
-    fn build_next_trie(&mut self, louds_trie: &mut LoudsTrie,
-                       terminals: &mut Vec<u32>, config: &mut Config,
-                       trie_id: usize) {
-        louds_trie.build_next_trie_fwd(self, terminals, config, trie_id);
+    fn build_next_trie<'m>(&mut self, louds_trie: &mut LoudsTrieView<'m>,
+                           terminals: &mut Vec<u64>, config: &Config,
+                           trie_id: usize) -> Result<(), MarisaError> {
+        louds_trie.build_next_trie_fwd(self, terminals, config, trie_id)
     }
 }
 
 impl<'a> CallBuildNextTrie for Vec<ReverseKey<'a>> {
-    fn build_next_trie(&mut self, louds_trie: &mut LoudsTrie,
-                       terminals: &mut Vec<u32>, config: &mut Config,
-                       trie_id: usize) {
-        louds_trie.build_next_trie_rev(self, terminals, config, trie_id);
+    fn build_next_trie<'m>(&mut self, louds_trie: &mut LoudsTrieView<'m>,
+                           terminals: &mut Vec<u64>, config: &Config,
+                           trie_id: usize) -> Result<(), MarisaError> {
+        louds_trie.build_next_trie_rev(self, terminals, config, trie_id)
     }
 }
 
 trait CallCache {
-    fn cache(&self, louds_trie: &mut LoudsTrie, parent: usize, child: usize,
-             weight: f32, label: u8);
+    fn cache<'m>(&self, louds_trie: &mut LoudsTrieView<'m>, parent: usize,
+                 child: usize, weight: f32, label: u8);
 }
 
 impl<'a> CallCache for Vec<Key<'a>> {
-    fn cache(&self, louds_trie: &mut LoudsTrie, parent: usize, child: usize,
-             weight: f32, label: u8) {
+    fn cache<'m>(&self, louds_trie: &mut LoudsTrieView<'m>, parent: usize,
+                 child: usize, weight: f32, label: u8) {
         louds_trie.cache_fwd(parent, child, weight, label);
     }
 }
 
 impl<'a> CallCache for Vec<ReverseKey<'a>> {
-    fn cache(&self, louds_trie: &mut LoudsTrie, parent: usize, child: usize,
-             weight: f32, _: u8) {
+    fn cache<'m>(&self, louds_trie: &mut LoudsTrieView<'m>, parent: usize,
+                 child: usize, weight: f32, _: u8) {
         louds_trie.cache_rev(parent, child, weight);
     }
 }
 
-impl LoudsTrie {
+impl<'m> LoudsTrieView<'m> {
     // We shouldn't expose this. Clients can just use build, map, and read.
-    fn new() -> LoudsTrie {
-        LoudsTrie { 
+    fn new() -> LoudsTrieView<'m> {
+        LoudsTrieView {
             louds_: BitVec::new(),
             terminal_flags_: BitVec::new(),
             link_flags_: BitVec::new(),
-            bases_: Vec::new(),
+            bases_: PodVec::new(),
             extras_: FlatVec::new(),
             tail_: Tail::new(),
             next_trie_: None,
-            cache_: Vec::new(),
+            cache_: PodVec::new(),
             cache_mask_: 0,
             num_l1_nodes_: 0,
             config_: Config::new(),
             // mapper: Mapper::new(),
+            common_prefix_: PodVec::new(),
+            weights_: PodVec::new(),
+            id_algorithm_version_: ID_ALGORITHM_VERSION,
+            paranoia_: Paranoia::default(),
         }
     }
 
     pub fn clear(&mut self) {
-        *self = LoudsTrie::new();
+        *self = LoudsTrieView::new();
     }
 
     pub fn has_child(&self, node_id: NodeID) -> bool {
@@ -223,16 +404,94 @@ impl LoudsTrie {
         }
     }
 
-    pub fn build<'a>(keys: &mut Vec<Key<'a> >, config: &Config) -> LoudsTrie {
-        let mut config = *config;
+    /// Builds a dictionary from `keyset`, and stores the id assigned to each
+    /// key back into it. Fails with a SizeError if the keys need more nodes
+    /// or tail bytes than the format can address. Keys that look like lines
+    /// of a Windows text file, as counted by `Keyset::validate`, are built
+    /// as they are, with a warning logged.
+    pub fn build(keyset: &mut Keyset, config: &Config)
+      -> Result<LoudsTrie, MarisaError> {
+        let artifacts = keyset.validate();
+        if !artifacts.is_empty() {
+            warn!("building keys with {}; see keyio::read_keys", artifacts);
+        }
+        let prefix_len = match common_prefix_len(keyset) {
+            len if len >= MIN_COMMON_PREFIX => len,
+            _ => 0,
+        };
+        let (trie, ids) = {
+            let mut keys: Vec<Key> = keyset.iter().enumerate()
+                .map(|(i, k)| {
+                    Key::new(&k[prefix_len..]).with_weight(keyset.weight(i))
+                })
+                .collect();
+            let mut trie = LoudsTrie::build_(&mut keys, config)?;
+            if prefix_len > 0 {
+                trie.common_prefix_ =
+                    keyset.key(0)[..prefix_len].to_vec().into();
+            }
+            let ids: Vec<usize> = keys.iter().map(|k| k.get_id()).collect();
+            (trie, ids)
+        };
+        for (i, id) in ids.into_iter().enumerate() {
+            keyset.set_id(i, id);
+        }
+        Ok(trie)
+    }
+
+    /// Builds a dictionary from keys, or from `(key, weight)` pairs, of any
+    /// type a Keyset can be collected from. Duplicate keys are merged as in
+    /// `build`: they share one id and their weights are summed.
+    ///
+    /// ```
+    /// use mars_trie::{Config, LoudsTrie};
+    ///
+    /// let trie = LoudsTrie::build_from(vec![("apple", 2.0), ("banana", 1.0)],
+    ///                                  &Config::new()).unwrap();
+    /// assert!(trie.lookup(b"apple").is_some());
+    /// let trie: LoudsTrie = vec!["apple", "banana"].into_iter().collect();
+    /// assert!(trie.lookup(b"cherry").is_none());
+    /// ```
+    pub fn build_from<I>(keys: I, config: &Config)
+      -> Result<LoudsTrie, MarisaError>
+      where I: IntoIterator, Keyset: FromIterator<I::Item> {
+        let mut keyset: Keyset = keys.into_iter().collect();
+        LoudsTrie::build(&mut keyset, config)
+    }
+
+    /// Builds a dictionary from keys, or `(key, weight)` pairs, that come in
+    /// strictly ascending byte order, without holding them all at once: the
+    /// memory it takes grows with the trie rather than with the keys. The
+    /// dictionary is the same, byte for byte, as `build` makes of them.
+    /// Fails with an InvalidKey error at the first key that is out of order
+    /// or repeats the one before it, or that `Keyset::try_push_weighted`
+    /// would refuse. Ids are not handed back; look the keys up.
+    ///
+    /// ```
+    /// use mars_trie::{Config, LoudsTrie};
+    ///
+    /// let trie = LoudsTrie::build_from_sorted(vec!["apple", "banana"],
+    ///                                         &Config::new()).unwrap();
+    /// assert!(trie.lookup(b"banana").is_some());
+    /// assert!(LoudsTrie::build_from_sorted(vec!["b", "a"], &Config::new())
+    ///             .is_err());
+    /// ```
+    pub fn build_from_sorted<I>(keys: I, config: &Config)
+      -> Result<LoudsTrie, MarisaError>
+      where I: IntoIterator, I::Item: KeyItem {
+        sorted::build(keys, config)
+    }
+
+    fn build_<'a>(keys: &mut Vec<Key<'a> >, config: &Config)
+      -> Result<LoudsTrie, MarisaError> {
         let mut out = LoudsTrie::new();
 
         let mut keys_cpy = keys.clone();
-        let mut terminals: Vec<u32> = Vec::new();
-        out.build_trie(&mut keys_cpy, &mut terminals, &mut config, 1);
+        let mut terminals: Vec<u64> = Vec::new();
+        out.build_trie(&mut keys_cpy, &mut terminals, config, 1)?;
 
-        let mut pairs: Vec<(u32, u32)> = Vec::new();
-        let mut pairs: Vec<(u32, u32)> = terminals.iter().enumerate()
+        checked_u32(terminals.len(), "number of keys")?;
+        let mut pairs: Vec<(u64, u32)> = terminals.iter().enumerate()
                                          .map(|(i, &x)| (x, i as u32))
                                          .collect();
         terminals.clear();
@@ -240,62 +499,75 @@ impl LoudsTrie {
 
         // FIXME: Clean up this usize/u32 situation.
 
+        out.terminal_flags_ = BitVec::with_capacity(out.bases_.len() + 1);
         let mut node_id: usize = 0;
         for pair in &pairs {
-            while node_id < pair.0 as usize {
-                out.terminal_flags_.push(false);
-                node_id += 1;
-            }
-            if node_id == pair.0 as usize {
+            if node_id <= pair.0 as usize {
+                out.terminal_flags_.push_run(false,
+                                             pair.0 as usize - node_id);
                 out.terminal_flags_.push(true);
-                node_id += 1;
+                node_id = pair.0 as usize + 1;
             }
         }
-        while node_id < out.bases_.len() {
-            out.terminal_flags_.push(false);
-            node_id += 1;
-        }
-        out.terminal_flags_.push(false);
-        out.terminal_flags_.build(false, true);
+        out.terminal_flags_.push_run(false, out.bases_.len() + 1 - node_id);
+        out.terminal_flags_.build_index(false, config.reverse_lookup());
 
         assert!(pairs.len() == keys.len());
         for pair in &pairs {
             keys[pair.1 as usize].set_id(
                 out.terminal_flags_.rank1(pair.0 as usize));
         }
-        out
+
+        if config.weights() {
+            let mut weights = vec![0.0f32; out.len()];
+            for key in keys.iter() {
+                weights[key.get_id()] += key.get_weight();
+            }
+            out.weights_ = weights.into();
+        }
+        out.config_.set_weights(config.weights());
+        out.config_.set_reverse_lookup(config.reverse_lookup());
+        Ok(out)
     }
 
     fn build_trie<'a, T>(
-        &mut self, keys: &mut Vec<T>, terminals: &mut Vec<u32>,
-        config: &mut Config, trie_id: usize)
-        where T: IKey<'a> + Ord + From<&'a[u8]>,
+        &mut self, keys: &mut Vec<T>, terminals: &mut Vec<u64>,
+        config: &Config, trie_id: usize) -> Result<(), MarisaError>
+        where T: IKey<'a> + Ord + Label + From<&'a[u8]>,
               Vec<T>: CallCache + CallBuildNextTrie
     {
-        self.build_current_trie(keys, terminals, config, trie_id);
+        self.build_current_trie(keys, terminals, config, trie_id)?;
+        self.build_links(keys, config, trie_id)
+    }
 
-        let mut next_terminals: Vec<u32> = Vec::new();
+    /// Builds the next trie or the tail from `keys`, the labels of this
+    /// trie's links in node order, and points the links into it.
+    fn build_links<T>(&mut self, keys: &mut Vec<T>, config: &Config,
+                      trie_id: usize) -> Result<(), MarisaError>
+        where Vec<T>: CallBuildNextTrie
+    {
+        let mut next_terminals: Vec<u64> = Vec::new();
         if !keys.is_empty() {
-            keys.build_next_trie(self, &mut next_terminals, config, trie_id);
+            keys.build_next_trie(self, &mut next_terminals, config,
+                                 trie_id)?;
         }
 
-        match &self.next_trie_ {
-            &Some(ref x) => {
-                let new_cfg = (x.num_tries() + 1) as usize
+        // Record what was actually built, which may differ from what was
+        // asked for: fewer tries if the links ran out, or a binary tail if a
+        // label contains a NUL.
+        let new_cfg = match &self.next_trie_ {
+            &Some(ref x) => (x.num_tries() + 1) as usize
                             | x.tail_mode() as usize
-                            | x.node_order() as usize;
-                assert!(new_cfg <= std::u32::MAX as usize);
-                *config = Config::parse(new_cfg as u32);
-            },
-            &None => {
-                let new_cfg = 1
-                            | self.tail_.mode() as usize
-                            | config.node_order() as usize
-                            | config.cache_level() as usize;
-                *config = Config::parse(new_cfg as u32);
-            }
-        }
-        self.link_flags_.build(false, false);
+                            | x.node_order() as usize
+                            | config.cache_level() as usize,
+            &None => 1
+                     | self.tail_.mode() as usize
+                     | config.node_order() as usize
+                     | config.cache_level() as usize,
+        };
+        assert!(new_cfg <= std::u32::MAX as usize);
+        self.config_ = Config::parse(new_cfg as u32)?;
+        self.link_flags_.build_index(false, false);
         let mut node_id: usize = 0;
         for nt in next_terminals.iter_mut() {
             while !self.link_flags_.at(node_id) {
@@ -305,20 +577,27 @@ impl LoudsTrie {
             *nt /= 256;
             node_id += 1;
         }
-        self.extras_.build(next_terminals.iter());
+        // Node ids are u32, and Tail::build keeps offsets below 2^40, so
+        // what is left of each fits an extra.
+        let extras: Vec<u32> = next_terminals.iter().map(|&nt| {
+            debug_assert!(nt <= std::u32::MAX as u64);
+            nt as u32
+        }).collect();
+        self.extras_.build(extras.iter());
         self.fill_cache();
+        Ok(())
     }
 
     fn build_current_trie<'a, T>(
-        &mut self, keys: &mut Vec<T>, terminals: &mut Vec<u32>, config: &Config,
-        trie_id: usize)
-        where T: IKey<'a> + Ord + From<&'a[u8]>, Vec<T>: CallCache
+        &mut self, keys: &mut Vec<T>, terminals: &mut Vec<u64>, config: &Config,
+        trie_id: usize) -> Result<(), MarisaError>
+        where T: IKey<'a> + Ord + Label + From<&'a[u8]>,
+              Vec<T>: CallCache
     {
         for (i, key) in keys.iter_mut().enumerate() {
             key.set_id(i);
         }
-        // FIXME: sort fn
-        keys.sort();
+        sort::sort(keys);
         let num_keys = keys.len();
 
         self.reserve_cache(config, trie_id, num_keys);
@@ -336,6 +615,7 @@ impl LoudsTrie {
         while let Some(mut range) = queue.pop_front() {
             let node_id: usize = self.link_flags_.len() - queue.len() - 1;
 
+            checked_u32(node_id, "number of nodes")?;
             while (range.begin() < range.end()) &&
                   (keys[range.begin()].len() == range.key_pos()) {
                 keys[range.begin()].set_terminal(node_id);
@@ -362,11 +642,16 @@ impl LoudsTrie {
             }
             w_ranges.push(WeightedRange::new(
                 range.begin(), range.end(), range.key_pos(), weight as f32));
-            if config.node_order() == NodeOrder::Weight {
-                // FIXME: This should be a std::stable_sort replacement. Not
-                //        sure if really needed... but I would guess it's not
-                //        here for no reason.
-                w_ranges.sort_by(|a, b| b.partial_cmp(a).unwrap()); // reverse
+            match config.node_order() {
+                // The keys are sorted, so the ranges already come in
+                // ascending label order.
+                NodeOrder::Label => {},
+                NodeOrder::Weight => {
+                    // FIXME: This should be a std::stable_sort replacement.
+                    //        Not sure if really needed... but I would guess
+                    //        it's not here for no reason.
+                    w_ranges.sort_by(|a, b| b.partial_cmp(a).unwrap());
+                }
             }
 
             if node_id == 0 {
@@ -403,17 +688,18 @@ impl LoudsTrie {
                 }
                 w_range.set_key_pos(key_pos);
                 queue.push_back(*w_range.range());
-                self.louds_.push(true);
             }
+            self.louds_.push_run(true, w_ranges.len());
             self.louds_.push(false);
         }
 
         self.louds_.push(false);
-        self.louds_.build(trie_id == 1, true);
+        self.louds_.build_index(trie_id == 1, true);
         self.bases_.shrink_to_fit();
 
         self.build_terminals(keys, terminals);
         *keys = next_keys;
+        Ok(())
     }
 
     fn cache_fwd(&mut self, parent: usize, child: usize, weight: f32, label: u8)
@@ -443,30 +729,31 @@ impl LoudsTrie {
  
     fn reserve_cache(&mut self, config: &Config, trie_id: usize,
                      num_keys: usize) {
-        let mut cache_size: usize = if trie_id == 1 { 256 } else { 1 };
-        while cache_size < (num_keys / config.cache_level() as usize) {
-            cache_size *= 2;
-        }
-        self.cache_.resize(cache_size, Cache::new());
+        let cache_size = cache_len(config, trie_id, num_keys);
+        self.cache_.to_mut().resize(cache_size, Cache::new());
         self.cache_mask_ = cache_size - 1;
     }
 
     fn build_tail<'a, T: Ord + IKey<'a>>(&mut self, keys: &Vec<T>,
-                                         terminals: &mut Vec<u32>,
-                                         config: &mut Config) {
+                                         terminals: &mut Vec<u64>,
+                                         config: &Config)
+      -> Result<(), MarisaError> {
         let mut entries: Vec<Entry<'a>> = Vec::new();
         entries.reserve(keys.len());
         for key in keys {
             entries.push(Entry::new(key.get_slice(), 0));
         }
-        self.tail_ = Tail::build(&mut entries, terminals, config.tail_mode());
+        self.tail_ = Tail::build(&mut entries, terminals,
+                                 config.tail_mode())?;
+        Ok(())
     }
 
     fn build_next_trie_fwd<'a>(&mut self, keys: &mut Vec<Key<'a>>,
-                               terminals: &mut Vec<u32>,
-                               config: &mut Config, trie_id: usize) {
+                               terminals: &mut Vec<u64>,
+                               config: &Config, trie_id: usize)
+      -> Result<(), MarisaError> {
         if trie_id == config.num_tries().get() as usize {
-            self.build_tail(keys, terminals, config);
+            self.build_tail(keys, terminals, config)
         } else {
             let mut reverse_keys: Vec<ReverseKey> = Vec::new();
             reverse_keys.reserve(keys.len());
@@ -474,65 +761,111 @@ impl LoudsTrie {
                 reverse_keys.push(ReverseKey::from_key(key));
             }
             keys.clear();
-            self.next_trie_ = Some(Box::new(LoudsTrie::new()));
+            self.next_trie_ = Some(Box::new(LoudsTrieView::new()));
             let mut next_trie = self.next_trie_.as_mut().unwrap();
             next_trie.build_trie(&mut reverse_keys, terminals, config,
-                                 trie_id + 1);
+                                 trie_id + 1)
         }
     }
 
     fn build_next_trie_rev<'a>(&mut self, keys: &mut Vec<ReverseKey<'a>>,
-                               terminals: &mut Vec<u32>,
-                               config: &mut Config, trie_id: usize) {
+                               terminals: &mut Vec<u64>,
+                               config: &Config, trie_id: usize)
+      -> Result<(), MarisaError> {
         if trie_id == config.num_tries().get() as usize {
-            self.build_tail(keys, terminals, config);
+            self.build_tail(keys, terminals, config)
         } else {
-            self.next_trie_ = Some(Box::new(LoudsTrie::new()));
+            self.next_trie_ = Some(Box::new(LoudsTrieView::new()));
             let mut next_trie = self.next_trie_.as_mut().unwrap();
-            next_trie.build_trie(keys, terminals, config, trie_id + 1);
+            next_trie.build_trie(keys, terminals, config, trie_id + 1)
         }
     }
 
     fn build_terminals<'a, T>(&mut self, keys: &Vec<T>,
-                              terminals: &mut Vec<u32>)
+                              terminals: &mut Vec<u64>)
       where T: IKey<'a> + Ord + From<&'a[u8]> {
-        let mut temp: Vec<u32> = Vec::new();
+        let mut temp: Vec<u64> = Vec::new();
         temp.resize(keys.len(), 0);
         for key in keys {
-            temp[key.get_id()] = key.get_terminal() as u32;
+            temp[key.get_id()] = key.get_terminal() as u64;
         }
         *terminals = temp;
     }
 
     fn fill_cache(&mut self) {
-        for item in (&mut self.cache_).iter_mut() {
-            let node_id = item.child() as usize;
-            if node_id != 0 {
-                item.set_base(self.bases_[node_id]);
-                let new_extra = if self.link_flags_.at(node_id)
-                    { self.extras_.at(self.link_flags_.rank1(node_id)) }
-                    else { INVALID_EXTRA };
-                item.set_extra(new_extra);
-            } else {
-                item.set_parent(std::u32::MAX);
-                item.set_child(std::u32::MAX);
+        for cache_id in 0..self.cache_.len() {
+            let node_id = self.cache_[cache_id].child() as usize;
+            if node_id == 0 || !self.fill_cache_entry(cache_id) {
+                self.cache_[cache_id].set_parent(std::u32::MAX);
+                self.cache_[cache_id].set_child(std::u32::MAX);
             }
         }
     }
 
-    pub fn id_lookup(&self, id: usize) -> Vec<u8> {
+    /// Replaces the build-time weight of a cache entry with the base and
+    /// extra of its child, which is what queries read. Returns false, and
+    /// leaves the entry as it was, if the child's link is too wide for it.
+    fn fill_cache_entry(&mut self, cache_id: usize) -> bool {
+        let node_id = self.cache_[cache_id].child() as usize;
+        match self.cache_extra(node_id) {
+            Some(extra) => {
+                self.cache_[cache_id].set_base(self.bases_[node_id]);
+                self.cache_[cache_id].set_extra(extra);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The extra a cache entry for `node_id` holds: INVALID_EXTRA for a node
+    /// without a link, or None for a link past the 24 bits an entry has
+    /// room for, that is, into the part of a tail beyond 4 GiB.
+    fn cache_extra(&self, node_id: usize) -> Option<u32> {
+        if !self.link_flags_.at(node_id) {
+            return Some(INVALID_EXTRA);
+        }
+        match self.extras_.at(self.link_flags_.rank1(node_id)) {
+            extra if extra < INVALID_EXTRA => Some(extra),
+            _ => None,
+        }
+    }
+
+    pub fn id_lookup(&self, id: usize) -> Result<Vec<u8>, MarisaError> {
         let mut v: Vec<u8> = Vec::new();
-        self.id_lookup_into_vec(id, &mut v);
-        v
+        self.id_lookup_into_vec(id, &mut v)?;
+        Ok(v)
+    }
+
+    /// Like `id_lookup`, but restores the key into `state` instead of
+    /// allocating.
+    pub fn id_lookup_with<'s>(&self, state: &'s mut SearchState, id: usize)
+      -> Result<&'s [u8], MarisaError> {
+        state.reset();
+        self.id_lookup_into_vec(id, &mut state.key_buf_)?;
+        Ok(&state.key_buf_)
     }
 
-    pub fn id_lookup_into_vec(&self, id: usize, key_out: &mut Vec<u8>) {
-        assert!(id < self.len());
+    /// Restores key `id` into `key_out`. Fails with a StateError if the
+    /// dictionary was built without `Config::reverse_lookup`, and with a
+    /// BoundError if there is no key `id`.
+    pub fn id_lookup_into_vec(&self, id: usize, key_out: &mut Vec<u8>)
+      -> Result<(), MarisaError> {
+        if !self.config_.reverse_lookup() {
+            return Err(MarisaError::StateError {
+                what: "id_lookup was called, but the dictionary was built \
+                       without reverse lookup" });
+        }
+        if id >= self.len() {
+            return Err(MarisaError::BoundError {
+                what: "key id", index: id as u64, len: self.len() as u64 });
+        }
         key_out.clear();
+        key_out.extend_from_slice(&self.common_prefix_);
+        let prefix_len = key_out.len();
 
         let mut node_id = self.terminal_flags_.select1(id);
         if node_id == 0 {
-            return;
+            return Ok(());
         }
         loop {
             if self.link_flags_.at(node_id) {
@@ -543,175 +876,1845 @@ impl LoudsTrie {
                 key_out.push(self.bases_[node_id]);
             }
             if node_id <= self.num_l1_nodes_ {
-                key_out.reverse();
-                return;
+                key_out[prefix_len..].reverse();
+                return Ok(());
             }
             // parent_node_id
             node_id = self.louds_.select1(node_id) - node_id - 1;
         }
     }
 
-    fn restore(&self, link: usize, key_out: &mut Vec<u8>) {
-        match &self.next_trie_ {
-            &Some(ref next) => {
-                next.restore_(link, key_out);
-            },
-            &None => {
-                self.tail_.restore(link, key_out);
+    /// Makes every query cross-check its results against `id_lookup`, and
+    /// panic with the query and the inconsistency if they disagree.
+    ///
+    /// A development aid for debug builds; it does nothing in release
+    /// builds. Without reverse lookup, returned ids are only range checked.
+    pub fn set_paranoia(&mut self, on: bool) {
+        self.paranoia_.set(on);
+    }
+
+    /// Panics unless key `id` is `query[..len]`. Called when paranoid.
+    fn check_key(&self, search: &str, query: &[u8], id: usize, len: usize) {
+        if id >= self.len() {
+            panic!("paranoia: {}({:?}) returned id {}, but there are only {} \
+                    keys", search, String::from_utf8_lossy(query), id,
+                   self.len());
+        }
+        if !self.config_.reverse_lookup() {
+            return;
+        }
+        let key = match self.id_lookup(id) {
+            Ok(key) => key,
+            Err(e) => panic!("paranoia: {}({:?}) returned id {}: {}", search,
+                             String::from_utf8_lossy(query), id, e),
+        };
+        if &key[..] != &query[..len] {
+            panic!("paranoia: {}({:?}) returned id {} for {:?}, but that id \
+                    restores to {:?}", search, String::from_utf8_lossy(query),
+                   id, String::from_utf8_lossy(&query[..len]),
+                   String::from_utf8_lossy(&key));
+        }
+    }
+
+    /// Panics unless `key` starts with `query` and looks up to `id`. Called
+    /// when paranoid.
+    fn check_lookup(&self, search: &str, query: &[u8], id: usize,
+                    key: &[u8]) {
+        if !key.starts_with(query) {
+            panic!("paranoia: {}({:?}) returned {:?}, which does not start \
+                    with the query", search, String::from_utf8_lossy(query),
+                   String::from_utf8_lossy(key));
+        }
+        let found = self.lookup_(key);
+        if found != Some(id) {
+            panic!("paranoia: {}({:?}) returned id {} for {:?}, but lookup \
+                    gives {:?}", search, String::from_utf8_lossy(query), id,
+                   String::from_utf8_lossy(key), found);
+        }
+    }
+
+    /// Returns the id of `query` if it is a key in the dictionary.
+    pub fn lookup(&self, query: &[u8]) -> Option<usize> {
+        let found = self.lookup_(query);
+        if self.paranoia_.is_on() {
+            if let Some(id) = found {
+                self.check_key("lookup", query, id, query.len());
             }
         }
+        found
     }
 
-    fn restore_(&self, node_id: usize, key_out: &mut Vec<u8>) {
-        assert!(node_id != 0, "MARISA_RANGE_ERROR");
+    /// Looks up each of `queries`, as `lookup` does, and puts the results in
+    /// `out` in the same order, after clearing it.
+    ///
+    /// The queries go down the trie a level at a time, a group at a time:
+    /// the memory each one's next step reads is prefetched for the whole
+    /// group before any of them takes it, so that on a dictionary bigger
+    /// than the cache the misses overlap instead of coming one after
+    /// another. Nothing is allocated but room in `out`.
+    pub fn lookup_batch(&self, queries: &[&[u8]],
+                        out: &mut Vec<Option<usize>>) {
+        out.clear();
+        out.reserve(queries.len());
+        for group in queries.chunks(BATCH_WIDTH) {
+            self.lookup_group(group, out);
+        }
+        if self.paranoia_.is_on() {
+            for (query, found) in queries.iter().zip(out.iter()) {
+                if let &Some(id) = found {
+                    self.check_key("lookup_batch", query, id, query.len());
+                }
+            }
+        }
+    }
 
-        let mut node_id = node_id;
+    fn lookup_group(&self, group: &[&[u8]], out: &mut Vec<Option<usize>>) {
+        let mut node_ids = [0usize; BATCH_WIDTH];
+        let mut query_pos = [self.common_prefix_.len(); BATCH_WIDTH];
+        // Whether each query can still be a key.
+        let mut live = [false; BATCH_WIDTH];
+        for (i, query) in group.iter().enumerate() {
+            live[i] = query.starts_with(&self.common_prefix_);
+        }
         loop {
-            let cache_id = self.get_cache_id(node_id);
-            if node_id == self.cache_[cache_id].child() as usize {
-                if self.cache_[cache_id].extra() != INVALID_EXTRA {
-                    self.restore(self.cache_[cache_id].link() as usize,
-                                 key_out);
-                } else {
-                    key_out.push(self.cache_[cache_id].label());
-                }
-                node_id = self.cache_[cache_id].parent() as usize;
-                if node_id == 0 {
-                    return;
-                }
-            } else {
-                if self.link_flags_.at(node_id) {
-                    self.restore(self.get_linked_node_id(node_id), key_out);
-                } else {
-                    key_out.push(self.bases_[node_id]);
+            let mut moving = false;
+            for (i, query) in group.iter().enumerate() {
+                if live[i] && query_pos[i] < query.len() {
+                    let cache_id = self.get_cache_id_with_label(
+                        node_ids[i], query[query_pos[i]]);
+                    prefetch(&self.cache_[cache_id]);
+                    self.louds_.prefetch_select0(node_ids[i]);
+                    moving = true;
                 }
-                if node_id <= self.num_l1_nodes_ {
-                    return;
+            }
+            if !moving {
+                break;
+            }
+            for (i, query) in group.iter().enumerate() {
+                if live[i] && query_pos[i] < query.len() {
+                    match self.find_child(node_ids[i], query,
+                                          &mut query_pos[i]) {
+                        Some(child_id) => node_ids[i] = child_id,
+                        None => live[i] = false,
+                    }
                 }
-                node_id = self.louds_.select1(node_id) - node_id - 1;
             }
         }
+        for i in 0..group.len() {
+            if live[i] {
+                self.terminal_flags_.prefetch_rank(node_ids[i]);
+            }
+        }
+        for i in 0..group.len() {
+            out.push(if live[i] && self.terminal_flags_.at(node_ids[i]) {
+                Some(self.terminal_flags_.rank1(node_ids[i]))
+            } else {
+                None
+            });
+        }
     }
 
-    fn num_tries(&self) -> usize {
-        self.config_.num_tries().get() as usize
-    }
-    fn num_keys(&self) -> usize {
-        self.len()
-    }
-    fn num_nodes(&self) -> usize {
-        (self.louds_.len() / 2) - 1
-    }
-    fn cache_level(&self) -> CacheLevel {
-        self.config_.cache_level()
+    fn lookup_(&self, query: &[u8]) -> Option<usize> {
+        if !query.starts_with(&self.common_prefix_) {
+            return None;
+        }
+        let mut node_id: usize = 0;
+        let mut query_pos: usize = self.common_prefix_.len();
+        while query_pos < query.len() {
+            match self.find_child(node_id, query, &mut query_pos) {
+                Some(child_id) => node_id = child_id,
+                None => return None,
+            }
+        }
+        if self.terminal_flags_.at(node_id) {
+            Some(self.terminal_flags_.rank1(node_id))
+        } else {
+            None
+        }
     }
-    fn tail_mode(&self) -> TailMode {
-        self.config_.tail_mode()
+
+    /// Like `lookup`, but also returns the weight of the key if weights were
+    /// kept.
+    pub fn lookup_with_weight(&self, query: &[u8])
+      -> Option<(usize, Option<f32>)> {
+        self.lookup(query).map(|id| (id, self.weight(id)))
     }
-    fn node_order(&self) -> NodeOrder {
-        self.config_.node_order()
+
+    /// The weight of key `id`, summed over every time it was pushed, or None
+    /// if the dictionary was not built with `Config::with_weights(true)`.
+    pub fn weight(&self, id: usize) -> Option<f32> {
+        assert!(id < self.len(), "MARISA_BOUND_ERROR");
+        self.weights_.get(id).cloned()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    /// Returns an iterator over the keys that are prefixes of `query`, from
+    /// shortest to longest. Each item is `(key_id, key_len)`.
+    pub fn common_prefix_search<'a, 'b>(&'a self, query: &'b [u8])
+      -> CommonPrefixIter<'a, 'b> {
+        CommonPrefixIter::new(self, query)
     }
-    pub fn len(&self) -> usize {
-        self.terminal_flags_.num_1s()
+
+    /// Returns an iterator over the keys that start with `query`. Each item
+    /// is `(key_id, key)`.
+    ///
+    /// If the dictionary was built with `NodeOrder::Label`, keys come in
+    /// ascending byte order. With `NodeOrder::Weight`, siblings are visited
+    /// heaviest first, so the order is unspecified. `PredictiveIter::order`
+    /// asks for one, and `max_results` and `max_extra_len` prune the search.
+    pub fn predictive_search<'a, 'b>(&'a self, query: &'b [u8])
+      -> PredictiveIter<'a, 'b> {
+        PredictiveIter::new(self, query)
     }
 
-    fn get_cache_id_with_label(&self, node_id: usize, label: u8) -> usize {
-        (node_id ^ (node_id << 5) ^ (label as usize)) & self.cache_mask_
+    /// Like `predictive_search`, but restores keys into `state` instead of
+    /// allocating, so a loop of searches through one state allocates only
+    /// while its buffers grow. Keys come in the same order.
+    pub fn predictive_search_with<'a, 'b, 's>(&'a self,
+                                              state: &'s mut SearchState,
+                                              query: &'b [u8])
+      -> PredictiveCursor<'a, 'b, 's> {
+        PredictiveCursor { walk_: PredictiveWalk::new(self, query),
+                           state_: state }
     }
 
-    fn get_cache_id(&self, node_id: usize) -> usize {
-        node_id & self.cache_mask_
+    /// Returns an iterator over all keys, as `predictive_search(b"")` does.
+    pub fn iter<'a>(&'a self) -> PredictiveIter<'a, 'static> {
+        self.predictive_search(b"")
     }
 
-    // FIXME: is this correct terminology for link_id?
-    fn get_link_id(&self, node_id: usize) -> usize {
-        self.link_flags_.rank1(node_id)
+    /// Calls `f(id, key, truncated)` for each key starting with `prefix`,
+    /// in id order. Keys are restored into `buf`, so nothing is allocated,
+    /// and a key longer than `buf` is cut short or left out as `long_keys`
+    /// says. Works without reverse lookup.
+    pub fn for_each_key_into<F>(&self, prefix: &[u8], buf: &mut [u8],
+                                long_keys: LongKeys, mut f: F)
+      where F: FnMut(usize, &[u8], bool) {
+        let node_id = match self.prefix_node(prefix) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        // The subtree's nodes at each depth have consecutive ids, and
+        // deeper ones have larger ids, so a level at a time is id order.
+        let (mut begin, mut end) = (node_id, node_id + 1);
+        while begin < end {
+            for node_id in begin..end {
+                if !self.terminal_flags_.at(node_id) {
+                    continue;
+                }
+                let len = self.key_len(node_id);
+                let truncated = len > buf.len();
+                if truncated && long_keys == LongKeys::Skip {
+                    continue;
+                }
+                self.key_into(node_id, len, buf);
+                f(self.terminal_flags_.rank1(node_id),
+                  &buf[..std::cmp::min(len, buf.len())], truncated);
+            }
+            begin = self.louds_.select0(begin) - begin;
+            end = self.louds_.select0(end) - end;
+        }
     }
 
-    fn get_linked_node_id(&self, node_id: usize) -> usize {
-        self.get_linked_node_id_2(node_id, self.get_link_id(node_id))
+    /// The number of keys that start with `prefix`, as
+    /// `predictive_search(prefix).count()`, without restoring any of them.
+    /// The subtree's nodes at each depth have consecutive ids, so its
+    /// terminals are counted with two ranks per level: the cost grows with
+    /// the length of `prefix` and the depth below it, not with the count.
+    pub fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let node_id = match self.prefix_node(prefix) {
+            Some(0) => return self.len(),
+            Some(node_id) => node_id,
+            None => return 0,
+        };
+        let mut count = 0;
+        let (mut begin, mut end) = (node_id, node_id + 1);
+        while begin < end {
+            count += self.terminal_flags_.rank1(end)
+                     - self.terminal_flags_.rank1(begin);
+            begin = self.louds_.select0(begin) - begin;
+            end = self.louds_.select0(end) - end;
+        }
+        count
     }
 
-    fn get_linked_node_id_2(&self, node_id: usize, link_id: usize) -> usize {
-        ((self.bases_[node_id] as u32) | (self.extras_.at(link_id) * 256))
-            as usize
+    /// The node a predictive search for `prefix` starts from, found without
+    /// keeping the labels descended through.
+    fn prefix_node(&self, prefix: &[u8]) -> Option<usize> {
+        let common = &self.common_prefix_;
+        let mut query_pos = if prefix.len() <= common.len() {
+            if !common.starts_with(prefix) {
+                return None;
+            }
+            prefix.len()
+        } else {
+            if !prefix.starts_with(common) {
+                return None;
+            }
+            common.len()
+        };
+        let mut node_id = 0;
+        while query_pos < prefix.len() {
+            node_id = self.predictive_find_child(node_id, prefix,
+                                                 &mut query_pos,
+                                                 &mut KeyLen(0))?;
+        }
+        Some(node_id)
     }
 
-    fn get_linked_ids(&self, node_id: usize) -> (NodeID, LinkID) {
-        let link_id = self.get_link_id(node_id);
-        let node_id = self.get_linked_node_id_2(node_id, link_id);
-        assert!(link_id <= std::u32::MAX as usize);
-        assert!(node_id <= std::u32::MAX as usize);
-        (NodeID(node_id as u32), LinkID(link_id as u32))
+    /// Length of the key that ends at `node_id`.
+    fn key_len(&self, node_id: usize) -> usize {
+        let mut len = KeyLen(self.common_prefix_.len());
+        let mut node_id = node_id;
+        while node_id != 0 {
+            if self.link_flags_.at(node_id) {
+                self.restore(self.get_linked_node_id(node_id), &mut len);
+            } else {
+                len.0 += 1;
+            }
+            if node_id <= self.num_l1_nodes_ {
+                break;
+            }
+            node_id = self.louds_.select1(node_id) - node_id - 1;
+        }
+        len.0
     }
 
-    fn update_link_id(&self, link_id: usize, node_id: usize) -> usize {
-        if link_id == INVALID_LINK_ID.0 as usize {
-            self.get_link_id(node_id)
-        } else {
-            link_id + 1
+    /// Writes as much of the key that ends at `node_id`, `len` bytes long,
+    /// as fits into `buf`. The labels come up from the last one, and each
+    /// is restored straight into its place.
+    fn key_into(&self, node_id: usize, len: usize, buf: &mut [u8]) {
+        let n = std::cmp::min(self.common_prefix_.len(), buf.len());
+        buf[..n].copy_from_slice(&self.common_prefix_[..n]);
+        let mut end = len;
+        let mut node_id = node_id;
+        while node_id != 0 {
+            if self.link_flags_.at(node_id) {
+                let link = self.get_linked_node_id(node_id);
+                let mut label_len = KeyLen(0);
+                self.restore(link, &mut label_len);
+                end -= label_len.0;
+                if end < buf.len() {
+                    self.restore(link, &mut SliceSink { buf: &mut *buf,
+                                                        pos: end });
+                }
+            } else {
+                end -= 1;
+                if end < buf.len() {
+                    buf[end] = self.bases_[node_id];
+                }
+            }
+            if node_id <= self.num_l1_nodes_ {
+                break;
+            }
+            node_id = self.louds_.select1(node_id) - node_id - 1;
         }
     }
-}
 
-/*
-    fn total_size() usize {
-        louds_.total_size()
-        + terminal_flags_.total_size()
-        + link_flags_.total_size()
-        + bases_.total_size()
-        + extras_.total_size()
-        + tail_.total_size()
-        + if (next_trie_.get() != NULL { next_trie_->total_size() } else { 0 }
-        + cache_.total_size()
-    }
-
-    fn io_size() -> usize {
-        Header().io_size()
-        + louds_.io_size()
-        + terminal_flags_.io_size()
-        + link_flags_.io_size()
-        + bases_.io_size()
-        + extras_.io_size()
-        + tail_.io_size()
-//match
-        + if next_trie_.get() != NULL { 
-             next_trie_->io_size() - Header().io_size() } else { 0 }
-        + cache_.io_size()
-        + (sizeof(u32) * 2)
+    /// Returns the key id and length of the longest key that is a prefix of
+    /// `query`, or None if no key is.
+    ///
+    /// Equivalent to `common_prefix_search(query).last()`, but only keeps
+    /// track of the most recent terminal while descending.
+    pub fn longest_prefix_match(&self, query: &[u8]) -> Option<(usize, usize)> {
+        if !query.starts_with(&self.common_prefix_) {
+            return None;
+        }
+        let mut node_id: usize = 0;
+        let mut query_pos: usize = self.common_prefix_.len();
+        let mut last = None;
+        if self.terminal_flags_.at(node_id) {
+            last = Some((self.terminal_flags_.rank1(node_id), query_pos));
+        }
+        while query_pos < query.len() {
+            // A failed descent may have consumed part of a link's label, so
+            // query_pos is not meaningful after this loop exits.
+            match self.find_child(node_id, query, &mut query_pos) {
+                Some(child_id) => node_id = child_id,
+                None => break,
+            }
+            if self.terminal_flags_.at(node_id) {
+                last = Some((self.terminal_flags_.rank1(node_id), query_pos));
+            }
+        }
+        if self.paranoia_.is_on() {
+            if let Some((id, len)) = last {
+                self.check_key("longest_prefix_match", query, id, len);
+            }
+        }
+        last
     }
 
-    fn map(mapper: &mut Mapper) -> LoudsTrie {
-        Header().map(mapper);
-    
-        let temp: LoudsTrie;
-        temp.map_(mapper);
-        temp.mapper_.swap(mapper);
-        temp
+    /// Descends from `node_id` to the child whose label matches `query` at
+    /// `query_pos`, and advances `query_pos` past that label.
+    ///
+    /// If None is returned, `query_pos` may have been advanced part way into
+    /// the label of a link.
+    fn find_child(&self, node_id: usize, query: &[u8], query_pos: &mut usize)
+      -> Option<usize> {
+        assert!(*query_pos < query.len(), "MARISA_BOUND_ERROR");
+
+        let cache_id = self.get_cache_id_with_label(node_id, query[*query_pos]);
+        if node_id == self.cache_[cache_id].parent() as usize {
+            // Siblings differ in their first byte, so a cached link that
+            // fails to match means no child matches.
+            if self.cache_[cache_id].extra() != INVALID_EXTRA {
+                let link = self.cache_[cache_id].link() as usize;
+                if !self.match_link(link, query, query_pos) {
+                    return None;
+                }
+            } else {
+                *query_pos += 1;
+            }
+            return Some(self.cache_[cache_id].child() as usize);
+        }
+
+        let mut louds_pos = self.louds_.select0(node_id) + 1;
+        if !self.louds_.at(louds_pos) {
+            return None;
+        }
+        let mut node_id = louds_pos - node_id - 1;
+        let mut link_id = INVALID_LINK_ID.0 as usize;
+        loop {
+            if self.link_flags_.at(node_id) {
+                link_id = self.update_link_id(link_id, node_id);
+                let prev_query_pos = *query_pos;
+                let link = self.get_linked_node_id_2(node_id, link_id);
+                if self.match_link(link, query, query_pos) {
+                    return Some(node_id);
+                } else if *query_pos != prev_query_pos {
+                    // The first byte matched, so no sibling can.
+                    return None;
+                }
+            } else if self.bases_[node_id] == query[*query_pos] {
+                *query_pos += 1;
+                return Some(node_id);
+            }
+            node_id += 1;
+            louds_pos += 1;
+            if !self.louds_.at(louds_pos) {
+                return None;
+            }
+        }
     }
 
-    fn read(Reader &reader) -> LoudsTrie {
-        Header().read(reader);
-        let temp: LoudsTrie;
-        temp.read_(reader);
-        temp
+    /// Like `find_child`, but the last label may extend past the end of
+    /// `query`. The labels descended through are appended to `key_out`.
+    fn predictive_find_child<S: KeySink>(&self, node_id: usize, query: &[u8],
+                                         query_pos: &mut usize, key_out: &mut S)
+      -> Option<usize> {
+        assert!(*query_pos < query.len(), "MARISA_BOUND_ERROR");
+
+        let cache_id = self.get_cache_id_with_label(node_id, query[*query_pos]);
+        if node_id == self.cache_[cache_id].parent() as usize {
+            if self.cache_[cache_id].extra() != INVALID_EXTRA {
+                let link = self.cache_[cache_id].link() as usize;
+                if !self.prefix_match(link, query, query_pos, key_out) {
+                    return None;
+                }
+            } else {
+                key_out.push(self.cache_[cache_id].label());
+                *query_pos += 1;
+            }
+            return Some(self.cache_[cache_id].child() as usize);
+        }
+
+        let mut louds_pos = self.louds_.select0(node_id) + 1;
+        if !self.louds_.at(louds_pos) {
+            return None;
+        }
+        let mut node_id = louds_pos - node_id - 1;
+        let mut link_id = INVALID_LINK_ID.0 as usize;
+        loop {
+            if self.link_flags_.at(node_id) {
+                link_id = self.update_link_id(link_id, node_id);
+                let prev_query_pos = *query_pos;
+                let link = self.get_linked_node_id_2(node_id, link_id);
+                if self.prefix_match(link, query, query_pos, key_out) {
+                    return Some(node_id);
+                } else if *query_pos != prev_query_pos {
+                    return None;
+                }
+            } else if self.bases_[node_id] == query[*query_pos] {
+                key_out.push(self.bases_[node_id]);
+                *query_pos += 1;
+                return Some(node_id);
+            }
+            node_id += 1;
+            louds_pos += 1;
+            if !self.louds_.at(louds_pos) {
+                return None;
+            }
+        }
     }
 
-    fn write(&self, writer: &mut Writer) {
-        Header().write(writer);
-        write_(writer);
+    fn prefix_match<S: KeySink>(&self, link: usize, query: &[u8],
+                                query_pos: &mut usize, key_out: &mut S)
+      -> bool {
+        match &self.next_trie_ {
+            &Some(ref next) => next.prefix_match_(link, query, query_pos,
+                                                  key_out),
+            &None => self.tail_.prefix_match(link, query, query_pos, key_out),
+        }
     }
 
-    void map_(Mapper &mapper);
-    void read_(Reader &reader);
-    void write_(Writer &writer) const;
- 
-void LoudsTrie::map_(Mapper &mapper) {
-  louds_.map(mapper);
+    /// Like `match_`, but succeeds if `query` runs out part way through the
+    /// label, in which case the rest of the label is restored into
+    /// `key_out`.
+    fn prefix_match_<S: KeySink>(&self, node_id: usize, query: &[u8],
+                                 query_pos: &mut usize, key_out: &mut S)
+      -> bool {
+        let mut node_id = node_id;
+        loop {
+            let cache_id = self.get_cache_id(node_id);
+            if node_id == self.cache_[cache_id].child() as usize {
+                if self.cache_[cache_id].extra() != INVALID_EXTRA {
+                    let link = self.cache_[cache_id].link() as usize;
+                    if !self.prefix_match(link, query, query_pos, key_out) {
+                        return false;
+                    }
+                } else if self.cache_[cache_id].label() == query[*query_pos] {
+                    key_out.push(self.cache_[cache_id].label());
+                    *query_pos += 1;
+                } else {
+                    return false;
+                }
+                node_id = self.cache_[cache_id].parent() as usize;
+                if node_id == 0 {
+                    return true;
+                }
+            } else {
+                if self.link_flags_.at(node_id) {
+                    let link = self.get_linked_node_id(node_id);
+                    if !self.prefix_match(link, query, query_pos, key_out) {
+                        return false;
+                    }
+                } else if self.bases_[node_id] == query[*query_pos] {
+                    key_out.push(self.bases_[node_id]);
+                    *query_pos += 1;
+                } else {
+                    return false;
+                }
+                if node_id <= self.num_l1_nodes_ {
+                    return true;
+                }
+                node_id = self.louds_.select1(node_id) - node_id - 1;
+            }
+            if *query_pos >= query.len() {
+                self.restore_(node_id, key_out);
+                return true;
+            }
+        }
+    }
+
+    fn match_link(&self, link: usize, query: &[u8], query_pos: &mut usize)
+      -> bool {
+        match &self.next_trie_ {
+            &Some(ref next) => next.match_(link, query, query_pos),
+            &None => self.tail_.match_(link, query, query_pos),
+        }
+    }
+
+    /// Matches the label stored from `node_id` up to the root of this
+    /// (non-root) trie against `query`.
+    fn match_(&self, node_id: usize, query: &[u8], query_pos: &mut usize)
+      -> bool {
+        let mut node_id = node_id;
+        loop {
+            let cache_id = self.get_cache_id(node_id);
+            if node_id == self.cache_[cache_id].child() as usize {
+                if self.cache_[cache_id].extra() != INVALID_EXTRA {
+                    let link = self.cache_[cache_id].link() as usize;
+                    if !self.match_link(link, query, query_pos) {
+                        return false;
+                    }
+                } else if self.cache_[cache_id].label() == query[*query_pos] {
+                    *query_pos += 1;
+                } else {
+                    return false;
+                }
+                node_id = self.cache_[cache_id].parent() as usize;
+                if node_id == 0 {
+                    return true;
+                }
+            } else {
+                if self.link_flags_.at(node_id) {
+                    let link = self.get_linked_node_id(node_id);
+                    if !self.match_link(link, query, query_pos) {
+                        return false;
+                    }
+                } else if self.bases_[node_id] == query[*query_pos] {
+                    *query_pos += 1;
+                } else {
+                    return false;
+                }
+                if node_id <= self.num_l1_nodes_ {
+                    return true;
+                }
+                node_id = self.louds_.select1(node_id) - node_id - 1;
+            }
+            if *query_pos >= query.len() {
+                return false;
+            }
+        }
+    }
+
+    fn restore<S: KeySink>(&self, link: usize, key_out: &mut S) {
+        match &self.next_trie_ {
+            &Some(ref next) => {
+                next.restore_(link, key_out);
+            },
+            &None => {
+                self.tail_.restore(link, key_out);
+            }
+        }
+    }
+
+    fn restore_<S: KeySink>(&self, node_id: usize, key_out: &mut S) {
+        assert!(node_id != 0, "MARISA_RANGE_ERROR");
+
+        let mut node_id = node_id;
+        loop {
+            let cache_id = self.get_cache_id(node_id);
+            if node_id == self.cache_[cache_id].child() as usize {
+                if self.cache_[cache_id].extra() != INVALID_EXTRA {
+                    self.restore(self.cache_[cache_id].link() as usize,
+                                 key_out);
+                } else {
+                    key_out.push(self.cache_[cache_id].label());
+                }
+                node_id = self.cache_[cache_id].parent() as usize;
+                if node_id == 0 {
+                    return;
+                }
+            } else {
+                if self.link_flags_.at(node_id) {
+                    self.restore(self.get_linked_node_id(node_id), key_out);
+                } else {
+                    key_out.push(self.bases_[node_id]);
+                }
+                if node_id <= self.num_l1_nodes_ {
+                    return;
+                }
+                node_id = self.louds_.select1(node_id) - node_id - 1;
+            }
+        }
+    }
+
+    /// The configuration the dictionary was actually built with. This can
+    /// differ from the one passed to `build`, e.g. a text tail is switched
+    /// to binary if a label contains a NUL byte.
+    pub fn config(&self) -> Config {
+        self.config_
+    }
+
+    /// The `ID_ALGORITHM_VERSION` the dictionary's ids were assigned under,
+    /// or 0 if libmarisa assigned them.
+    pub fn id_algorithm_version(&self) -> u32 {
+        self.id_algorithm_version_
+    }
+
+    fn num_tries(&self) -> usize {
+        self.config_.num_tries().get() as usize
+    }
+    fn num_keys(&self) -> usize {
+        self.len()
+    }
+    fn num_nodes(&self) -> usize {
+        (self.louds_.len() / 2) - 1
+    }
+    fn cache_level(&self) -> CacheLevel {
+        self.config_.cache_level()
+    }
+    fn tail_mode(&self) -> TailMode {
+        self.config_.tail_mode()
+    }
+    fn node_order(&self) -> NodeOrder {
+        self.config_.node_order()
+    }
+
+    /// Size in bytes of the dictionary's in-memory structures.
+    pub fn total_size(&self) -> usize {
+        self.louds_.total_size()
+        + self.terminal_flags_.total_size()
+        + self.link_flags_.total_size()
+        + self.bases_.len()
+        + self.extras_.total_size()
+        + self.tail_.total_size()
+        + self.next_trie_.as_ref().map_or(0, |next| next.total_size())
+        + slice_total_size(&self.cache_)
+        + self.common_prefix_.len()
+        + slice_total_size(&self.weights_)
+    }
+
+    /// Whether every part is borrowed from the buffer the dictionary was
+    /// mapped from, as it is on a little-endian platform unless the
+    /// dictionary is of format version 2, rather than copied out of it.
+    pub fn is_mapped(&self) -> bool {
+        self.trie_is_mapped()
+        && self.common_prefix_.is_mapped()
+        && self.weights_.is_mapped()
+    }
+
+    /// The parts each trie has, as opposed to those only the first has.
+    fn trie_is_mapped(&self) -> bool {
+        self.louds_.is_mapped()
+        && self.terminal_flags_.is_mapped()
+        && self.link_flags_.is_mapped()
+        && self.bases_.is_mapped()
+        && self.extras_.is_mapped()
+        && self.tail_.is_mapped()
+        && self.cache_.is_mapped()
+        && self.next_trie_.as_ref().map_or(true, |next| {
+               next.trie_is_mapped()
+           })
+    }
+
+    /// Checks that every cache entry is an edge of the trie, filed under
+    /// the slot the queries look it up by, so that a cache hit agrees with
+    /// the slow path. Takes time linear in the cache size.
+    pub fn validate(&self) -> Result<(), MarisaError> {
+        self.validate_cache(true)
+    }
+
+    /// The root trie's cache is keyed by parent and first label, and the
+    /// next tries' caches by child. Next tries go first, since restoring a
+    /// link's first label goes through their caches.
+    fn validate_cache(&self, is_root: bool) -> Result<(), MarisaError> {
+        if let &Some(ref next) = &self.next_trie_ {
+            next.validate_cache(false)?;
+        }
+        let bad = Err(MarisaError::FormatError { what: "cache" });
+        if !self.cache_.len().is_power_of_two()
+           || self.cache_mask_ != self.cache_.len() - 1 {
+            return bad;
+        }
+        let num_nodes = self.num_nodes();
+        let mut label = Vec::new();
+        for (cache_id, item) in self.cache_.iter().enumerate() {
+            if item.parent() == std::u32::MAX
+               && item.child() == std::u32::MAX {
+                continue;
+            }
+            let parent = item.parent() as usize;
+            let child = item.child() as usize;
+            if child == 0 || child >= num_nodes
+               || self.louds_.select1(child) - child - 1 != parent {
+                return bad;
+            }
+            let first_label = if self.link_flags_.at(child) {
+                if item.extra() == INVALID_EXTRA
+                   || item.link() as usize != self.get_linked_node_id(child) {
+                    return bad;
+                }
+                label.clear();
+                self.restore(item.link() as usize, &mut label);
+                label[0]
+            } else {
+                if item.extra() != INVALID_EXTRA
+                   || item.base() != self.bases_[child] {
+                    return bad;
+                }
+                item.base()
+            };
+            let expected_id = if is_root {
+                self.get_cache_id_with_label(parent, first_label)
+            } else {
+                self.get_cache_id(child)
+            };
+            if cache_id != expected_id {
+                return bad;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the dictionary in a format `read_from` understands: a header,
+    /// then the payload: a table of where each section starts, each trie
+    /// from the root down, then the common prefix and the weights. The
+    /// header holds the payload's length and a CRC-32, so the payload is
+    /// serialized twice, once to hash it. Lengths and words are
+    /// little-endian whatever the platform.
+    #[cfg(feature = "std")]
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_to_(w)
+    }
+
+    fn write_to_<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut hashed = Hashed::new(io::sink());
+        self.write_payload_(&mut hashed)?;
+        let flags = self.config_.flags();
+        Header::new(self.id_algorithm_version_, flags, hashed.num_bytes(),
+                    hashed.crc()).write(w)?;
+        self.write_payload_(w)
+    }
+
+    /// An identity of the dictionary's contents: the length and CRC-32 of
+    /// the payload `write_to` writes. A saved and reloaded copy keeps it;
+    /// a dictionary built from other keys, or with another config, or with
+    /// a retuned cache, gets another. Takes a pass over the dictionary.
+    pub fn build_id(&self) -> u64 {
+        let mut hashed = Hashed::new(io::sink());
+        self.write_payload_(&mut hashed).expect("io::sink failed");
+        (hashed.num_bytes() << 32) ^ hashed.crc().value() as u64
+    }
+
+    fn write_payload_<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.section_table().write(w)?;
+        self.write_(w, Layout::Native)?;
+        self.common_prefix_.write_to(w)?;
+        self.weights_.write_to(w)
+    }
+
+    /// Reads a dictionary written by `write_to`, by this version of the
+    /// crate or one that wrote format version 2. The magic string and the
+    /// format version are checked first; a newer version is an
+    /// `UnsupportedVersion` error. Truncated, malformed or damaged input,
+    /// caught by the checksum, is a `FormatError`, not a panic, and a
+    /// failure of `r` itself is an `Io` error.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: io::Read>(r: &mut R)
+      -> Result<LoudsTrie, MarisaError> {
+        LoudsTrie::read_from_(r, true)
+    }
+
+    /// Like `read_from`, without checking the payload against its
+    /// checksum. Malformed input is still an error rather than a panic,
+    /// but damage that leaves the format intact gives wrong answers.
+    #[cfg(feature = "std")]
+    pub fn read_from_unchecked<R: io::Read>(r: &mut R)
+      -> Result<LoudsTrie, MarisaError> {
+        LoudsTrie::read_from_(r, false)
+    }
+
+    #[cfg(feature = "std")]
+    fn read_from_<R: io::Read>(r: &mut R, verify: bool)
+      -> Result<LoudsTrie, MarisaError> {
+        let header = Header::read(r)?;
+        let mut payload = Hashed::new(io::Read::take(r, header.payload_len()));
+        let out = LoudsTrie::read_payload_(&mut Reader::new(&mut payload),
+                                           &header, Checks::All)?;
+        if payload.num_bytes() != header.payload_len() {
+            return Err(MarisaError::FormatError { what: "payload length" });
+        }
+        if verify && !header.checks(payload.crc()) {
+            return Err(MarisaError::FormatError { what: "checksum" });
+        }
+        Ok(out)
+    }
+
+    /// Maps a dictionary written by `write_to` from `buf`, borrowing its
+    /// parts, the bit vectors' indexes included, rather than copying them.
+    /// `buf` must start 8-byte aligned, as a memory map does, so callers
+    /// that read a dictionary into memory allocate through a `Vec<u64>`
+    /// or an `AlignedBytes`; any other buffer is a `FormatError`. Only a
+    /// dictionary of format version 2, which is copied, may lie anywhere,
+    /// and on a big-endian platform every part is copied. The header and
+    /// checksum are checked as by `read_from`, and then the parts against
+    /// each other, trusting the bit vectors' indexes once the checksum
+    /// holds; `verify` checks those as well. Without the `std` feature,
+    /// this is how dictionaries are loaded.
+    pub fn map_from_slice<'b>(buf: &'b [u8])
+      -> Result<LoudsTrieView<'b>, MarisaError> {
+        LoudsTrieView::map_from_slice_(buf, true)
+    }
+
+    /// Like `map_from_slice`, but checks only the header and the length of
+    /// each part, so that mapping takes the same time however large the
+    /// dictionary is. For dictionaries whose integrity is known: damage to
+    /// one can make queries panic, loop or give wrong answers, though never
+    /// read outside `buf`.
+    pub fn map_from_slice_unchecked<'b>(buf: &'b [u8])
+      -> Result<LoudsTrieView<'b>, MarisaError> {
+        LoudsTrieView::map_from_slice_(buf, false)
+    }
+
+    fn map_from_slice_(buf: &'m [u8], verify: bool)
+      -> Result<LoudsTrieView<'m>, MarisaError> {
+        let (header, mut pos) = Header::map(buf)?;
+        if Layout::native(header.version()) == Layout::Native
+           && buf.as_ptr() as usize % 8 != 0 {
+            return Err(MarisaError::FormatError { what: "alignment" });
+        }
+        let payload_len = checked_usize(header.payload_len())?;
+        let payload = map_bytes(buf, &mut pos, payload_len, "payload")?;
+        if verify {
+            let mut crc = Crc32::new();
+            crc.update(payload);
+            if !header.checks(&crc) {
+                return Err(MarisaError::FormatError { what: "checksum" });
+            }
+        }
+        let checks = if verify { Checks::All } else { Checks::Lengths };
+        let mut mapper = Mapper::new(payload);
+        let out = LoudsTrieView::read_payload_(&mut mapper, &header, checks)?;
+        if !mapper.is_empty() {
+            return Err(MarisaError::FormatError { what: "payload length" });
+        }
+        Ok(out)
+    }
+
+    fn read_payload_<S: Source<'m>>(r: &mut S, header: &Header,
+                                    checks: Checks)
+      -> Result<LoudsTrieView<'m>, MarisaError> {
+        let layout = Layout::native(header.version());
+        if layout == Layout::Native {
+            r.read_section_table(header.payload_len())?;
+        }
+        let mut out = LoudsTrieView::read_(r, 1, layout, checks)?;
+        if header.flags() != out.config_.flags() {
+            return Err(MarisaError::FormatError { what: "config flags" });
+        }
+        out.common_prefix_ = layout.read_bytes(r)?;
+        out.weights_ = layout.read_values(r, "weights")?;
+        if layout == Layout::Native && !r.sections_done() {
+            return Err(MarisaError::FormatError { what: "section table" });
+        }
+        let num_weights = if out.config_.weights() { out.len() } else { 0 };
+        if out.weights_.len() != num_weights {
+            return Err(MarisaError::FormatError {
+                what: "number of weights" });
+        }
+        out.id_algorithm_version_ = header.id_algorithm_version();
+        if checks == Checks::All {
+            out.validate()?;
+        }
+        Ok(out)
+    }
+
+    /// Writes the dictionary in the layout of libmarisa 0.2, which its
+    /// tools and bindings can load. Fails with `InvalidInput` for a
+    /// dictionary that uses what libmarisa lacks: key weights, a common
+    /// prefix stripped from long keys, or a tail over 4 GiB.
+    ///
+    /// Experimental: the layout follows libmarisa's writers, but has not
+    /// yet been checked against a dictionary built by its tools, and may
+    /// change if it turns out not to match.
+    #[cfg(feature = "std")]
+    pub fn write_libmarisa<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        if !self.weights_.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "libmarisa dictionaries cannot hold key weights"));
+        }
+        if !self.common_prefix_.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "libmarisa dictionaries cannot hold a stripped common \
+                 prefix"));
+        }
+        if self.has_wide_links() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "libmarisa dictionaries cannot hold links past 4 GiB"));
+        }
+        Header::write_libmarisa(w)?;
+        self.write_(w, Layout::Libmarisa)
+    }
+
+    /// Reads a dictionary written by libmarisa 0.2, or by
+    /// `write_libmarisa`. Its ids are the ones libmarisa assigned, so
+    /// `id_algorithm_version` is 0.
+    ///
+    /// Experimental, as `write_libmarisa` is.
+    #[cfg(feature = "std")]
+    pub fn read_libmarisa<R: io::Read>(r: &mut R)
+      -> Result<LoudsTrie, MarisaError> {
+        Header::read_libmarisa(r)?;
+        let mut out = LoudsTrie::read_(&mut Reader::new(r), 1,
+                                       Layout::Libmarisa, Checks::All)?;
+        out.id_algorithm_version_ = 0;
+        out.validate()?;
+        Ok(out)
+    }
+
+    /// Writes the dictionary to the file at `path` with `write_to`,
+    /// replacing anything already there. The file is written beside `path`
+    /// and renamed over it, so a failed or interrupted save leaves `path`
+    /// as it was; see `save_with`.
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_with(path, &WriteOptions::new())
+    }
+
+    /// Like `save`, written as `options` say.
+    #[cfg(feature = "std")]
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, options: &WriteOptions)
+      -> io::Result<()> {
+        write_file(path.as_ref(), options, |w| self.write_to(w))
+    }
+
+    /// Reads a dictionary saved by `save` from the file at `path`.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LoudsTrie, MarisaError> {
+        LoudsTrie::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    /// Like `load`, with `read_from_unchecked`.
+    #[cfg(feature = "std")]
+    pub fn load_unchecked<P: AsRef<Path>>(path: P)
+      -> Result<LoudsTrie, MarisaError> {
+        let mut r = io::BufReader::new(File::open(path)?);
+        LoudsTrie::read_from_unchecked(&mut r)
+    }
+
+    /// Like `save`, with `write_libmarisa`.
+    #[cfg(feature = "std")]
+    pub fn save_libmarisa<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_libmarisa_with(path, &WriteOptions::new())
+    }
+
+    /// Like `save_with`, with `write_libmarisa`.
+    #[cfg(feature = "std")]
+    pub fn save_libmarisa_with<P: AsRef<Path>>(&self, path: P,
+                                               options: &WriteOptions)
+      -> io::Result<()> {
+        write_file(path.as_ref(), options, |w| self.write_libmarisa(w))
+    }
+
+    /// Like `load`, with `read_libmarisa`.
+    #[cfg(feature = "std")]
+    pub fn load_libmarisa<P: AsRef<Path>>(path: P)
+      -> Result<LoudsTrie, MarisaError> {
+        LoudsTrie::read_libmarisa(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    /// The table `write_payload_` starts with.
+    fn section_table(&self) -> SectionTable {
+        let mut lens = Vec::new();
+        self.section_lens(&mut lens);
+        lens.push(self.common_prefix_.io_size());
+        lens.push(self.weights_.io_size());
+        SectionTable::new(&lens)
+    }
+
+    /// The bytes from the start of each section the native `write_` writes
+    /// to the start of the next.
+    fn section_lens(&self, out: &mut Vec<usize>) {
+        out.push(self.louds_.io_size());
+        out.push(self.terminal_flags_.io_size());
+        out.push(self.link_flags_.io_size());
+        out.push(self.bases_.io_size());
+        out.push(self.extras_.io_size());
+        out.push(self.tail_.io_size());
+        if let &Some(ref next) = &self.next_trie_ {
+            next.section_lens(out);
+        }
+        // The number of level 1 nodes and the flags follow the cache.
+        out.push(self.cache_.io_size() + 8 + 8);
+    }
+
+    fn write_<W: io::Write>(&self, w: &mut W, layout: Layout)
+      -> io::Result<()> {
+        layout.write_bit_vec(w, &self.louds_)?;
+        if layout == Layout::Libmarisa && !self.config_.reverse_lookup() {
+            // libmarisa always reads the select1 table.
+            let mut terminal_flags = self.terminal_flags_.clone();
+            terminal_flags.build_index(false, true);
+            layout.write_bit_vec(w, &terminal_flags)?;
+        } else {
+            layout.write_bit_vec(w, &self.terminal_flags_)?;
+        }
+        layout.write_bit_vec(w, &self.link_flags_)?;
+        layout.write_bytes(w, &self.bases_)?;
+        layout.write_flat_vec(w, &self.extras_)?;
+        layout.write_tail(w, &self.tail_)?;
+        if let &Some(ref next) = &self.next_trie_ {
+            next.write_(w, layout)?;
+        }
+        layout.write_cache(w, &self.cache_)?;
+        layout.write_u32(w, self.num_l1_nodes_ as u32)?;
+        let flags = match layout {
+            Layout::Native | Layout::NativeV2 => self.config_.flags(),
+            Layout::Libmarisa => self.config_.num_tries().get()
+                                 | self.tail_mode() as u32
+                                 | self.node_order() as u32,
+        };
+        layout.write_u32(w, flags)
+    }
+
+    /// Reads one trie and the ones below it, checking that the parts agree
+    /// with each other well enough for queries not to index out of bounds.
+    /// As in marisa, a trie has a next trie iff it has links but no tail.
+    /// Only the native layout stores the bit vectors' indexes; the others
+    /// are copied and indexed, and so checked in full.
+    fn read_<S: Source<'m>>(r: &mut S, trie_id: usize, layout: Layout,
+                            checks: Checks)
+      -> io::Result<LoudsTrieView<'m>> {
+        if trie_id > MAX_NUM_TRIES as usize {
+            return Err(invalid_data("number of tries"));
+        }
+        let checks = if layout == Layout::Native {
+            checks
+        } else {
+            Checks::All
+        };
+        let mut out = LoudsTrieView::new();
+        out.louds_ = layout.read_bit_vec(r)?;
+        if !louds_shape_ok(&out.louds_)
+           || (checks == Checks::All && !parents_come_first(&out.louds_)) {
+            return Err(invalid_data("louds"));
+        }
+        let num_nodes = out.num_nodes();
+
+        out.terminal_flags_ = layout.read_bit_vec(r)?;
+        let terminal_len = if trie_id == 1 { num_nodes + 1 } else { 0 };
+        if out.terminal_flags_.len() != terminal_len {
+            return Err(invalid_data("terminal flags"));
+        }
+        out.link_flags_ = layout.read_bit_vec(r)?;
+        if out.link_flags_.len() != num_nodes || out.link_flags_.at(0) {
+            return Err(invalid_data("link flags"));
+        }
+        out.bases_ = layout.read_bytes(r)?;
+        if out.bases_.len() != num_nodes {
+            return Err(invalid_data("bases"));
+        }
+        out.extras_ = layout.read_flat_vec(r)?;
+        if out.extras_.len() != out.link_flags_.num_1s() {
+            return Err(invalid_data("extras"));
+        }
+        out.tail_ = layout.read_tail(r)?;
+        if !out.extras_.is_empty() && out.tail_.is_empty() {
+            out.next_trie_ = Some(Box::new(
+                LoudsTrieView::read_(r, trie_id + 1, layout, checks)?));
+        }
+
+        out.cache_ = layout.read_cache(r)?;
+        if !out.cache_.len().is_power_of_two() {
+            return Err(invalid_data("cache length"));
+        }
+        out.cache_mask_ = out.cache_.len() - 1;
+        out.num_l1_nodes_ = layout.read_u32(r)? as usize;
+        if out.num_l1_nodes_ >= num_nodes {
+            return Err(invalid_data("number of level 1 nodes"));
+        }
+        let flags = layout.read_u32(r)?;
+        out.config_ = Config::parse(flags)
+                      .map_err(|_| invalid_data("config flags"))?;
+        let num_tries = match &out.next_trie_ {
+            &Some(ref next) => next.num_tries() + 1,
+            &None => 1,
+        };
+        if out.num_tries() != num_tries {
+            return Err(invalid_data("number of tries"));
+        }
+
+        if layout == Layout::Native {
+            out.check_index(trie_id)?;
+        } else {
+            out.louds_.build_index(trie_id == 1, true);
+            out.link_flags_.build_index(false, false);
+            if trie_id == 1 {
+                let reverse_lookup = out.config_.reverse_lookup();
+                out.terminal_flags_.build_index(false, reverse_lookup);
+            }
+        }
+        if checks == Checks::All {
+            out.check_links()?;
+        }
+        Ok(out)
+    }
+
+    /// Checks that a trie read in the native layout came with the indexes
+    /// its queries use, as the build makes them.
+    fn check_index(&self, trie_id: usize) -> io::Result<()> {
+        let louds = &self.louds_;
+        let terminal = &self.terminal_flags_;
+        let louds_ok = louds.is_rank_enabled() && louds.is_select1_enabled()
+                       && (trie_id != 1 || louds.is_select0_enabled());
+        let terminal_ok = trie_id != 1
+            || (terminal.is_rank_enabled()
+                && (!self.config_.reverse_lookup()
+                    || terminal.is_select1_enabled()));
+        if !louds_ok || !terminal_ok || !self.link_flags_.is_rank_enabled() {
+            return Err(invalid_data("bit vector index"));
+        }
+        Ok(())
+    }
+
+    /// Checks that every link leads to a node of the next trie, or into
+    /// the tail.
+    fn check_links(&self) -> io::Result<()> {
+        let mut link_id = 0;
+        for node_id in 0..self.num_nodes() {
+            if !self.link_flags_.at(node_id) {
+                continue;
+            }
+            let linked = self.get_linked_node_id_2(node_id, link_id);
+            let in_range = match &self.next_trie_ {
+                &Some(ref next) => linked != 0 && linked < next.num_nodes(),
+                &None => linked < self.tail_.len(),
+            };
+            if !in_range {
+                return Err(invalid_data("link"));
+            }
+            link_id += 1;
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn len(&self) -> usize {
+        self.terminal_flags_.num_1s()
+    }
+
+    fn get_cache_id_with_label(&self, node_id: usize, label: u8) -> usize {
+        (node_id ^ (node_id << 5) ^ (label as usize)) & self.cache_mask_
+    }
+
+    fn get_cache_id(&self, node_id: usize) -> usize {
+        node_id & self.cache_mask_
+    }
+
+    // FIXME: is this correct terminology for link_id?
+    fn get_link_id(&self, node_id: usize) -> usize {
+        self.link_flags_.rank1(node_id)
+    }
+
+    fn get_linked_node_id(&self, node_id: usize) -> usize {
+        self.get_linked_node_id_2(node_id, self.get_link_id(node_id))
+    }
+
+    /// Whether any link of any trie needs more than 32 bits.
+    fn has_wide_links(&self) -> bool {
+        (0..self.extras_.len()).any(|i| self.extras_.at(i) > 0x00FF_FFFF)
+        || match &self.next_trie_ {
+            &Some(ref next) => next.has_wide_links(),
+            &None => false,
+        }
+    }
+
+    /// The node of the next trie, or the tail offset, that the link of
+    /// `node_id` leads to. Tail offsets can take 40 bits.
+    fn get_linked_node_id_2(&self, node_id: usize, link_id: usize) -> usize {
+        (self.bases_[node_id] as usize)
+        | ((self.extras_.at(link_id) as usize) << 8)
+    }
+
+    fn get_linked_ids(&self, node_id: usize) -> (usize, LinkID) {
+        let link_id = self.get_link_id(node_id);
+        let linked = self.get_linked_node_id_2(node_id, link_id);
+        assert!(link_id <= std::u32::MAX as usize);
+        (linked, LinkID(link_id as u32))
+    }
+
+    fn update_link_id(&self, link_id: usize, node_id: usize) -> usize {
+        if link_id == INVALID_LINK_ID.0 as usize {
+            self.get_link_id(node_id)
+        } else {
+            link_id + 1
+        }
+    }
+}
+
+/// Collects keys into a dictionary built with the default config. Panics
+/// where `build_from` would fail.
+impl<K> FromIterator<K> for LoudsTrie where Keyset: FromIterator<K> {
+    fn from_iter<I: IntoIterator<Item = K>>(keys: I) -> LoudsTrie {
+        match LoudsTrie::build_from(keys, &Config::new()) {
+            Ok(trie) => trie,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+/// Iterator returned by `LoudsTrie::common_prefix_search`.
+pub struct CommonPrefixIter<'a, 'b> {
+    trie_: &'a LoudsTrieView<'a>,
+    query_: &'b [u8],
+    node_id_: usize,
+    query_pos_: usize,
+    started_: bool,
+    finished_: bool,
+}
+
+impl<'a, 'b> CommonPrefixIter<'a, 'b> {
+    fn new(trie: &'a LoudsTrieView<'a>, query: &'b [u8])
+      -> CommonPrefixIter<'a, 'b> {
+        CommonPrefixIter { trie_: trie, query_: query, node_id_: 0,
+                           query_pos_: 0, started_: false, finished_: false }
+    }
+}
+
+impl<'a, 'b> Iterator for CommonPrefixIter<'a, 'b> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if self.finished_ {
+            return None;
+        }
+        let terminal_flags = &self.trie_.terminal_flags_;
+        if !self.started_ {
+            self.started_ = true;
+            let prefix = &self.trie_.common_prefix_;
+            if !self.query_.starts_with(prefix) {
+                self.finished_ = true;
+                return None;
+            }
+            self.query_pos_ = prefix.len();
+            if terminal_flags.at(self.node_id_) {
+                return Some((terminal_flags.rank1(self.node_id_),
+                             self.query_pos_));
+            }
+        }
+        while self.query_pos_ < self.query_.len() {
+            match self.trie_.find_child(self.node_id_, self.query_,
+                                        &mut self.query_pos_) {
+                Some(child_id) => self.node_id_ = child_id,
+                None => break,
+            }
+            if terminal_flags.at(self.node_id_) {
+                let id = terminal_flags.rank1(self.node_id_);
+                if self.trie_.paranoia_.is_on() {
+                    self.trie_.check_key("common_prefix_search", self.query_,
+                                         id, self.query_pos_);
+                }
+                return Some((id, self.query_pos_));
+            }
+        }
+        self.finished_ = true;
+        None
+    }
+
+    /// Every key found is a prefix of the query, so there is at most one
+    /// per remaining query byte, plus the one at the current position
+    /// before the first call.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.finished_ {
+            (0, Some(0))
+        } else if !self.started_ {
+            (0, Some(self.query_.len() + 1))
+        } else {
+            (0, Some(self.query_.len() - self.query_pos_))
+        }
+    }
+}
+
+impl<'a, 'b> std::iter::FusedIterator for CommonPrefixIter<'a, 'b> {}
+
+impl<'a, 'b> std::fmt::Debug for CommonPrefixIter<'a, 'b> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CommonPrefixIter")
+         .field("query", &String::from_utf8_lossy(self.query_))
+         .field("query_pos", &self.query_pos_)
+         .field("finished", &self.finished_)
+         .finish()
+    }
+}
+
+/// Whether `louds` starts with the super-root's "10", has a 0 for every
+/// node and a 1 for every node but the root. Constant time; see
+/// `parents_come_first` for the rest of the shape.
+fn louds_shape_ok(louds: &BitVec) -> bool {
+    let len = louds.len();
+    len >= 4 && len % 2 == 0 && louds.num_1s() == len / 2 - 1
+    && louds.at(0) && !louds.at(1)
+}
+
+/// Whether every node's parent, `select1(node) - node - 1`, comes before
+/// it, so that walks up a trie read from a file cannot loop.
+fn parents_come_first(louds: &BitVec) -> bool {
+    let mut num_0s = 0;
+    let mut num_1s = 0;
+    for i in 0..louds.len() {
+        if !louds.at(i) {
+            num_0s += 1;
+        } else if num_0s > num_1s {
+            return false;
+        } else {
+            num_1s += 1;
+        }
+    }
+    true
+}
+
+/// Number of cache entries for trie `trie_id` built from `num_keys` keys.
+fn cache_len(config: &Config, trie_id: usize, num_keys: usize) -> usize {
+    let mut cache_size: usize = if trie_id == 1 { 256 } else { 1 };
+    while cache_size < (num_keys / config.cache_level() as usize) {
+        cache_size *= 2;
+    }
+    cache_size
+}
+
+/// Length of the longest prefix shared by every key in `keyset`.
+fn common_prefix_len(keyset: &Keyset) -> usize {
+    let mut keys = keyset.iter();
+    let first = match keys.next() {
+        Some(first) => first,
+        None => return 0,
+    };
+    keys.fold(first.len(), |len, key| {
+        first[..len].iter().zip(key.iter()).take_while(|&(a, b)| a == b)
+            .count()
+    })
+}
+
+/// The buffers a predictive search restores keys into, kept from one
+/// search to the next so that they are allocated once; see
+/// `LoudsTrie::predictive_search_with`.
+///
+/// Every search clears the state before it starts, so a state can go from
+/// one dictionary to another: nothing of an earlier search, such as a node
+/// id of another trie, is ever read again. A search borrows its state
+/// mutably for as long as it runs, so a state serves one search at a time;
+/// keep one per thread.
+#[derive(Clone, Debug, Default)]
+pub struct SearchState {
+    key_buf_: Vec<u8>,
+    /// One entry per node on the path from the last node matched by the
+    /// query: the louds position and node id of its next unvisited child,
+    /// and the length of its key.
+    history_: Vec<(usize, usize, usize)>,
+    /// For `SearchOrder::Lex`, the nodes still to visit, last one first:
+    /// the first byte of the label, the node id, and the length of the
+    /// parent's key.
+    pending_: Vec<(u8, usize, usize)>,
+    /// For `SearchOrder::Id`, the nodes still to visit and the lengths of
+    /// their keys.
+    queue_: VecDeque<(usize, usize)>,
+}
+
+impl SearchState {
+    pub fn new() -> SearchState {
+        SearchState { key_buf_: Vec::new(), history_: Vec::new(),
+                      pending_: Vec::new(), queue_: VecDeque::new() }
+    }
+
+    /// Empties the buffers, keeping their capacity. Searches do this
+    /// themselves.
+    pub fn reset(&mut self) {
+        self.key_buf_.clear();
+        self.history_.clear();
+        self.pending_.clear();
+        self.queue_.clear();
+    }
+
+    fn is_done(&self) -> bool {
+        self.history_.is_empty() && self.pending_.is_empty()
+        && self.queue_.is_empty()
+    }
+}
+
+/// The walk shared by `PredictiveIter` and `PredictiveCursor`, which differ
+/// in who owns the state it walks with.
+struct PredictiveWalk<'a, 'b> {
+    trie_: &'a LoudsTrieView<'a>,
+    query_: &'b [u8],
+    started_: bool,
+    /// Number of keys returned so far.
+    num_found_: usize,
+    max_results_: usize,
+    /// Keys longer than this are neither returned nor descended into.
+    max_len_: usize,
+    order_: Option<SearchOrder>,
+    /// Number of nodes whose labels were looked at under the query's node.
+    num_visited_: usize,
+}
+
+impl<'a, 'b> PredictiveWalk<'a, 'b> {
+    fn new(trie: &'a LoudsTrieView<'a>, query: &'b [u8])
+      -> PredictiveWalk<'a, 'b> {
+        PredictiveWalk { trie_: trie, query_: query, started_: false,
+                         num_found_: 0, max_results_: std::usize::MAX,
+                         max_len_: std::usize::MAX, order_: None,
+                         num_visited_: 0 }
+    }
+
+    fn set_max_extra_len(&mut self, d: usize) {
+        self.max_len_ = self.query_.len().saturating_add(d);
+    }
+
+    fn set_order(&mut self, order: SearchOrder) {
+        assert!(!self.started_, "MARISA_STATE_ERROR");
+        // Label order already visits siblings in byte order.
+        self.order_ = match order {
+            SearchOrder::Lex
+                if self.trie_.node_order() == NodeOrder::Label => None,
+            order => Some(order),
+        };
+    }
+
+    /// Appends the label of `node_id` to the key, unless that makes it
+    /// longer than `max_len_`.
+    fn push_label(&mut self, state: &mut SearchState, node_id: usize)
+      -> bool {
+        self.num_visited_ += 1;
+        let trie = self.trie_;
+        let mut sink = LimitSink { buf: &mut state.key_buf_,
+                                   limit: self.max_len_, overflowed: false };
+        if trie.link_flags_.at(node_id) {
+            trie.restore(trie.get_linked_node_id(node_id), &mut sink);
+        } else {
+            sink.push(trie.bases_[node_id]);
+        }
+        !sink.overflowed
+    }
+
+    /// Pushes the children of `node_id`, whose key is in the buffer, so
+    /// that the one with the smallest label is popped first.
+    fn push_children_lex(&mut self, state: &mut SearchState,
+                         node_id: usize) {
+        let trie = self.trie_;
+        let start = state.pending_.len();
+        let mut louds_pos = trie.louds_.select0(node_id) + 1;
+        let mut child_id = louds_pos - node_id - 1;
+        while trie.louds_.at(louds_pos) {
+            let first = if trie.link_flags_.at(child_id) {
+                let mut first = FirstByte::default();
+                trie.restore(trie.get_linked_node_id(child_id), &mut first);
+                first.0.unwrap()
+            } else {
+                trie.bases_[child_id]
+            };
+            state.pending_.push((first, child_id, state.key_buf_.len()));
+            louds_pos += 1;
+            child_id += 1;
+        }
+        state.pending_[start..].sort_by(|a, b| b.0.cmp(&a.0));
+    }
+
+    /// Queues the children of `node_id`, whose key is `key_len` bytes long,
+    /// leaving out those whose keys would be too long.
+    fn queue_children(&mut self, state: &mut SearchState, node_id: usize,
+                      key_len: usize) {
+        let trie = self.trie_;
+        let mut louds_pos = trie.louds_.select0(node_id) + 1;
+        let mut child_id = louds_pos - node_id - 1;
+        while trie.louds_.at(louds_pos) {
+            self.num_visited_ += 1;
+            let mut len = KeyLen(key_len);
+            if trie.link_flags_.at(child_id) {
+                trie.restore(trie.get_linked_node_id(child_id), &mut len);
+            } else {
+                len.0 += 1;
+            }
+            if len.0 <= self.max_len_ {
+                state.queue_.push_back((child_id, len.0));
+            }
+            louds_pos += 1;
+            child_id += 1;
+        }
+    }
+
+    fn push_node(&self, state: &mut SearchState, node_id: usize) {
+        let louds_pos = self.trie_.louds_.select0(node_id) + 1;
+        state.history_.push((louds_pos, louds_pos - node_id - 1,
+                             state.key_buf_.len()));
+    }
+
+    fn found(&mut self, state: &SearchState, node_id: usize)
+      -> Option<usize> {
+        let terminal_flags = &self.trie_.terminal_flags_;
+        if !terminal_flags.at(node_id) {
+            return None;
+        }
+        self.num_found_ += 1;
+        let id = terminal_flags.rank1(node_id);
+        if self.trie_.paranoia_.is_on() {
+            self.trie_.check_lookup("predictive_search", self.query_, id,
+                                    &state.key_buf_);
+        }
+        Some(id)
+    }
+
+    /// Moves to the next key, leaving it in `state.key_buf_`, and returns
+    /// its id.
+    fn advance(&mut self, state: &mut SearchState) -> Option<usize> {
+        if self.num_found_ >= self.max_results_ {
+            self.started_ = true;
+            state.reset();
+            return None;
+        }
+        if !self.started_ {
+            self.started_ = true;
+            state.reset();
+            let node_id = match self.find_query_node(state) {
+                Some(node_id) => node_id,
+                None => return None,
+            };
+            match self.order_ {
+                None => self.push_node(state, node_id),
+                Some(SearchOrder::Lex) => self.push_children_lex(state,
+                                                                 node_id),
+                Some(SearchOrder::Id) => {
+                    let key_len = state.key_buf_.len();
+                    state.queue_.push_back((node_id, key_len));
+                    return self.advance_id(state);
+                },
+            }
+            if let Some(found) = self.found(state, node_id) {
+                return Some(found);
+            }
+        }
+        match self.order_ {
+            None => self.advance_dfs(state),
+            Some(SearchOrder::Lex) => self.advance_lex(state),
+            Some(SearchOrder::Id) => self.advance_id(state),
+        }
+    }
+
+    /// Descends to the node the query ends at, restoring its key, or to
+    /// nowhere if the key is too long.
+    fn find_query_node(&self, state: &mut SearchState) -> Option<usize> {
+        let trie = self.trie_;
+        let prefix = &trie.common_prefix_;
+        let mut query_pos = if self.query_.len() <= prefix.len() {
+            if !prefix.starts_with(self.query_) {
+                return None;
+            }
+            self.query_.len()
+        } else {
+            if !self.query_.starts_with(prefix) {
+                return None;
+            }
+            prefix.len()
+        };
+        if prefix.len() > self.max_len_ {
+            return None;
+        }
+        state.key_buf_.extend_from_slice(prefix);
+        let mut sink = LimitSink { buf: &mut state.key_buf_,
+                                   limit: self.max_len_, overflowed: false };
+        let mut node_id: usize = 0;
+        while query_pos < self.query_.len() {
+            node_id = trie.predictive_find_child(node_id, self.query_,
+                                                 &mut query_pos, &mut sink)?;
+        }
+        if sink.overflowed {
+            return None;
+        }
+        Some(node_id)
+    }
+
+    fn advance_dfs(&mut self, state: &mut SearchState) -> Option<usize> {
+        let trie = self.trie_;
+        loop {
+            let node_id = match state.history_.last_mut() {
+                None => return None,
+                Some(&mut (ref mut louds_pos, ref mut node_id, key_len)) => {
+                    if !trie.louds_.at(*louds_pos) {
+                        None
+                    } else {
+                        state.key_buf_.truncate(key_len);
+                        *louds_pos += 1;
+                        *node_id += 1;
+                        Some(*node_id - 1)
+                    }
+                }
+            };
+            let node_id = match node_id {
+                Some(node_id) => node_id,
+                None => {
+                    state.history_.pop();
+                    continue;
+                }
+            };
+            if !self.push_label(state, node_id) {
+                continue;
+            }
+            self.push_node(state, node_id);
+            if let Some(found) = self.found(state, node_id) {
+                return Some(found);
+            }
+        }
+    }
+
+    fn advance_lex(&mut self, state: &mut SearchState) -> Option<usize> {
+        while let Some((_, node_id, key_len)) = state.pending_.pop() {
+            state.key_buf_.truncate(key_len);
+            if !self.push_label(state, node_id) {
+                continue;
+            }
+            self.push_children_lex(state, node_id);
+            if let Some(found) = self.found(state, node_id) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Nodes come off the queue in node id order, and so their keys in id
+    /// order.
+    fn advance_id(&mut self, state: &mut SearchState) -> Option<usize> {
+        let trie = self.trie_;
+        while let Some((node_id, key_len)) = state.queue_.pop_front() {
+            self.queue_children(state, node_id, key_len);
+            if trie.terminal_flags_.at(node_id) {
+                state.key_buf_.clear();
+                state.key_buf_.resize(key_len, 0);
+                trie.key_into(node_id, key_len, &mut state.key_buf_);
+                return self.found(state, node_id);
+            }
+        }
+        None
+    }
+
+    /// No subtree counts are kept, so the upper bound is the number of keys
+    /// not yet returned.
+    fn size_hint(&self, state: &SearchState) -> (usize, Option<usize>) {
+        if self.started_ && state.is_done() {
+            (0, Some(0))
+        } else {
+            let left = std::cmp::min(self.trie_.len(), self.max_results_);
+            (0, Some(left - self.num_found_))
+        }
+    }
+}
+
+/// Iterator returned by `LoudsTrie::predictive_search`.
+pub struct PredictiveIter<'a, 'b> {
+    walk_: PredictiveWalk<'a, 'b>,
+    state_: SearchState,
+}
+
+impl<'a, 'b> PredictiveIter<'a, 'b> {
+    fn new(trie: &'a LoudsTrieView<'a>, query: &'b [u8])
+      -> PredictiveIter<'a, 'b> {
+        PredictiveIter { walk_: PredictiveWalk::new(trie, query),
+                         state_: SearchState::new() }
+    }
+
+    /// Turns the items into `(key_id, key, weight)`. The weight is None when
+    /// the dictionary was built without `Config::with_weights(true)`, as
+    /// with `LoudsTrie::weight`.
+    pub fn with_weights(self) -> WeightedPredictiveIter<'a, 'b> {
+        WeightedPredictiveIter { iter_: self }
+    }
+
+    /// Stops the search after `k` keys.
+    pub fn max_results(mut self, k: usize) -> PredictiveIter<'a, 'b> {
+        self.walk_.max_results_ = k;
+        self
+    }
+
+    /// Leaves out keys more than `d` bytes longer than the query, and does
+    /// not descend past them; a label that would overshoot is cut off
+    /// where it does.
+    pub fn max_extra_len(mut self, d: usize) -> PredictiveIter<'a, 'b> {
+        self.walk_.set_max_extra_len(d);
+        self
+    }
+
+    /// Visits the keys in `order`. Panics if the search has started.
+    pub fn order(mut self, order: SearchOrder) -> PredictiveIter<'a, 'b> {
+        self.walk_.set_order(order);
+        self
+    }
+}
+
+impl<'a, 'b> Iterator for PredictiveIter<'a, 'b> {
+    type Item = (usize, Vec<u8>);
+
+    fn next(&mut self) -> Option<(usize, Vec<u8>)> {
+        let state = &mut self.state_;
+        self.walk_.advance(state).map(|id| (id, state.key_buf_.clone()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.walk_.size_hint(&self.state_)
+    }
+}
+
+impl<'a, 'b> std::iter::FusedIterator for PredictiveIter<'a, 'b> {}
+
+impl<'a, 'b> std::fmt::Debug for PredictiveIter<'a, 'b> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PredictiveIter")
+         .field("query", &String::from_utf8_lossy(self.walk_.query_))
+         .field("depth", &self.state_.history_.len())
+         .field("key_len", &self.state_.key_buf_.len())
+         .field("num_found", &self.walk_.num_found_)
+         .finish()
+    }
+}
+
+/// Returned by `LoudsTrie::predictive_search_with`. Like `PredictiveIter`,
+/// but each key is lent out of the search state rather than copied into a
+/// Vec of its own, so `next` is a method rather than an `Iterator`.
+pub struct PredictiveCursor<'a, 'b, 's> {
+    walk_: PredictiveWalk<'a, 'b>,
+    state_: &'s mut SearchState,
+}
+
+impl<'a, 'b, 's> PredictiveCursor<'a, 'b, 's> {
+    /// As `PredictiveIter::max_results`.
+    pub fn max_results(mut self, k: usize) -> PredictiveCursor<'a, 'b, 's> {
+        self.walk_.max_results_ = k;
+        self
+    }
+
+    /// As `PredictiveIter::max_extra_len`.
+    pub fn max_extra_len(mut self, d: usize)
+      -> PredictiveCursor<'a, 'b, 's> {
+        self.walk_.set_max_extra_len(d);
+        self
+    }
+
+    /// As `PredictiveIter::order`.
+    pub fn order(mut self, order: SearchOrder)
+      -> PredictiveCursor<'a, 'b, 's> {
+        self.walk_.set_order(order);
+        self
+    }
+
+    /// The id and bytes of the next key, which are overwritten by the call
+    /// after.
+    pub fn next(&mut self) -> Option<(usize, &[u8])> {
+        match self.walk_.advance(self.state_) {
+            Some(id) => Some((id, &self.state_.key_buf_[..])),
+            None => None,
+        }
+    }
+}
+
+impl<'a, 'b, 's> std::fmt::Debug for PredictiveCursor<'a, 'b, 's> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PredictiveCursor")
+         .field("query", &String::from_utf8_lossy(self.walk_.query_))
+         .field("depth", &self.state_.history_.len())
+         .field("num_found", &self.walk_.num_found_)
+         .finish()
+    }
+}
+
+/// Iterator returned by `PredictiveIter::with_weights`.
+#[derive(Debug)]
+pub struct WeightedPredictiveIter<'a, 'b> {
+    iter_: PredictiveIter<'a, 'b>,
+}
+
+impl<'a, 'b> Iterator for WeightedPredictiveIter<'a, 'b> {
+    type Item = (usize, Vec<u8>, Option<f32>);
+
+    fn next(&mut self) -> Option<(usize, Vec<u8>, Option<f32>)> {
+        let weights = &self.iter_.walk_.trie_.weights_;
+        self.iter_.next().map(|(id, key)| {
+            let weight = weights.get(id).cloned();
+            (id, key, weight)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter_.size_hint()
+    }
+}
+
+impl<'a, 'b> std::iter::FusedIterator for WeightedPredictiveIter<'a, 'b> {}
+
+/*
+    fn map(mapper: &mut Mapper) -> LoudsTrie {
+        Header().map(mapper);
+    
+        let temp: LoudsTrie;
+        temp.map_(mapper);
+        temp.mapper_.swap(mapper);
+        temp
+    }
+
+    void map_(Mapper &mapper);
+ 
+void LoudsTrie::map_(Mapper &mapper) {
+  louds_.map(mapper);
   terminal_flags_.map(mapper);
   link_flags_.map(mapper);
   bases_.map(mapper);
@@ -735,97 +2738,2361 @@ void LoudsTrie::map_(Mapper &mapper) {
     config_.parse((int)temp_config_flags);
   }
 }
+*/
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use config::{CacheLevel, Config, MAX_NUM_TRIES, MIN_NUM_TRIES, NodeOrder,
+                 NumTries, TailMode};
+    use keyset::Keyset;
+    use quickcheck as qc;
+    use std;
+    use std::default::Default;
+    use std::sync::Arc;
+    use std::thread;
+    use aligned_bytes::AlignedBytes;
+    use alloc_counter;
+    use base::checked_u32;
+    use cache::Cache;
+    use error::MarisaError;
+    use header::{FORMAT_VERSION, Header};
+    use iter;
+    use rand;
+    use rand::Rng;
+    use super::{ID_ALGORITHM_VERSION, LongKeys, LoudsTrie, LoudsTrieView,
+                PredictiveIter, SearchOrder, SearchState};
+
+    fn build_prop(v: Vec<String>, num_tries: NumTries) -> qc::TestResult {
+        if v.iter().any(|x| x.is_empty()) {
+            return qc::TestResult::discard();
+        }
+        let mut keyset = Keyset::new();
+        for s in &v {
+            keyset.push(s.as_bytes());
+        }
+        let config = Config::new().with_num_tries(num_tries);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let mut ids_seen = Vec::new();
+        for i in 0..keyset.len() {
+            let id = keyset.id(i).unwrap();
+            ids_seen.push(id);
+            let s = trie.id_lookup(id).unwrap();
+            if !s.iter().eq(keyset.key(i).iter()) {
+                return qc::TestResult::failed();
+            }
+        }
+        ids_seen.sort();
+        for (&a, b) in ids_seen.iter().zip(0..ids_seen.len()) {
+            if a != b { return qc::TestResult::failed(); }
+        }
+        qc::TestResult::passed()
+    }
+
+    #[test]
+    fn louds_trie_build_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(build_prop as fn(Vec<String>, NumTries)
+                       -> qc::TestResult);
+    }
+
+    /// Whether `trie` holds exactly the keys of `keyset`, with the ids the
+    /// build stored into it.
+    fn holds_keyset(trie: &LoudsTrieView, keyset: &Keyset) -> bool {
+        let mut expected: Vec<&[u8]> = keyset.iter().collect();
+        expected.sort();
+        expected.dedup();
+        let mut keys: Vec<Vec<u8>> = trie.iter().map(|(_, key)| key)
+                                         .collect();
+        keys.sort();
+        (0..keyset.len()).all(|i| {
+            let id = keyset.id(i);
+            trie.lookup(keyset.key(i)) == id
+            && (!trie.config().reverse_lookup()
+                || trie.id_lookup(id.unwrap()).unwrap() == keyset.key(i))
+        })
+        && keys.iter().map(|key| &key[..]).eq(expected.into_iter())
+        && trie.len() == keys.len()
+    }
+
+    #[test]
+    fn keyset_round_trip_qc() {
+        let _ = env_logger::init();
+        fn prop(keyset: Keyset, config: Config) -> bool {
+            let mut keyset = keyset;
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            let bytes = write_bytes(&trie);
+            holds_keyset(&trie, &keyset)
+            && holds_keyset(&LoudsTrie::map_from_slice(
+                                &AlignedBytes::from_slice(&bytes)).unwrap(),
+                            &keyset)
+        }
+        qc::quickcheck(prop as fn(Keyset, Config) -> bool);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn keyset_save_load_qc() {
+        let _ = env_logger::init();
+        fn prop(keyset: Keyset, config: Config) -> bool {
+            let mut keyset = keyset;
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            let bytes = write_bytes(&trie);
+            let read = LoudsTrie::read_from(&mut &bytes[..]).unwrap();
+            holds_keyset(&read, &keyset)
+            && read.config().flags() == trie.config().flags()
+            && write_bytes(&read) == bytes
+        }
+        qc::quickcheck(prop as fn(Keyset, Config) -> bool);
+    }
+
+    /// The shrinkers make progress and keep what they promise to.
+    #[test]
+    fn keyset_shrink() {
+        let _ = env_logger::init();
+        let keyset: Keyset = vec![&b"ab\x00c"[..], b"ab", b"ab\x00c", b"\xFF"]
+                             .into_iter().collect();
+        let shrunk: Vec<Keyset> = qc::Arbitrary::shrink(&keyset).collect();
+        assert!(shrunk.iter().any(|k| k.is_empty()));
+        assert!(shrunk.iter().all(|k| k.total_length() < keyset.total_length()
+                                      || k.len() < keyset.len()));
+        // Cutting the first byte out of every key.
+        let cut: Vec<&[u8]> = vec![b"b\x00c", b"b", b"b\x00c", b""];
+        assert!(shrunk.iter().any(|k| k.iter().eq(cut.iter().cloned())));
+
+        let config = Config::new().with_num_tries(NumTries::new(8))
+                                  .with_weights(true)
+                                  .with_tail_mode(TailMode::Binary);
+        let shrunk: Vec<Config> = qc::Arbitrary::shrink(&config).collect();
+        assert_eq!(shrunk, vec![config.with_num_tries(NumTries::new(4)),
+                                config.with_tail_mode(TailMode::Text),
+                                config.with_weights(false)]);
+    }
+
+    #[test]
+    fn louds_trie_build_manual() {
+        let _ = env_logger::init();
+        let n = NumTries::default();
+        assert!(!build_prop(vec!["\u{80}".to_string()], n).is_failure());
+        assert!(!build_prop(vec!["\u{4b8ca}".to_string(),
+                                 "\u{d2c4a}".to_string()], n).is_failure());
+    }
+
+    fn lookup_prop(v: Vec<Vec<u8>>, others: Vec<Vec<u8>>, num_tries: NumTries)
+      -> bool {
+        let mut keyset = Keyset::new();
+        for k in &v {
+            keyset.push(k);
+        }
+        let config = Config::new().with_num_tries(num_tries);
+        let mut trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        trie.set_paranoia(true);
+
+        let mut distinct = v.clone();
+        distinct.sort();
+        distinct.dedup();
+        if trie.len() != distinct.len() {
+            return false;
+        }
+        for i in 0..keyset.len() {
+            if trie.lookup(keyset.key(i)) != keyset.id(i) {
+                return false;
+            }
+        }
+        others.iter().all(|q| {
+            trie.lookup(q).is_some() == v.contains(q)
+        })
+    }
+
+    #[test]
+    fn lookup_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(lookup_prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, NumTries) -> bool);
+    }
+
+    #[test]
+    fn lookup_manual() {
+        let _ = env_logger::init();
+        let n = NumTries::default();
+        let v = |s: &[&str]| s.iter().map(|x| x.as_bytes().to_vec())
+                             .collect::<Vec<_>>();
+        assert!(lookup_prop(v(&["apple", "apply", "ape"]),
+                            v(&["", "a", "ap", "appl", "apples", "b"]), n));
+        assert!(lookup_prop(v(&["", "a"]), v(&["b", "aa"]), n));
+        assert!(lookup_prop(v(&[]), v(&["", "a"]), n));
+    }
+
+    /// `lookup_batch` over the keys, their halves and `others`, with empty
+    /// queries among them, gives what `lookup` gives one at a time.
+    fn lookup_batch_prop(keys: Vec<Vec<u8>>, others: Vec<Vec<u8>>,
+                         config: Config) -> bool {
+        let mut trie = LoudsTrie::build_from(keys.iter(), &config).unwrap();
+        trie.set_paranoia(true);
+        let mut queries: Vec<&[u8]> = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            queries.push(key);
+            queries.push(&key[..key.len() / 2]);
+            if i % 5 == 0 {
+                queries.push(b"");
+            }
+        }
+        queries.extend(others.iter().map(|q| &q[..]));
+        // What was in `out` before is dropped.
+        let mut out = vec![Some(7); 3];
+        trie.lookup_batch(&queries, &mut out);
+        out.len() == queries.len()
+        && queries.iter().zip(&out).all(|(q, &found)| trie.lookup(q) == found)
+    }
+
+    #[test]
+    fn lookup_batch_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(lookup_batch_prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, Config) -> bool);
+    }
+
+    #[test]
+    fn lookup_batch_manual() {
+        let _ = env_logger::init();
+        let v = |s: &[&str]| s.iter().map(|x| x.as_bytes().to_vec())
+                             .collect::<Vec<_>>();
+        assert!(lookup_batch_prop(v(&["apple", "apply", "ape"]),
+                                  v(&["", "a", "appl", "apples", "b"]),
+                                  Config::new()));
+        assert!(lookup_batch_prop(v(&["", "a"]), v(&["b", "aa", ""]),
+                                  Config::new()));
+        assert!(lookup_batch_prop(v(&[]), v(&["", "a"]), Config::new()));
+
+        // A stripped common prefix, which misses fall short of or stray
+        // from, and batches that end part way through a group.
+        let keys = shared_prefix_keys(40);
+        let trie = LoudsTrie::build_from(keys.iter(), &Config::new())
+                   .unwrap();
+        let mut queries: Vec<&[u8]> = Vec::new();
+        for (i, key) in keys.iter().step_by(97).enumerate() {
+            queries.push(key);
+            queries.push(&key[..(i * 7) % key.len()]);
+            queries.push(&key[1..]);
+        }
+        let mut out = Vec::with_capacity(queries.len());
+        for &len in &[0, 1, 15, 16, 17, 100, queries.len()] {
+            let batch = &queries[..len];
+            // Once `out` has room, nothing is allocated.
+            assert_eq!(alloc_counter::allocations(|| {
+                trie.lookup_batch(batch, &mut out)
+            }), 0);
+            let expected: Vec<_> = batch.iter().map(|q| trie.lookup(q))
+                                        .collect();
+            assert_eq!(out, expected);
+        }
+        assert!(out.iter().filter(|x| x.is_some()).count() > 100);
+    }
+
+    /// Run with
+    /// `RUST_LOG=info cargo test --release -- --ignored lookup_batch_bench`.
+    /// The dictionary is meant to be well past the size of the last level
+    /// cache, where prefetching pays.
+    #[test]
+    #[ignore]
+    fn lookup_batch_bench() {
+        let _ = env_logger::init();
+        let n = 8000000u64;
+        let key = |i: u64| format!("{:x}/{}", (i * 2654435761) % 1000000007,
+                                   i % 1000);
+        let trie = LoudsTrie::build_from((0..n).map(&key), &Config::new())
+                   .unwrap();
+        // Every other query misses.
+        let owned: Vec<String> = (0..1000000u64).map(|i| {
+            key((i * 7919) % n) + if i % 2 == 0 { "" } else { "x" }
+        }).collect();
+        let queries: Vec<&[u8]> = owned.iter().map(|q| q.as_bytes())
+                                       .collect();
+        info!("{} keys, {} bytes", trie.len(), trie.io_size());
+
+        let start = std::time::Instant::now();
+        let sequential: Vec<Option<usize>> = queries.iter()
+            .map(|q| trie.lookup(q)).collect();
+        let sequential_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut batched = Vec::with_capacity(queries.len());
+        let mut out = Vec::with_capacity(1024);
+        for chunk in queries.chunks(1024) {
+            trie.lookup_batch(chunk, &mut out);
+            batched.extend_from_slice(&out);
+        }
+        let batched_time = start.elapsed();
+
+        assert_eq!(sequential, batched);
+        info!("sequential: {:?}, batched: {:?}", sequential_time,
+              batched_time);
+    }
+
+    #[test]
+    fn duplicates_share_an_id() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        keyset.push_weighted(b"d", 1.0);
+        keyset.push_weighted(b"o", 2.5);
+        keyset.push_weighted(b"d", 2.0);
+        let config = Config::new().with_node_order(NodeOrder::Weight);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+
+        assert_eq!(trie.len(), 2);
+        assert_eq!(keyset.id(0), keyset.id(2));
+        assert!(keyset.id(0) != keyset.id(1));
+        assert_eq!(trie.lookup(b"d"), keyset.id(0));
+        // 1.0 + 2.0 outweighs 2.5, so "d" is the first child of the root.
+        assert_eq!(trie.bases_[1], b'd');
+    }
+
+    #[test]
+    fn effective_config() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        keyset.push(b"ab\0cdef");
+        keyset.push(b"ab\0cdeg");
+        keyset.push(b"xyz");
+        let config = Config::new().with_tail_mode(TailMode::Text)
+                                  .with_num_tries(NumTries::new(1))
+                                  .with_cache_level(CacheLevel::Small);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        assert_eq!(trie.config().tail_mode(), TailMode::Binary);
+        assert_eq!(trie.config().num_tries().get(), 1);
+        assert_eq!(trie.config().cache_level(), CacheLevel::Small);
+
+        // Auto settles on one of the two, and says which.
+        let short: Vec<String> = (0..100).map(|i| {
+            format!("{:03}{:03}", i, i * 37 % 1000)
+        }).collect();
+        let long: Vec<String> = (0..100).map(|i| {
+            format!("{:03}: a label much longer than eight bytes", i)
+        }).collect();
+        let nul: Vec<String> = (0..100).map(|i| format!("{:03}\0ab", i))
+                                       .collect();
+        let config = Config::new().with_tail_mode(TailMode::Auto)
+                                  .with_num_tries(NumTries::new(1));
+        for &(ref keys, mode) in &[(short, TailMode::Binary),
+                                   (long, TailMode::Text),
+                                   (nul, TailMode::Binary)] {
+            let trie = LoudsTrie::build_from(keys, &config).unwrap();
+            assert_eq!(trie.config().tail_mode(), mode);
+            for key in keys {
+                assert!(trie.lookup(key.as_bytes()).is_some(), "{:?}", key);
+            }
+        }
+
+        let mut keyset = Keyset::new();
+        keyset.push(b"a");
+        keyset.push(b"b");
+        let config = Config::new().with_num_tries(NumTries::new(5));
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        // No links, so there was nothing to put in a second trie.
+        assert_eq!(trie.config().num_tries().get(), 1);
+        for i in 0..keyset.len() {
+            assert_eq!(trie.lookup(keyset.key(i)), keyset.id(i));
+        }
+    }
+
+    fn build_with_tries(keyset: &mut Keyset, n: u32) -> LoudsTrie {
+        let config = Config::new().with_num_tries(NumTries::new(n));
+        LoudsTrie::build(keyset, &config).unwrap()
+    }
+
+    #[test]
+    fn next_tries_agree() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for i in 0..2000 {
+            keyset.push(format!("http://example.com/{}/{}/index.html",
+                                i % 37, i).as_bytes());
+            keyset.push(format!("{}-suffix-shared-by-many-keys", i * 17)
+                        .as_bytes());
+        }
+        // Suffixes of each other in the next trie, in an order where a
+        // plain forward sort of the reversed labels would be wrong.
+        for s in &["X1", "Xaba1", "Xaba2", "Y1", "Yba1", "Yba2"] {
+            keyset.push(s.as_bytes());
+        }
+
+        let single = build_with_tries(&mut keyset, 1);
+        assert_eq!(single.config().num_tries().get(), 1);
+        let queries: Vec<Vec<u8>> = keyset.iter()
+            .flat_map(|k| vec![k.to_vec(), k[..k.len() / 2].to_vec()])
+            .collect();
+        let expected: Vec<Option<usize>> = queries.iter()
+            .map(|q| single.lookup(q)).collect();
+
+        for &n in &[2, 3, 5] {
+            let trie = build_with_tries(&mut keyset, n);
+            assert!(trie.config().num_tries().get() > 1);
+            let found: Vec<Option<usize>> = queries.iter()
+                .map(|q| trie.lookup(q)).collect();
+            assert_eq!(found, expected);
+            for i in 0..keyset.len() {
+                assert_eq!(trie.id_lookup(keyset.id(i).unwrap()).unwrap(),
+                           keyset.key(i));
+            }
+            // The shared suffixes of the link labels are stored once in the
+            // next trie instead of once per label.
+            assert!(trie.total_size() < single.total_size(),
+                    "{} tries: {} >= {}", n, trie.total_size(),
+                    single.total_size());
+        }
+    }
+
+    fn naive_longest_prefix(keys: &[String], query: &[u8]) -> Option<usize> {
+        keys.iter().map(|k| k.as_bytes())
+            .filter(|k| query.starts_with(k))
+            .map(|k| k.len())
+            .max()
+    }
+
+    fn longest_prefix_match_prop(v: Vec<String>, queries: Vec<String>,
+                                 num_tries: NumTries) -> qc::TestResult {
+        let mut keyset = Keyset::new();
+        for s in &v {
+            keyset.push(s.as_bytes());
+        }
+        let config = Config::new().with_num_tries(num_tries);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+
+        let mut all_queries: Vec<Vec<u8>> = Vec::new();
+        for s in v.iter().chain(queries.iter()) {
+            let mut q: Vec<u8> = s.as_bytes().to_vec();
+            all_queries.push(q.clone());
+            q.extend(b"xyz");
+            all_queries.push(q);
+        }
+
+        for q in &all_queries {
+            let found = trie.longest_prefix_match(q);
+            if found != trie.common_prefix_search(q).last() {
+                return qc::TestResult::failed();
+            }
+            if found.map(|(_, len)| len) != naive_longest_prefix(&v, q) {
+                return qc::TestResult::failed();
+            }
+            if let Some((id, len)) = found {
+                if trie.id_lookup(id).unwrap() != &q[..len] {
+                    return qc::TestResult::failed();
+                }
+            }
+        }
+        qc::TestResult::passed()
+    }
+
+    #[test]
+    fn longest_prefix_match_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(longest_prefix_match_prop
+                       as fn(Vec<String>, Vec<String>, NumTries)
+                       -> qc::TestResult);
+    }
+
+    #[test]
+    fn longest_prefix_match_manual() {
+        let _ = env_logger::init();
+        let words = ["a", "app", "apple", "applesauce", "banana"];
+        for &n in &[1, 2, 3] {
+            let mut keyset = Keyset::new();
+            for s in &words {
+                keyset.push(s.as_bytes());
+            }
+            let config = Config::new().with_num_tries(NumTries::new(n));
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+
+            let len_of = |q: &str| trie.longest_prefix_match(q.as_bytes())
+                                   .map(|(_, len)| len);
+            assert_eq!(len_of("applesauces"), Some(10));
+            // "applesa" stops part way into the tail of "applesauce".
+            assert_eq!(len_of("applesa"), Some(5));
+            assert_eq!(len_of("appl"), Some(3));
+            assert_eq!(len_of("bananas"), Some(6));
+            assert_eq!(len_of("banan"), None);
+            assert_eq!(len_of(""), None);
+            assert_eq!(len_of("b"), None);
+        }
+    }
+
+    #[test]
+    fn longest_prefix_match_empty_key() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for s in &["", "ab", "abcd"] {
+            keyset.push(s.as_bytes());
+        }
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let id = |i| keyset.id(i).unwrap();
+        let empty_id = id(0);
+
+        assert_eq!(trie.longest_prefix_match(b""), Some((empty_id, 0)));
+        assert_eq!(trie.longest_prefix_match(b"x"), Some((empty_id, 0)));
+        assert_eq!(trie.longest_prefix_match(b"abc"), Some((id(1), 2)));
+        assert_eq!(trie.common_prefix_search(b"abcde").collect::<Vec<_>>(),
+                   vec![(empty_id, 0), (id(1), 2), (id(2), 4)]);
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test -- --ignored` logs the
+    /// timings.
+    #[test]
+    #[ignore]
+    fn longest_prefix_match_bench() {
+        let _ = env_logger::init();
+        let owned: Vec<String> = (0..20000).map(|i| {
+            format!("{}", i * 7919).chars().rev().collect()
+        }).collect();
+        let mut keyset = Keyset::new();
+        for s in &owned {
+            keyset.push(s.as_bytes());
+        }
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let queries: Vec<String> = owned.iter()
+                                   .map(|s| format!("{}0123456789", s))
+                                   .collect();
+
+        let start = std::time::Instant::now();
+        let mut a = 0;
+        for q in &queries {
+            a += trie.longest_prefix_match(q.as_bytes()).unwrap().1;
+        }
+        let lpm_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut b = 0;
+        for q in &queries {
+            b += trie.common_prefix_search(q.as_bytes()).last().unwrap().1;
+        }
+        let cps_time = start.elapsed();
+
+        assert_eq!(a, b);
+        info!("longest_prefix_match: {:?}, \
+               common_prefix_search().last(): {:?}", lpm_time, cps_time);
+    }
+
+    fn predictive_prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>,
+                       num_tries: NumTries, node_order: NodeOrder) -> bool {
+        let mut keyset = Keyset::new();
+        for k in &v {
+            keyset.push(k);
+        }
+        let config = Config::new().with_num_tries(num_tries)
+                                  .with_node_order(node_order);
+        let mut trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        trie.set_paranoia(true);
+
+        let mut distinct = v.clone();
+        distinct.sort();
+        distinct.dedup();
+        let prefixes = v.iter().flat_map(|k| {
+            vec![k[..k.len() / 2].to_vec(), k.clone()]
+        });
+        prefixes.chain(queries.into_iter()).all(|q| {
+            let found: Vec<(usize, Vec<u8>)> =
+                trie.predictive_search(&q).collect();
+            let mut keys: Vec<Vec<u8>> = found.iter()
+                .map(|&(_, ref key)| key.clone()).collect();
+            if node_order == NodeOrder::Label {
+                if keys.windows(2).any(|w| w[0] >= w[1]) {
+                    return false;
+                }
+            } else {
+                keys.sort();
+            }
+            let expected: Vec<Vec<u8>> = distinct.iter()
+                .filter(|k| k.starts_with(&q)).cloned().collect();
+            keys == expected
+            && found.iter().all(|&(id, ref key)| trie.lookup(key) == Some(id))
+        })
+    }
+
+    #[test]
+    fn predictive_search_qc() {
+        let _ = env_logger::init();
+        fn prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>, num_tries: NumTries)
+          -> bool {
+            predictive_prop(v.clone(), queries.clone(), num_tries,
+                            NodeOrder::Label)
+            && predictive_prop(v, queries, num_tries, NodeOrder::Weight)
+        }
+        qc::quickcheck(prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, NumTries) -> bool);
+    }
+
+    fn bounded_predictive_prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>,
+                               num_tries: NumTries, node_order: NodeOrder,
+                               k: u8, d: u8) -> bool {
+        let config = Config::new().with_num_tries(num_tries)
+                                  .with_node_order(node_order);
+        let mut trie = LoudsTrie::build_from(v.iter().cloned(), &config)
+                       .unwrap();
+        trie.set_paranoia(true);
+        let (k, d) = (k as usize % 8, d as usize % 4);
+
+        let prefixes = v.iter().map(|key| key[..key.len() / 2].to_vec());
+        prefixes.chain(queries.into_iter()).all(|q| {
+            let short: Vec<(usize, Vec<u8>)> = trie.predictive_search(&q)
+                .filter(|&(_, ref key)| key.len() <= q.len() + d).collect();
+            let mut by_id = short.clone();
+            by_id.sort();
+            let mut by_key: Vec<(usize, Vec<u8>)> = short.iter()
+                .map(|&(id, ref key)| (id, key.clone())).collect();
+            by_key.sort_by(|a, b| a.1.cmp(&b.1));
+            let bounded = |order: Option<SearchOrder>| {
+                let mut iter = trie.predictive_search(&q).max_results(k)
+                                   .max_extra_len(d);
+                if let Some(order) = order {
+                    iter = iter.order(order);
+                }
+                iter.collect::<Vec<(usize, Vec<u8>)>>()
+            };
+            bounded(None) == short.iter().take(k).cloned().collect::<Vec<_>>()
+            && bounded(Some(SearchOrder::Id))
+               == by_id.into_iter().take(k).collect::<Vec<_>>()
+            && bounded(Some(SearchOrder::Lex))
+               == by_key.into_iter().take(k).collect::<Vec<_>>()
+        })
+    }
+
+    #[test]
+    fn bounded_predictive_search_qc() {
+        let _ = env_logger::init();
+        fn prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>, num_tries: NumTries,
+                k: u8, d: u8) -> bool {
+            bounded_predictive_prop(v.clone(), queries.clone(), num_tries,
+                                    NodeOrder::Label, k, d)
+            && bounded_predictive_prop(v, queries, num_tries,
+                                       NodeOrder::Weight, k, d)
+        }
+        qc::quickcheck(prop as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, NumTries,
+                                  u8, u8) -> bool);
+    }
+
+    #[test]
+    fn bounded_predictive_search_prunes() {
+        let _ = env_logger::init();
+        let mut keys: Vec<String> = (0..5000).map(|i| (i * 7).to_string())
+                                             .collect();
+        keys.push("1-and-a-long-way-past-the-limit".to_owned());
+        keys.push("1x".to_owned());
+        for &num_tries in &[1, 3] {
+            let config = Config::new().with_num_tries(NumTries::new(num_tries));
+            let trie = LoudsTrie::build_from(keys.iter(), &config).unwrap();
+            let visited = |mut iter: PredictiveIter| {
+                while let Some(_) = iter.next() {}
+                iter.walk_.num_visited_
+            };
+            let all = visited(trie.predictive_search(b"1"));
+            for &order in &[SearchOrder::Id, SearchOrder::Lex] {
+                assert_eq!(visited(trie.predictive_search(b"1").order(order)),
+                           all);
+                assert!(visited(trie.predictive_search(b"1").order(order)
+                                .max_extra_len(1)) < all);
+                assert!(visited(trie.predictive_search(b"1").order(order)
+                                .max_results(3)) < all);
+            }
+            assert!(visited(trie.predictive_search(b"1").max_extra_len(1))
+                    < all);
+            assert!(visited(trie.predictive_search(b"1").max_results(3)) < all);
+
+            // The long label overshoots two extra bytes on its own.
+            let mut found: Vec<Vec<u8>> = trie.predictive_search(b"1")
+                .max_extra_len(2).map(|(_, key)| key).collect();
+            found.sort();
+            let mut expected: Vec<Vec<u8>> = keys.iter()
+                .filter(|key| key.starts_with("1") && key.len() <= 3)
+                .map(|key| key.as_bytes().to_vec()).collect();
+            expected.sort();
+            assert_eq!(found, expected);
+            assert!(expected.contains(&b"1x".to_vec()));
+
+            let lex: Vec<Vec<u8>> = trie.predictive_search(b"14")
+                .order(SearchOrder::Lex).map(|(_, key)| key).collect();
+            assert!(lex.windows(2).all(|w| w[0] < w[1]));
+            let ids: Vec<usize> = trie.predictive_search(b"14")
+                .order(SearchOrder::Id).map(|(id, _)| id).collect();
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            assert_eq!(lex.len(), ids.len());
+        }
+    }
+
+    #[test]
+    fn bounded_search_with_state() {
+        let _ = env_logger::init();
+        let trie = LoudsTrie::build(&mut id_fixture(), &Config::new())
+                   .unwrap();
+        let expected: Vec<(usize, Vec<u8>)> = trie.predictive_search(b"")
+            .order(SearchOrder::Lex).max_results(4).collect();
+        let mut state = SearchState::new();
+        let mut cursor = trie.predictive_search_with(&mut state, b"")
+                             .order(SearchOrder::Lex).max_results(4);
+        let mut found = Vec::new();
+        while let Some((id, key)) = cursor.next() {
+            found.push((id, key.to_vec()));
+        }
+        assert_eq!(found, expected);
+        assert_eq!(found.len(), 4);
+    }
+
+    /// Runs `query` through one reused state against two dictionaries in
+    /// turn, and compares every answer with the allocating API.
+    #[test]
+    fn reused_search_state() {
+        let _ = env_logger::init();
+        let numbers: Vec<String> = (0..3000).map(|i| (i * 7).to_string())
+                                            .collect();
+        let tries = [LoudsTrie::build(&mut id_fixture(), &Config::new())
+                     .unwrap(),
+                     LoudsTrie::build_from(numbers, &Config::new()).unwrap()];
+        let keys: Vec<Vec<Vec<u8>>> = tries.iter().map(|t| {
+            t.iter().map(|(_, key)| key).collect()
+        }).collect();
+
+        let mut state = SearchState::new();
+        let query = |i: usize, state: &mut SearchState| {
+            let (trie, keys) = (&tries[i % 2], &keys[i % 2]);
+            let key = &keys[(i / 2) % keys.len()];
+            match (i / 2) % 3 {
+                0 => {
+                    let prefix = &key[..key.len() / 2];
+                    let mut cursor = trie.predictive_search_with(state,
+                                                                 prefix);
+                    let mut expected = trie.predictive_search(prefix);
+                    while let Some((id, found)) = cursor.next() {
+                        assert_eq!(Some((id, found.to_vec())),
+                                   expected.next());
+                    }
+                    assert_eq!(expected.next(), None);
+                    assert_eq!(cursor.next(), None);
+                },
+                1 => {
+                    let id = trie.lookup(key).unwrap();
+                    assert_eq!(trie.id_lookup_with(state, id).unwrap(),
+                               &trie.id_lookup(id).unwrap()[..]);
+                },
+                _ => {
+                    // Stopped early, leaving the state mid-walk.
+                    let mut cursor = trie.predictive_search_with(state, b"");
+                    assert_eq!(cursor.next().map(|(id, k)| (id, k.to_vec())),
+                               trie.iter().next());
+                },
+            }
+        };
+        for i in 0..100000 {
+            query(i, &mut state);
+        }
+        // Once the buffers have grown, nothing is allocated.
+        assert_eq!(alloc_counter::allocations(|| {
+            for i in 0..1000 {
+                let (trie, keys) = (&tries[i % 2], &keys[i % 2]);
+                let key = &keys[(i / 2) % keys.len()];
+                let mut cursor = trie.predictive_search_with(
+                    &mut state, &key[..key.len() / 2]);
+                while let Some(_) = cursor.next() {}
+                let id = trie.lookup(key).unwrap();
+                assert_eq!(trie.id_lookup_with(&mut state, id).unwrap(),
+                           &key[..]);
+            }
+        }), 0);
+
+        assert_eq!(tries[0].id_lookup_with(&mut state, tries[0].len())
+                           .unwrap_err(),
+                   MarisaError::BoundError {
+                       what: "key id", index: tries[0].len() as u64,
+                       len: tries[0].len() as u64 });
+    }
+
+    /// Every query from 8 threads at once on one dictionary gives the
+    /// answer it gives on one thread.
+    #[test]
+    fn concurrent_queries() {
+        let _ = env_logger::init();
+        let keys: Vec<String> = (0..5000u64).map(|i| {
+            format!("{:x}/{}", i.wrapping_mul(0x9E37_79B9) >> 12, i % 101)
+        }).collect();
+        let config = Config::new().with_num_tries(NumTries::new(3));
+        let trie = Arc::new(LoudsTrie::build_from(keys.iter(), &config)
+                            .unwrap());
+        let queries: Vec<Vec<u8>> = keys.iter().flat_map(|key| {
+            let key = key.as_bytes();
+            vec![key.to_vec(), key[..key.len() / 2].to_vec()]
+        }).collect();
+        let answer = |trie: &LoudsTrie, state: &mut SearchState,
+                      query: &[u8]| {
+            let id = trie.lookup(query);
+            let key = id.map(|id| trie.id_lookup_with(state, id).unwrap()
+                                      .to_vec());
+            let prefixes: Vec<(usize, usize)> =
+                trie.common_prefix_search(query).collect();
+            let mut cursor = trie.predictive_search_with(state, query);
+            let mut num_predicted = 0;
+            while let Some(_) = cursor.next() {
+                num_predicted += 1;
+            }
+            (id, key, prefixes, num_predicted)
+        };
+        let mut state = SearchState::new();
+        let expected: Vec<_> = queries.iter().map(|query| {
+            answer(&trie, &mut state, query)
+        }).collect();
+        let queries = Arc::new(queries);
+        let expected = Arc::new(expected);
+
+        let threads: Vec<_> = (0..8).map(|t| {
+            let (trie, queries) = (trie.clone(), queries.clone());
+            let expected = expected.clone();
+            thread::spawn(move || {
+                let mut state = SearchState::new();
+                // Each thread starts at a different place, so they are not
+                // all asking the same thing at once.
+                for i in 0..queries.len() {
+                    let i = (i + t * queries.len() / 8) % queries.len();
+                    assert_eq!(answer(&trie, &mut state, &queries[i]),
+                               expected[i]);
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn predictive_with_weights() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for i in 0..2000u32 {
+            let key = format!("{}", (i * 7919) % 3001);
+            keyset.push_weighted(key.as_bytes(), (i % 17) as f32);
+        }
+        for &order in &[NodeOrder::Label, NodeOrder::Weight] {
+            for &weights in &[false, true] {
+                let config = Config::new().with_node_order(order)
+                                          .with_weights(weights);
+                let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+                for q in &[&b""[..], b"1", b"29", b"300"] {
+                    let plain: Vec<_> = trie.predictive_search(q).collect();
+                    let weighted: Vec<_> = trie.predictive_search(q)
+                                               .with_weights().collect();
+                    assert_eq!(plain.len(), weighted.len());
+                    for (p, w) in plain.iter().zip(weighted.iter()) {
+                        assert_eq!((p.0, &p.1), (w.0, &w.1));
+                        assert_eq!(w.2, trie.weight(w.0));
+                        assert_eq!(w.2.is_some(), weights);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test --release -- --ignored`
+    /// logs the timings.
+    #[test]
+    #[ignore]
+    fn predictive_with_weights_bench() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for i in 0..1000000u64 {
+            let key = format!("{}", (i * 2654435761) % 1000000007);
+            keyset.push_weighted(key.as_bytes(), (i % 1000) as f32);
+        }
+        let config = Config::new().with_node_order(NodeOrder::Label)
+                                  .with_weights(true);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let queries: Vec<String> = (0..1000).map(|i| format!("{}", i))
+                                            .collect();
+
+        let start = std::time::Instant::now();
+        let mut a = 0.0;
+        for q in &queries {
+            let found: Vec<_> = trie.predictive_search(q.as_bytes())
+                                    .collect();
+            for &(id, _) in &found {
+                a += trie.weight(id).unwrap();
+            }
+        }
+        let two_pass_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut b = 0.0;
+        for q in &queries {
+            for (_, _, weight) in trie.predictive_search(q.as_bytes())
+                                      .with_weights() {
+                b += weight.unwrap();
+            }
+        }
+        let fused_time = start.elapsed();
+
+        assert_eq!(a, b);
+        info!("collect then weight(id): {:?}, with_weights: {:?}",
+              two_pass_time, fused_time);
+    }
+
+    /// Checks that at every step, size_hint brackets the number of items
+    /// left, and that the iterator stays done.
+    fn size_hints_hold<I: Iterator>(mut iter: I) -> bool {
+        let mut hints = vec![iter.size_hint()];
+        let mut count = 0;
+        while iter.next().is_some() {
+            hints.push(iter.size_hint());
+            count += 1;
+        }
+        iter.next().is_none() && iter.size_hint() == (0, Some(0))
+        && hints.iter().enumerate().all(|(i, &(lo, hi))| {
+            let left = count - i;
+            lo <= left && hi.map_or(true, |hi| left <= hi)
+        })
+    }
+
+    #[test]
+    fn iterators_size_hints_qc() {
+        let _ = env_logger::init();
+        fn prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>) -> bool {
+            let mut keyset = Keyset::new();
+            for k in &v {
+                keyset.push(k);
+            }
+            let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+            v.iter().chain(queries.iter()).all(|q| {
+                size_hints_hold(trie.common_prefix_search(q))
+                && size_hints_hold(trie.predictive_search(q))
+                && size_hints_hold(trie.predictive_search(q).with_weights())
+            })
+        }
+        qc::quickcheck(prop as fn(Vec<Vec<u8>>, Vec<Vec<u8>>) -> bool);
+    }
+
+    /// Holds the iterators by their public names.
+    struct Resumable<'a, 'b> {
+        prefixes: iter::CommonPrefixIter<'a, 'b>,
+        predictions: iter::PredictiveIter<'a, 'b>,
+        weighted: iter::WeightedPredictiveIter<'a, 'b>,
+    }
+
+    #[test]
+    fn iterators_can_be_stored() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for k in &["a", "ab", "abc", "abd", "b"] {
+            keyset.push(k.as_bytes());
+        }
+        let config = Config::new().with_node_order(NodeOrder::Label);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let mut r = Resumable {
+            prefixes: trie.common_prefix_search(b"abc"),
+            predictions: trie.predictive_search(b"ab"),
+            weighted: trie.iter().with_weights(),
+        };
+        assert_eq!(r.prefixes.next().map(|x| x.1), Some(1));
+        assert_eq!(r.predictions.next().unwrap().1, b"ab");
+        assert_eq!(r.weighted.next().unwrap().1, b"a");
+
+        let debug = format!("{:?}", r.predictions);
+        assert!(debug.starts_with("PredictiveIter"), "{}", debug);
+        assert!(!debug.contains("louds"), "{}", debug);
+
+        let rest: Vec<usize> = r.prefixes.map(|x| x.1).collect();
+        assert_eq!(rest, vec![2, 3]);
+        let rest: Vec<Vec<u8>> = r.predictions.map(|x| x.1).collect();
+        assert_eq!(rest, vec![b"abc".to_vec(), b"abd".to_vec()]);
+        assert_eq!(r.weighted.count(), 4);
+    }
+
+    /// The fixture keyset, as `(weight, key)` lines.
+    fn id_fixture() -> Keyset {
+        let mut keyset = Keyset::new();
+        for line in include_str!("../../tests/data/id_fixture.txt").lines() {
+            let mut fields = line.splitn(2, '\t');
+            let weight: f32 = fields.next().unwrap().parse().unwrap();
+            keyset.push_weighted(fields.next().unwrap().as_bytes(), weight);
+        }
+        keyset
+    }
+
+    /// The configs the golden ids were recorded under.
+    fn id_golden_configs() -> Vec<Config> {
+        vec![Config::new(),
+             Config::new().with_num_tries(NumTries::new(1))
+                          .with_node_order(NodeOrder::Label),
+             Config::new().with_num_tries(NumTries::new(5))
+                          .with_node_order(NodeOrder::Label)
+                          .with_tail_mode(TailMode::Binary),
+             Config::new().with_num_tries(NumTries::new(2))
+                          .with_cache_level(CacheLevel::Huge)
+                          .with_weights(true)]
+    }
+
+    /// One line per fixture key, with its id under each golden config.
+    fn id_golden_lines() -> Vec<String> {
+        let mut keyset = id_fixture();
+        let ids: Vec<Vec<usize>> = id_golden_configs().iter().map(|config| {
+            LoudsTrie::build(&mut keyset, config).unwrap();
+            (0..keyset.len()).map(|i| keyset.id(i).unwrap()).collect()
+        }).collect();
+        (0..keyset.len()).map(|i| {
+            ids.iter().map(|v| v[i].to_string()).collect::<Vec<_>>()
+               .join("\t")
+        }).collect()
+    }
+
+    /// Fails when a change to the build assigns different ids. If that is
+    /// intended, bump ID_ALGORITHM_VERSION and regenerate the golden file
+    /// with `cargo test print_id_golden -- --ignored --nocapture`.
+    #[test]
+    fn ids_match_golden() {
+        let _ = env_logger::init();
+        let golden = include_str!("../../tests/data/id_golden.txt");
+        let mut lines = golden.lines();
+        assert_eq!(lines.next(),
+                   Some(&format!("# ID_ALGORITHM_VERSION {}",
+                                 ID_ALGORITHM_VERSION)[..]),
+                   "the golden ids are for another ID_ALGORITHM_VERSION");
+        let expected: Vec<&str> = lines.collect();
+        let actual = id_golden_lines();
+        assert_eq!(expected.len(), actual.len());
+        for (i, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+            assert!(e == a, "id assignment changed for fixture key {}: \
+                             expected {}, got {}; bump \
+                             ID_ALGORITHM_VERSION", i, e, a);
+        }
+        let trie = LoudsTrie::build(&mut id_fixture(), &Config::new()).unwrap();
+        assert_eq!(trie.id_algorithm_version(), ID_ALGORITHM_VERSION);
+    }
+
+    #[test]
+    #[ignore]
+    fn print_id_golden() {
+        println!("# ID_ALGORITHM_VERSION {}", ID_ALGORITHM_VERSION);
+        for line in id_golden_lines() {
+            println!("{}", line);
+        }
+    }
+
+    #[test]
+    fn node_orders_agree() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for i in 0..3000 {
+            let key = format!("{}/{}", i % 17, i * 31);
+            // Later keys are heavier, so weight order reverses siblings.
+            keyset.push_weighted(key.as_bytes(), i as f32);
+        }
+        for &n in &[1, 2, 3] {
+            let build = |keyset: &mut Keyset, order| {
+                let config = Config::new().with_num_tries(NumTries::new(n))
+                                          .with_node_order(order);
+                LoudsTrie::build(keyset, &config).unwrap()
+            };
+            let by_label = build(&mut keyset, NodeOrder::Label);
+            let label_ids: Vec<Option<usize>> =
+                (0..keyset.len()).map(|i| keyset.id(i)).collect();
+            let by_weight = build(&mut keyset, NodeOrder::Weight);
+
+            let queries: Vec<Vec<u8>> = keyset.iter()
+                .flat_map(|k| vec![k.to_vec(), k[..k.len() - 1].to_vec()])
+                .collect();
+            for q in &queries {
+                assert_eq!(by_label.lookup(q).is_some(),
+                           by_weight.lookup(q).is_some());
+            }
+            for i in 0..keyset.len() {
+                assert_eq!(by_label.lookup(keyset.key(i)), label_ids[i]);
+                assert_eq!(by_weight.lookup(keyset.key(i)), keyset.id(i));
+            }
+
+            let mut sorted: Vec<Vec<u8>> = keyset.iter()
+                .map(|k| k.to_vec()).collect();
+            sorted.sort();
+            let label_keys: Vec<Vec<u8>> = by_label.iter()
+                .map(|(_, key)| key).collect();
+            assert_eq!(label_keys, sorted);
+            let mut weight_keys: Vec<Vec<u8>> = by_weight.iter()
+                .map(|(_, key)| key).collect();
+            assert!(weight_keys != sorted);
+            weight_keys.sort();
+            assert_eq!(weight_keys, sorted);
+        }
+    }
+
+    fn nul_keys() -> Vec<Vec<u8>> {
+        let mut v: Vec<Vec<u8>> = Vec::new();
+        for n in 1..6 {
+            v.push(vec![0; n]);
+        }
+        for i in 0..300 {
+            let body = format!("key-{}-with-a-long-shared-suffix", i * 7)
+                       .into_bytes();
+            for &pos in &[0, body.len() / 3, body.len() / 2, body.len()] {
+                let mut k = body.clone();
+                k.insert(pos, 0);
+                v.push(k);
+            }
+            // Keys that only differ in a NUL deep inside a link label.
+            let mut k = body.clone();
+            k[body.len() - 3] = 0;
+            v.push(k);
+            v.push(body);
+        }
+        v
+    }
+
+    #[test]
+    fn nul_bytes_at_every_depth() {
+        let _ = env_logger::init();
+        let keys = nul_keys();
+        let mut keyset = Keyset::new();
+        for k in &keys {
+            keyset.push(k);
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+        sorted.dedup();
+        for &mode in &[TailMode::Text, TailMode::Binary] {
+            for &n in &[1, 2, 3] {
+                let config = Config::new().with_num_tries(NumTries::new(n))
+                                          .with_tail_mode(mode)
+                                          .with_node_order(NodeOrder::Label);
+                let mut trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+                trie.set_paranoia(true);
+                assert_eq!(trie.config().num_tries().get(), n);
+                // With one trie the NULs reach the tail, which must fall back
+                // to binary. With more, they can end up in the bases of a
+                // next trie instead, and a text tail is kept.
+                if n == 1 {
+                    assert_eq!(trie.config().tail_mode(), TailMode::Binary);
+                }
+                assert_eq!(trie.len(), sorted.len());
+                for i in 0..keyset.len() {
+                    let id = keyset.id(i).unwrap();
+                    assert_eq!(trie.lookup(keyset.key(i)), Some(id));
+                    assert_eq!(trie.id_lookup(id).unwrap(), keyset.key(i));
+                }
+                assert_eq!(trie.lookup(&[0; 6]), None);
+                assert_eq!(trie.lookup(b"key-0-with-a-long-shared-suffi\0"),
+                           None);
+                let all: Vec<Vec<u8>> = trie.iter().map(|(_, k)| k).collect();
+                assert_eq!(all, sorted);
+                let nuls: Vec<Vec<u8>> = trie.predictive_search(&[0, 0, 0])
+                    .map(|(_, k)| k).collect();
+                assert_eq!(nuls, vec![vec![0; 3], vec![0; 4], vec![0; 5]]);
+            }
+        }
+    }
+
+    #[test]
+    fn weights_are_summed_and_kept() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        for _ in 0..5 {
+            keyset.push(b"often");
+        }
+        keyset.push_weighted(b"once", 2.5);
+        keyset.push_weighted(b"rarely", 0.5);
+        keyset.push_weighted(b"rarely", 0.25);
+
+        for &n in &[1, 2, 3] {
+            let config = Config::new().with_num_tries(NumTries::new(n))
+                                      .with_weights(true);
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            assert!(trie.config().weights());
+            assert_eq!(trie.weight(keyset.id(0).unwrap()), Some(5.0));
+            assert_eq!(trie.lookup_with_weight(b"once"),
+                       Some((keyset.id(5).unwrap(), Some(2.5))));
+            assert_eq!(trie.lookup_with_weight(b"rarely"),
+                       Some((keyset.id(6).unwrap(), Some(0.75))));
+            assert_eq!(trie.lookup_with_weight(b"never"), None);
+            // The summed weight of "often" outweighs "once" and puts it
+            // first among the root's children.
+            assert_eq!(trie.iter().next().unwrap().1, b"often");
+
+            let without = LoudsTrie::build(&mut keyset,
+                                           &config.with_weights(false))
+                          .unwrap();
+            assert!(!without.config().weights());
+            assert_eq!(without.weight(keyset.id(0).unwrap()), None);
+            assert_eq!(without.total_size() + 3 * 4, trie.total_size());
+        }
+    }
+
+    /// Builds every select table on every bit vector, as if the build did
+    /// not know which ones the queries use.
+    fn build_all_tables(trie: &mut LoudsTrie) {
+        trie.louds_.build_index(true, true);
+        trie.terminal_flags_.build_index(true, true);
+        trie.link_flags_.build_index(true, true);
+        if let Some(ref mut next) = trie.next_trie_ {
+            build_all_tables(next);
+        }
+    }
+
+    #[test]
+    fn minimal_select_tables() {
+        let _ = env_logger::init();
+        let mut x: u64 = 1;
+        let mut keyset = Keyset::new();
+        for _ in 0..200000 {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let len = 1 + (x >> 59) as usize;
+            let key: Vec<u8> = (0..len).map(|i| b'a' + ((x >> (i * 2)) % 16)
+                                                      as u8).collect();
+            keyset.push(&key);
+        }
+
+        let mut all = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let minimal = all.total_size();
+        build_all_tables(&mut all);
+        let config = Config::new().with_reverse_lookup(false);
+        let no_reverse = LoudsTrie::build(&mut keyset, &config).unwrap();
+        debug!("all tables: {}, minimal: {}, without reverse lookup: {}",
+               all.total_size(), minimal, no_reverse.total_size());
+        assert!(all.total_size() > minimal);
+        assert!(minimal > no_reverse.total_size());
+
+        assert!(!no_reverse.config().reverse_lookup());
+        for i in 0..keyset.len() {
+            let id = keyset.id(i).unwrap();
+            assert_eq!(no_reverse.lookup(keyset.key(i)), Some(id));
+            assert_eq!(all.id_lookup(id).unwrap(), keyset.key(i));
+        }
+    }
+
+    #[test]
+    fn id_lookup_needs_reverse_lookup() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        keyset.push(b"a");
+        let config = Config::new().with_reverse_lookup(false);
+        let mut trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        trie.set_paranoia(true);
+        assert_eq!(trie.lookup(b"a"), Some(0));
+        match trie.id_lookup(0) {
+            Err(MarisaError::StateError { what }) =>
+                assert!(what.contains("built without reverse lookup")),
+            x => panic!("{:?}", x),
+        }
+    }
+
+    /// Each kind of error the public API returns, from the call that
+    /// returns it.
+    #[test]
+    #[cfg(feature = "std")]
+    fn error_variants_are_reachable() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        assert_eq!(checked_u32(1 << 32, "keys"),
+                   Err(MarisaError::SizeError { what: "keys",
+                                                limit: std::u32::MAX as u64,
+                                                actual: 1 << 32 }));
+        assert_eq!(trie.id_lookup(trie.len()),
+                   Err(MarisaError::BoundError { what: "key id",
+                                                 index: trie.len() as u64,
+                                                 len: trie.len() as u64 }));
+        assert_eq!(Config::parse(0x4000).unwrap_err(),
+                   MarisaError::CodeError { what: "tail mode", code: 0x4000 });
+        assert_eq!(Config::parse(1 << 31).unwrap_err(),
+                   MarisaError::CodeError { what: "config flags",
+                                            code: 1 << 31 });
+        let bytes = write_bytes(&trie);
+        assert_eq!(LoudsTrie::read_from(&mut &bytes[..bytes.len() - 1])
+                   .unwrap_err(),
+                   MarisaError::FormatError { what: "truncated input" });
+        let config = Config::new().with_reverse_lookup(false);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        match trie.id_lookup(0) {
+            Err(MarisaError::StateError { .. }) => {},
+            x => panic!("{:?}", x),
+        }
+        match LoudsTrie::load("/nonexistent/mars-trie.dic") {
+            Err(MarisaError::Io(ref e))
+                if e.kind() == std::io::ErrorKind::NotFound => {},
+            x => panic!("{:?}", x.map(|_| ())),
+        }
+        let err = MarisaError::Io(std::io::Error::new(
+            std::io::ErrorKind::Other, "disk on fire"));
+        assert_eq!(err.to_string(), "MARISA_IO_ERROR: disk on fire");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "paranoia: lookup(\"a\") returned id")]
+    fn paranoia_catches_bad_cache() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        keyset.push(b"a");
+        keyset.push(b"b");
+        let mut trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        // Point the cache entry for (root, 'a') at the node for "b".
+        let from = trie.get_cache_id_with_label(0, b'a');
+        let to = trie.get_cache_id_with_label(0, b'b');
+        trie.cache_[from] = trie.cache_[to];
+        trie.cache_[from].set_parent(0);
+        assert_eq!(trie.lookup(b"a"), keyset.id(1));
+
+        trie.set_paranoia(true);
+        trie.lookup(b"a");
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn paranoia_is_free_in_release() {
+        assert_eq!(std::mem::size_of::<super::Paranoia>(), 0);
+    }
+
+    fn shared_prefix_keys(prefix_len: usize) -> Vec<Vec<u8>> {
+        let prefix: Vec<u8> = (0..prefix_len).map(|i| b'a' + (i % 26) as u8)
+                              .collect();
+        (0..20000).map(|i| {
+            let mut k = prefix.clone();
+            k.extend(format!("{}/{}", i % 97, i * 13).as_bytes());
+            k
+        }).collect()
+    }
 
-void LoudsTrie::read_(Reader &reader) {
-  louds_.read(reader);
-  terminal_flags_.read(reader);
-  link_flags_.read(reader);
-  bases_.read(reader);
-  extras_.read(reader);
-  tail_.read(reader);
-  if ((link_flags_.num_1s() != 0) && tail_.empty()) {
-    next_trie_.reset(new (std::nothrow) LoudsTrie);
-    MARISA_THROW_IF(next_trie_.get() == NULL, MARISA_MEMORY_ERROR);
-    next_trie_->read_(reader);
-  }
-  cache_.read(reader);
-  cache_mask_ = cache_.size() - 1;
-  {
-    u32 temp_num_l1_nodes;
-    reader.read(&temp_num_l1_nodes);
-    num_l1_nodes_ = temp_num_l1_nodes;
-  }
-  {
-    u32 temp_config_flags;
-    reader.read(&temp_config_flags);
-    config_.parse((int)temp_config_flags);
-  }
-}
+    #[test]
+    fn long_shared_prefix_is_one_link() {
+        let _ = env_logger::init();
+        let build = |keys: &[Vec<u8>]| {
+            let mut keyset = Keyset::new();
+            for k in keys {
+                keyset.push(k);
+            }
+            LoudsTrie::build(&mut keyset, &Config::new()).unwrap()
+        };
+        let short = shared_prefix_keys(0);
+        let long = shared_prefix_keys(1024);
+        let short_trie = build(&short);
+        let long_trie = build(&long);
+        // The shared prefix is stripped and stored once, so the trie is
+        // the same shape with or without it.
+        assert_eq!(long_trie.common_prefix_.len(), 1024);
+        assert_eq!(long_trie.num_nodes(), short_trie.num_nodes());
+        assert_eq!(long_trie.lookup(&long[0][..1024]), None);
+        assert_eq!(long_trie.lookup(&short[0]), None);
+        assert_eq!(long_trie.longest_prefix_match(&short[0]), None);
+        for k in long.iter().take(100) {
+            let id = long_trie.lookup(k).unwrap();
+            assert_eq!(&long_trie.id_lookup(id).unwrap(), k);
+            assert_eq!(long_trie.longest_prefix_match(k), Some((id, k.len())));
+            assert_eq!(long_trie.common_prefix_search(k).last(),
+                       Some((id, k.len())));
+        }
+        assert_eq!(long_trie.predictive_search(&long[0][..10]).count(),
+                   long.len());
+        assert_eq!(long_trie.predictive_search(b"b").count(), 0);
+        assert_eq!(long_trie.predictive_search(&long[5]).next().unwrap().1,
+                   long[5]);
 
-void LoudsTrie::write_(Writer &writer) const {
-  louds_.write(writer);
-  terminal_flags_.write(writer);
-  link_flags_.write(writer);
-  bases_.write(writer);
-  extras_.write(writer);
-  tail_.write(writer);
-  if (next_trie_.get() != NULL) {
-    next_trie_->write_(writer);
-  }
-  cache_.write(writer);
-  writer.write((u32)num_l1_nodes_);
-  writer.write((u32)config_.flags());
-}
-*/
+        let per_query = |trie: &LoudsTrie, keys: &[Vec<u8>]| {
+            count_select0(|| {
+                for k in keys {
+                    assert!(trie.lookup(k).is_some());
+                }
+            }) as f64 / keys.len() as f64
+        };
+        assert!(per_query(&long_trie, &long)
+                <= per_query(&short_trie, &short) + 1.0);
+    }
 
-#[cfg(test)]
-mod test {
-    use env_logger;
-    use config::{Config, MAX_NUM_TRIES, MIN_NUM_TRIES, NumTries};
-    use key::Key;
-    use key::IKey;
-    use quickcheck as qc;
-    use std;
-    use std::default::Default;
-    use super::LoudsTrie;
+    #[test]
+    fn huge_queries_stop_where_descent_dies() {
+        let _ = env_logger::init();
+        let words = ["a", "aa", "aaa", "ab", "abracadabra", "b"];
+        let mut huge = vec![b'a'; 10 << 20];
+        for &n in &[1, 2, 3] {
+            let mut keyset = Keyset::new();
+            for s in &words {
+                keyset.push(s.as_bytes());
+            }
+            let config = Config::new().with_num_tries(NumTries::new(n));
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            let max_len = words.iter().map(|w| w.len()).max().unwrap();
 
-    fn build_prop(v: Vec<String>, num_tries: NumTries) -> qc::TestResult {
-        if v.iter().any(|x| x.is_empty()) {
-            return qc::TestResult::discard();
+            // Each descent costs at most one select0, so the work done is
+            // bounded by the longest key, not by the 10 MB query.
+            let mut found = Vec::new();
+            let calls = count_select0(|| {
+                assert_eq!(trie.lookup(&huge), None);
+                found = trie.common_prefix_search(&huge).collect();
+                assert_eq!(trie.longest_prefix_match(&huge),
+                           Some((keyset.id(2).unwrap(), 3)));
+                assert_eq!(trie.predictive_search(&huge).next(), None);
+            });
+            assert!(calls <= 4 * (max_len + 1), "{} select0 calls", calls);
+            assert_eq!(found, vec![(keyset.id(0).unwrap(), 1),
+                                   (keyset.id(1).unwrap(), 2),
+                                   (keyset.id(2).unwrap(), 3)]);
+
+            // The query diverges inside the tail of "abracadabra".
+            huge[..8].copy_from_slice(b"abracada");
+            let calls = count_select0(|| {
+                assert_eq!(trie.lookup(&huge), None);
+                assert_eq!(trie.common_prefix_search(&huge).count(), 2);
+            });
+            assert!(calls <= 2 * (max_len + 1), "{} select0 calls", calls);
+            huge[..8].copy_from_slice(b"aaaaaaaa");
         }
-        let mut keys: Vec<Key> = v.iter().map(|s| Key::new(s.as_bytes()))
-                                 .collect();
-        let config = Config::new().with_num_tries(num_tries);
-        let trie = LoudsTrie::build(&mut keys, &config);
-        let mut ids_seen = Vec::new();
-        for key in keys {
-            ids_seen.push(key.get_id());
-            let s = trie.id_lookup(key.get_id());
-            if !s.iter().eq(key.get_slice().iter()) {
-                return qc::TestResult::failed();
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test -- --ignored` logs the
+    /// timings.
+    #[test]
+    #[ignore]
+    fn long_shared_prefix_bench() {
+        let _ = env_logger::init();
+        for &prefix_len in &[0, 64, 1024] {
+            let keys = shared_prefix_keys(prefix_len);
+            let mut keyset = Keyset::new();
+            for k in &keys {
+                keyset.push(k);
             }
+            let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+            let start = std::time::Instant::now();
+            for _ in 0..5 {
+                for k in &keys {
+                    assert!(trie.lookup(k).is_some());
+                }
+            }
+            let elapsed = start.elapsed();
+            info!("prefix {:4}: {} nodes, {:?} for {} lookups", prefix_len,
+                  trie.num_nodes(), elapsed, 5 * keys.len());
         }
-        ids_seen.sort();
-        for (&a, b) in ids_seen.iter().zip(0..ids_seen.len()) {
-            if a != b { return qc::TestResult::failed(); }
+    }
+
+    const CACHE_LEVELS: [CacheLevel; 5] = [CacheLevel::Huge, CacheLevel::Large,
+                                           CacheLevel::Normal, CacheLevel::Small,
+                                           CacheLevel::Tiny];
+
+    fn cache_levels_prop(v: Vec<Vec<u8>>, others: Vec<Vec<u8>>,
+                         num_tries: NumTries) -> bool {
+        let mut keyset = Keyset::new();
+        for (i, k) in v.iter().enumerate() {
+            keyset.push_weighted(k, (i % 7) as f32);
         }
-        qc::TestResult::passed()
+        let build = |keyset: &mut Keyset, level| {
+            let config = Config::new().with_num_tries(num_tries)
+                                      .with_cache_level(level);
+            LoudsTrie::build(keyset, &config).unwrap()
+        };
+        let queries: Vec<&[u8]> = v.iter().chain(others.iter())
+                                   .map(|q| &q[..]).collect();
+        let expected: Vec<Option<usize>> = {
+            let trie = build(&mut keyset, CacheLevel::Huge);
+            queries.iter().map(|q| trie.lookup(q)).collect()
+        };
+        CACHE_LEVELS.iter().all(|&level| {
+            let trie = build(&mut keyset, level);
+            queries.iter().map(|q| trie.lookup(q)).eq(expected.iter().cloned())
+            && (0..keyset.len()).all(|i| {
+                trie.id_lookup(keyset.id(i).unwrap()).unwrap() == keyset.key(i)
+            })
+        })
     }
 
     #[test]
-    fn louds_trie_build_qc() {
+    fn cache_levels_agree_qc() {
         let _ = env_logger::init();
-        qc::quickcheck(build_prop as fn(Vec<String>, NumTries)
-                       -> qc::TestResult);
+        qc::quickcheck(cache_levels_prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, NumTries) -> bool);
     }
 
     #[test]
-    fn louds_trie_build_manual() {
+    fn cache_levels_agree_manual() {
         let _ = env_logger::init();
-        let n = NumTries::default();
-        assert!(!build_prop(vec!["\u{80}".to_string()], n).is_failure());
-        assert!(!build_prop(vec!["\u{4b8ca}".to_string(),
-                                 "\u{d2c4a}".to_string()], n).is_failure());
+        let mut v: Vec<Vec<u8>> = Vec::new();
+        for i in 0..5000 {
+            v.push(format!("{}/{}/page-{}", i % 13, i % 101, i).into_bytes());
+        }
+        let others: Vec<Vec<u8>> = v.iter()
+            .map(|k| k[..k.len() - 1].to_vec()).collect();
+        for &n in &[1, 2, 3] {
+            assert!(cache_levels_prop(v.clone(), others.clone(),
+                                      NumTries::new(n)));
+        }
+    }
+
+    fn count_select0<F: FnOnce()>(f: F) -> usize {
+        use vector::bit_vec::SELECT0_CALLS;
+        let before = SELECT0_CALLS.with(|n| n.get());
+        f();
+        SELECT0_CALLS.with(|n| n.get()) - before
+    }
+
+    /// A 3-trie dictionary whose caches have a few hundred entries each.
+    fn cached_trie() -> (Keyset, LoudsTrie) {
+        let mut keyset = Keyset::new();
+        for i in 0..30000u64 {
+            let key = format!("{}/{}", (i * 7919) % 1009, i * 2654435761);
+            keyset.push(key.as_bytes());
+        }
+        let config = Config::new().with_cache_level(CacheLevel::Huge);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        (keyset, trie)
+    }
+
+    fn used_cache_ids(trie: &LoudsTrie) -> Vec<usize> {
+        (0..trie.cache_.len()).filter(|&i| {
+            trie.cache_[i].child() != std::u32::MAX
+        }).collect()
+    }
+
+    /// The `how`th way to break a cache entry of a trie with `num_nodes`
+    /// nodes.
+    fn corrupt(c: &mut Cache, num_nodes: u32, how: usize) {
+        match how {
+            0 => c.set_child(num_nodes + 5),
+            1 => c.set_child(0),
+            2 => { let x = c.child(); c.set_child(x + 1) },
+            3 => { let x = c.parent(); c.set_parent(x + 1) },
+            4 => { let x = c.base(); c.set_base(x ^ 1) },
+            _ => c.set_extra(0x123),
+        }
+    }
+
+    #[test]
+    fn validate_catches_bad_cache() {
+        let _ = env_logger::init();
+        let (keyset, mut trie) = cached_trie();
+        assert_eq!(trie.validate(), Ok(()));
+        let root_ids = used_cache_ids(&trie);
+        let next_ids = used_cache_ids(trie.next_trie_.as_ref().unwrap());
+        assert!(root_ids.len() >= 20 && next_ids.len() >= 20);
+
+        for how in 0..6 {
+            for &in_next in &[false, true] {
+                for i in 0..20 {
+                    let (id, saved) = {
+                        let (t, ids) = if in_next {
+                            (&mut **trie.next_trie_.as_mut().unwrap(),
+                             &next_ids)
+                        } else {
+                            (&mut trie, &root_ids)
+                        };
+                        let id = ids[i * ids.len() / 20];
+                        let saved = t.cache_[id];
+                        let n = t.num_nodes() as u32;
+                        corrupt(&mut t.cache_[id], n, how);
+                        (id, saved)
+                    };
+                    assert!(trie.validate().is_err(), "how {}", how);
+                    // Without validation, queries may go wrong but must
+                    // not read out of bounds.
+                    let _ = std::panic::catch_unwind(
+                        std::panic::AssertUnwindSafe(|| {
+                            for k in keyset.iter() {
+                                trie.lookup(k);
+                            }
+                        }));
+                    if in_next {
+                        trie.next_trie_.as_mut().unwrap().cache_[id] = saved;
+                    } else {
+                        trie.cache_[id] = saved;
+                    }
+                }
+            }
+        }
+        assert_eq!(trie.validate(), Ok(()));
+
+        // A real edge filed under another slot is as bad as a wrong edge.
+        trie.cache_.swap(root_ids[0], root_ids[1]);
+        assert!(trie.validate().is_err());
+    }
+
+    #[test]
+    fn bigger_cache_skips_louds() {
+        let _ = env_logger::init();
+        // A few hot keys with high weights, and a long tail of cold ones.
+        let keys: Vec<String> = (0..100000).map(|i| {
+            format!("{}", (i as u64 * 2654435761) % 1000000007)
+        }).collect();
+        let hot = 2000;
+        let mut keyset = Keyset::new();
+        for (i, k) in keys.iter().enumerate() {
+            keyset.push_weighted(k.as_bytes(),
+                                 if i < hot { 1000.0 } else { 1.0 });
+        }
+        let queries: Vec<&[u8]> = (0..50000).map(|i| {
+            keys[if i % 10 == 0 { i % keys.len() } else { i % hot }]
+                .as_bytes()
+        }).collect();
+
+        let mut counts = Vec::new();
+        for &level in &[CacheLevel::Huge, CacheLevel::Tiny] {
+            let config = Config::new().with_cache_level(level);
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            counts.push(count_select0(|| {
+                for q in &queries {
+                    assert!(trie.lookup(q).is_some());
+                }
+            }));
+        }
+        assert!(counts[0] < counts[1],
+                "Huge: {} select0 calls, Tiny: {}", counts[0], counts[1]);
+    }
+
+    /// What `write_to` writes, with or without std.
+    fn write_bytes(trie: &LoudsTrieView) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        trie.write_to_(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Dictionaries covering every part of the format: next tries, text and
+    /// binary tails, weights, a common prefix and no reverse lookup.
+    fn io_tries() -> Vec<(Keyset, LoudsTrie)> {
+        let mut out = Vec::new();
+        for config in id_golden_configs() {
+            let mut keyset = id_fixture();
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            out.push((keyset, trie));
+        }
+        let mut nul = Keyset::new();
+        for k in &nul_keys() {
+            nul.push(k);
+        }
+        let config = Config::new().with_num_tries(NumTries::new(1))
+                                  .with_reverse_lookup(false);
+        let trie = LoudsTrie::build(&mut nul, &config).unwrap();
+        out.push((nul, trie));
+        let mut shared = Keyset::new();
+        for k in &shared_prefix_keys(100) {
+            shared.push(k);
+        }
+        let trie = LoudsTrie::build(&mut shared, &Config::new()).unwrap();
+        assert!(!trie.common_prefix_.is_empty());
+        out.push((shared, trie));
+        let mut empty = Keyset::new();
+        let trie = LoudsTrie::build(&mut empty, &Config::new()).unwrap();
+        out.push((empty, trie));
+        out
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_round_trip() {
+        let _ = env_logger::init();
+        for (keyset, trie) in io_tries() {
+            let bytes = write_bytes(&trie);
+            let mut cursor = std::io::Cursor::new(&bytes[..]);
+            let read = LoudsTrie::read_from(&mut cursor).unwrap();
+            assert_eq!(cursor.position() as usize, bytes.len());
+            assert_eq!(write_bytes(&read), bytes);
+
+            let all: Vec<(usize, Vec<u8>)> = trie.iter().collect();
+            assert_eq!(read.iter().collect::<Vec<_>>(), all);
+            assert_eq!(read.config().flags(), trie.config().flags());
+            assert_eq!(read.config().cache_level(),
+                       trie.config().cache_level());
+            assert_eq!(read.id_algorithm_version(), ID_ALGORITHM_VERSION);
+            assert_eq!(read.total_size(), trie.total_size());
+            for i in 0..keyset.len() {
+                let id = keyset.id(i).unwrap();
+                assert_eq!(read.lookup(keyset.key(i)), Some(id));
+                assert_eq!(read.weight(id), trie.weight(id));
+                if read.config().reverse_lookup() {
+                    assert_eq!(read.id_lookup(id).unwrap(), keyset.key(i));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_rejects_truncated_and_garbage() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let config = Config::new().with_weights(true);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let bytes = write_bytes(&trie);
+        for len in 0..bytes.len() {
+            assert!(LoudsTrie::read_from(&mut &bytes[..len]).is_err(),
+                    "read {} of {} bytes", len, bytes.len());
+        }
+        // The checksum catches every flipped byte. Without it, a flipped
+        // byte may still be a valid dictionary, but reading it must not
+        // panic, and neither must a full enumeration of what was accepted.
+        for i in 0..bytes.len() {
+            let mut bad = bytes.clone();
+            bad[i] ^= 0xA5;
+            assert!(LoudsTrie::read_from(&mut &bad[..]).is_err(),
+                    "flipped byte {} of {}", i, bytes.len());
+            if let Ok(trie) = LoudsTrie::read_from_unchecked(&mut &bad[..]) {
+                assert!(trie.iter().count() <= trie.len());
+            }
+        }
+        let garbage: Vec<u8> = (0..4096u32).map(|i| {
+            (i.wrapping_mul(2654435761) >> 13) as u8
+        }).collect();
+        assert!(LoudsTrie::read_from(&mut &garbage[..]).is_err());
+        let mut header_then_garbage = bytes[..Header::io_size()].to_vec();
+        header_then_garbage.extend_from_slice(&garbage);
+        assert!(LoudsTrie::read_from(&mut &header_then_garbage[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_rejects_huge_lengths_without_allocating() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let native = write_bytes(&trie);
+        let mut libmarisa = Vec::new();
+        trie.write_libmarisa(&mut libmarisa).unwrap();
+        // The louds length, the common prefix length and the number of
+        // weights, then libmarisa's louds size in bytes.
+        let n = native.len();
+        let fields = [(&native, Header::io_size(), false),
+                      (&native, n - 16, false), (&native, n - 8, false),
+                      (&libmarisa, 16, true)];
+        for &(bytes, pos, is_libmarisa) in &fields {
+            for &len in &[5u64 << 30, 1 << 40, std::u64::MAX] {
+                let mut bad = bytes.clone();
+                for i in 0..8 {
+                    bad[pos + i] = (len >> (8 * i)) as u8;
+                }
+                let allocated = alloc_counter::bytes_allocated(|| {
+                    let err = if is_libmarisa {
+                        LoudsTrie::read_libmarisa(&mut &bad[..])
+                    } else {
+                        LoudsTrie::read_from(&mut &bad[..])
+                    }.map(|_| ()).unwrap_err();
+                    match err {
+                        MarisaError::FormatError { .. } => {},
+                        _ => panic!("{:?}", err),
+                    }
+                });
+                assert!(allocated < 1 << 20, "{} bytes at {}", allocated, pos);
+            }
+        }
+    }
+
+    /// Takes the view type, so owned and mapped dictionaries both do.
+    fn all_keys(trie: &LoudsTrieView) -> Vec<(usize, Vec<u8>)> {
+        trie.predictive_search(b"").collect()
+    }
+
+    #[test]
+    fn map_from_slice_round_trip() {
+        let _ = env_logger::init();
+        for (keyset, trie) in io_tries() {
+            let bytes = write_bytes(&trie);
+            let buf = AlignedBytes::from_slice(&bytes);
+            let view = LoudsTrie::map_from_slice(&buf).unwrap();
+            let unchecked = LoudsTrie::map_from_slice_unchecked(&buf).unwrap();
+            assert_eq!(view.is_mapped(), cfg!(target_endian = "little"));
+            assert_eq!(unchecked.is_mapped(), view.is_mapped());
+            assert_eq!(all_keys(&unchecked), all_keys(&trie));
+            assert!(!trie.is_mapped());
+            assert_eq!(write_bytes(&view), bytes);
+            assert_eq!(all_keys(&view), all_keys(&trie));
+            assert_eq!(view.config().flags(), trie.config().flags());
+            assert_eq!(view.total_size(), trie.total_size());
+            for i in 0..keyset.len() {
+                let id = keyset.id(i).unwrap();
+                assert_eq!(view.lookup(keyset.key(i)), Some(id));
+                assert_eq!(view.weight(id), trie.weight(id));
+                if view.config().reverse_lookup() {
+                    assert_eq!(view.id_lookup(id).unwrap(), keyset.key(i));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn map_from_slice_rejects_misaligned() {
+        let _ = env_logger::init();
+        for (_, trie) in io_tries() {
+            let bytes = write_bytes(&trie);
+            for shift in 1..8 {
+                let mut buf = AlignedBytes::zeroed(shift + bytes.len());
+                buf[shift..].copy_from_slice(&bytes);
+                let expected = MarisaError::FormatError { what: "alignment" };
+                assert_eq!(LoudsTrie::map_from_slice(&buf[shift..])
+                               .unwrap_err(), expected);
+                assert_eq!(LoudsTrie::map_from_slice_unchecked(&buf[shift..])
+                               .unwrap_err(), expected);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn readers_go_by_the_section_table() {
+        let _ = env_logger::init();
+        let u64_at = |bytes: &[u8], pos: usize| {
+            bytes[pos..pos + 8].iter().rev()
+                               .fold(0, |x, &b| (x << 8) | b as u64)
+        };
+        let add_to_u64 = |bytes: &mut Vec<u8>, pos: usize, n: u64| {
+            let x = u64_at(bytes, pos) + n;
+            for i in 0..8 {
+                bytes[pos + i] = (x >> (8 * i)) as u8;
+            }
+        };
+        // The magic string, the format and id versions and the flags.
+        let payload_len_at = 16 + 4 + 4 + 4;
+        let start = Header::io_size();
+        for (_, trie) in io_tries() {
+            let bytes = write_bytes(&trie);
+            let num_sections = u64_at(&bytes, start) as usize;
+            // Eight more zeros in front of each section in turn.
+            for i in 0..num_sections {
+                let offset = u64_at(&bytes, start + 8 + 8 * i) as usize;
+                let mut moved = bytes.clone();
+                for j in i..num_sections {
+                    add_to_u64(&mut moved, start + 8 + 8 * j, 8);
+                }
+                add_to_u64(&mut moved, payload_len_at, 8);
+                let rest = moved.split_off(start + offset);
+                moved.extend(&[0u8; 8]);
+                moved.extend(rest);
+                let read = LoudsTrie::read_from_unchecked(&mut &moved[..])
+                           .unwrap();
+                let buf = AlignedBytes::from_slice(&moved);
+                let view = LoudsTrie::map_from_slice_unchecked(&buf).unwrap();
+                assert_eq!(all_keys(&read), all_keys(&trie));
+                assert_eq!(all_keys(&view), all_keys(&trie));
+                assert_eq!(view.is_mapped(), cfg!(target_endian = "little"));
+                // Padding that is not zero is damage.
+                moved[start + offset] = 1;
+                let buf = AlignedBytes::from_slice(&moved);
+                assert_eq!(LoudsTrie::map_from_slice_unchecked(&buf)
+                               .unwrap_err(),
+                           MarisaError::FormatError {
+                               what: "section padding" });
+            }
+        }
+    }
+
+    /// tests/data/format_v2.dic, as format version 2 wrote it, and its keys
+    /// as `id\tweight\tkey` lines. It was built with weights, a binary
+    /// tail, weight order, three tries and a common prefix.
+    const FORMAT_V2: &'static [u8] =
+        include_bytes!("../../tests/data/format_v2.dic");
+    const FORMAT_V2_KEYS: &'static str =
+        include_str!("../../tests/data/format_v2_keys.txt");
+
+    fn has_format_v2_keys(trie: &LoudsTrieView) {
+        let mut num_keys = 0;
+        for line in FORMAT_V2_KEYS.lines() {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            let id: usize = fields[0].parse().unwrap();
+            let weight: f32 = fields[1].parse().unwrap();
+            assert_eq!(trie.lookup(fields[2].as_bytes()), Some(id));
+            assert_eq!(trie.weight(id), Some(weight));
+            assert_eq!(trie.id_lookup(id).unwrap(), fields[2].as_bytes());
+            num_keys += 1;
+        }
+        assert_eq!(trie.len(), num_keys);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn reads_and_maps_format_version_2() {
+        let _ = env_logger::init();
+        assert_eq!(Header::map(FORMAT_V2).unwrap().0.version(), 2);
+        let read = LoudsTrie::read_from(&mut &FORMAT_V2[..]).unwrap();
+        has_format_v2_keys(&read);
+        assert_eq!(read.config().num_tries().get(), 3);
+        assert!(read.config().weights());
+        let aligned = AlignedBytes::from_slice(FORMAT_V2);
+        let mut shifted = AlignedBytes::zeroed(FORMAT_V2.len() + 1);
+        shifted[1..].copy_from_slice(FORMAT_V2);
+        for buf in &[&aligned[..], &shifted[1..]] {
+            for view in vec![LoudsTrie::map_from_slice(buf).unwrap(),
+                             LoudsTrie::map_from_slice_unchecked(buf)
+                                 .unwrap()] {
+                has_format_v2_keys(&view);
+                assert!(!view.is_mapped());
+                assert_eq!(all_keys(&view), all_keys(&read));
+            }
+        }
+        // Written again, it is in the current format, and maps in place.
+        let bytes = write_bytes(&read);
+        assert_eq!(Header::map(&bytes).unwrap().0.version(), FORMAT_VERSION);
+        let aligned = AlignedBytes::from_slice(&bytes);
+        let view = LoudsTrie::map_from_slice(&aligned).unwrap();
+        has_format_v2_keys(&view);
+        assert_eq!(view.is_mapped(), cfg!(target_endian = "little"));
+        // A damaged version 2 dictionary fails its checksum.
+        let mut bad = FORMAT_V2.to_vec();
+        let n = bad.len();
+        bad[n - 1] ^= 0x40;
+        assert_eq!(LoudsTrie::map_from_slice(&bad).unwrap_err(),
+                   MarisaError::FormatError { what: "checksum" });
+    }
+
+    #[test]
+    fn map_from_slice_rejects_truncated_and_garbage() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let config = Config::new().with_weights(true);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let bytes = AlignedBytes::from_slice(&write_bytes(&trie));
+        for len in 0..bytes.len() {
+            assert!(LoudsTrie::map_from_slice(&bytes[..len]).is_err(),
+                    "mapped {} of {} bytes", len, bytes.len());
+        }
+        assert_eq!(LoudsTrie::map_from_slice(&bytes[..8]).unwrap_err(),
+                   MarisaError::FormatError { what: "header" });
+        let n = bytes.len();
+        assert_eq!(LoudsTrie::map_from_slice(&bytes[..n - 1]).unwrap_err(),
+                   MarisaError::FormatError { what: "payload" });
+        for i in 0..bytes.len() {
+            let mut bad = bytes.clone();
+            bad[i] ^= 0xA5;
+            assert!(LoudsTrie::map_from_slice(&bad).is_err(),
+                    "flipped byte {} of {}", i, bytes.len());
+            // Mapping without the checks must not panic, though queries
+            // on what it maps may.
+            let _ = LoudsTrie::map_from_slice_unchecked(&bad);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn checksum_can_be_skipped() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let config = Config::new().with_weights(true);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let mut bytes = write_bytes(&trie);
+        // The top byte of the last weight, before any padding: still a
+        // dictionary, with a different weight.
+        let n = bytes.len() - 4 * (trie.len() % 2);
+        bytes[n - 1] ^= 0x40;
+        let bytes = AlignedBytes::from_slice(&bytes);
+        let last = trie.len() - 1;
+        assert_eq!(LoudsTrie::read_from(&mut &bytes[..]).unwrap_err(),
+                   MarisaError::FormatError { what: "checksum" });
+        assert_eq!(LoudsTrie::map_from_slice(&bytes).unwrap_err(),
+                   MarisaError::FormatError { what: "checksum" });
+        let read = LoudsTrie::read_from_unchecked(&mut &bytes[..]).unwrap();
+        let view = LoudsTrie::map_from_slice_unchecked(&bytes).unwrap();
+        assert!(read.weight(last) != trie.weight(last));
+        assert_eq!(view.weight(last), read.weight(last));
+        assert_eq!(all_keys(&view), all_keys(&trie));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn newer_format_version_is_unsupported() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let mut bytes = write_bytes(&LoudsTrie::build(&mut keyset,
+                                                      &Config::new()).unwrap());
+        bytes[16] = FORMAT_VERSION as u8 + 1;
+        let expected = MarisaError::UnsupportedVersion {
+            found: FORMAT_VERSION + 1, supported: FORMAT_VERSION };
+        assert_eq!(LoudsTrie::map_from_slice_unchecked(&bytes).unwrap_err(),
+                   expected);
+        assert_eq!(LoudsTrie::read_from_unchecked(&mut &bytes[..])
+                   .unwrap_err(), expected);
+    }
+
+    #[test]
+    fn collect_from_key_types() {
+        let _ = env_logger::init();
+        let keys = |trie: &LoudsTrie| -> Vec<Vec<u8>> {
+            all_keys(trie).into_iter().map(|(_, k)| k).collect()
+        };
+        let expected = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let tries: Vec<LoudsTrie> = vec![
+            vec!["apple", "banana"].into_iter().collect(),
+            vec!["apple".to_string(), "banana".to_string()].into_iter()
+                .collect(),
+            expected.iter().collect(),
+            expected.clone().into_iter().collect(),
+            vec![&b"apple"[..], b"banana"].into_iter().collect(),
+        ];
+        for trie in &tries {
+            assert_eq!(keys(trie), expected);
+        }
+        let trie: LoudsTrie = vec![b"plum", b"pear"].into_iter().collect();
+        assert_eq!(keys(&trie), vec![b"pear".to_vec(), b"plum".to_vec()]);
+
+        let empty: LoudsTrie = Vec::<&str>::new().into_iter().collect();
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.lookup(b""), None);
+    }
+
+    #[test]
+    fn build_from_weighted_pairs() {
+        let _ = env_logger::init();
+        let config = Config::new().with_weights(true);
+        let trie = LoudsTrie::build_from(
+            vec![("b".to_string(), 1.0), ("a".to_string(), 2.0),
+                 ("b".to_string(), 0.5)],
+            &config).unwrap();
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.lookup_with_weight(b"a").map(|(_, w)| w),
+                   Some(Some(2.0)));
+        assert_eq!(trie.lookup_with_weight(b"b").map(|(_, w)| w),
+                   Some(Some(1.5)));
+
+        let trie: LoudsTrie = vec![(&b"x"[..], 1.0), (b"y", 3.0)]
+                              .into_iter().collect();
+        assert!(trie.lookup(b"x").is_some() && trie.lookup(b"y").is_some());
+        let trie = LoudsTrie::build_from(Vec::<(&str, f32)>::new(), &config)
+                   .unwrap();
+        assert_eq!(trie.len(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn save_and_load() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let path = std::env::temp_dir().join(
+            format!("mars-trie-save-and-load-{}.dic", std::process::id()));
+        trie.save(&path).unwrap();
+        let loaded = LoudsTrie::load(&path);
+        let unchecked = LoudsTrie::load_unchecked(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.iter().collect::<Vec<_>>(),
+                   trie.iter().collect::<Vec<_>>());
+        assert_eq!(all_keys(&unchecked.unwrap()), all_keys(&trie));
+        assert!(LoudsTrie::load(&path).is_err());
+    }
+
+    /// Links are the base byte and an extra of up to 32 bits. An extra
+    /// from 2^24 up is past what a cache entry and libmarisa can hold.
+    #[test]
+    #[cfg(feature = "std")]
+    fn wide_links() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let config = Config::new().with_num_tries(NumTries::new(1));
+        let mut trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        assert!(!trie.has_wide_links());
+        let num_links = trie.extras_.len();
+        assert!(num_links > 2);
+        let cache_id = (0..trie.cache_.len()).find(|&i| {
+            let child = trie.cache_[i].child();
+            child != std::u32::MAX && trie.link_flags_.at(child as usize)
+        }).unwrap();
+        let link_node = trie.cache_[cache_id].child() as usize;
+        let narrow = trie.get_linked_node_id(link_node);
+        let link_id = trie.get_link_id(link_node);
+        assert_eq!(trie.cache_extra(link_node),
+                   Some(trie.extras_.at(link_id)));
+
+        let original: Vec<u32> = (0..num_links).map(|i| trie.extras_.at(i))
+                                               .collect();
+        let extras = |top: u32| -> Vec<u32> {
+            let mut v = original.clone();
+            v[link_id] = top;
+            v
+        };
+        let edge = extras(0x00FF_FFFE);
+        trie.extras_.build(&edge);
+        assert_eq!(trie.cache_extra(link_node), Some(0x00FF_FFFE));
+        assert!(!trie.has_wide_links());
+
+        for &top in &[0x0100_0000u32, std::u32::MAX] {
+            let wide = extras(top);
+            trie.extras_.build(&wide);
+            assert_eq!(trie.get_linked_node_id(link_node) as u64,
+                       (narrow as u64 & 0xFF) | ((top as u64) << 8));
+            assert_eq!(trie.cache_extra(link_node), None);
+            assert!(trie.has_wide_links());
+            let mut bytes = Vec::new();
+            assert_eq!(trie.write_libmarisa(&mut bytes).unwrap_err().kind(),
+                       std::io::ErrorKind::InvalidInput);
+            // The entry for the node is not truncated.
+            let link = trie.cache_[cache_id].link();
+            assert!(!trie.fill_cache_entry(cache_id));
+            assert_eq!(trie.cache_[cache_id].link(), link);
+        }
+    }
+
+    /// Builds a tail of over 4 GiB. Not run by default: it needs about
+    /// 15 GiB of memory and a few minutes. Peak RSS grows by 3.4 kB a key,
+    /// measured at up to 800,000 keys, and this builds 4,500,000.
+    #[test]
+    #[ignore]
+    #[cfg(feature = "std")]
+    fn tail_over_4_gib() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        let mut rng = rand::XorShiftRng::new_unseeded();
+        let mut key = vec![0u8; 1000];
+        let num_keys = 4_500_000;
+        for _ in 0..num_keys {
+            rng.fill_bytes(&mut key);
+            keyset.push(&key);
+        }
+        let config = Config::new().with_num_tries(NumTries::new(1))
+                                  .with_tail_mode(TailMode::Binary);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        assert!(trie.tail_.len() as u64 > 1 << 32);
+        assert!(trie.has_wide_links());
+        for i in (0..num_keys).step_by(9973) {
+            let id = keyset.id(i).unwrap();
+            assert_eq!(trie.lookup(keyset.key(i)), Some(id));
+            assert_eq!(trie.id_lookup(id).unwrap(), keyset.key(i));
+            assert_eq!(trie.predictive_search(&keyset.key(i)[..3])
+                       .filter(|&(found, _)| found == id).count(), 1);
+        }
+        assert!(trie.write_libmarisa(&mut std::io::sink()).is_err());
+
+        let path = std::env::temp_dir().join(
+            format!("mars-trie-tail-over-4-gib-{}.dic", std::process::id()));
+        trie.save(&path).unwrap();
+        let loaded = LoudsTrie::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        for i in (0..num_keys).step_by(9973) {
+            assert_eq!(loaded.lookup(keyset.key(i)), keyset.id(i));
+        }
+    }
+
+    /// Everything `for_each_key_into` reports for `prefix`.
+    fn keys_into(trie: &LoudsTrie, prefix: &[u8], buf_len: usize,
+                 long_keys: LongKeys) -> Vec<(usize, Vec<u8>, bool)> {
+        let mut buf = vec![0; buf_len];
+        let mut out = Vec::new();
+        trie.for_each_key_into(prefix, &mut buf, long_keys,
+                               |id, key, truncated| {
+            out.push((id, key.to_vec(), truncated));
+        });
+        out
+    }
+
+    #[test]
+    fn for_each_key_into_matches_predictive() {
+        let _ = env_logger::init();
+        let mut tries = Vec::new();
+        for config in id_golden_configs() {
+            tries.push(LoudsTrie::build(&mut id_fixture(), &config).unwrap());
+        }
+        let mut shared = Keyset::new();
+        for k in shared_prefix_keys(100).iter().take(2000) {
+            shared.push(k);
+        }
+        tries.push(LoudsTrie::build(&mut shared, &Config::new()).unwrap());
+        let no_reverse = Config::new().with_reverse_lookup(false);
+        tries.push(LoudsTrie::build(&mut id_fixture(), &no_reverse).unwrap());
+
+        for trie in &tries {
+            let max_len = trie.iter().map(|(_, k)| k.len()).max().unwrap();
+            let mut prefixes: Vec<Vec<u8>> = vec![Vec::new()];
+            for (_, key) in trie.iter().step_by(trie.len() / 40 + 1) {
+                prefixes.push(key[..key.len() / 2].to_vec());
+                prefixes.push(key.clone());
+            }
+            prefixes.push(b"\xff no such prefix".to_vec());
+            for prefix in &prefixes {
+                let mut expected: Vec<(usize, Vec<u8>)> =
+                    trie.predictive_search(prefix).collect();
+                expected.sort();
+                let full = keys_into(trie, prefix, max_len, LongKeys::Skip);
+                assert_eq!(full.iter().map(|x| (x.0, x.1.clone()))
+                               .collect::<Vec<_>>(), expected);
+                assert!(full.iter().all(|x| !x.2));
+
+                // A buffer shorter than the longest key.
+                let short = max_len / 3;
+                let cut: Vec<(usize, Vec<u8>, bool)> = expected.iter()
+                    .map(|&(id, ref k)| {
+                        let n = std::cmp::min(k.len(), short);
+                        (id, k[..n].to_vec(), k.len() > short)
+                    }).collect();
+                assert_eq!(keys_into(trie, prefix, short, LongKeys::Truncate),
+                           cut);
+                let kept: Vec<(usize, Vec<u8>, bool)> = cut.into_iter()
+                    .filter(|x| !x.2).collect();
+                assert_eq!(keys_into(trie, prefix, short, LongKeys::Skip),
+                           kept);
+                assert_eq!(keys_into(trie, prefix, 0, LongKeys::Truncate)
+                               .len(), expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn count_prefix_qc() {
+        let _ = env_logger::init();
+        fn prop(v: Vec<Vec<u8>>, queries: Vec<Vec<u8>>, config: Config)
+          -> bool {
+            let trie = LoudsTrie::build_from(v.iter().cloned(), &config)
+                       .unwrap();
+            // Whole keys end at terminals, half keys often inside a tail,
+            // and a key with a byte added is usually absent.
+            let prefixes = v.iter().flat_map(|k| {
+                let mut longer = k.clone();
+                longer.push(k.len() as u8);
+                vec![k[..k.len() / 2].to_vec(), k.clone(), longer]
+            });
+            prefixes.chain(queries.into_iter()).chain(Some(Vec::new()))
+                .all(|q| trie.count_prefix(&q)
+                         == trie.predictive_search(&q).count())
+        }
+        qc::quickcheck(prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, Config) -> bool);
+    }
+
+    #[test]
+    fn count_prefix_mid_tail() {
+        let _ = env_logger::init();
+        let mut shared = Keyset::new();
+        for k in shared_prefix_keys(100).iter().take(500) {
+            shared.push(k);
+        }
+        let mut keyset = Keyset::new();
+        for k in &["apple", "applesauce", "apricot", "banana", "band"] {
+            keyset.push(k.as_bytes());
+        }
+        for keyset in &mut [keyset, shared] {
+            for &mode in &[TailMode::Text, TailMode::Binary] {
+                let config = Config::new().with_tail_mode(mode);
+                let trie = LoudsTrie::build(keyset, &config).unwrap();
+                for (_, key) in trie.iter().step_by(trie.len() / 10 + 1) {
+                    for n in 0..key.len() + 1 {
+                        let mut bad = key[..n].to_vec();
+                        bad.push(0xFF);
+                        for q in &[&key[..n], &bad[..]] {
+                            assert_eq!(trie.count_prefix(q),
+                                       trie.predictive_search(q).count());
+                        }
+                    }
+                }
+            }
+        }
+        let trie = LoudsTrie::build_from(
+            vec!["apple", "applesauce", "apricot"], &Config::new()).unwrap();
+        assert_eq!(trie.count_prefix(b"applesa"), 1);
+        assert_eq!(trie.count_prefix(b"apple"), 2);
+        assert_eq!(trie.count_prefix(b"appx"), 0);
+        assert_eq!(trie.count_prefix(b"a"), 3);
+    }
+
+    #[test]
+    fn for_each_key_into_does_not_allocate() {
+        let _ = env_logger::init();
+        let mut shared = Keyset::new();
+        for k in &shared_prefix_keys(100) {
+            shared.push(k);
+        }
+        let tries = vec![
+            LoudsTrie::build(&mut id_fixture(), &Config::new()).unwrap(),
+            LoudsTrie::build(&mut shared, &Config::new()).unwrap()];
+        let mut buf = [0u8; 16];
+        for trie in &tries {
+            for &long_keys in &[LongKeys::Truncate, LongKeys::Skip] {
+                let mut seen = 0;
+                let n = alloc_counter::allocations(|| {
+                    trie.for_each_key_into(b"", &mut buf, long_keys,
+                                           |_, key, _| seen += key.len());
+                });
+                assert_eq!(n, 0);
+                assert!(seen > 0 || long_keys == LongKeys::Skip);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn write_libmarisa_bytes(trie: &LoudsTrie) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        trie.write_libmarisa(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn libmarisa_round_trip() {
+        let _ = env_logger::init();
+        for (keyset, trie) in io_tries() {
+            if trie.config().weights() || !trie.common_prefix_.is_empty() {
+                let mut bytes = Vec::new();
+                let e = trie.write_libmarisa(&mut bytes).unwrap_err();
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+                continue;
+            }
+            let bytes = write_libmarisa_bytes(&trie);
+            assert_eq!(bytes.len() % 8, 0);
+            let read = LoudsTrie::read_libmarisa(&mut &bytes[..]).unwrap();
+            assert_eq!(write_libmarisa_bytes(&read), bytes);
+            assert_eq!(read.id_algorithm_version(), 0);
+            assert!(read.config().reverse_lookup());
+            assert_eq!(read.iter().collect::<Vec<_>>(),
+                       trie.iter().collect::<Vec<_>>());
+            for i in 0..keyset.len() {
+                let id = keyset.id(i).unwrap();
+                assert_eq!(read.lookup(keyset.key(i)), Some(id));
+                assert_eq!(read.id_lookup(id).unwrap(), keyset.key(i));
+            }
+            for len in 0..bytes.len() {
+                assert!(LoudsTrie::read_libmarisa(&mut &bytes[..len])
+                        .is_err());
+            }
+            // The layouts are not interchangeable.
+            assert!(LoudsTrie::read_from(&mut &bytes[..]).is_err());
+            assert!(LoudsTrie::read_libmarisa(&mut &write_bytes(&trie)[..])
+                    .is_err());
+        }
+    }
+
+    /// The layout as libmarisa 0.2 writes it, section by section, for a
+    /// single-trie dictionary of "a" and "b".
+    #[test]
+    #[cfg(feature = "std")]
+    fn libmarisa_layout() {
+        let _ = env_logger::init();
+        let mut keyset = Keyset::new();
+        keyset.push(b"a");
+        keyset.push(b"b");
+        let config = Config::new().with_num_tries(NumTries::new(1))
+                                  .with_node_order(NodeOrder::Label);
+        let bytes = write_libmarisa_bytes(&LoudsTrie::build(&mut keyset,
+                                                            &config).unwrap());
+        let le_at = |pos: usize, len: usize| {
+            (0..len).fold(0u64, |x, i| x | (bytes[pos + i] as u64) << (8 * i))
+        };
+        let u64_at = |pos: usize| le_at(pos, 8);
+        let u32_at = |pos: usize| le_at(pos, 4) as u32;
+        assert_eq!(&bytes[..16], b"We love Marisa.\0");
+        // louds: 3 nodes, "10" "110" "0" "0" "0".
+        assert_eq!(u64_at(16), 8);
+        assert_eq!(u64_at(24), 0b0001101);
+        assert_eq!((u32_at(32), u32_at(36)), (8, 3));
+        // Two 12-byte rank indexes, the last holding the total of 1s.
+        assert_eq!(u64_at(40), 24);
+        assert_eq!((u32_at(48), u32_at(60)), (0, 3));
+        // select0 and select1 tables, each ending with the length.
+        assert_eq!(u64_at(72), 8);
+        assert_eq!((u32_at(80), u32_at(84)), (1, 8));
+        assert_eq!(u64_at(88), 8);
+        assert_eq!((u32_at(96), u32_at(100)), (0, 8));
+        // The last two words are the number of level 1 nodes and the
+        // config flags, without the cache level.
+        let end = bytes.len();
+        assert_eq!(u32_at(end - 8), 2);
+        assert_eq!(u32_at(end - 4), 1 | TailMode::Text as u32
+                                      | NodeOrder::Label as u32);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn no_reverse_lookup_exports_select1() {
+        let _ = env_logger::init();
+        let mut keyset = id_fixture();
+        let config = Config::new().with_reverse_lookup(false);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+        let bytes = write_libmarisa_bytes(&trie);
+        let read = LoudsTrie::read_libmarisa(&mut &bytes[..]).unwrap();
+        let mut full = keyset.clone();
+        let with_select1 = LoudsTrie::build(&mut full, &Config::new()).unwrap();
+        assert_eq!(write_libmarisa_bytes(&with_select1), bytes);
+        for i in 0..keyset.len() {
+            assert_eq!(read.id_lookup(keyset.id(i).unwrap()).unwrap(),
+                       keyset.key(i));
+        }
     }
 
 /*
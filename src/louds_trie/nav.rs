@@ -1,15 +1,19 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 use std;
-use base::*;
-use super::{LoudsTrie, NodeID, LoudsPos, LinkID, INVALID_LINK_ID};
+use super::{LoudsTrieView, NodeID, LoudsPos, LinkID, INVALID_LINK_ID};
 
 #[derive(Copy, Clone)]
 struct State<'a> {
-    trie_: &'a LoudsTrie,
+    trie_: &'a LoudsTrieView<'a>,
     node_id_: NodeID,
     louds_pos_: LoudsPos,
     link_id_: LinkID,
     key_pos_: u32,
     //key_id_: u32,
+    /// Set on the state `go_to_child` pushes from a cut state, which has
+    /// the same node but its whole label.
+    finishes_cut_: bool,
 }
 
 impl<'a> std::fmt::Debug for State<'a> {
@@ -19,44 +23,151 @@ impl<'a> std::fmt::Debug for State<'a> {
             .field("louds_pos_", &self.louds_pos_)
             .field("link_id_", &self.link_id_)
             .field("key_pos_", &self.key_pos_)
+            .field("finishes_cut_", &self.finishes_cut_)
             .finish()
     }
 }
 
 impl<'a> State<'a> {
-    fn new(trie: &'a LoudsTrie, node_id: NodeID, louds_pos: LoudsPos,
-           link_id: LinkID, key_pos: u32) -> State<'a> {
+    fn new(trie: &'a LoudsTrieView<'a>, node_id: NodeID,
+           louds_pos: LoudsPos, link_id: LinkID, key_pos: u32) -> State<'a> {
         State { trie_: trie, node_id_: node_id, louds_pos_: louds_pos,
-                link_id_: link_id, key_pos_: key_pos }
+                link_id_: link_id, key_pos_: key_pos, finishes_cut_: false }
     }
 }
 
+/// A cursor over the nodes of a dictionary, for walking it from the root
+/// a node at a time. A node's string is the bytes on the path to it; a
+/// label taken from a next trie or the tail has several bytes, and
+/// `seek_prefix` can stop part way through one.
+///
+/// ```
+/// use mars_trie::{Config, LoudsTrie, Nav, NumTries};
+///
+/// let config = Config::new().with_num_tries(NumTries::new(1));
+/// let trie = LoudsTrie::build_from(vec!["apple", "apricot"], &config)
+///     .unwrap();
+/// let mut nav = Nav::new(&trie);
+/// // "ple" is the label of a single node, kept in the tail.
+/// assert!(nav.seek_prefix(b"appl"));
+/// assert_eq!(nav.get_u8(), b"appl");
+/// assert!(!nav.is_leaf());
+/// assert!(nav.go_to_child());
+/// assert_eq!(nav.get_u8(), b"apple");
+/// assert_eq!(nav.key_id(), trie.lookup(b"apple"));
+/// ```
 #[derive(Debug)]
 pub struct Nav<'a> {
-    trie_: &'a LoudsTrie,
+    trie_: &'a LoudsTrieView<'a>,
     history_: Vec<State<'a> >,
     key_buf_: Vec<u8>,
+    /// The rest of the last node's label when `seek_prefix` stopped part
+    /// way through it, which makes the last state a cut one. Empty
+    /// otherwise.
+    rest_: Vec<u8>,
 }
 
-// For lookups, marisa does caching based on the input character.
-// We can't do that here. May want to remove or rethink the cache
-// implementation in light of this.
+// LoudsTrie::lookup consults the cache keyed on the next query byte. Nav
+// walks one byte at a time and keeps its own louds position, so it does not.
 
 impl<'a> Nav<'a> {
-    fn new(trie: &'a LoudsTrie) -> Nav<'a> {
+    pub fn new(trie: &'a LoudsTrieView<'a>) -> Nav<'a> {
         let mut out = Nav { trie_: trie, history_: Vec::new(),
-                            key_buf_: Vec::new() };
-        out.history_.push(State::new(trie, NodeID(0), LoudsPos(0),
-                          INVALID_LINK_ID, 0));
+                            key_buf_: Vec::new(), rest_: Vec::new() };
+        out.reset();
         out
     }
 
+    fn reset(&mut self) {
+        let trie = self.trie_;
+        self.history_.clear();
+        self.history_.push(State::new(trie, NodeID(0), LoudsPos(0),
+                           INVALID_LINK_ID, 0));
+        self.key_buf_.clear();
+        self.key_buf_.extend_from_slice(&trie.common_prefix_);
+        self.rest_.clear();
+    }
+
+    fn is_cut(&self) -> bool {
+        !self.rest_.is_empty()
+    }
+
+    /// Stops part way through the last node's label, after `len` bytes of
+    /// the string.
+    fn cut(&mut self, len: usize) {
+        self.rest_ = self.key_buf_[len..].to_vec();
+        self.key_buf_.truncate(len);
+    }
+
+    /// Moves to the node reached from the root by `prefix`. A prefix that
+    /// ends part way through a label leaves the cursor inside it: the
+    /// string is then exactly `prefix`, the cursor is not a leaf, and its
+    /// only child is the node with its whole label.
+    ///
+    /// If no key starts with `prefix`, returns false and leaves the cursor
+    /// where the match stopped, so `get_u8().len()` bytes of it matched.
+    pub fn seek_prefix(&mut self, prefix: &[u8]) -> bool {
+        self.reset();
+        let mut pos = matching_len(&self.key_buf_, prefix);
+        if pos < self.key_buf_.len() {
+            self.cut(pos);
+            return pos == prefix.len();
+        }
+        while pos < prefix.len() {
+            if !self.go_to_child() {
+                return false;
+            }
+            // Siblings differ in the first byte of their labels.
+            loop {
+                let start = self.history_.last().unwrap().key_pos_ as usize;
+                if self.key_buf_[start] == prefix[pos] {
+                    break;
+                }
+                if !self.go_to_sibling() {
+                    self.go_to_parent();
+                    return false;
+                }
+            }
+            let start = self.history_.last().unwrap().key_pos_ as usize;
+            let n = matching_len(&self.key_buf_[start..], &prefix[pos..]);
+            pos += n;
+            if start + n < self.key_buf_.len() {
+                self.cut(start + n);
+                return pos == prefix.len();
+            }
+        }
+        true
+    }
+
+    /// Moves to the node key `id` ends at, with the path from the root in
+    /// the history. Returns false if there is no key `id`, or if the
+    /// dictionary was built without reverse lookup.
+    pub fn seek_key_id(&mut self, id: usize) -> bool {
+        let trie = self.trie_;
+        if !trie.config_.reverse_lookup() || id >= trie.len() {
+            return false;
+        }
+        self.reset();
+        let mut path = Vec::new();
+        let mut node_id = trie.terminal_flags_.select1(id);
+        while node_id != 0 {
+            path.push(node_id);
+            node_id = trie.louds_.select1(node_id) - node_id - 1;
+        }
+        for &node_id in path.iter().rev() {
+            let louds_pos = trie.louds_.select1(node_id);
+            assert!(louds_pos <= std::u32::MAX as usize);
+            self.push(NodeID(node_id as u32), LoudsPos(louds_pos as u32));
+        }
+        true
+    }
+
     fn get_link_id(&self) -> LinkID {
         self.history_.last().unwrap().link_id_
     }
 
-    fn push_str<'b>(&mut self, key: &'b[u8], trie: &'a LoudsTrie, node_id: NodeID,
-                    louds_pos: LoudsPos, link_id: LinkID) {
+    fn push_str<'b>(&mut self, key: &'b[u8], trie: &'a LoudsTrieView<'a>,
+                    node_id: NodeID, louds_pos: LoudsPos, link_id: LinkID) {
 
         debug!("push_str(key: {:?})", key);
 
@@ -67,55 +178,48 @@ impl<'a> Nav<'a> {
                                       old_len as u32));
     }
 
-    fn push(&mut self, mut node_id: NodeID, louds_pos: LoudsPos) {
+    fn push(&mut self, node_id: NodeID, louds_pos: LoudsPos) {
         debug!("push (node_id: {:?}, louds_pos: {:?})", node_id, louds_pos);
-        let mut trie = self.history_.last().unwrap().trie_;
-        loop {
-            debug!("push loop");
-            if self.trie_.link_flags_.at(node_id.0 as usize) {
-                debug!("Link flags TRUE");
-                let (linked_node_id, link_id) =
-                    self.trie_.get_linked_ids(node_id.0 as usize);
-                debug!("linked_node_id: {:?}, link_id: {:?}",
-                       linked_node_id, link_id);
-                // Proceed either to next trie or tail
-                match &trie.next_trie_ {
-                    &Some(ref next_trie) => {
-                        debug!("Link TRUE--next trie");
-                        trie = &**next_trie;
-                        node_id = linked_node_id; // not sure about this
-                        continue;
-                    },
-                    &None => {
-                        debug!("Link TRUE--tail");
-                        // FIXME: Shouldn't need this temporary vector.
-                        //        'restore' should return an iterator, and
-                        //        state.push should consume it.
-                        let mut v = Vec::new();
-                        trie.tail_.restore(linked_node_id.0 as usize, &mut v);
-
-                        // Not sure if these values are correct/useful.
-                        // If some stuff is only needed for some nodes...
-                        // should reflect that in the types we use
-                        self.push_str(&v, trie, node_id, louds_pos, link_id);
-                    }
-                }
-            } else {
-                debug!("Link flags FALSE");
-                let node_char = [ trie.bases_[node_id.0 as usize] ];
-                self.push_str(&node_char, trie, node_id, louds_pos,
-                              INVALID_LINK_ID);
-            }
-            break;
+        // The history only ever refers to nodes of the root trie. A link's
+        // label is restored in full, whether it lives in the next trie or in
+        // the tail.
+        let trie = self.trie_;
+        if trie.link_flags_.at(node_id.0 as usize) {
+            let (linked_node_id, link_id) =
+                trie.get_linked_ids(node_id.0 as usize);
+            debug!("linked_node_id: {:?}, link_id: {:?}",
+                   linked_node_id, link_id);
+            // FIXME: Shouldn't need this temporary vector.
+            //        'restore' should return an iterator, and
+            //        state.push should consume it.
+            let mut v = Vec::new();
+            trie.restore(linked_node_id, &mut v);
+            self.push_str(&v, trie, node_id, louds_pos, link_id);
+        } else {
+            let node_char = [ trie.bases_[node_id.0 as usize] ];
+            self.push_str(&node_char, trie, node_id, louds_pos,
+                          INVALID_LINK_ID);
         }
         debug!("done with push");
     }
     pub fn has_child(&self) -> bool {
+        if self.is_cut() {
+            return true;
+        }
         self.history_.last().map(|s| s.trie_.has_child(s.node_id_))
             .unwrap_or(false)
     }
     pub fn go_to_child(&mut self) -> bool {
         debug!("go_to_child");
+        if self.is_cut() {
+            let mut s = *self.history_.last().unwrap();
+            s.key_pos_ = self.key_buf_.len() as u32;
+            s.finishes_cut_ = true;
+            self.key_buf_.extend_from_slice(&self.rest_);
+            self.rest_.clear();
+            self.history_.push(s);
+            return true;
+        }
         if let Some((node_id, louds_pos)) =
             self.history_.last()
             .and_then(|s| { self.trie_.child_pos(s.node_id_) })
@@ -133,7 +237,7 @@ impl<'a> Nav<'a> {
     pub fn has_prev_sibling(&self) -> bool {
         // FIXME: Is this all...?
         self.history_.last().map(|h| {
-            h.trie_.louds_.at(h.louds_pos_.0 as usize - 1)
+            !h.finishes_cut_ && h.trie_.louds_.at(h.louds_pos_.0 as usize - 1)
         }).unwrap_or(false)
     }
     pub fn go_to_prev_sibling(&mut self) -> bool {
@@ -146,16 +250,18 @@ impl<'a> Nav<'a> {
     }
     pub fn has_sibling(&self) -> bool {
         self.history_.last().map(|h| {
-            h.trie_.louds_.at(h.louds_pos_.0 as usize + 1)
+            !h.finishes_cut_ && h.trie_.louds_.at(h.louds_pos_.0 as usize + 1)
         }).unwrap_or(false)
     }
     pub fn go_to_sibling(&mut self) -> bool {
         debug!("go_to_sibling");
         if let Some(&s) = self.history_.last() {
-            let cur_len = self.key_buf_.len();
-            assert!((s.key_pos_ as usize) <= cur_len);
-            self.key_buf_.truncate(s.key_pos_ as usize);
-            if s.trie_.louds_.at(s.louds_pos_.0 as usize + 1) {
+            if !s.finishes_cut_ &&
+               s.trie_.louds_.at(s.louds_pos_.0 as usize + 1) {
+                let cur_len = self.key_buf_.len();
+                assert!((s.key_pos_ as usize) <= cur_len);
+                self.key_buf_.truncate(s.key_pos_ as usize);
+                self.rest_.clear();
                 debug!("  (node_id: {:?} louds_pos: {:?})",
                        s.node_id_.0 + 1, s.louds_pos_.0 + 1);
                 self.history_.pop();
@@ -182,6 +288,12 @@ impl<'a> Nav<'a> {
         if let Some(s) = self.history_.pop() {
             let cur_len = self.key_buf_.len();
             assert!((s.key_pos_ as usize) <= cur_len);
+            // Going up from a whole label goes back inside it.
+            self.rest_ = if s.finishes_cut_ {
+                self.key_buf_[s.key_pos_ as usize..].to_vec()
+            } else {
+                Vec::new()
+            };
             self.key_buf_.truncate(s.key_pos_ as usize);
             if let Some(s) = self.history_.last() {
                 let node_id = s.node_id_;
@@ -196,11 +308,26 @@ impl<'a> Nav<'a> {
         }
     }
     pub fn is_leaf(&self) -> bool {
+        if self.is_cut() {
+            return false;
+        }
         self.history_.last().map(|s| {
             // Use root trie
             self.trie_.terminal_flags_.at(s.node_id_.0 as usize)
         }).unwrap_or(false)
     }
+    /// The id of the key that ends at the cursor, if one does.
+    pub fn key_id(&self) -> Option<usize> {
+        if !self.is_leaf() {
+            return None;
+        }
+        let node_id = self.history_.last().unwrap().node_id_.0 as usize;
+        Some(self.trie_.terminal_flags_.rank1(node_id))
+    }
+    /// The number of nodes on the path from the root, the root included.
+    pub fn depth(&self) -> usize {
+        self.history_.len()
+    }
     //pub fn get_string(&self) -> &str {
     //    panic!("not implemented")
     //}
@@ -212,8 +339,14 @@ impl<'a> Nav<'a> {
     }
 }
 
+/// Length of the longest common prefix of `a` and `b`.
+fn matching_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|&(x, y)| x == y).count()
+}
+
 #[derive(Copy, Clone, Debug)]
 enum DFT {
+    Start,
     ToChild,
     ToSibling,
     ToParentSibling,
@@ -222,10 +355,14 @@ enum DFT {
 
 impl DFT {
     fn new() -> DFT {
-        DFT::ToChild
+        DFT::Start
     }
     fn depth_first_traversal_step<'a>(&mut self, nav: &mut Nav<'a>) -> bool {
         match *self {
+            DFT::Start => {
+                *self = DFT::ToChild;
+                return true;
+            },
             DFT::ToChild => {
                 if nav.go_to_child() {
                     return true;
@@ -275,11 +412,13 @@ impl DFT {
 
 #[cfg(test)]
 mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
     use env_logger;
     use std::cmp::Ordering;
     use quickcheck as qc;
-    use config::{Config, NumTries};
-    use key::Key;
+    use config::{Config, NumTries, TailMode};
+    use keyset::Keyset;
     use super::{DFT, Nav};
     use super::super::LoudsTrie;
 
@@ -309,10 +448,12 @@ mod test {
             //debug!("");
             return qc::TestResult::discard();
         }
-        let mut keys: Vec<Key> = v.iter().map(|s| Key::new(s.as_bytes()))
-                                 .collect();
+        let mut keyset = Keyset::new();
+        for s in &v {
+            keyset.push(s.as_bytes());
+        }
         let config = Config::new().with_num_tries(num_tries);
-        let trie = LoudsTrie::build(&mut keys, &config);
+        let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
         //debug!("trie: {:#?}", trie);
         debug_print_louds_bv(&trie);
 
@@ -328,6 +469,7 @@ mod test {
         }
 
         vv1.sort();
+        vv1.dedup();
         vv2.sort();
         debug!("vv1: {:?}", vv1);
         debug!("vv2: {:?}", vv2);
@@ -410,5 +552,138 @@ mod test {
         //assert_p(navr_prop_str_2(
         //    vec!["Testing", "trouble", "Trouble", "Threep", "Test"]));
     }
-}
 
+    /// The keys of the subtree under the cursor, found with the cursor
+    /// alone. Leaves the cursor where it was.
+    fn walk(nav: &mut Nav, out: &mut Vec<Vec<u8>>) {
+        if nav.is_leaf() {
+            out.push(nav.get_u8().to_vec());
+        }
+        if nav.go_to_child() {
+            loop {
+                walk(nav, out);
+                if !nav.go_to_sibling() {
+                    break;
+                }
+            }
+            nav.go_to_parent();
+        }
+    }
+
+    fn seek_prop(v: Vec<String>, num_tries: NumTries) -> qc::TestResult {
+        if v.iter().any(|x| x.is_empty()) {
+            return qc::TestResult::discard();
+        }
+        let config = Config::new().with_num_tries(num_tries);
+        let trie = LoudsTrie::build_from(v.iter().map(|s| s.as_bytes()),
+                                         &config).unwrap();
+        let mut nav = Nav::new(&trie);
+        for s in &v {
+            for end in 0..s.len() + 1 {
+                let prefix = &s.as_bytes()[..end];
+                if !nav.seek_prefix(prefix) || nav.get_u8() != prefix {
+                    return qc::TestResult::failed();
+                }
+                let mut walked = Vec::new();
+                walk(&mut nav, &mut walked);
+                walked.sort();
+                let mut found: Vec<Vec<u8>> = trie.predictive_search(prefix)
+                    .map(|(_, key)| key).collect();
+                found.sort();
+                if walked != found || nav.get_u8() != prefix {
+                    return qc::TestResult::failed();
+                }
+            }
+        }
+        for id in 0..trie.len() {
+            if !nav.seek_key_id(id) || !nav.is_leaf()
+               || nav.get_u8() != &trie.id_lookup(id).unwrap()[..] {
+                return qc::TestResult::failed();
+            }
+            let key = nav.get_u8().to_vec();
+            while nav.history_.len() > 1 {
+                nav.go_to_parent();
+                if !key.starts_with(nav.get_u8()) {
+                    return qc::TestResult::failed();
+                }
+            }
+        }
+        qc::TestResult::from_bool(!nav.seek_key_id(trie.len()))
+    }
+
+    #[test]
+    fn seek_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(seek_prop as fn(Vec<String>, NumTries)
+                       -> qc::TestResult);
+    }
+
+    #[test]
+    fn seek_into_a_tail() {
+        let _ = env_logger::init();
+        let keys = ["banana", "bandana", "apple", "applesauce", "cherry"];
+        for &tail_mode in &[TailMode::Text, TailMode::Binary] {
+            let config = Config::new().with_num_tries(NumTries::new(1))
+                                      .with_tail_mode(tail_mode);
+            let trie = LoudsTrie::build_from(keys.iter().map(|k| k.as_bytes()),
+                                             &config).unwrap();
+            let mut nav = Nav::new(&trie);
+            assert!(nav.seek_prefix(b"appl"));
+            assert!(nav.is_cut());
+            assert!(!nav.is_leaf());
+            assert_eq!(nav.get_u8(), b"appl");
+            assert!(nav.go_to_child());
+            assert!(!nav.has_sibling());
+            assert!(!nav.go_to_sibling());
+            assert!(nav.go_to_parent());
+            assert_eq!(nav.get_u8(), b"appl");
+            let mut walked = Vec::new();
+            walk(&mut nav, &mut walked);
+            walked.sort();
+            assert_eq!(walked, vec![b"apple".to_vec(), b"applesauce".to_vec()]);
+            assert_eq!(nav.get_u8(), b"appl");
+
+            assert!(nav.seek_prefix(b"ch"));
+            assert!(nav.go_to_child());
+            assert_eq!(nav.get_u8(), b"cherry");
+            assert!(nav.is_leaf());
+            assert!(!nav.go_to_sibling());
+            assert!(nav.go_to_parent());
+            assert_eq!(nav.get_u8(), b"ch");
+
+            // Failed seeks stop where the match did.
+            assert!(!nav.seek_prefix(b"bandx"));
+            assert_eq!(nav.get_u8(), b"band");
+            assert!(!nav.seek_prefix(b"bx"));
+            assert_eq!(nav.get_u8(), b"b");
+            assert!(!nav.seek_prefix(b"cherry pie"));
+            assert_eq!(nav.get_u8(), b"cherry");
+            assert!(!nav.seek_prefix(b"z"));
+            assert_eq!(nav.get_u8(), b"");
+        }
+    }
+
+    #[test]
+    fn seek_into_the_common_prefix() {
+        let _ = env_logger::init();
+        let trie = LoudsTrie::build_from(vec![&b"prefix-a"[..], b"prefix-b"],
+                                         &Config::new()).unwrap();
+        let mut nav = Nav::new(&trie);
+        assert!(nav.seek_prefix(b"pre"));
+        assert_eq!(nav.get_u8(), b"pre");
+        let mut walked = Vec::new();
+        walk(&mut nav, &mut walked);
+        walked.sort();
+        assert_eq!(walked, vec![b"prefix-a".to_vec(), b"prefix-b".to_vec()]);
+        assert!(!nav.seek_prefix(b"prep"));
+        assert_eq!(nav.get_u8(), b"pre");
+    }
+
+    #[test]
+    fn seek_key_id_needs_reverse_lookup() {
+        let config = Config::new().with_reverse_lookup(false);
+        let trie = LoudsTrie::build_from(vec![&b"a"[..], b"b"], &config)
+                   .unwrap();
+        assert!(!Nav::new(&trie).seek_key_id(0));
+    }
+}
@@ -0,0 +1,74 @@
+//! Batches of queries spread over rayon's thread pool. Queries only need
+//! `&LoudsTrie`, so the workers share the dictionary and keep no state of
+//! their own.
+
+use rayon::prelude::*;
+use super::LoudsTrieView;
+
+/// Queries per rayon job. Lookups take well under a microsecond, so
+/// splitting finer than this costs more in scheduling than it saves.
+const MIN_CHUNK_LEN: usize = 256;
+
+impl<'m> LoudsTrieView<'m> {
+    /// `lookup` of each query, in the order of `queries`, run on rayon's
+    /// current thread pool.
+    pub fn par_lookup_many(&self, queries: &[&[u8]]) -> Vec<Option<usize>> {
+        queries.par_iter()
+               .with_min_len(MIN_CHUNK_LEN)
+               .map(|query| self.lookup(query))
+               .collect()
+    }
+
+    /// The number of keys starting with each prefix, in the order of
+    /// `prefixes`, run on rayon's current thread pool.
+    pub fn par_predictive_count(&self, prefixes: &[&[u8]]) -> Vec<usize> {
+        prefixes.par_iter()
+                .with_min_len(MIN_CHUNK_LEN)
+                .map(|prefix| self.predictive_search(prefix).count())
+                .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use config::Config;
+    use keyset::Keyset;
+    use rayon;
+    use super::super::LoudsTrie;
+
+    fn queries() -> Vec<Vec<u8>> {
+        (0..20000u64).map(|i| {
+            format!("{}", (i * 2654435761) % 100003).into_bytes()
+        }).collect()
+    }
+
+    #[test]
+    fn par_queries_match_serial() {
+        let _ = env_logger::init();
+        let queries = queries();
+        let mut keyset = Keyset::new();
+        for q in queries.iter().step_by(3) {
+            keyset.push(q);
+        }
+        let trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let refs: Vec<&[u8]> = queries.iter().map(|q| &q[..]).collect();
+        let prefixes: Vec<&[u8]> = refs.iter().map(|q| &q[..q.len() / 2])
+                                       .collect();
+
+        let lookups: Vec<Option<usize>> = refs.iter()
+            .map(|q| trie.lookup(q)).collect();
+        let counts: Vec<usize> = prefixes.iter()
+            .map(|p| trie.predictive_search(p).count()).collect();
+        assert!(lookups.iter().any(|x| x.is_some()));
+        assert!(lookups.iter().any(|x| x.is_none()));
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build()
+                   .unwrap();
+        pool.install(|| {
+            assert_eq!(trie.par_lookup_many(&refs), lookups);
+            assert_eq!(trie.par_predictive_count(&prefixes), counts);
+        });
+        assert_eq!(trie.par_lookup_many(&[]), vec![]);
+    }
+}
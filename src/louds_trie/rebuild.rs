@@ -0,0 +1,227 @@
+//! Building the next version of a dictionary from the last one: its keys,
+//! plus some, less some.
+//!
+//! The old keys are enumerated in byte order and merged with the additions,
+//! which are sorted first, on their way into `build_from_sorted`. Only the
+//! additions and removals are held, never a second copy of the old keys.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::iter::Peekable;
+use std::vec;
+use config::Config;
+use error::MarisaError;
+use keyset::KeyItem;
+use super::{LoudsTrie, LoudsTrieView, SearchOrder, WeightedPredictiveIter};
+
+/// The old keys and the additions in byte order, each key once with its
+/// weights summed, and without the removals.
+struct Merge<'a> {
+    old_: Peekable<WeightedPredictiveIter<'a, 'static>>,
+    additions_: Peekable<vec::IntoIter<(Vec<u8>, f32)>>,
+    removals_: Peekable<vec::IntoIter<Vec<u8>>>,
+}
+
+impl<'a> Merge<'a> {
+    fn is_removed(&mut self, key: &[u8]) -> bool {
+        while self.removals_.peek().map_or(false, |r| &r[..] < key) {
+            self.removals_.next();
+        }
+        self.removals_.peek().map_or(false, |r| &r[..] == key)
+    }
+}
+
+impl<'a> Iterator for Merge<'a> {
+    type Item = (Vec<u8>, f32);
+
+    fn next(&mut self) -> Option<(Vec<u8>, f32)> {
+        loop {
+            let take_old = match (self.old_.peek(), self.additions_.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(old), Some(new)) => old.1 <= new.0,
+            };
+            let (key, weight) = if take_old {
+                let (_, key, weight) = self.old_.next().unwrap();
+                let mut weight = weight.unwrap_or(1.0);
+                if self.additions_.peek().map_or(false, |new| new.0 == key) {
+                    weight += self.additions_.next().unwrap().1;
+                }
+                (key, weight)
+            } else {
+                self.additions_.next().unwrap()
+            };
+            if !self.is_removed(&key) {
+                return Some((key, weight));
+            }
+        }
+    }
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// Builds a new dictionary of this one's keys and `additions`, which
+    /// are keys or `(key, weight)` pairs, less `removals`. A key in both
+    /// this dictionary and `additions` gets the sum of its weights; this
+    /// dictionary's keys weigh what `weight` says, or 1 if it has no
+    /// weights. A removal takes a key out wherever it came from.
+    ///
+    /// The keys are streamed into `build_from_sorted` in byte order, so
+    /// besides the two dictionaries only the additions and removals are
+    /// held. Key ids are fresh; `remap_ids` maps the old ones to them.
+    ///
+    /// ```
+    /// use mars_trie::{Config, LoudsTrie};
+    ///
+    /// let config = Config::new().with_weights(true);
+    /// let old = LoudsTrie::build_from(vec![("a", 1.0), ("b", 2.0)], &config)
+    ///     .unwrap();
+    /// let new = old.rebuild_with(vec![("b", 1.0), ("c", 4.0)], vec!["a"],
+    ///                            &config).unwrap();
+    /// assert_eq!(new.lookup(b"a"), None);
+    /// assert_eq!(new.weight(new.lookup(b"b").unwrap()), Some(3.0));
+    /// let remap = old.remap_ids(&new);
+    /// assert_eq!(remap[old.lookup(b"a").unwrap()], None);
+    /// assert_eq!(remap[old.lookup(b"b").unwrap()], new.lookup(b"b"));
+    /// ```
+    pub fn rebuild_with<I, J>(&self, additions: I, removals: J,
+                              config: &Config)
+      -> Result<LoudsTrie, MarisaError>
+      where I: IntoIterator, I::Item: KeyItem,
+            J: IntoIterator, J::Item: AsRef<[u8]> {
+        let mut sorted: Vec<(Vec<u8>, f32)> = additions.into_iter()
+            .map(|item| (item.key_bytes().to_vec(), item.key_weight()))
+            .collect();
+        // Stable, so repeats are summed in the order they came.
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut merged: Vec<(Vec<u8>, f32)> = Vec::with_capacity(sorted.len());
+        for (key, weight) in sorted {
+            match merged.last_mut() {
+                Some(last) if last.0 == key => {
+                    last.1 += weight;
+                    continue;
+                },
+                _ => {},
+            }
+            merged.push((key, weight));
+        }
+        let mut removals: Vec<Vec<u8>> = removals.into_iter()
+            .map(|key| key.as_ref().to_vec()).collect();
+        removals.sort();
+        LoudsTrie::build_from_sorted(Merge {
+            old_: self.iter().order(SearchOrder::Lex).with_weights()
+                      .peekable(),
+            additions_: merged.into_iter().peekable(),
+            removals_: removals.into_iter().peekable(),
+        }, config)
+    }
+
+    /// For each key id of this dictionary, the id of the same key in `new`,
+    /// or None if `new` lacks it.
+    pub fn remap_ids(&self, new: &LoudsTrieView) -> Vec<Option<usize>> {
+        let mut out = vec![None; self.len()];
+        for (_, id, new_id) in self.intersect(new) {
+            out[id] = new_id;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use std::collections::BTreeMap;
+    use env_logger;
+    use quickcheck as qc;
+    use config::{Config, NodeOrder};
+    use super::super::LoudsTrie;
+
+    type Weighted = Vec<(Vec<u8>, u8)>;
+
+    /// Sums small whole weights, so that the sums are exact either way.
+    fn add(map: &mut BTreeMap<Vec<u8>, f32>, keys: &Weighted) {
+        for &(ref key, weight) in keys {
+            *map.entry(key.clone()).or_insert(0.0) += weight as f32;
+        }
+    }
+
+    fn rebuild_prop(old_keys: Weighted, additions: Weighted,
+                    removals: Vec<Vec<u8>>, label_order: bool) -> bool {
+        let order = if label_order { NodeOrder::Label } else {
+            NodeOrder::Weight
+        };
+        let config = Config::new().with_weights(true).with_node_order(order);
+        let weighted = |keys: &Weighted| -> Vec<(Vec<u8>, f32)> {
+            keys.iter().map(|&(ref k, w)| (k.clone(), w as f32)).collect()
+        };
+        let old = LoudsTrie::build_from(weighted(&old_keys), &config)
+                  .unwrap();
+        // Some of the removals are keys of either side.
+        let mut removals = removals;
+        removals.extend(old_keys.iter().step_by(3).map(|x| x.0.clone()));
+        removals.extend(additions.iter().step_by(4).map(|x| x.0.clone()));
+        let new = old.rebuild_with(weighted(&additions), &removals, &config)
+                  .unwrap();
+
+        let mut expected = BTreeMap::new();
+        add(&mut expected, &old_keys);
+        add(&mut expected, &additions);
+        for key in &removals {
+            expected.remove(key);
+        }
+        let found: BTreeMap<Vec<u8>, f32> = new.iter().with_weights()
+            .map(|(_, key, weight)| (key, weight.unwrap())).collect();
+        if found != expected {
+            return false;
+        }
+        let remap = old.remap_ids(&new);
+        remap.len() == old.len() && old.iter().all(|(id, key)| {
+            remap[id] == new.lookup(&key)
+        })
+    }
+
+    #[test]
+    fn rebuild_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(rebuild_prop
+                       as fn(Weighted, Weighted, Vec<Vec<u8>>, bool) -> bool);
+    }
+
+    #[test]
+    fn rebuild_overlapping() {
+        let _ = env_logger::init();
+        let keys = |from: usize, to: usize| -> Vec<String> {
+            (from..to).map(|i| format!("key/{}/{}", i % 13, i)).collect()
+        };
+        let old = LoudsTrie::build_from(keys(0, 1000), &Config::new())
+                  .unwrap();
+        let additions: Vec<(String, f32)> = keys(800, 1500).into_iter()
+            .map(|key| (key, 2.0)).collect();
+        let removals = keys(500, 900);
+        let config = Config::new().with_weights(true);
+        let new = old.rebuild_with(additions, &removals, &config).unwrap();
+        assert_eq!(new.len(), 500 + 600);
+        for (i, key) in keys(0, 1500).iter().enumerate() {
+            let id = new.lookup(key.as_bytes());
+            assert_eq!(id.is_some(), i < 500 || i >= 900, "{}", key);
+            // Old keys weigh 1, as the old trie kept no weights.
+            let weight = if i < 800 { 1.0 } else if i < 1000 { 3.0 } else {
+                2.0
+            };
+            if let Some(id) = id {
+                assert_eq!(new.weight(id), Some(weight), "{}", key);
+            }
+        }
+        let remap = old.remap_ids(&new);
+        assert_eq!(remap.iter().filter(|x| x.is_some()).count(), 600);
+        for (id, key) in old.iter() {
+            assert_eq!(remap[id], new.lookup(&key));
+        }
+
+        // Rebuilding with nothing changed gives the same dictionary.
+        let same = old.rebuild_with(Vec::<&str>::new(), Vec::<&str>::new(),
+                                    &old.config()).unwrap();
+        assert_eq!(same.build_id(), old.build_id());
+    }
+}
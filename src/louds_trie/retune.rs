@@ -0,0 +1,208 @@
+//! Refilling the root trie's cache from a sample of queries.
+//!
+//! The build caches, for each slot, the edge with the most keys below it.
+//! Without weights that is a guess at what queries will go through, and a
+//! poor one when a few keys get most of the queries. The root trie's cache
+//! is the one lookups and searches consult on every step down, so it is the
+//! one retuned; the next tries' caches serve link restoring, which follows
+//! from the keys rather than the queries.
+
+use std::collections::HashMap;
+use super::LoudsTrieView;
+
+/// What `LoudsTrie::retune_cache` saw in its sample. A hit rate is the
+/// share of the steps down the root trie that the cache would have
+/// answered, 0 for a sample that took no steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheRetune {
+    num_steps_: u64,
+    old_hits_: u64,
+    new_hits_: u64,
+}
+
+impl CacheRetune {
+    /// Steps down the root trie the sample queries took.
+    pub fn num_steps(&self) -> u64 {
+        self.num_steps_
+    }
+    /// The hit rate of the cache before retuning.
+    pub fn old_hit_rate(&self) -> f64 {
+        self.rate(self.old_hits_)
+    }
+    /// The hit rate of the retuned cache; never lower than the old one.
+    pub fn new_hit_rate(&self) -> f64 {
+        self.rate(self.new_hits_)
+    }
+
+    fn rate(&self, hits: u64) -> f64 {
+        if self.num_steps_ == 0 { 0.0 }
+        else { hits as f64 / self.num_steps_ as f64 }
+    }
+}
+
+/// An edge from `parent` to `child`, whose label starts with `label`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Edge {
+    parent: usize,
+    child: usize,
+    label: u8,
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// Replays `sample_queries` as lookups, counts how often each edge of
+    /// the root trie is taken, and refills each cache slot with the most
+    /// taken of the edges that share it. Slots no sampled edge maps to keep
+    /// their entries, as does a slot whose entry is taken as often as any
+    /// other edge of it. Query results do not change, only which steps the
+    /// cache answers; the retuned cache is saved with the dictionary.
+    pub fn retune_cache(&mut self, sample_queries: &[&[u8]]) -> CacheRetune {
+        let mut counts: HashMap<Edge, u64> = HashMap::new();
+        for query in sample_queries {
+            self.count_steps(query, &mut counts);
+        }
+
+        let num_steps = counts.values().sum();
+        // An entry has no room for a link far into a huge tail.
+        let mut edges: Vec<(usize, Edge, u64)> = counts.into_iter()
+            .filter(|&(edge, _)| self.cache_extra(edge.child).is_some())
+            .map(|(edge, count)| {
+                (self.get_cache_id_with_label(edge.parent, edge.label), edge,
+                 count)
+            })
+            .collect();
+        // By slot, then the most taken first, then by node for a result
+        // that does not depend on hashing.
+        edges.sort_by(|a, b| {
+            a.0.cmp(&b.0).then(b.2.cmp(&a.2))
+               .then((a.1.parent, a.1.child).cmp(&(b.1.parent, b.1.child)))
+        });
+
+        let mut out = CacheRetune { num_steps_: num_steps, old_hits_: 0,
+                                    new_hits_: 0 };
+        let mut i = 0;
+        while i < edges.len() {
+            let cache_id = edges[i].0;
+            let end = i + edges[i..].iter()
+                                    .take_while(|e| e.0 == cache_id).count();
+            let resident = edges[i..end].iter().find(|e| self.is_cached(e.1));
+            let resident_count = resident.map_or(0, |e| e.2);
+            out.old_hits_ += resident_count;
+            out.new_hits_ += edges[i].2;
+            if edges[i].2 > resident_count {
+                let edge = edges[i].1;
+                self.cache_[cache_id].set_parent(edge.parent as u32);
+                self.cache_[cache_id].set_child(edge.child as u32);
+                let filled = self.fill_cache_entry(cache_id);
+                debug_assert!(filled);
+            }
+            i = end;
+        }
+        out
+    }
+
+    /// Walks down the root trie along `query` as `lookup` does, counting
+    /// the edges taken.
+    fn count_steps(&self, query: &[u8], counts: &mut HashMap<Edge, u64>) {
+        if !query.starts_with(&self.common_prefix_) {
+            return;
+        }
+        let mut node_id: usize = 0;
+        let mut query_pos: usize = self.common_prefix_.len();
+        while query_pos < query.len() {
+            let label = query[query_pos];
+            match self.find_child(node_id, query, &mut query_pos) {
+                Some(child_id) => {
+                    let edge = Edge { parent: node_id, child: child_id,
+                                      label: label };
+                    *counts.entry(edge).or_insert(0) += 1;
+                    node_id = child_id;
+                },
+                None => return,
+            }
+        }
+    }
+
+    fn is_cached(&self, edge: Edge) -> bool {
+        let item = &self.cache_[self.get_cache_id_with_label(edge.parent,
+                                                             edge.label)];
+        item.parent() as usize == edge.parent
+        && item.child() as usize == edge.child
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use config::Config;
+    use keyset::Keyset;
+    use louds_trie::LoudsTrie;
+    use super::CacheRetune;
+
+    /// Keys sharing few prefixes, so the root trie has many more edges than
+    /// cache slots.
+    fn many_keys() -> Keyset {
+        let mut keyset = Keyset::new();
+        for i in 0..20000u64 {
+            keyset.push(format!("{:x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+                        .as_bytes());
+        }
+        keyset
+    }
+
+    fn results(trie: &LoudsTrie, queries: &[Vec<u8>])
+      -> Vec<(Option<usize>, Vec<(usize, usize)>, usize)> {
+        queries.iter().map(|q| {
+            (trie.lookup(q), trie.common_prefix_search(q).collect(),
+             trie.predictive_search(&q[..q.len() / 2]).count())
+        }).collect()
+    }
+
+    #[test]
+    fn skewed_sample_raises_hit_rate() {
+        let _ = env_logger::init();
+        let mut keyset = many_keys();
+        let mut trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let mut queries: Vec<Vec<u8>> = keyset.iter().map(|k| k.to_vec())
+                                              .collect();
+        queries.push(b"not a key".to_vec());
+        let before = results(&trie, &queries);
+
+        // A few keys queried over and over, and one miss.
+        let hot: Vec<&[u8]> = (0..50).flat_map(|_| {
+            queries.iter().step_by(397).map(|q| &q[..])
+        }).collect();
+        let retune = trie.retune_cache(&hot);
+        assert!(retune.num_steps() > 0);
+        assert!(retune.old_hit_rate() < retune.new_hit_rate(), "{:?}",
+                retune);
+        assert_eq!(trie.validate(), Ok(()));
+        trie.set_paranoia(true);
+        assert_eq!(results(&trie, &queries), before);
+
+        // Retuning to the same sample again changes nothing.
+        let again = trie.retune_cache(&hot);
+        assert_eq!(again.old_hit_rate(), retune.new_hit_rate());
+        assert_eq!(again.new_hit_rate(), retune.new_hit_rate());
+
+        let mut bytes = Vec::new();
+        trie.write_to(&mut bytes).unwrap();
+        let mut read = LoudsTrie::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(read.retune_cache(&hot), again);
+        assert_eq!(results(&read, &queries), before);
+    }
+
+    #[test]
+    fn empty_sample_changes_nothing() {
+        let _ = env_logger::init();
+        let mut keyset = many_keys();
+        let mut trie = LoudsTrie::build(&mut keyset, &Config::new()).unwrap();
+        let cache = trie.cache_.iter().map(|c| (c.parent(), c.child()))
+                               .collect::<Vec<_>>();
+        let none = CacheRetune { num_steps_: 0, old_hits_: 0, new_hits_: 0 };
+        assert_eq!(trie.retune_cache(&[]), none);
+        assert_eq!(trie.retune_cache(&[&b"zzz not a key"[..]]).old_hit_rate(),
+                   0.0);
+        assert_eq!(trie.cache_.iter().map(|c| (c.parent(), c.child()))
+                              .collect::<Vec<_>>(), cache);
+    }
+}
@@ -0,0 +1,179 @@
+//! The table a native payload starts with, of where each of its sections
+//! starts: the bit vectors, vectors and tail of each trie in the order
+//! `write_` writes them, then the common prefix and the weights. Offsets
+//! are from the start of the payload, which is 8-byte aligned in the file,
+//! and are multiples of 8, so a section of an aligned buffer is aligned
+//! too. The bytes from the end of one section to the start of the next are
+//! zeros, and readers go by the table rather than by where a section ends.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::io;
+use base::checked_usize;
+use vector::util::{invalid_data, read_bytes, read_len, read_u64, write_u64};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SectionTable {
+    offsets_: Vec<u64>,
+    /// The section `next` gives the offset of.
+    next_: usize,
+}
+
+impl SectionTable {
+    /// The table for sections of `lens` bytes, put one after another
+    /// behind the table. Every length is a multiple of 8, as the vectors
+    /// pad themselves, so no padding goes between them.
+    pub fn new(lens: &[usize]) -> SectionTable {
+        let mut pos = 8 + 8 * lens.len();
+        let mut offsets = Vec::with_capacity(lens.len());
+        for &len in lens {
+            debug_assert!(len % 8 == 0, "MARISA_FORMAT_ERROR");
+            offsets.push(pos as u64);
+            pos += len;
+        }
+        SectionTable { offsets_: offsets, next_: 0 }
+    }
+
+    /// The number of bytes `write` produces.
+    pub fn io_size(&self) -> usize {
+        8 + 8 * self.offsets_.len()
+    }
+
+    pub fn write<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u64(w, self.offsets_.len() as u64)?;
+        for &offset in &self.offsets_ {
+            write_u64(w, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a table from the start of a payload of `payload_len` bytes,
+    /// checking that its offsets are aligned, in order and inside the
+    /// payload past the table.
+    pub fn read<R: io::Read>(r: &mut R, payload_len: u64)
+      -> io::Result<SectionTable> {
+        let len = read_len(r)?;
+        if len as u64 >= payload_len / 8 {
+            return Err(invalid_data("section table"));
+        }
+        let mut offsets = Vec::new();
+        let mut pos = 8 + 8 * len as u64;
+        for _ in 0..len {
+            let offset = read_u64(r)?;
+            if offset % 8 != 0 || offset < pos || offset > payload_len {
+                return Err(invalid_data("section table"));
+            }
+            offsets.push(offset);
+            pos = offset;
+        }
+        Ok(SectionTable { offsets_: offsets, next_: 0 })
+    }
+
+    /// The number of zeros from `pos`, where the last section read ended,
+    /// to the start of the next section.
+    pub fn next(&mut self, pos: u64) -> io::Result<u64> {
+        match self.offsets_.get(self.next_) {
+            Some(&offset) if offset >= pos => {
+                self.next_ += 1;
+                Ok(offset - pos)
+            },
+            _ => Err(invalid_data("section table")),
+        }
+    }
+
+    /// Whether every section has been read.
+    pub fn is_done(&self) -> bool {
+        self.next_ == self.offsets_.len()
+    }
+}
+
+/// Reads the `len` zeros in front of a section.
+pub fn skip_padding<R: io::Read>(r: &mut R, len: u64) -> io::Result<()> {
+    let len = checked_usize(len)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if read_bytes(r, len)?.iter().any(|&b| b != 0) {
+        return Err(invalid_data("section padding"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use super::{SectionTable, skip_padding};
+
+    fn written(table: &SectionTable) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn io_round_trip() {
+        let _ = env_logger::init();
+        fn prop(lens: Vec<u8>) -> bool {
+            let lens: Vec<usize> = lens.iter().map(|&n| 8 * n as usize)
+                                       .collect();
+            let table = SectionTable::new(&lens);
+            let bytes = written(&table);
+            let payload_len = (bytes.len() + lens.iter().sum::<usize>())
+                              as u64;
+            let mut read = SectionTable::read(&mut &bytes[..], payload_len)
+                           .unwrap();
+            let mut pos = bytes.len() as u64;
+            bytes.len() == table.io_size() && read == table
+            && lens.iter().all(|&len| {
+                let ok = read.next(pos).unwrap() == 0;
+                pos += len as u64;
+                ok
+            })
+            && read.is_done() && read.next(pos).is_err()
+        }
+        qc::quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn rejects_bad_offsets() {
+        let _ = env_logger::init();
+        let table = SectionTable::new(&[8, 16]);
+        let bytes = written(&table);
+        assert_eq!(bytes[8..], [24, 0, 0, 0, 0, 0, 0, 0,
+                                32, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert!(SectionTable::read(&mut &bytes[..], 48).is_ok());
+        // Past the end of the payload.
+        assert!(SectionTable::read(&mut &bytes[..], 31).is_err());
+        for len in 0..bytes.len() {
+            assert!(SectionTable::read(&mut &bytes[..len], 48).is_err());
+        }
+        // Misaligned, inside the table, and out of order.
+        for &(i, offset) in &[(8, 25), (8, 16), (16, 16)] {
+            let mut bad = bytes.clone();
+            bad[i] = offset;
+            assert!(SectionTable::read(&mut &bad[..], 48).is_err(),
+                    "offset {} at {}", offset, i);
+        }
+        // A count the payload has no room for.
+        let mut bad = bytes.clone();
+        bad[7] = 0x10;
+        assert!(SectionTable::read(&mut &bad[..], 48).is_err());
+    }
+
+    #[test]
+    fn sections_are_read_in_order() {
+        let _ = env_logger::init();
+        let mut bytes = Vec::new();
+        SectionTable::new(&[8]).write(&mut bytes).unwrap();
+        // The section is moved 8 bytes further on.
+        bytes[8] += 8;
+        let mut table = SectionTable::read(&mut &bytes[..], 40).unwrap();
+        assert!(table.clone().next(32).is_err());
+        assert_eq!(table.next(16).unwrap(), 8);
+        assert!(table.is_done());
+        assert!(skip_padding(&mut &[0u8; 8][..], 8).is_ok());
+        assert!(skip_padding(&mut &[0, 0, 1][..], 3).is_err());
+        assert!(skip_padding(&mut &[0u8; 2][..], 3).is_err());
+    }
+}
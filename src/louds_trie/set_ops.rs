@@ -0,0 +1,325 @@
+//! The keys two tries share, and the keys one has that the other lacks,
+//! found by walking both tries at once rather than looking up every key of
+//! one in the other.
+//!
+//! A cursor walks the first trie depth first. Each node's label is matched
+//! in the second trie from where its parent's label left off, which can be
+//! part way through one of the second trie's labels: the two tries split
+//! their tails at different points. A subtree whose label stops matching is
+//! skipped by an intersection and listed whole by a difference.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use super::LoudsTrieView;
+use super::nav::Nav;
+
+/// Moves `nav`, whose string starts with `s[..pos]`, down until its string
+/// starts with all of `s`. Returns false if no key of its trie starts with
+/// `s`, leaving `nav` wherever the match stopped.
+fn advance(nav: &mut Nav, s: &[u8], mut pos: usize) -> bool {
+    while pos < s.len() {
+        if pos == nav.get_u8().len() {
+            if !nav.go_to_child() {
+                return false;
+            }
+            // Siblings differ in the first byte of their labels.
+            while nav.get_u8()[pos] != s[pos] {
+                if !nav.go_to_sibling() {
+                    return false;
+                }
+            }
+        }
+        let buf = nav.get_u8();
+        while pos < s.len() && pos < buf.len() {
+            if buf[pos] != s[pos] {
+                return false;
+            }
+            pos += 1;
+        }
+    }
+    true
+}
+
+fn back_to(nav: &mut Nav, depth: usize) {
+    while nav.depth() > depth {
+        nav.go_to_parent();
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Step {
+    Visit,
+    Child,
+    Sibling,
+    Done,
+}
+
+/// The walk behind `Intersection` and `Difference`.
+#[derive(Debug)]
+struct DualWalk<'a> {
+    a_: Nav<'a>,
+    b_: Nav<'a>,
+    /// For each node on `a_`'s path: where its label starts, how deep `b_`
+    /// was before the label was matched, and whether it matched.
+    path_: Vec<(usize, usize, bool)>,
+    /// Whether to walk the subtrees that stopped matching.
+    unmatched_: bool,
+    step_: Step,
+}
+
+impl<'a> DualWalk<'a> {
+    fn new(a: &'a LoudsTrieView<'a>, b: &'a LoudsTrieView<'a>,
+           unmatched: bool) -> DualWalk<'a> {
+        let mut out = DualWalk { a_: Nav::new(a), b_: Nav::new(b),
+                                 path_: Vec::new(), unmatched_: unmatched,
+                                 step_: Step::Visit };
+        out.enter(0, true);
+        out
+    }
+
+    /// Matches the label `a_` has just moved to, which starts at `from`.
+    fn enter(&mut self, from: usize, parent_matched: bool) {
+        let depth = self.b_.depth();
+        let matched = parent_matched
+                      && advance(&mut self.b_, self.a_.get_u8(), from);
+        if !matched {
+            back_to(&mut self.b_, depth);
+        }
+        self.path_.push((from, depth, matched));
+        self.step_ = if matched || self.unmatched_ {
+            Step::Visit
+        } else {
+            Step::Sibling
+        };
+    }
+
+    /// The id of the next key of `a_`'s trie, and the id of the same key
+    /// in `b_`'s trie if it has it. The key is `a_.get_u8()`.
+    fn next(&mut self) -> Option<(usize, Option<usize>)> {
+        loop {
+            match self.step_ {
+                Step::Visit => {
+                    self.step_ = Step::Child;
+                    if let Some(id) = self.a_.key_id() {
+                        // `b_` can have matched part of a longer label.
+                        let len = self.a_.get_u8().len();
+                        let whole = self.path_.last().unwrap().2
+                                    && self.b_.get_u8().len() == len;
+                        return Some((id, if whole { self.b_.key_id() }
+                                         else { None }));
+                    }
+                },
+                Step::Child => {
+                    let from = self.a_.get_u8().len();
+                    let matched = self.path_.last().unwrap().2;
+                    if self.a_.go_to_child() {
+                        self.enter(from, matched);
+                    } else {
+                        self.step_ = Step::Sibling;
+                    }
+                },
+                Step::Sibling => {
+                    let (from, depth, _) = self.path_.pop().unwrap();
+                    back_to(&mut self.b_, depth);
+                    if self.path_.is_empty() {
+                        self.step_ = Step::Done;
+                    } else if self.a_.go_to_sibling() {
+                        let matched = self.path_.last().unwrap().2;
+                        self.enter(from, matched);
+                    } else {
+                        self.a_.go_to_parent();
+                    }
+                },
+                Step::Done => return None,
+            }
+        }
+    }
+}
+
+/// Iterator returned by `LoudsTrie::intersect`.
+#[derive(Debug)]
+pub struct Intersection<'a>(DualWalk<'a>);
+
+impl<'a> Iterator for Intersection<'a> {
+    type Item = (Vec<u8>, usize, Option<usize>);
+
+    fn next(&mut self) -> Option<(Vec<u8>, usize, Option<usize>)> {
+        while let Some((id, other)) = self.0.next() {
+            if other.is_some() {
+                return Some((self.0.a_.get_u8().to_vec(), id, other));
+            }
+        }
+        None
+    }
+}
+
+/// Iterator returned by `LoudsTrie::difference`.
+#[derive(Debug)]
+pub struct Difference<'a>(DualWalk<'a>);
+
+impl<'a> Iterator for Difference<'a> {
+    type Item = (Vec<u8>, usize, Option<usize>);
+
+    fn next(&mut self) -> Option<(Vec<u8>, usize, Option<usize>)> {
+        while let Some((id, other)) = self.0.next() {
+            if other.is_none() {
+                return Some((self.0.a_.get_u8().to_vec(), id, None));
+            }
+        }
+        None
+    }
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// Returns an iterator over the keys that both `self` and `other` hold.
+    /// Each item is `(key, id, other_id)`, where `other_id` is the key's id
+    /// in `other` and is never None. Keys come in the order of a depth
+    /// first walk of `self`, which is ascending byte order if it was built
+    /// with `NodeOrder::Label`.
+    ///
+    /// ```
+    /// use mars_trie::{Config, LoudsTrie};
+    ///
+    /// let old = LoudsTrie::build_from(vec!["apple", "banana", "cherry"],
+    ///                                 &Config::new()).unwrap();
+    /// let new = LoudsTrie::build_from(vec!["banana", "cherry", "date"],
+    ///                                 &Config::new()).unwrap();
+    /// let mut kept: Vec<Vec<u8>> = old.intersect(&new)
+    ///     .map(|(key, _, _)| key).collect();
+    /// kept.sort();
+    /// assert_eq!(kept, vec![b"banana".to_vec(), b"cherry".to_vec()]);
+    /// let added: Vec<Vec<u8>> = new.difference(&old)
+    ///     .map(|(key, _, _)| key).collect();
+    /// assert_eq!(added, vec![b"date".to_vec()]);
+    /// ```
+    pub fn intersect<'a, 'o>(&'a self, other: &'a LoudsTrieView<'o>)
+      -> Intersection<'a> {
+        Intersection(DualWalk::new(self, other, false))
+    }
+
+    /// Returns an iterator over the keys that `self` holds and `other` does
+    /// not, in the same order and form as `intersect`, with `other_id`
+    /// always None. With two snapshots of a dictionary,
+    /// `old.difference(&new)` is the keys removed and `new.difference(&old)`
+    /// the keys added.
+    pub fn difference<'a, 'o>(&'a self, other: &'a LoudsTrieView<'o>)
+      -> Difference<'a> {
+        Difference(DualWalk::new(self, other, true))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use config::{Config, NodeOrder, NumTries, TailMode};
+    use super::super::LoudsTrie;
+
+    type Item = (Vec<u8>, usize, Option<usize>);
+
+    /// What the walks must find, from every key of `a` looked up in `b`.
+    fn expected(a: &LoudsTrie, b: &LoudsTrie) -> (Vec<Item>, Vec<Item>) {
+        let (mut both, mut only) = (Vec::new(), Vec::new());
+        for (id, key) in a.iter() {
+            match b.lookup(&key) {
+                Some(other) => both.push((key, id, Some(other))),
+                None => only.push((key, id, None)),
+            }
+        }
+        both.sort();
+        only.sort();
+        (both, only)
+    }
+
+    fn sorted<I: Iterator<Item=Item>>(iter: I) -> Vec<Item> {
+        let mut out: Vec<Item> = iter.collect();
+        out.sort();
+        out
+    }
+
+    fn agrees(a: &LoudsTrie, b: &LoudsTrie) -> bool {
+        let (both, only) = expected(a, b);
+        sorted(a.intersect(b)) == both && sorted(a.difference(b)) == only
+    }
+
+    fn set_ops_prop(shared: Vec<Vec<u8>>, only_a: Vec<Vec<u8>>,
+                    only_b: Vec<Vec<u8>>, config_a: Config,
+                    config_b: Config) -> bool {
+        let a = LoudsTrie::build_from(
+            shared.iter().chain(&only_a).cloned(), &config_a).unwrap();
+        let b = LoudsTrie::build_from(
+            shared.iter().chain(&only_b).cloned(), &config_b).unwrap();
+        agrees(&a, &b) && agrees(&b, &a)
+    }
+
+    #[test]
+    fn set_ops_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(set_ops_prop
+                       as fn(Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<Vec<u8>>,
+                             Config, Config) -> bool);
+    }
+
+    /// Keys with long shared stretches, so that both tries have long tails
+    /// and links, split where each trie's own keys branch.
+    fn keys(from: usize, to: usize) -> Vec<String> {
+        (from..to).map(|i| {
+            format!("https://example.com/{}/item-{}/{}", i % 7, i % 50, i)
+        }).collect()
+    }
+
+    #[test]
+    fn tails_split_at_different_points() {
+        let _ = env_logger::init();
+        let a_keys = keys(0, 600);
+        let mut b_keys = keys(400, 1000);
+        b_keys.extend(vec!["https://example.com/3/item-3".to_owned(),
+                           "https://example.com/3/item-33/".to_owned(),
+                           "https://example.com/3/item-3/30".to_owned()]);
+        for &num_tries in &[1, 3] {
+            for &mode in &[TailMode::Text, TailMode::Binary] {
+                for &order in &[NodeOrder::Label, NodeOrder::Weight] {
+                    let config = Config::new()
+                        .with_num_tries(NumTries::new(num_tries))
+                        .with_tail_mode(mode).with_node_order(order);
+                    let a = LoudsTrie::build_from(a_keys.iter(), &config)
+                            .unwrap();
+                    let b = LoudsTrie::build_from(b_keys.iter(),
+                                                  &Config::new()).unwrap();
+                    assert!(agrees(&a, &b));
+                    assert!(agrees(&b, &a));
+                    assert_eq!(a.intersect(&b).count(), 200);
+                    assert_eq!(a.difference(&b).count(), 400);
+                    assert_eq!(b.difference(&a).count(), 403);
+                    if order == NodeOrder::Label {
+                        let keys: Vec<Vec<u8>> = a.intersect(&b)
+                            .map(|(key, _, _)| key).collect();
+                        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edge_cases() {
+        let _ = env_logger::init();
+        let build = |keys: &[&str]| {
+            LoudsTrie::build_from(keys.iter().cloned(), &Config::new()).unwrap()
+        };
+        let empty = build(&[]);
+        let a = build(&["", "a", "ab", "abc"]);
+        // Only a common prefix, which one trie strips and the other keeps.
+        let b = build(&["prefix-1", "prefix-2"]);
+        let c = build(&["prefix-1", "prefix", "p"]);
+        for &(x, y) in &[(&empty, &a), (&a, &empty), (&a, &a), (&b, &c),
+                         (&c, &b), (&a, &c), (&b, &b)] {
+            assert!(agrees(x, y));
+        }
+        assert_eq!(a.intersect(&a).count(), 4);
+        assert_eq!(b.intersect(&c).map(|(key, _, _)| key).collect::<Vec<_>>(),
+                   vec![b"prefix-1".to_vec()]);
+    }
+}
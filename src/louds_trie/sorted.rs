@@ -0,0 +1,427 @@
+//! Building a dictionary from keys that arrive in sorted order, without
+//! collecting them into a Keyset first.
+//!
+//! The first trie is laid out breadth first, and a key that comes later can
+//! still split the label of a node that is deep in the trie, so no part of
+//! the trie is final before the last key. What is kept instead of the keys
+//! is the trie itself: one node per branch or key end, and the label bytes
+//! of each node once, in a shared pool. Keys that share prefixes, which is
+//! what sorted input looks like, cost little more than their suffixes.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use std::collections::VecDeque;
+use base::checked_u32;
+use config::{Config, NodeOrder};
+use error::MarisaError;
+use key::Key;
+use keyio::KeyDisplay;
+use keyset::KeyItem;
+use vector::bit_vec::BitVec;
+use super::{LoudsTrie, MIN_COMMON_PREFIX};
+
+const NONE: u32 = std::u32::MAX;
+
+struct Node {
+    label_begin_: usize,
+    label_len_: u32,
+    first_child_: u32,
+    next_sibling_: u32,
+    /// The summed weight of the keys below, once the node is closed.
+    weight_: f32,
+    /// The weight of the key ending here, if one does.
+    key_weight_: f32,
+    terminal_: bool,
+}
+
+impl Node {
+    fn new(label_begin: usize, label_len: usize) -> Node {
+        Node { label_begin_: label_begin, label_len_: label_len as u32,
+               first_child_: NONE, next_sibling_: NONE, weight_: 0.0,
+               key_weight_: 0.0, terminal_: false }
+    }
+}
+
+/// The trie of the keys pushed so far.
+struct SortedTrie {
+    labels_: Vec<u8>,
+    nodes_: Vec<Node>,
+    /// The nodes on the path of the last key, root first: each with where
+    /// its label ends in the key and the weights of its keys summed so far.
+    frontier_: Vec<(u32, usize, f64)>,
+    last_: Vec<u8>,
+    num_keys_: usize,
+    common_len_: usize,
+}
+
+impl SortedTrie {
+    fn new() -> SortedTrie {
+        SortedTrie { labels_: Vec::new(), nodes_: vec![Node::new(0, 0)],
+                     frontier_: vec![(0, 0, 0.0)], last_: Vec::new(),
+                     num_keys_: 0, common_len_: 0 }
+    }
+
+    fn push(&mut self, key: &[u8], weight: f32) -> Result<(), MarisaError> {
+        let lcp = self.last_.iter().zip(key.iter())
+                      .take_while(|&(a, b)| a == b).count();
+        let reason = if !(weight >= 0.0) {
+            Some("has a negative or NaN weight")
+        } else if key.len() > std::u32::MAX as usize {
+            Some("is too long")
+        } else if self.num_keys_ > 0 && key == &self.last_[..] {
+            Some("is a duplicate of the key before it")
+        } else if self.num_keys_ > 0 && (lcp == key.len()
+                  || lcp < self.last_.len() && key[lcp] < self.last_[lcp]) {
+            Some("sorts before the key before it")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(MarisaError::InvalidKey {
+                index: self.num_keys_ as u64, reason: reason,
+                excerpt: KeyDisplay::new(key).to_string() });
+        }
+        checked_u32(self.nodes_.len() + 1, "number of nodes")?;
+
+        // Close the nodes that the new key leaves, deepest first. The last
+        // one closed is the last child of the node the key branches from.
+        let mut last_child = NONE;
+        while self.frontier_.len() > 1 {
+            let begin = self.frontier_[self.frontier_.len() - 2].1;
+            if begin < lcp {
+                break;
+            }
+            let (node, _, sum) = self.frontier_.pop().unwrap();
+            self.nodes_[node as usize].weight_ = sum as f32;
+            last_child = node;
+        }
+        let top = self.frontier_.len() - 1;
+        let (node, end, sum) = self.frontier_[top];
+        if end > lcp {
+            // The key branches off inside the label of `node`: its lower
+            // part moves to a new child, which already has all its keys.
+            let cut = lcp - self.frontier_[top.saturating_sub(1)].1;
+            let new = self.nodes_.len() as u32;
+            let lower = {
+                let upper = &mut self.nodes_[node as usize];
+                let lower = Node {
+                    label_begin_: upper.label_begin_ + cut,
+                    label_len_: upper.label_len_ - cut as u32,
+                    first_child_: upper.first_child_,
+                    next_sibling_: NONE,
+                    weight_: sum as f32,
+                    key_weight_: upper.key_weight_,
+                    terminal_: upper.terminal_,
+                };
+                upper.label_len_ = cut as u32;
+                upper.first_child_ = new;
+                upper.key_weight_ = 0.0;
+                upper.terminal_ = false;
+                lower
+            };
+            last_child = new;
+            self.nodes_.push(lower);
+            self.frontier_[top].1 = lcp;
+        }
+
+        let (node, _, _) = self.frontier_[top];
+        if self.num_keys_ == 0 && key.is_empty() {
+            // Only the first key can end at the root.
+            let root = &mut self.nodes_[0];
+            root.terminal_ = true;
+            root.key_weight_ = 0.0 + weight;
+        } else {
+            let leaf = self.nodes_.len() as u32;
+            let mut new = Node::new(self.labels_.len(), key.len() - lcp);
+            new.terminal_ = true;
+            new.key_weight_ = 0.0 + weight;
+            self.labels_.extend_from_slice(&key[lcp..]);
+            self.nodes_.push(new);
+            if last_child == NONE {
+                self.nodes_[node as usize].first_child_ = leaf;
+            } else {
+                self.nodes_[last_child as usize].next_sibling_ = leaf;
+            }
+            // Summed from the first key, as the batch build does.
+            for entry in &mut self.frontier_[1..] {
+                entry.2 += weight as f64;
+            }
+            self.frontier_.push((leaf, key.len(), weight as f64));
+        }
+
+        self.common_len_ = match self.num_keys_ {
+            0 => key.len(),
+            _ => std::cmp::min(self.common_len_, lcp),
+        };
+        self.num_keys_ += 1;
+        self.last_.clear();
+        self.last_.extend_from_slice(key);
+        Ok(())
+    }
+
+    /// Closes the nodes of the last key.
+    fn finish(&mut self) {
+        while let Some((node, _, sum)) = self.frontier_.pop() {
+            self.nodes_[node as usize].weight_ = sum as f32;
+        }
+        self.last_ = Vec::new();
+    }
+}
+
+fn label<'a>(labels: &'a [u8], node: &Node) -> &'a [u8] {
+    &labels[node.label_begin_..][..node.label_len_ as usize]
+}
+
+/// The children of `node`, in the order they get node ids.
+fn ordered_children(nodes: &[Node], node: u32, order: NodeOrder) -> Vec<u32> {
+    let mut children = Vec::new();
+    let mut child = nodes[node as usize].first_child_;
+    while child != NONE {
+        children.push(child);
+        child = nodes[child as usize].next_sibling_;
+    }
+    if let NodeOrder::Weight = order {
+        // Stable, so that ties stay in label order.
+        children.sort_by(|&a, &b| {
+            nodes[b as usize].weight_.partial_cmp(&nodes[a as usize].weight_)
+                .unwrap()
+        });
+    }
+    children
+}
+
+/// Lays out `trie` as `build_current_trie` would lay out its keys, and
+/// builds the rest of the dictionary from there.
+fn build_trie(trie: SortedTrie, config: &Config)
+  -> Result<LoudsTrie, MarisaError> {
+    let SortedTrie { labels_: labels, nodes_: nodes, num_keys_: num_keys,
+                     common_len_: common_len, .. } = trie;
+    checked_u32(num_keys, "number of keys")?;
+    let mut out = LoudsTrie::new();
+    let mut root = 0;
+    if num_keys > 0 && common_len >= MIN_COMMON_PREFIX {
+        // All keys go through the only child of the root, whose label is
+        // their common prefix.
+        root = nodes[0].first_child_;
+        out.common_prefix_ =
+            label(&labels, &nodes[root as usize]).to_vec().into();
+    }
+
+    out.reserve_cache(config, 1, num_keys);
+    out.louds_.push(true);
+    out.louds_.push(false);
+    out.bases_.push(0);
+    out.link_flags_.push(false);
+
+    let mut next_keys: Vec<Key> = Vec::new();
+    let mut terminal_flags = BitVec::new();
+    let mut weights: Vec<f32> = Vec::new();
+    let mut queue: VecDeque<u32> = VecDeque::new();
+    queue.push_back(root);
+    let mut node_id: usize = 0;
+    while let Some(node) = queue.pop_front() {
+        checked_u32(node_id, "number of nodes")?;
+        let terminal = nodes[node as usize].terminal_;
+        terminal_flags.push(terminal);
+        if terminal && config.weights() {
+            weights.push(nodes[node as usize].key_weight_);
+        }
+
+        let children = ordered_children(&nodes, node, config.node_order());
+        if node_id == 0 {
+            out.num_l1_nodes_ = children.len();
+        }
+        for &child in &children {
+            let label = label(&labels, &nodes[child as usize]);
+            let weight = nodes[child as usize].weight_;
+            let bases_len = out.bases_.len();
+            out.cache_fwd(node_id, bases_len, weight, label[0]);
+            if label.len() == 1 {
+                out.bases_.push(label[0]);
+                out.link_flags_.push(false);
+            } else {
+                out.bases_.push(0);
+                out.link_flags_.push(true);
+                next_keys.push(Key::new(label).with_weight(weight));
+            }
+            queue.push_back(child);
+        }
+        out.louds_.push_run(true, children.len());
+        out.louds_.push(false);
+        node_id += 1;
+    }
+    out.louds_.push(false);
+    out.louds_.build_index(true, true);
+    out.bases_.shrink_to_fit();
+    drop(nodes);
+
+    out.build_links(&mut next_keys, config, 1)?;
+
+    terminal_flags.push(false);
+    terminal_flags.build_index(false, config.reverse_lookup());
+    out.terminal_flags_ = terminal_flags;
+    out.weights_ = weights.into();
+    out.config_.set_weights(config.weights());
+    out.config_.set_reverse_lookup(config.reverse_lookup());
+    Ok(out)
+}
+
+pub fn build<I>(keys: I, config: &Config) -> Result<LoudsTrie, MarisaError>
+  where I: IntoIterator, I::Item: KeyItem {
+    let mut trie = SortedTrie::new();
+    for item in keys {
+        trie.push(item.key_bytes(), item.key_weight())?;
+    }
+    trie.finish();
+    build_trie(trie, config)
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use alloc_counter;
+    use config::{Config, NodeOrder, NumTries, TailMode};
+    use error::MarisaError;
+    use keyset::Keyset;
+    use louds_trie::{LoudsTrie, LoudsTrieView};
+
+    fn write_bytes(trie: &LoudsTrieView) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        trie.write_to_(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn configs() -> Vec<Config> {
+        vec![Config::new(),
+             Config::new().with_num_tries(NumTries::new(1)),
+             Config::new().with_num_tries(NumTries::new(5))
+                          .with_tail_mode(TailMode::Binary),
+             Config::new().with_node_order(NodeOrder::Label),
+             Config::new().with_weights(true),
+             Config::new().with_reverse_lookup(false)]
+    }
+
+    /// `keys`, sorted and without duplicates, build the same dictionary
+    /// either way.
+    fn same_as_batch(keys: &[(Vec<u8>, f32)], config: &Config) -> bool {
+        let mut keyset = Keyset::new();
+        for &(ref key, weight) in keys {
+            keyset.push_weighted(key, weight);
+        }
+        let batch = LoudsTrie::build(&mut keyset, config).unwrap();
+        let sorted = LoudsTrie::build_from_sorted(
+            keys.iter().map(|&(ref key, weight)| (&key[..], weight)), config)
+            .unwrap();
+        write_bytes(&batch) == write_bytes(&sorted)
+    }
+
+    fn weighted<K: AsRef<[u8]>>(keys: &[K]) -> Vec<(Vec<u8>, f32)> {
+        keys.iter().enumerate().map(|(i, key)| {
+            (key.as_ref().to_vec(), (i % 3) as f32 + 0.5)
+        }).collect()
+    }
+
+    #[test]
+    fn same_as_batch_build() {
+        let _ = env_logger::init();
+        let stem = "a common stem longer than thirty-two bytes/";
+        let words: Vec<String> = (0..3000).map(|i| {
+            format!("{}{}", ["", "pre", "prefix", "x"][i % 4], i * 7919 % 5000)
+        }).collect();
+        let mut words: Vec<&str> = words.iter().map(|w| &w[..]).collect();
+        words.sort();
+        words.dedup();
+        let stemmed: Vec<String> = words.iter().map(|w| {
+            format!("{}{}", stem, w)
+        }).collect();
+        let mut stemmed: Vec<&str> = stemmed.iter().map(|w| &w[..])
+                                            .collect();
+        stemmed.insert(0, &stem[..stem.len() - 1]);
+        let sets = vec![
+            weighted::<&str>(&[]),
+            weighted(&[""]),
+            weighted(&[&b"abcd"[..], b"abce", b"abd", b"b\x00", b"b\x00\xff",
+                       b"\xff"]),
+            weighted(&["", "a", "ab", "abc", "b"]),
+            weighted(&[stem]),
+            weighted(&[stem, &stemmed[1]]),
+            weighted(&words),
+            weighted(&stemmed),
+        ];
+        for keys in &sets {
+            for config in &configs() {
+                assert!(same_as_batch(keys, config), "{:?} {:?}",
+                        keys.len(), config);
+            }
+        }
+    }
+
+    #[test]
+    fn same_as_batch_build_qc() {
+        let _ = env_logger::init();
+        fn prop(keyset: Keyset) -> bool {
+            let mut keys: Vec<(Vec<u8>, f32)> = keyset.iter().enumerate()
+                .map(|(i, key)| (key.to_vec(), keyset.weight(i))).collect();
+            keys.sort_by(|a, b| a.0.cmp(&b.0));
+            keys.dedup_by(|a, b| a.0 == b.0);
+            configs().iter().all(|config| same_as_batch(&keys, config))
+        }
+        qc::quickcheck(prop as fn(Keyset) -> bool);
+    }
+
+    #[test]
+    fn rejects_unsorted_input() {
+        let _ = env_logger::init();
+        let config = Config::new();
+        let err = |index: u64, reason: &'static str, excerpt: &str| {
+            MarisaError::InvalidKey { index: index, reason: reason,
+                                      excerpt: excerpt.to_owned() }
+        };
+        assert_eq!(LoudsTrie::build_from_sorted(vec!["a", "c", "b"], &config)
+                       .unwrap_err(),
+                   err(2, "sorts before the key before it", "b"));
+        assert_eq!(LoudsTrie::build_from_sorted(vec!["ab", "a"], &config)
+                       .unwrap_err(),
+                   err(1, "sorts before the key before it", "a"));
+        assert_eq!(LoudsTrie::build_from_sorted(vec!["", "a", "a"], &config)
+                       .unwrap_err(),
+                   err(2, "is a duplicate of the key before it", "a"));
+        assert_eq!(LoudsTrie::build_from_sorted(vec!["", ""], &config)
+                       .unwrap_err(),
+                   err(1, "is a duplicate of the key before it", ""));
+        assert_eq!(LoudsTrie::build_from_sorted(vec![("a", 1.0), ("b", -1.0)],
+                                                &config).unwrap_err(),
+                   err(1, "has a negative or NaN weight", "b"));
+    }
+
+    #[test]
+    #[ignore]
+    fn build_from_sorted_memory() {
+        let _ = env_logger::init();
+        // Sorted keys sharing long stems, like the paths of a web site.
+        let keys = || (0..2000000u64).map(|i| {
+            format!("https://example.org/archive/{:04}/{:04}/{}", i / 5000,
+                    i / 5 % 1000, ["alpha", "beta", "delta", "gamma", "pi"]
+                                  [i as usize % 5])
+        });
+        let config = Config::new();
+        let mut batch_bytes = Vec::new();
+        let batch = alloc_counter::peak_bytes(|| {
+            let mut keyset: Keyset = keys().collect();
+            let trie = LoudsTrie::build(&mut keyset, &config).unwrap();
+            batch_bytes = write_bytes(&trie);
+        });
+        let mut sorted_bytes = Vec::new();
+        let sorted = alloc_counter::peak_bytes(|| {
+            let trie = LoudsTrie::build_from_sorted(keys(), &config).unwrap();
+            sorted_bytes = write_bytes(&trie);
+        });
+        info!("peak bytes: {} batch, {} sorted", batch, sorted);
+        assert!(batch_bytes == sorted_bytes);
+        assert!(sorted < batch, "{} >= {}", sorted, batch);
+    }
+}
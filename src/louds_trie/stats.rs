@@ -0,0 +1,252 @@
+//! Where the bytes of a dictionary go, to compare configs by what they
+//! build rather than by guessing.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::fmt;
+use header::Header;
+use vector::io::SerializableVec;
+use super::LoudsTrieView;
+use super::levels::LevelStats;
+
+/// The bytes `write_to` spends on each kind of section, summed over the
+/// nested tries. They add up to `LoudsTrie::io_size`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SectionSizes {
+    pub header: usize,
+    /// Where each of the sections after it starts.
+    pub section_table: usize,
+    pub louds: usize,
+    pub terminal_flags: usize,
+    pub link_flags: usize,
+    pub bases: usize,
+    pub extras: usize,
+    pub tail: usize,
+    pub cache: usize,
+    /// The number of first-level nodes and the flags word of each trie.
+    pub trie_fields: usize,
+    pub common_prefix: usize,
+    pub weights: usize,
+}
+
+impl SectionSizes {
+    pub fn total(&self) -> usize {
+        self.header + self.section_table + self.louds + self.terminal_flags
+        + self.link_flags + self.bases + self.extras + self.tail + self.cache
+        + self.trie_fields + self.common_prefix + self.weights
+    }
+}
+
+/// What a dictionary is made of, from `LoudsTrie::stats`. Counts are
+/// summed over the nested tries unless they say otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrieStats {
+    pub num_keys: usize,
+    pub num_tries: usize,
+    /// Nodes, the root of each trie included.
+    pub num_nodes: usize,
+    /// Children of the root of the first trie.
+    pub num_l1_nodes: usize,
+    pub louds_bits: usize,
+    /// Edges labelled through a link into the next trie or the tail.
+    pub num_links: usize,
+    /// Bytes of the tail buffer, which only the last trie has.
+    pub tail_bytes: usize,
+    pub cache_entries: usize,
+    /// Bytes of the common prefix stripped from every key.
+    pub common_prefix_len: usize,
+    /// The edges of each trie, as `level_stats` gives them.
+    pub levels: Vec<LevelStats>,
+    /// Bytes in memory, as `total_size` gives them.
+    pub total_size: usize,
+    pub sections: SectionSizes,
+}
+
+impl TrieStats {
+    /// Bytes written by `write_to`.
+    pub fn io_size(&self) -> usize {
+        self.sections.total()
+    }
+}
+
+impl fmt::Display for TrieStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "keys: {}", self.num_keys)?;
+        writeln!(f, "tries: {}", self.num_tries)?;
+        writeln!(f, "nodes: {} ({} first-level)", self.num_nodes,
+                 self.num_l1_nodes)?;
+        writeln!(f, "links: {}", self.num_links)?;
+        writeln!(f, "tail bytes: {}", self.tail_bytes)?;
+        writeln!(f, "cache entries: {}", self.cache_entries)?;
+        writeln!(f, "total size: {}", self.total_size)?;
+        writeln!(f, "io size: {}", self.io_size())?;
+        let s = &self.sections;
+        let sections = [("header", s.header),
+                        ("section table", s.section_table), ("louds", s.louds),
+                        ("terminal flags", s.terminal_flags),
+                        ("link flags", s.link_flags), ("bases", s.bases),
+                        ("extras", s.extras), ("tail", s.tail),
+                        ("cache", s.cache), ("trie fields", s.trie_fields),
+                        ("common prefix", s.common_prefix),
+                        ("weights", s.weights)];
+        let total = self.io_size() as f64;
+        for &(name, size) in &sections {
+            writeln!(f, "  {:<16}{:>12} {:>6.1}%", name, size,
+                     100.0 * size as f64 / total)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// The number of bytes `write_to` produces, without writing them.
+    pub fn io_size(&self) -> usize {
+        self.section_sizes().total()
+    }
+
+    /// Counts and section sizes of the dictionary, nested tries included.
+    pub fn stats(&self) -> TrieStats {
+        let mut out = TrieStats {
+            num_keys: self.len(),
+            num_tries: 0,
+            num_nodes: 0,
+            num_l1_nodes: self.num_l1_nodes_,
+            louds_bits: 0,
+            num_links: 0,
+            tail_bytes: 0,
+            cache_entries: 0,
+            common_prefix_len: self.common_prefix_.len(),
+            levels: self.level_stats(),
+            total_size: self.total_size(),
+            sections: self.section_sizes(),
+        };
+        let mut trie = Some(self);
+        while let Some(t) = trie {
+            out.num_tries += 1;
+            out.num_nodes += t.num_nodes();
+            out.louds_bits += t.louds_.len();
+            out.num_links += t.link_flags_.num_1s();
+            out.tail_bytes += t.tail_.len();
+            out.cache_entries += t.cache_.len();
+            trie = t.next_trie_.as_ref().map(|next| &**next);
+        }
+        out
+    }
+
+    /// Mirrors `write_to` and the native `write_`.
+    fn section_sizes(&self) -> SectionSizes {
+        let mut out = SectionSizes {
+            header: Header::io_size(),
+            section_table: self.section_table().io_size(),
+            common_prefix: self.common_prefix_.io_size(),
+            weights: self.weights_.io_size(),
+            ..SectionSizes::default()
+        };
+        let mut trie = Some(self);
+        while let Some(t) = trie {
+            out.louds += t.louds_.io_size();
+            out.terminal_flags += t.terminal_flags_.io_size();
+            out.link_flags += t.link_flags_.io_size();
+            out.bases += t.bases_.io_size();
+            out.extras += t.extras_.io_size();
+            out.tail += t.tail_.io_size();
+            out.cache += t.cache_.io_size();
+            out.trie_fields += 8 + 8;
+            trie = t.next_trie_.as_ref().map(|next| &**next);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use config::{Config, NumTries, TailMode};
+    use louds_trie::LoudsTrie;
+
+    fn urls() -> Vec<String> {
+        (0..3000u64).map(|i| {
+            format!("http://{:x}.example/{:x}/{}.html",
+                    i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 44, i % 97,
+                    i)
+        }).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_size_is_what_write_to_writes() {
+        let _ = env_logger::init();
+        let keys = urls();
+        for &num_tries in &[1, 2, 4] {
+            for &tail_mode in &[TailMode::Text, TailMode::Binary] {
+                for &weights in &[false, true] {
+                    let config = Config::new()
+                        .with_num_tries(NumTries::new(num_tries))
+                        .with_tail_mode(tail_mode)
+                        .with_weights(weights);
+                    let trie = LoudsTrie::build_from(keys.iter(), &config)
+                               .unwrap();
+                    let mut bytes = Vec::new();
+                    trie.write_to(&mut bytes).unwrap();
+                    let stats = trie.stats();
+                    assert_eq!(trie.io_size(), bytes.len());
+                    assert_eq!(stats.io_size(), bytes.len());
+                    assert_eq!(stats.num_tries, stats.levels.len());
+                    assert_eq!(stats.num_keys, keys.len());
+                    assert_eq!(stats.total_size, trie.total_size());
+                    assert_eq!(stats.sections.weights,
+                               8 + if weights { 4 * keys.len() } else { 0 });
+                    assert!(stats.tail_bytes > 0);
+                }
+            }
+        }
+
+        let prefixed: Vec<String> = (0..100).map(|i| {
+            format!("{}{}", "/".repeat(40), i)
+        }).collect();
+        let empty = Vec::new();
+        for keys in &[prefixed, empty] {
+            let trie = LoudsTrie::build_from(keys.iter(), &Config::new())
+                       .unwrap();
+            let mut bytes = Vec::new();
+            trie.write_to(&mut bytes).unwrap();
+            assert_eq!(trie.io_size(), bytes.len());
+        }
+    }
+
+    #[test]
+    fn counts_add_up() {
+        let _ = env_logger::init();
+        let trie = LoudsTrie::build_from(urls().iter(), &Config::new())
+                   .unwrap();
+        let stats = trie.stats();
+        let levels = &stats.levels;
+        assert_eq!(stats.num_nodes,
+                   levels.iter().map(|l| l.num_nodes()).sum::<usize>());
+        assert_eq!(stats.num_links,
+                   levels.iter().map(|l| l.num_link_edges()).sum::<usize>());
+        // Each node has a 1 for its edge in and a 0 for its end, and each
+        // trie has a super-root above its root, with a 1 and a 0 of its own.
+        assert_eq!(stats.louds_bits,
+                   2 * stats.num_nodes + 2 * stats.num_tries);
+        // Too short a shared prefix to strip.
+        assert_eq!(stats.common_prefix_len, 0);
+        assert!(stats.num_l1_nodes > 0 && stats.cache_entries > 0);
+        let shown = stats.to_string();
+        assert!(shown.starts_with("keys: 3000\ntries: 3\n"), "{}", shown);
+        assert!(shown.contains(&format!("io size: {}", trie.io_size())));
+
+        let prefixed: Vec<String> = (0..100).map(|i| {
+            format!("{}{}", "/".repeat(40), i)
+        }).collect();
+        let prefixed = LoudsTrie::build_from(prefixed.iter(), &Config::new())
+                       .unwrap();
+        assert_eq!(prefixed.stats().common_prefix_len, 40);
+
+        let empty = LoudsTrie::build_from(Vec::<&str>::new(), &Config::new())
+                    .unwrap();
+        assert_eq!(empty.stats().num_keys, 0);
+    }
+}
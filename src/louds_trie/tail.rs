@@ -1,46 +1,69 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::borrow::Cow;
+use std::io;
 use std;
+use base::checked_u32;
 use config::TailMode;
-use entry;
+use error::MarisaError;
 use entry::Entry;
+use louds_trie::key_sink::KeySink;
+use sort;
 use vector::bit_vec::BitVec;
+use vector::intrinsic::Ctz;
+use vector::io::SerializableVec;
+use vector::pod_vec::PodVec;
+use vector::util::{invalid_data, read_bytes, read_len, read_libmarisa_vec,
+                   write_libmarisa_vec};
 
+/// The outcome of comparing a query against a string in the tail.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TailMatch {
+    /// The whole string matched.
+    Matched,
+    /// A byte of the query differs from the string.
+    Mismatch,
+    /// The query ended before the string did, with every byte matching.
+    QueryEnded,
+}
+
+/// A link stores a tail offset as its node's base byte and a u32 extra, so
+/// offsets are 40 bits.
+const MAX_TAIL_BYTES: u64 = 1 << 40;
+
+/// The strings left over below the last trie. A tail from `map` borrows
+/// its bytes and its end flags from the mapped buffer.
 #[derive(Debug)]
-pub struct Tail {
-    buf_: Vec<u8>,
-    end_flags_: BitVec,
+pub struct Tail<'a> {
+    buf_: Cow<'a, [u8]>,
+    end_flags_: BitVec<'a>,
 }
 
-impl Tail {
-    pub fn new() -> Tail {
-        Tail { buf_: Vec::new(), end_flags_: BitVec::new() }
+impl<'a> Tail<'a> {
+    pub fn new() -> Tail<'a> {
+        Tail { buf_: Cow::Borrowed(&[]), end_flags_: BitVec::new() }
     }
 
-    pub fn build<'a>(entries: &mut Vec<Entry<'a>>, offsets: &mut Vec<u32>,
-                     mode: TailMode) -> Tail {
-        let mode = match mode {
-            TailMode::Text => {
-                if entries.iter().any(
-                  |entry| entry.iter().any(|x| *x == 0)) {
-                    TailMode::Binary
-                } else {
-                    TailMode::Text
-                }
-            }
-            x @ TailMode::Binary => x,
-        };
+    /// Stores the labels of `entries`, and sets `offsets` to where each one
+    /// starts. Fails, leaving `offsets` untouched, if the tail would not be
+    /// addressable by links.
+    pub fn build(entries: &mut Vec<Entry>, offsets: &mut Vec<u64>,
+                 mode: TailMode) -> Result<Tail<'a>, MarisaError> {
+        Tail::build_with_limit(entries, offsets, mode, MAX_TAIL_BYTES)
+    }
 
+    fn build_with_limit(entries: &mut Vec<Entry>, offsets: &mut Vec<u64>,
+                        mode: TailMode, max_bytes: u64)
+      -> Result<Tail<'a>, MarisaError> {
         for (i, entry) in entries.iter_mut().enumerate() {
-            assert!(i <= std::u32::MAX as usize);
-            entry.set_id(i as u32);
+            entry.set_id(checked_u32(i, "number of tail entries")?);
         }
+        let mode = sort_for_mode(entries, mode);
 
-        let mut out = Tail::new();
+        let mut buf = Vec::new();
+        let mut end_flags = BitVec::new();
 
-        // FIXME: marisa-trie used "multi-key quicksort"/"three-way radix
-        //        quicksort" here. Consider bringing that back.
-        entries.sort_by(&entry::cmp_slice);
-
-        let mut tmp: Vec<u32> = Vec::new();
+        let mut tmp: Vec<usize> = Vec::new();
         tmp.resize(entries.len(), 0);
 
         let mut optLast: Option<&Entry> = None;
@@ -51,8 +74,6 @@ impl Tail {
                 Some(last) => {
                     if entry.common_count(last) == entry.len() {
                         let diff = last.len() - entry.len();
-                        assert!(diff <= std::u32::MAX as usize);
-                        let diff = diff as u32;
                         tmp[entry.get_id() as usize] =
                             tmp[last.get_id() as usize] + diff;
                         false
@@ -64,84 +85,227 @@ impl Tail {
             };
 
             if doPush {
-                tmp[entry.get_id() as usize] = out.buf_.len() as u32;
+                tmp[entry.get_id() as usize] = buf.len();
 
-                out.buf_.extend(entry.iter().rev());
+                buf.extend(entry.iter().rev());
 
                 match mode {
-                    TailMode::Text => { out.buf_.push(0); },
+                    TailMode::Text => { buf.push(0); },
                     TailMode::Binary => {
-                        for _ in 1..entry.len() {
-                            out.end_flags_.push(false);
-                        }
-                        out.end_flags_.push(true);
+                        end_flags.push_run(false,
+                                           entry.len().saturating_sub(1));
+                        end_flags.push(true);
                     }
+                    TailMode::Auto => unreachable!(),
                 }
-                assert!(out.buf_.len() <= std::u32::MAX as usize,
-                        "MARISA_SIZE_ERROR");
             }
             optLast = Some(&entry);
         }
-        out.buf_.shrink_to_fit();
+        if buf.len() as u64 > max_bytes {
+            return Err(MarisaError::SizeError { what: "tail",
+                                                limit: max_bytes,
+                                                actual: buf.len() as u64 });
+        }
+        buf.shrink_to_fit();
 
-        *offsets = tmp;
-        out
+        *offsets = tmp.into_iter().map(|offset| offset as u64).collect();
+        Ok(Tail { buf_: Cow::Owned(buf), end_flags_: end_flags })
+    }
+
+    /// What `len` and `total_size` would be for a tail built from
+    /// `entries`, without building it. Sorts `entries` as `build` does.
+    pub fn size_for(entries: &mut Vec<Entry>, mode: TailMode)
+      -> (usize, usize) {
+        let mode = sort_for_mode(entries, mode);
+        let (bytes, num_strings) = merged_len(entries);
+        match mode {
+            TailMode::Text => (bytes + num_strings, bytes + num_strings),
+            _ => (bytes, binary_size(bytes)),
+        }
     }
 
-    pub fn restore(&self, offset: usize, key_out: &mut Vec<u8>) {
+    pub fn restore<S: KeySink>(&self, offset: usize, key_out: &mut S) {
         assert!(!self.buf_.is_empty(), "MARISA_STATE_ERROR");
 
+        let end = if self.end_flags_.is_empty() {
+            find_nul(&self.buf_, offset)
+        } else {
+            self.end_flags_.next_1(offset).map_or(self.buf_.len(), |e| e + 1)
+        };
+        key_out.push_slice(&self.buf_[offset..end]);
+    }
+
+    /// Matches the string starting at `offset` against `query`, beginning at
+    /// `query_pos`. `query_pos` is advanced past every matching byte, so on
+    /// failure it tells whether anything matched at all.
+    pub fn match_(&self, offset: usize, query: &[u8], query_pos: &mut usize)
+      -> bool {
+        self.match_query(offset, query, query_pos) == TailMatch::Matched
+    }
+
+    /// Like `match_`, but tells a mismatch apart from the query ending part
+    /// way through the string.
+    pub fn match_query(&self, offset: usize, query: &[u8],
+                       query_pos: &mut usize) -> TailMatch {
+        assert!(!self.buf_.is_empty(), "MARISA_STATE_ERROR");
+        assert!(*query_pos <= query.len(), "MARISA_BOUND_ERROR");
+
+        if *query_pos >= query.len() {
+            return TailMatch::QueryEnded;
+        }
+        if !self.end_flags_.is_empty() {
+            return self.match_binary(offset, query, query_pos);
+        }
+        if self.buf_[offset] != query[*query_pos] {
+            return TailMatch::Mismatch;
+        }
+        *query_pos += 1;
+        // Eight bytes at a time while both have them, looking for the
+        // first byte that differs or ends the string.
+        let buf = &self.buf_[..];
+        let mut offset = offset + 1;
+        loop {
+            if offset + WORD <= buf.len() && *query_pos + WORD <= query.len() {
+                let word = load(buf, offset);
+                let nul = zero_bytes(word).ctz() as usize / 8;
+                let diff = (word ^ load(query, *query_pos)).ctz() as usize / 8;
+                if nul == WORD && diff == WORD {
+                    offset += WORD;
+                    *query_pos += WORD;
+                    continue;
+                }
+                *query_pos += std::cmp::min(nul, diff);
+                return if nul <= diff { TailMatch::Matched }
+                       else { TailMatch::Mismatch };
+            }
+            if buf[offset] == 0 {
+                return TailMatch::Matched;
+            }
+            if *query_pos >= query.len() {
+                return TailMatch::QueryEnded;
+            }
+            if buf[offset] != query[*query_pos] {
+                return TailMatch::Mismatch;
+            }
+            offset += 1;
+            *query_pos += 1;
+        }
+    }
+
+    /// `match_query` for a binary tail, where the end flags say how long
+    /// the string is before any of it is compared.
+    fn match_binary(&self, offset: usize, query: &[u8], query_pos: &mut usize)
+      -> TailMatch {
+        let end = self.end_flags_.next_1(offset)
+                      .expect("MARISA_BOUND_ERROR") + 1;
+        let rest = &query[*query_pos..];
+        let len = std::cmp::min(end - offset, rest.len());
+        let matched = common_len(&self.buf_[offset..], rest, len);
+        *query_pos += matched;
+        if matched < len {
+            TailMatch::Mismatch
+        } else if len == end - offset {
+            TailMatch::Matched
+        } else {
+            TailMatch::QueryEnded
+        }
+    }
+
+    /// Like `match_`, but also succeeds if `query` runs out part way through
+    /// the string. The matched bytes, and then the rest of the string, are
+    /// appended to `key_out`.
+    pub fn prefix_match<S: KeySink>(&self, offset: usize, query: &[u8],
+                                    query_pos: &mut usize, key_out: &mut S)
+      -> bool {
+        assert!(!self.buf_.is_empty(), "MARISA_STATE_ERROR");
+        assert!(*query_pos < query.len(), "MARISA_BOUND_ERROR");
+
+        let mut offset = offset;
         if self.end_flags_.is_empty() {
-            for &c in self.buf_.iter().skip(offset) {
-                if 0 == c { break; } // null-terminated
-                key_out.push(c);
+            loop {
+                if self.buf_[offset] != query[*query_pos] {
+                    return false;
+                }
+                key_out.push(self.buf_[offset]);
+                *query_pos += 1;
+                offset += 1;
+                if self.buf_[offset] == 0 {
+                    return true;
+                }
+                if *query_pos >= query.len() {
+                    self.restore(offset, key_out);
+                    return true;
+                }
             }
         } else {
-            for (i, &c) in self.buf_.iter().skip(offset).enumerate() {
-                key_out.push(c);
-                if self.end_flags_.at(i + offset) { break; }
+            loop {
+                if self.buf_[offset] != query[*query_pos] {
+                    return false;
+                }
+                key_out.push(self.buf_[offset]);
+                *query_pos += 1;
+                if self.end_flags_.at(offset) {
+                    return true;
+                }
+                offset += 1;
+                if *query_pos >= query.len() {
+                    self.restore(offset, key_out);
+                    return true;
+                }
             }
         }
     }
 
-/*
-    void map(Mapper &mapper);
-    void read(Reader &reader);
-    void write(Writer &writer) const;
-    void map_(Mapper &mapper);
-    void read_(Reader &reader);
-    void write_(Writer &writer) const;
-
-void Tail::map(Mapper &mapper) {
-  Tail temp;
-  temp.map_(mapper);
-  swap(temp);
-}
+    /// Writes the buffer and then the end flags as libmarisa does.
+    pub fn write_libmarisa<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_libmarisa_vec(w, &self.buf_)?;
+        self.end_flags_.write_libmarisa(w)
+    }
 
-void Tail::read(Reader &reader) {
-  Tail temp;
-  temp.read_(reader);
-  swap(temp);
-}
+    pub fn read_libmarisa<R: io::Read>(r: &mut R) -> io::Result<Tail<'a>> {
+        let buf = read_libmarisa_vec(r, 1, "tail")?;
+        let end_flags = BitVec::read_libmarisa(r)?;
+        Tail::checked(Cow::Owned(buf), end_flags).map_err(invalid_data)
+    }
 
-void Tail::write(Writer &writer) const {
-  write_(writer);
-}
-void Tail::map_(Mapper &mapper) {
-  buf_.map(mapper);
-  end_flags_.map(mapper);
-}
+    /// Reads a tail as format version 2 wrote it: the buffer as a length
+    /// and the bytes, unpadded, then the end flags as `BitVec::read_v2`
+    /// reads them.
+    pub fn read_v2<R: io::Read>(r: &mut R) -> io::Result<Tail<'a>> {
+        let len = read_len(r)?;
+        let buf = read_bytes(r, len)?;
+        let end_flags = BitVec::read_v2(r)?;
+        Tail::checked(Cow::Owned(buf), end_flags).map_err(invalid_data)
+    }
 
-void Tail::read_(Reader &reader) {
-  buf_.read(reader);
-  end_flags_.read(reader);
-}
+    /// Checks what `restore` and `match_` rely on: every string ends inside
+    /// the buffer.
+    fn checked(buf: Cow<'a, [u8]>, end_flags: BitVec<'a>)
+      -> Result<Tail<'a>, &'static str> {
+        let out = Tail { buf_: buf, end_flags_: end_flags };
+        out.check_ends()?;
+        Ok(out)
+    }
 
-void Tail::write_(Writer &writer) const {
-  buf_.write(writer);
-  end_flags_.write(writer);
-}
-*/
+    fn check_ends(&self) -> Result<(), &'static str> {
+        if self.end_flags_.is_empty() {
+            if self.buf_.last().map_or(false, |&c| c != 0) {
+                return Err("text tail without a final NUL");
+            }
+        } else {
+            if self.end_flags_.len() != self.buf_.len()
+               || !self.end_flags_.at(self.buf_.len() - 1) {
+                return Err("tail end flags");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks what loading checks, and the end flags' count of 1s.
+    pub fn verify(&self) -> Result<(), &'static str> {
+        self.end_flags_.verify()?;
+        self.check_ends()
+    }
 
     pub fn clear(&mut self) {
         *self = Tail::new();
@@ -165,13 +329,526 @@ void Tail::write_(Writer &writer) const {
     pub fn len(&self) -> usize {
         self.buf_.len()
     }
-/*
-    usize total_size() const {
-      return buf_.total_size() + end_flags_.total_size();
+    pub fn total_size(&self) -> usize {
+        self.buf_.len() + self.end_flags_.total_size()
     }
-    usize io_size() const {
-      return buf_.io_size() + end_flags_.io_size();
+
+    /// Whether the buffer and end flags are borrowed from a mapped buffer.
+    pub fn is_mapped(&self) -> bool {
+        let buf_mapped = match self.buf_ {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        };
+        buf_mapped && self.end_flags_.is_mapped()
     }
-*/
+
+    fn bytes<'b>(&'b self) -> PodVec<'b, u8> {
+        PodVec(Cow::Borrowed(&self.buf_[..]))
+    }
+}
+
+/// The buffer as a byte vector, then the end flags, which are empty for a
+/// text tail. Mapping borrows the buffer.
+impl<'a> SerializableVec<'a> for Tail<'a> {
+    fn io_size(&self) -> usize {
+        self.bytes().io_size() + self.end_flags_.io_size()
+    }
+
+    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.bytes().write_to(w)?;
+        self.end_flags_.write_to(w)
+    }
+
+    fn read_from<R: io::Read>(r: &mut R) -> io::Result<Tail<'a>> {
+        let buf = PodVec::read_from(r)?.0;
+        let end_flags = BitVec::read_from(r)?;
+        Tail::checked(buf, end_flags).map_err(invalid_data)
+    }
+
+    fn map_at(buf: &'a [u8], pos: &mut usize)
+      -> Result<Tail<'a>, MarisaError> {
+        let bytes = PodVec::map_at(buf, pos)?.0;
+        let end_flags = BitVec::map_at(buf, pos)?;
+        Tail::checked(bytes, end_flags)
+            .map_err(|what| MarisaError::FormatError { what: what })
+    }
+}
+
+/// The byte at a time versions of `restore` and `match_query`, which the
+/// word at a time ones must agree with.
+#[cfg(test)]
+impl<'a> Tail<'a> {
+    fn restore_bytewise(&self, offset: usize, key_out: &mut Vec<u8>) {
+        if self.end_flags_.is_empty() {
+            for &c in self.buf_.iter().skip(offset) {
+                if 0 == c { break; } // null-terminated
+                key_out.push(c);
+            }
+        } else {
+            for (i, &c) in self.buf_.iter().skip(offset).enumerate() {
+                key_out.push(c);
+                if self.end_flags_.at(i + offset) { break; }
+            }
+        }
+    }
+
+    fn match_query_bytewise(&self, offset: usize, query: &[u8],
+                            query_pos: &mut usize) -> TailMatch {
+        let mut offset = offset;
+        loop {
+            if *query_pos >= query.len() {
+                return TailMatch::QueryEnded;
+            }
+            if self.buf_[offset] != query[*query_pos] {
+                return TailMatch::Mismatch;
+            }
+            *query_pos += 1;
+            if self.end_flags_.is_empty() {
+                offset += 1;
+                if self.buf_[offset] == 0 {
+                    return TailMatch::Matched;
+                }
+            } else {
+                if self.end_flags_.at(offset) {
+                    return TailMatch::Matched;
+                }
+                offset += 1;
+            }
+        }
+    }
+}
+
+const WORD: usize = 8;
+
+/// The `WORD` bytes of `bytes` at `pos`, the first in the lowest byte.
+#[inline]
+fn load(bytes: &[u8], pos: usize) -> u64 {
+    let mut word = [0u8; WORD];
+    word.copy_from_slice(&bytes[pos..pos + WORD]);
+    u64::from_le_bytes(word)
 }
 
+/// A word with the high bit of the lowest zero byte of `word` set, and no
+/// lower ones. Bytes above that one may be marked whether zero or not.
+#[inline]
+fn zero_bytes(word: u64) -> u64 {
+    word.wrapping_sub(0x0101010101010101) & !word & 0x8080808080808080
+}
+
+/// The number of bytes at the start of `a` and `b`, up to `len`, that
+/// are the same.
+fn common_len(a: &[u8], b: &[u8], len: usize) -> usize {
+    let mut i = 0;
+    while i + WORD <= len {
+        let diff = load(a, i) ^ load(b, i);
+        if diff != 0 {
+            return i + diff.ctz() as usize / 8;
+        }
+        i += WORD;
+    }
+    while i < len && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+/// Where the first NUL in `buf` at or after `pos` is, or the end of `buf`.
+fn find_nul(buf: &[u8], mut pos: usize) -> usize {
+    while pos + WORD <= buf.len() {
+        let nul = zero_bytes(load(buf, pos));
+        if nul != 0 {
+            return pos + nul.ctz() as usize / 8;
+        }
+        pos += WORD;
+    }
+    pos + buf[pos..].iter().position(|&c| c == 0).unwrap_or(buf.len() - pos)
+}
+
+/// Sorts `entries` as `build` stores them, and picks the mode `mode` comes
+/// to for them: Text and Auto become Binary if a label holds a NUL, and
+/// Auto becomes whichever of the two makes the smaller tail.
+fn sort_for_mode(entries: &mut Vec<Entry>, mode: TailMode) -> TailMode {
+    sort::sort(entries);
+    if mode == TailMode::Binary
+       || entries.iter().any(|entry| entry.iter().any(|x| x == 0)) {
+        return TailMode::Binary;
+    }
+    if mode == TailMode::Text {
+        return TailMode::Text;
+    }
+    let (bytes, num_strings) = merged_len(entries);
+    if bytes + num_strings <= binary_size(bytes) {
+        TailMode::Text
+    } else {
+        TailMode::Binary
+    }
+}
+
+/// The number of bytes in the strings `build` stores for sorted `entries`,
+/// without terminators, and the number of strings. An entry that is a
+/// suffix of the one after it is not stored again.
+fn merged_len(entries: &[Entry]) -> (usize, usize) {
+    let mut bytes = 0;
+    let mut num_strings = 0;
+    let mut last: Option<&Entry> = None;
+    for entry in entries.iter().rev() {
+        let shared = match last {
+            Some(last) => entry.common_count(last) == entry.len(),
+            None => false,
+        };
+        if !shared {
+            bytes += entry.len();
+            num_strings += 1;
+        }
+        last = Some(entry);
+    }
+    (bytes, num_strings)
+}
+
+/// The size of a binary tail of `bytes` bytes, end flags included.
+fn binary_size(bytes: usize) -> usize {
+    bytes + BitVec::total_size_for(bytes, 0, None)
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use std::borrow::Cow;
+    use aligned_bytes::AlignedBytes;
+    use env_logger;
+    use quickcheck as qc;
+    use config::TailMode;
+    use entry::Entry;
+    use error::MarisaError;
+    use std;
+    use vector::bit_vec::BitVec;
+    use vector::io::SerializableVec;
+    use super::{MAX_TAIL_BYTES, Tail, TailMatch};
+
+    /// Builds a tail from `strs`, returning it and the offset of each.
+    fn build(strs: &[&[u8]], mode: TailMode) -> (Tail<'static>, Vec<usize>) {
+        let mut entries: Vec<Entry> = strs.iter()
+            .map(|s| Entry::new(s, 0)).collect();
+        let mut offsets = Vec::new();
+        let tail = Tail::build(&mut entries, &mut offsets, mode).unwrap();
+        (tail, offsets.into_iter().map(|x| x as usize).collect())
+    }
+
+    #[test]
+    fn oversized_tail_is_an_error() {
+        let strs: Vec<Vec<u8>> = (0..100).map(|i| format!("{}-label", i)
+                                                   .into_bytes()).collect();
+        let mut entries: Vec<Entry> = strs.iter()
+            .map(|s| Entry::new(s, 0)).collect();
+        let mut offsets = vec![7];
+        let size = Tail::build(&mut entries, &mut offsets, TailMode::Text)
+                   .unwrap().len();
+        assert_eq!(offsets.len(), 100);
+
+        offsets = vec![7];
+        assert!(Tail::build_with_limit(&mut entries, &mut offsets,
+                                       TailMode::Text, size as u64).is_ok());
+        offsets = vec![7];
+        assert_eq!(Tail::build_with_limit(&mut entries, &mut offsets,
+                                          TailMode::Text, size as u64 - 1)
+                   .unwrap_err(),
+                   MarisaError::SizeError { what: "tail",
+                                           limit: size as u64 - 1,
+                                           actual: size as u64 });
+        assert_eq!(offsets, vec![7]);
+    }
+
+    #[test]
+    fn verify_checks_the_end_flags() {
+        let strs: &[&[u8]] = &[b"abc", b"de"];
+        let (text, _) = build(strs, TailMode::Text);
+        assert_eq!(text.verify(), Ok(()));
+        let (binary, _) = build(strs, TailMode::Binary);
+        assert_eq!(binary.verify(), Ok(()));
+
+        let mut end_flags = binary.end_flags_.clone();
+        end_flags.push(true);
+        let longer = Tail { buf_: binary.buf_.clone(), end_flags_: end_flags };
+        assert_eq!(longer.verify(), Err("tail end flags"));
+        let mut text_buf = text.buf_.into_owned();
+        text_buf.pop();
+        let unended = Tail { buf_: text_buf.into(),
+                             end_flags_: text.end_flags_ };
+        assert_eq!(unended.verify(), Err("text tail without a final NUL"));
+    }
+
+    #[test]
+    fn largest_offset_fits_a_link() {
+        // The base byte holds the low 8 bits, the extra the rest.
+        assert_eq!((MAX_TAIL_BYTES - 1) >> 8, std::u32::MAX as u64);
+    }
+
+    #[test]
+    fn suffixes_are_shared() {
+        let strs: &[&[u8]] = &[b"abc", b"bc", b"abc", b"c", b"ABC", b"AB"];
+        // "abc\0" holds "bc" and "c" too, then "ABC\0" and "AB\0".
+        let (tail, offsets) = build(strs, TailMode::Text);
+        assert_eq!(tail.len(), 11);
+        for (s, &offset) in strs.iter().zip(offsets.iter()) {
+            let mut key = Vec::new();
+            tail.restore(offset, &mut key);
+            assert_eq!(&key[..], *s);
+        }
+
+        let (tail, offsets) = build(strs, TailMode::Binary);
+        assert_eq!(tail.mode(), TailMode::Binary);
+        assert_eq!(tail.len(), 8);
+        for &mode in &[TailMode::Text, TailMode::Binary,
+                      TailMode::Auto] {
+            let (tail, _) = build(strs, mode);
+            let mut entries: Vec<Entry> = strs.iter()
+                .map(|s| Entry::new(s, 0)).collect();
+            assert_eq!(Tail::size_for(&mut entries, mode),
+                       (tail.len(), tail.total_size()));
+        }
+        for (s, &offset) in strs.iter().zip(offsets.iter()) {
+            let mut key = Vec::new();
+            tail.restore(offset, &mut key);
+            assert_eq!(&key[..], *s);
+        }
+    }
+
+    #[test]
+    fn auto_picks_the_smaller_tail() {
+        let short: Vec<Vec<u8>> = (0..50).map(|i| format!("{:02}", i)
+                                                  .into_bytes()).collect();
+        let long: Vec<Vec<u8>> = (0..50).map(|i| {
+            format!("{:02} a label well over eight bytes", i).into_bytes()
+        }).collect();
+        let nul: Vec<Vec<u8>> = (0..50).map(|i| format!("{:02}\0", i)
+                                                .into_bytes()).collect();
+        // A NUL for each string against a bit for each byte.
+        let cases = [(short, TailMode::Binary), (long, TailMode::Text),
+                     (nul, TailMode::Binary)];
+        for &(ref strs, expected) in &cases {
+            let strs: Vec<&[u8]> = strs.iter().map(|s| &s[..]).collect();
+            let (tail, offsets) = build(&strs, TailMode::Auto);
+            assert_eq!(tail.mode(), expected);
+            let (text, _) = build(&strs, TailMode::Text);
+            let (binary, _) = build(&strs, TailMode::Binary);
+            let smaller = if text.mode() == TailMode::Binary {
+                binary.total_size()
+            } else {
+                std::cmp::min(text.total_size(), binary.total_size())
+            };
+            assert_eq!(tail.total_size(), smaller);
+            for (s, &offset) in strs.iter().zip(offsets.iter()) {
+                let mut key = Vec::new();
+                tail.restore(offset, &mut key);
+                assert_eq!(&key[..], *s);
+            }
+        }
+    }
+
+    /// Checks that every string restores the same from `other`.
+    fn same_strings(tail: &Tail, other: &Tail, offsets: &[usize]) -> bool {
+        offsets.iter().all(|&offset| {
+            let (mut a, mut b) = (Vec::new(), Vec::new());
+            tail.restore(offset, &mut a);
+            other.restore(offset, &mut b);
+            a == b
+        })
+    }
+
+    #[test]
+    fn io_round_trip() {
+        let strs: &[&[u8]] = &[b"abc", b"bc", b"x", b"ABC", b"AB"];
+        for &mode in &[TailMode::Text, TailMode::Binary] {
+            let (tail, offsets) = build(strs, mode);
+            let mut bytes = Vec::new();
+            tail.write_to(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), tail.io_size());
+
+            let read = Tail::read_from(&mut &bytes[..]).unwrap();
+            assert_eq!(read.mode(), mode);
+            assert_eq!(read.len(), tail.len());
+            assert!(same_strings(&tail, &read, &offsets));
+
+            let buf = AlignedBytes::from_slice(&bytes);
+            let mut used = 0;
+            let mapped = Tail::map_at(&buf, &mut used).unwrap();
+            assert_eq!(used, bytes.len());
+            assert_eq!(mapped.mode(), mode);
+            assert!(same_strings(&tail, &mapped, &offsets));
+        }
+
+        // A text tail is its buffer, padded, and an empty bit vector.
+        let (tail, _) = build(strs, TailMode::Text);
+        let mut bytes = Vec::new();
+        tail.write_to(&mut bytes).unwrap();
+        assert_eq!(tail.len(), 13);
+        assert_eq!(bytes.len(), 8 + 16 + 48);
+        assert_eq!(&bytes[8 + 13..], &[0u8; 3 + 48][..]);
+    }
+
+    #[test]
+    fn io_truncated() {
+        let strs: &[&[u8]] = &[b"abc", b"bc", b"x\0y"];
+        for &mode in &[TailMode::Text, TailMode::Binary] {
+            let (tail, _) = build(strs, mode);
+            let mut bytes = Vec::new();
+            tail.write_to(&mut bytes).unwrap();
+            for len in 0..bytes.len() {
+                assert!(Tail::read_from(&mut &bytes[..len]).is_err());
+                assert!(Tail::map_at(&bytes[..len], &mut 0).is_err());
+            }
+        }
+
+        // A length claiming more than is there fails without trying to
+        // allocate it.
+        let mut bytes = vec![0xFF; 8];
+        bytes.extend(b"abc\0");
+        assert!(Tail::read_from(&mut &bytes[..]).is_err());
+        assert_eq!(Tail::map_at(&bytes, &mut 0).unwrap_err(),
+                   MarisaError::FormatError { what: "vector" });
+    }
+
+    fn match_at(tail: &Tail, offset: usize, query: &[u8], query_pos: usize)
+      -> (TailMatch, usize) {
+        let mut pos = query_pos;
+        let m = tail.match_query(offset, query, &mut pos);
+        (m, pos)
+    }
+
+    #[test]
+    fn match_query_text() {
+        let (tail, offsets) = build(&[b"x", b"abc", b"bc"], TailMode::Text);
+        assert_eq!(tail.mode(), TailMode::Text);
+        let (x, abc, bc) = (offsets[0], offsets[1], offsets[2]);
+
+        assert_eq!(match_at(&tail, x, b"x", 0), (TailMatch::Matched, 1));
+        assert_eq!(match_at(&tail, x, b"-xy", 1), (TailMatch::Matched, 2));
+        assert_eq!(match_at(&tail, x, b"y", 0), (TailMatch::Mismatch, 0));
+        assert_eq!(match_at(&tail, x, b"x", 1), (TailMatch::QueryEnded, 1));
+
+        assert_eq!(match_at(&tail, abc, b"abcd", 0), (TailMatch::Matched, 3));
+        assert_eq!(match_at(&tail, abc, b"ab", 0), (TailMatch::QueryEnded, 2));
+        assert_eq!(match_at(&tail, abc, b"abd", 0), (TailMatch::Mismatch, 2));
+        assert_eq!(match_at(&tail, bc, b"abc", 1), (TailMatch::Matched, 3));
+        assert_eq!(match_at(&tail, bc, b"ab", 1), (TailMatch::QueryEnded, 2));
+
+        let mut pos = 0;
+        assert!(tail.match_(abc, b"abc", &mut pos));
+        pos = 0;
+        assert!(!tail.match_(abc, b"ab", &mut pos));
+    }
+
+    #[test]
+    fn match_query_binary() {
+        let (tail, offsets) = build(&[b"a\0b", b"\0", b"\0\0c"],
+                                    TailMode::Text);
+        assert_eq!(tail.mode(), TailMode::Binary);
+        let (a0b, nul, nulnulc) = (offsets[0], offsets[1], offsets[2]);
+
+        assert_eq!(match_at(&tail, a0b, b"a\0b", 0), (TailMatch::Matched, 3));
+        assert_eq!(match_at(&tail, a0b, b"a\0", 0),
+                   (TailMatch::QueryEnded, 2));
+        assert_eq!(match_at(&tail, a0b, b"a\0c", 0), (TailMatch::Mismatch, 2));
+        assert_eq!(match_at(&tail, nul, b"\0", 0), (TailMatch::Matched, 1));
+        assert_eq!(match_at(&tail, nul, b"\0\0", 0), (TailMatch::Matched, 1));
+        assert_eq!(match_at(&tail, nulnulc, b"\0\0", 0),
+                   (TailMatch::QueryEnded, 2));
+        assert_eq!(match_at(&tail, nulnulc, b"\0\0c", 0),
+                   (TailMatch::Matched, 3));
+    }
+
+    /// A tail of `bytes`, as text with a NUL at the end, or as binary with
+    /// `ends` as its end flags and the last one set, matched from `offset`
+    /// against a query holding a piece of the string and then `extra`.
+    fn words_prop(bytes: Vec<u8>, ends: Vec<bool>, binary: bool,
+                  offset: usize, extra: Vec<u8>, query_pos: usize,
+                  take: usize) -> qc::TestResult {
+        // Plenty of NULs, but also strings longer than a word.
+        let mut buf: Vec<u8> = bytes.iter().map(|&b| {
+            if b < 32 { 0 } else { b % 3 + 1 }
+        }).collect();
+        let mut end_flags = BitVec::new();
+        if binary {
+            if buf.is_empty() {
+                return qc::TestResult::discard();
+            }
+            for i in 0..buf.len() {
+                end_flags.push(i + 1 == buf.len()
+                               || ends.get(i).cloned().unwrap_or(false));
+            }
+        } else {
+            buf.push(0);
+        }
+        let offset = offset % buf.len();
+        if !binary && buf[offset] == 0 {
+            return qc::TestResult::discard();
+        }
+        let tail = Tail { buf_: Cow::Owned(buf), end_flags_: end_flags };
+
+        let query_pos = query_pos % (extra.len() + 1);
+        let piece = &tail.buf_[offset..];
+        let piece = &piece[..take % (piece.len() + 1)];
+        let mut query = extra[..query_pos].to_vec();
+        query.extend_from_slice(piece);
+        query.extend_from_slice(&extra[query_pos..]);
+
+        let (mut words_pos, mut bytes_pos) = (query_pos, query_pos);
+        let words = tail.match_query(offset, &query, &mut words_pos);
+        let bytes = tail.match_query_bytewise(offset, &query, &mut bytes_pos);
+        let (mut words_key, mut bytes_key) = (Vec::new(), Vec::new());
+        tail.restore(offset, &mut words_key);
+        tail.restore_bytewise(offset, &mut bytes_key);
+        qc::TestResult::from_bool(words == bytes && words_pos == bytes_pos
+                                  && words_key == bytes_key)
+    }
+
+    #[test]
+    fn words_agree_with_bytes_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(words_prop as fn(Vec<u8>, Vec<bool>, bool, usize,
+                                        Vec<u8>, usize, usize)
+                                     -> qc::TestResult);
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test --release -- --ignored`
+    /// logs the timings of both versions on URL-like strings.
+    #[test]
+    #[ignore]
+    fn long_tail_bench() {
+        let _ = env_logger::init();
+        let strs: Vec<Vec<u8>> = (0..20000).map(|i| {
+            format!("example.org/archive/{}/{}/index-{}.html", i % 97, i % 13,
+                    i).into_bytes()
+        }).collect();
+        let strs: Vec<&[u8]> = strs.iter().map(|s| &s[..]).collect();
+        for &mode in &[TailMode::Text, TailMode::Binary] {
+            let (tail, offsets) = build(&strs, mode);
+            let time = |bytewise: bool| {
+                let start = std::time::Instant::now();
+                let mut key = Vec::new();
+                for _ in 0..20 {
+                    for (s, &offset) in strs.iter().zip(offsets.iter()) {
+                        let mut pos = 0;
+                        let m = if bytewise {
+                            tail.match_query_bytewise(offset, s, &mut pos)
+                        } else {
+                            tail.match_query(offset, s, &mut pos)
+                        };
+                        assert_eq!(m, TailMatch::Matched);
+                        key.clear();
+                        if bytewise {
+                            tail.restore_bytewise(offset, &mut key);
+                        } else {
+                            tail.restore(offset, &mut key);
+                        }
+                        assert_eq!(&key[..], *s);
+                    }
+                }
+                start.elapsed()
+            };
+            info!("{:?}: {:?} by word, {:?} by byte", mode, time(false),
+                  time(true));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Searches in terms of `str` and `String`, for dictionaries of text.
+//!
+//! Queries are borrowed as bytes, so they cost nothing over the byte API.
+//! Keys read back are checked: the trie holds bytes, and a key that is not
+//! UTF-8 is a `Utf8Error` unless a `_lossy` reader is asked for, which puts
+//! U+FFFD in place of the bad bytes. Labels split keys wherever the bytes
+//! branch, often inside a character, but a key is checked only once it is
+//! whole.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use error::MarisaError;
+use super::{LoudsTrieView, PredictiveIter};
+
+fn utf8(id: usize, key: Vec<u8>) -> Result<String, MarisaError> {
+    String::from_utf8(key).map_err(|e| MarisaError::Utf8Error {
+        id: id as u64,
+        valid_up_to: e.utf8_error().valid_up_to() as u64,
+    })
+}
+
+/// Copies only if there is something to replace.
+fn utf8_lossy(key: Vec<u8>) -> String {
+    match String::from_utf8(key) {
+        Ok(s) => s,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// As `lookup`, for a `str`.
+    pub fn lookup_str(&self, query: &str) -> Option<usize> {
+        self.lookup(query.as_bytes())
+    }
+
+    /// As `id_lookup`, but fails with `Utf8Error` if key `id` is not UTF-8.
+    pub fn id_lookup_string(&self, id: usize) -> Result<String, MarisaError> {
+        self.id_lookup(id).and_then(|key| utf8(id, key))
+    }
+
+    /// As `id_lookup_string`, but replaces bytes that are not UTF-8.
+    pub fn id_lookup_string_lossy(&self, id: usize)
+      -> Result<String, MarisaError> {
+        self.id_lookup(id).map(utf8_lossy)
+    }
+
+    /// As `predictive_search(query.as_bytes()).strings()`.
+    ///
+    /// ```
+    /// use mars_trie::{Config, LoudsTrie};
+    ///
+    /// let trie = LoudsTrie::build_from(vec!["日本", "日曜", "月曜"],
+    ///                                  &Config::new()).unwrap();
+    /// let mut found: Vec<String> = trie.predictive_search_str("日")
+    ///     .map(|item| item.unwrap().1).collect();
+    /// found.sort();
+    /// assert_eq!(found, vec!["日曜", "日本"]);
+    /// let id = trie.lookup_str("月曜").unwrap();
+    /// assert_eq!(trie.id_lookup_string(id).unwrap(), "月曜");
+    /// ```
+    pub fn predictive_search_str<'a, 'b>(&'a self, query: &'b str)
+      -> StrPredictiveIter<'a, 'b> {
+        self.predictive_search(query.as_bytes()).strings()
+    }
+}
+
+impl<'a, 'b> PredictiveIter<'a, 'b> {
+    /// Turns the items into `Ok((key_id, key))` with the key as a `String`,
+    /// or `Err(Utf8Error)` for a key that is not UTF-8. The search goes on
+    /// after an error.
+    pub fn strings(self) -> StrPredictiveIter<'a, 'b> {
+        StrPredictiveIter { iter_: self }
+    }
+
+    /// Turns the items into `(key_id, key)` with the key as a `String`,
+    /// replacing bytes that are not UTF-8.
+    pub fn strings_lossy(self) -> LossyPredictiveIter<'a, 'b> {
+        LossyPredictiveIter { iter_: self }
+    }
+}
+
+/// Iterator returned by `PredictiveIter::strings`.
+#[derive(Debug)]
+pub struct StrPredictiveIter<'a, 'b> {
+    iter_: PredictiveIter<'a, 'b>,
+}
+
+impl<'a, 'b> Iterator for StrPredictiveIter<'a, 'b> {
+    type Item = Result<(usize, String), MarisaError>;
+
+    fn next(&mut self) -> Option<Result<(usize, String), MarisaError>> {
+        self.iter_.next().map(|(id, key)| utf8(id, key).map(|s| (id, s)))
+    }
+}
+
+impl<'a, 'b> std::iter::FusedIterator for StrPredictiveIter<'a, 'b> {}
+
+/// Iterator returned by `PredictiveIter::strings_lossy`.
+#[derive(Debug)]
+pub struct LossyPredictiveIter<'a, 'b> {
+    iter_: PredictiveIter<'a, 'b>,
+}
+
+impl<'a, 'b> Iterator for LossyPredictiveIter<'a, 'b> {
+    type Item = (usize, String);
+
+    fn next(&mut self) -> Option<(usize, String)> {
+        self.iter_.next().map(|(id, key)| (id, utf8_lossy(key)))
+    }
+}
+
+impl<'a, 'b> std::iter::FusedIterator for LossyPredictiveIter<'a, 'b> {}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use config::{Config, NodeOrder, NumTries, TailMode};
+    use error::MarisaError;
+    use super::super::LoudsTrie;
+
+    fn strings_prop(keys: Vec<String>, config: Config) -> bool {
+        let config = config.with_reverse_lookup(true);
+        let trie = LoudsTrie::build_from(keys.iter(), &config).unwrap();
+        keys.iter().all(|key| {
+            let id = trie.lookup_str(key).unwrap();
+            let half = key.char_indices().nth(key.chars().count() / 2)
+                          .map_or(key.len(), |(i, _)| i);
+            trie.id_lookup_string(id).as_ref() == Ok(key)
+            && trie.predictive_search_str(&key[..half])
+                   .all(|item| item.map_or(false, |(_, s)| {
+                       s.starts_with(&key[..half])
+                   }))
+        })
+    }
+
+    #[test]
+    fn strings_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(strings_prop as fn(Vec<String>, Config) -> bool);
+    }
+
+    /// Keys that branch inside characters: "日" is E6 97 A5 and "曜" is
+    /// E6 9B 9C, so siblings part after E6, and the rest of each character
+    /// goes to a link into the next trie or the tail.
+    #[test]
+    fn characters_split_across_labels() {
+        let _ = env_logger::init();
+        let keys: Vec<String> = (0..300).map(|i| {
+            let stem = ["日本", "日曜", "曜日", "本日"][i % 4];
+            format!("{}{}{}", stem, "語".repeat(i % 5), i)
+        }).collect();
+        for &num_tries in &[1, 2, 3] {
+            for &mode in &[TailMode::Text, TailMode::Binary] {
+                for &order in &[NodeOrder::Label, NodeOrder::Weight] {
+                    let config = Config::new()
+                        .with_num_tries(NumTries::new(num_tries))
+                        .with_tail_mode(mode).with_node_order(order);
+                    assert!(strings_prop(keys.clone(), config));
+                    let trie = LoudsTrie::build_from(keys.iter(), &config)
+                               .unwrap();
+                    let found = trie.predictive_search_str("日")
+                                    .collect::<Result<Vec<_>, _>>().unwrap();
+                    assert_eq!(found.len(), 150);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_that_are_not_utf8() {
+        let _ = env_logger::init();
+        // "日" cut short, and a stray continuation byte after "ok".
+        let keys: Vec<&[u8]> = vec![&b"\xE6\x97"[..], &b"ok\x9B"[..],
+                                    "日本".as_bytes()];
+        let trie = LoudsTrie::build_from(keys.iter().cloned(), &Config::new())
+                   .unwrap();
+        let cut = trie.lookup(b"\xE6\x97").unwrap();
+        assert_eq!(trie.id_lookup_string(cut),
+                   Err(MarisaError::Utf8Error { id: cut as u64,
+                                                valid_up_to: 0 }));
+        assert_eq!(trie.id_lookup_string_lossy(cut).unwrap(), "\u{FFFD}");
+        let stray = trie.lookup(b"ok\x9B").unwrap();
+        assert_eq!(trie.id_lookup_string(stray),
+                   Err(MarisaError::Utf8Error { id: stray as u64,
+                                                valid_up_to: 2 }));
+        assert_eq!(trie.id_lookup_string_lossy(stray).unwrap(),
+                   "ok\u{FFFD}");
+        let id = trie.lookup_str("日本").unwrap();
+        assert_eq!(trie.id_lookup_string(id).unwrap(), "日本");
+
+        // Bad keys are errors, and the search goes on past them.
+        let mut found = trie.predictive_search_str("").collect::<Vec<_>>();
+        found.sort_by_key(|item| item.as_ref().ok().map(|x| x.0));
+        assert_eq!(found.len(), 3);
+        assert_eq!(found.iter().filter(|item| item.is_err()).count(), 2);
+        assert_eq!(found[2], Ok((id, "日本".to_owned())));
+        let mut lossy: Vec<String> = trie.iter().strings_lossy()
+                                         .map(|(_, s)| s).collect();
+        lossy.sort();
+        assert_eq!(lossy, vec!["ok\u{FFFD}", "日本", "\u{FFFD}"]);
+
+        assert!(trie.id_lookup_string(3).is_err());
+        assert!(trie.id_lookup_string_lossy(3).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Checking a dictionary's structure after the fact, for dictionaries that
+//! came from somewhere untrusted or have been in memory a long time.
+
+use error::MarisaError;
+use super::{LoudsTrieView, louds_shape_ok, parents_come_first};
+
+fn bad(what: &'static str) -> MarisaError {
+    MarisaError::FormatError { what: what }
+}
+
+impl<'m> LoudsTrieView<'m> {
+    /// Checks what loading checks, and more: that the rank and select
+    /// tables agree with the bits they index, that the root has
+    /// `num_l1_nodes` children, that there is a weight per key when the
+    /// config asks for weights, and that the cache agrees with the trie
+    /// as in `validate`. Takes time linear in the size of the dictionary.
+    /// The first mismatch found is a `FormatError` naming the part.
+    pub fn verify(&self) -> Result<(), MarisaError> {
+        self.verify_(1)?;
+        let num_weights = if self.config_.weights() { self.len() } else { 0 };
+        if self.weights_.len() != num_weights {
+            return Err(bad("weights"));
+        }
+        self.validate()
+    }
+
+    fn verify_(&self, trie_id: usize) -> Result<(), MarisaError> {
+        self.louds_.verify().map_err(bad)?;
+        if !louds_shape_ok(&self.louds_) || !parents_come_first(&self.louds_)
+           || self.louds_.is_select0_enabled() != (trie_id == 1)
+           || !self.louds_.is_select1_enabled() {
+            return Err(bad("louds"));
+        }
+        let num_l1_nodes = (2..self.louds_.len())
+                           .take_while(|&i| self.louds_.at(i)).count();
+        if self.num_l1_nodes_ != num_l1_nodes {
+            return Err(bad("number of level 1 nodes"));
+        }
+        let num_nodes = self.num_nodes();
+
+        self.terminal_flags_.verify().map_err(bad)?;
+        let terminal_len = if trie_id == 1 { num_nodes + 1 } else { 0 };
+        if self.terminal_flags_.len() != terminal_len {
+            return Err(bad("terminal flags"));
+        }
+        self.link_flags_.verify().map_err(bad)?;
+        if self.link_flags_.len() != num_nodes || self.link_flags_.at(0)
+           || !self.link_flags_.is_rank_enabled() {
+            return Err(bad("link flags"));
+        }
+        if self.bases_.len() != num_nodes {
+            return Err(bad("bases"));
+        }
+        if self.extras_.len() != self.link_flags_.num_1s() {
+            return Err(bad("extras"));
+        }
+        self.tail_.verify().map_err(bad)?;
+        let has_next = !self.extras_.is_empty() && self.tail_.is_empty();
+        if has_next != self.next_trie_.is_some() {
+            return Err(bad("next trie"));
+        }
+        let num_tries = match &self.next_trie_ {
+            &Some(ref next) => next.num_tries() + 1,
+            &None => 1,
+        };
+        if self.num_tries() != num_tries {
+            return Err(bad("number of tries"));
+        }
+        self.check_links()?;
+        match &self.next_trie_ {
+            &Some(ref next) => next.verify_(trie_id + 1),
+            &None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use std;
+    use env_logger;
+    use config::{Config, NumTries, TailMode};
+    use error::MarisaError;
+    use louds_trie::LoudsTrie;
+    use louds_trie::tail::Tail;
+    use vector::bit_vec::BitVec;
+
+    fn keys() -> Vec<String> {
+        (0..2000u64).map(|i| {
+            format!("/{:x}/{}/item-{}", i.wrapping_mul(0x9E37_79B9) >> 20,
+                    i % 13, i)
+        }).collect()
+    }
+
+    fn config() -> Config {
+        Config::new().with_num_tries(NumTries::new(3))
+                     .with_tail_mode(TailMode::Binary)
+                     .with_weights(true)
+    }
+
+    /// A fresh copy of the dictionary, to corrupt.
+    fn fresh() -> LoudsTrie {
+        LoudsTrie::build_from(keys().iter(), &config()).unwrap()
+    }
+
+    fn is_format_error(trie: &LoudsTrie) -> bool {
+        match trie.verify() {
+            Err(MarisaError::FormatError { .. }) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn built_and_read_tries_verify() {
+        let _ = env_logger::init();
+        for &num_tries in &[1, 2, 4] {
+            for &tail_mode in &[TailMode::Text, TailMode::Binary] {
+                for &weights in &[false, true] {
+                    let config = Config::new()
+                        .with_num_tries(NumTries::new(num_tries))
+                        .with_tail_mode(tail_mode)
+                        .with_weights(weights);
+                    let trie = LoudsTrie::build_from(keys().iter(), &config)
+                               .unwrap();
+                    assert_eq!(trie.verify(), Ok(()));
+                }
+            }
+        }
+        #[cfg(feature = "std")]
+        {
+            let mut bytes = Vec::new();
+            fresh().write_to(&mut bytes).unwrap();
+            let read = LoudsTrie::read_from(&mut &bytes[..]).unwrap();
+            assert_eq!(read.verify(), Ok(()));
+        }
+        let empty = LoudsTrie::build_from(Vec::<&str>::new(), &Config::new())
+                    .unwrap();
+        assert_eq!(empty.verify(), Ok(()));
+    }
+
+    #[test]
+    fn catches_corruption() {
+        let _ = env_logger::init();
+        assert!(fresh().next_trie_.is_some());
+
+        let mut trie = fresh();
+        trie.num_l1_nodes_ += 1;
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError {
+                       what: "number of level 1 nodes" }));
+
+        let mut trie = fresh();
+        trie.bases_.to_mut().pop();
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError { what: "bases" }));
+
+        let mut trie = fresh();
+        trie.weights_.to_mut().pop();
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError { what: "weights" }));
+
+        let mut trie = fresh();
+        trie.terminal_flags_.push(false);
+        assert!(is_format_error(&trie));
+
+        // The same bits, but without the select0 table lookups need.
+        let mut trie = fresh();
+        let mut louds = BitVec::new();
+        for i in 0..trie.louds_.len() {
+            louds.push(trie.louds_.at(i));
+        }
+        louds.build_index(false, true);
+        trie.louds_ = louds;
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError { what: "louds" }));
+
+        // A link that points past the end of the last trie's tail.
+        let mut trie = fresh();
+        {
+            let mut last = &mut trie;
+            while last.next_trie_.is_some() {
+                last = last.next_trie_.as_mut().unwrap();
+            }
+            let config = config().with_num_tries(NumTries::new(1));
+            let mut short = LoudsTrie::build_from(vec!["abc", "de"], &config)
+                            .unwrap();
+            assert!(!short.tail_.is_empty());
+            last.tail_ = std::mem::replace(&mut short.tail_, Tail::new());
+        }
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError { what: "link" }));
+
+        // A cache entry filed under the wrong slot.
+        let mut trie = fresh();
+        let filled = trie.cache_.iter()
+                     .position(|c| c.child() != std::u32::MAX).unwrap();
+        let entry = trie.cache_[filled];
+        let other = (filled + 1) & trie.cache_mask_;
+        trie.cache_[other] = entry;
+        assert_eq!(trie.verify(),
+                   Err(MarisaError::FormatError { what: "cache" }));
+    }
+}
@@ -1,196 +1,286 @@
-#ifndef MARISA_GRIMOIRE_ALGORITHM_SORT_H_
-#define MARISA_GRIMOIRE_ALGORITHM_SORT_H_
+//! Multi-key quicksort, a.k.a. three-way radix quicksort, as used by
+//! marisa-trie to sort keys and tail entries.
+//!
+//! Units are partitioned on the byte at the current depth only, so a long
+//! prefix shared by many units is looked at once per level instead of once
+//! per comparison.
 
-#include "marisa/base.h"
+use std;
+use std::cmp::Ordering;
+use algo::cmp;
 
-namespace marisa {
-namespace grimoire {
-namespace algorithm {
-namespace details {
+const INSERTION_SORT_THRESHOLD: usize = 10;
 
-enum {
-  MARISA_INSERTION_SORT_THRESHOLD = 10
-};
-
-template <typename T>
-int get_label(const T &unit, std::size_t depth) {
-  MARISA_DEBUG_IF(depth > unit.length(), MARISA_BOUND_ERROR);
-
-  return (depth < unit.length()) ? (int)(UInt8)unit[depth] : -1;
+/// A byte string to be sorted, visited from index 0 up. Impls read their
+/// bytes with `cmp::forward_at` or `cmp::reverse_at`, so that sorting
+/// agrees with `cmp::forward` or `cmp::reverse`.
+pub trait Label {
+    fn label_len(&self) -> usize;
+    fn label_at(&self, i: usize) -> u8;
 }
 
-template <typename T>
-int median(const T &a, const T &b, const T &c, std::size_t depth) {
-  const int x = get_label(a, depth);
-  const int y = get_label(b, depth);
-  const int z = get_label(c, depth);
-  if (x < y) {
-    if (y < z) {
-      return y;
-    } else if (x < z) {
-      return z;
-    }
-    return x;
-  } else if (x < z) {
-    return x;
-  } else if (y < z) {
-    return z;
-  }
-  return y;
+/// The byte at `depth`, or -1 past the end so shorter units sort first.
+fn get_label<T: Label>(unit: &T, depth: usize) -> i32 {
+    assert!(depth <= unit.label_len(), "MARISA_BOUND_ERROR");
+    if depth < unit.label_len() { unit.label_at(depth) as i32 } else { -1 }
 }
 
-template <typename T>
-int compare(const T &lhs, const T &rhs, std::size_t depth) {
-  for (std::size_t i = depth; i < lhs.length(); ++i) {
-    if (i == rhs.length()) {
-      return 1;
-    }
-    if (lhs[i] != rhs[i]) {
-      return (UInt8)lhs[i] - (UInt8)rhs[i];
+fn median<T: Label>(a: &T, b: &T, c: &T, depth: usize) -> i32 {
+    let x = get_label(a, depth);
+    let y = get_label(b, depth);
+    let z = get_label(c, depth);
+    if x < y {
+        if y < z { y } else if x < z { z } else { x }
+    } else if x < z {
+        x
+    } else if y < z {
+        z
+    } else {
+        y
     }
-  }
-  if (lhs.length() == rhs.length()) {
-    return 0;
-  }
-  return (lhs.length() < rhs.length()) ? -1 : 1;
 }
 
-template <typename Iterator>
-std::size_t insertion_sort(Iterator l, Iterator r, std::size_t depth) {
-  MARISA_DEBUG_IF(l > r, MARISA_BOUND_ERROR);
-
-  std::size_t count = 1;
-  for (Iterator i = l + 1; i < r; ++i) {
-    int result = 0;
-    for (Iterator j = i; j > l; --j) {
-      result = compare(*(j - 1), *j, depth);
-      if (result <= 0) {
-        break;
-      }
-      marisa::swap(*(j - 1), *j);
-    }
-    if (result != 0) {
-      ++count;
+/// Length of the prefix, starting at `depth`, shared by every unit in `v`.
+/// Gives up as soon as it reaches 0, which is cheap on unskewed input.
+fn shared_len<T: Label>(v: &[T], depth: usize) -> usize {
+    let first = &v[0];
+    let mut len = first.label_len() - depth;
+    for unit in &v[1..] {
+        if len == 0 {
+            break;
+        }
+        let max = std::cmp::min(len, unit.label_len() - depth);
+        len = (0..max).take_while(|&i| {
+            unit.label_at(depth + i) == first.label_at(depth + i)
+        }).count();
     }
-  }
-  return count;
+    len
 }
 
-template <typename Iterator>
-std::size_t sort(Iterator l, Iterator r, std::size_t depth) {
-  MARISA_DEBUG_IF(l > r, MARISA_BOUND_ERROR);
-
-  std::size_t count = 0;
-  while ((r - l) > MARISA_INSERTION_SORT_THRESHOLD) {
-    Iterator pl = l;
-    Iterator pr = r;
-    Iterator pivot_l = l;
-    Iterator pivot_r = r;
-
-    const int pivot = median(*l, *(l + (r - l) / 2), *(r - 1), depth);
-    for ( ; ; ) {
-      while (pl < pr) {
-        const int label = get_label(*pl, depth);
-        if (label > pivot) {
-          break;
-        } else if (label == pivot) {
-          marisa::swap(*pl, *pivot_l);
-          ++pivot_l;
+fn insertion_sort<T: Label>(v: &mut [T], depth: usize) -> usize {
+    let mut count = 1;
+    for i in 1..v.len() {
+        let mut result = Ordering::Equal;
+        let mut j = i;
+        while j > 0 {
+            result = cmp::labels(&v[j - 1], &v[j], depth);
+            if result != Ordering::Greater {
+                break;
+            }
+            v.swap(j - 1, j);
+            j -= 1;
         }
-        ++pl;
-      }
-      while (pl < pr) {
-        const int label = get_label(*--pr, depth);
-        if (label < pivot) {
-          break;
-        } else if (label == pivot) {
-          marisa::swap(*pr, *--pivot_r);
+        if result != Ordering::Equal {
+            count += 1;
         }
-      }
-      if (pl >= pr) {
-        break;
-      }
-      marisa::swap(*pl, *pr);
-      ++pl;
-    }
-    while (pivot_l > l) {
-      marisa::swap(*--pivot_l, *--pl);
-    }
-    while (pivot_r < r) {
-      marisa::swap(*pivot_r, *pr);
-      ++pivot_r;
-      ++pr;
     }
+    count
+}
 
-    if (((pl - l) > (pr - pl)) || ((r - pr) > (pr - pl))) {
-      if ((pr - pl) == 1) {
-        ++count;
-      } else if ((pr - pl) > 1) {
-        if (pivot == -1) {
-          ++count;
-        } else {
-          count += sort(pl, pr, depth + 1);
-        }
-      }
+fn sort_<T: Label>(v: &mut [T], mut l: usize, mut r: usize,
+                   mut depth: usize) -> usize {
+    let mut count = 0;
+    while r - l > INSERTION_SORT_THRESHOLD {
+        // A long prefix shared by the whole range would otherwise cost one
+        // partitioning pass per byte.
+        depth += shared_len(&v[l..r], depth);
+
+        let mut pl = l;
+        let mut pr = r;
+        let mut pivot_l = l;
+        let mut pivot_r = r;
 
-      if ((pl - l) < (r - pr)) {
-        if ((pl - l) == 1) {
-          ++count;
-        } else if ((pl - l) > 1) {
-          count += sort(l, pl, depth);
+        let pivot = median(&v[l], &v[l + (r - l) / 2], &v[r - 1], depth);
+        loop {
+            while pl < pr {
+                let label = get_label(&v[pl], depth);
+                if label > pivot {
+                    break;
+                } else if label == pivot {
+                    if pl != pivot_l {
+                        v.swap(pl, pivot_l);
+                    }
+                    pivot_l += 1;
+                }
+                pl += 1;
+            }
+            while pl < pr {
+                pr -= 1;
+                let label = get_label(&v[pr], depth);
+                if label < pivot {
+                    break;
+                } else if label == pivot {
+                    pivot_r -= 1;
+                    v.swap(pr, pivot_r);
+                }
+            }
+            if pl >= pr {
+                break;
+            }
+            v.swap(pl, pr);
+            pl += 1;
         }
-        l = pr;
-      } else {
-        if ((r - pr) == 1) {
-          ++count;
-        } else if ((r - pr) > 1) {
-          count += sort(pr, r, depth);
+        while pivot_l > l {
+            pivot_l -= 1;
+            pl -= 1;
+            v.swap(pivot_l, pl);
         }
-        r = pl;
-      }
-    } else {
-      if ((pl - l) == 1) {
-        ++count;
-      } else if ((pl - l) > 1) {
-        count += sort(l, pl, depth);
-      }
-
-      if ((r - pr) == 1) {
-        ++count;
-      } else if ((r - pr) > 1) {
-        count += sort(pr, r, depth);
-      }
-
-      l = pl, r = pr;
-      if ((pr - pl) == 1) {
-        ++count;
-      } else if ((pr - pl) > 1) {
-        if (pivot == -1) {
-          l = r;
-          ++count;
+        while pivot_r < r {
+            v.swap(pivot_r, pr);
+            pivot_r += 1;
+            pr += 1;
+        }
+
+        // [l, pl) sorts before the pivot, [pl, pr) has the pivot label at
+        // this depth and [pr, r) sorts after it. Recurse on the smaller
+        // parts and loop on the largest.
+        if (pl - l) > (pr - pl) || (r - pr) > (pr - pl) {
+            if pr - pl == 1 {
+                count += 1;
+            } else if pr - pl > 1 {
+                if pivot == -1 {
+                    count += 1;
+                } else {
+                    count += sort_(v, pl, pr, depth + 1);
+                }
+            }
+
+            if (pl - l) < (r - pr) {
+                if pl - l == 1 {
+                    count += 1;
+                } else if pl - l > 1 {
+                    count += sort_(v, l, pl, depth);
+                }
+                l = pr;
+            } else {
+                if r - pr == 1 {
+                    count += 1;
+                } else if r - pr > 1 {
+                    count += sort_(v, pr, r, depth);
+                }
+                r = pl;
+            }
         } else {
-          ++depth;
+            if pl - l == 1 {
+                count += 1;
+            } else if pl - l > 1 {
+                count += sort_(v, l, pl, depth);
+            }
+
+            if r - pr == 1 {
+                count += 1;
+            } else if r - pr > 1 {
+                count += sort_(v, pr, r, depth);
+            }
+
+            l = pl;
+            r = pr;
+            if pr - pl == 1 {
+                count += 1;
+            } else if pr - pl > 1 {
+                if pivot == -1 {
+                    l = r;
+                    count += 1;
+                } else {
+                    depth += 1;
+                }
+            }
         }
-      }
     }
-  }
 
-  if ((r - l) > 1) {
-    count += insertion_sort(l, r, depth);
-  }
-  return count;
+    if r - l > 1 {
+        count += insertion_sort(&mut v[l..r], depth);
+    }
+    count
 }
 
-}  // namespace details
+/// Sorts `v` by label and returns the number of distinct labels. Not
+/// stable.
+pub fn sort<T: Label>(v: &mut [T]) -> usize {
+    let len = v.len();
+    if len <= 1 {
+        return len;
+    }
+    sort_(v, 0, len, 0)
+}
 
-template <typename Iterator>
-std::size_t sort(Iterator begin, Iterator end) {
-  MARISA_DEBUG_IF(begin > end, MARISA_BOUND_ERROR);
-  return details::sort(begin, end, 0);
-};
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use key::{IKey, Key, ReverseKey};
+    use quickcheck as qc;
+    use std;
+    use super::sort;
 
-}  // namespace algorithm
-}  // namespace grimoire
-}  // namespace marisa
+    fn sort_prop(v: Vec<Vec<u8>>) -> bool {
+        let mut keys: Vec<Key> = v.iter().map(|k| Key::new(k)).collect();
+        let count = sort(&mut keys);
+        let mut expected = v.clone();
+        expected.sort();
+        let sorted: Vec<&[u8]> = keys.iter().map(|k| k.get_slice()).collect();
+        let mut distinct = expected.clone();
+        distinct.dedup();
 
-#endif  // MARISA_GRIMOIRE_ALGORITHM_SORT_H_
+        let mut rkeys: Vec<ReverseKey> = v.iter().map(|k| ReverseKey::new(k))
+                                         .collect();
+        sort(&mut rkeys);
+        let mut rexpected: Vec<ReverseKey> = v.iter()
+            .map(|k| ReverseKey::new(k)).collect();
+        rexpected.sort();
+
+        sorted == expected.iter().map(|k| &k[..]).collect::<Vec<_>>()
+        && count == distinct.len()
+        && rkeys == rexpected
+    }
+
+    #[test]
+    fn sort_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(sort_prop as fn(Vec<Vec<u8>>) -> bool);
+    }
+
+    #[test]
+    fn sort_manual() {
+        let _ = env_logger::init();
+        let v = |s: &[&str]| s.iter().map(|x| x.as_bytes().to_vec())
+                             .collect::<Vec<_>>();
+        assert!(sort_prop(v(&["b", "a", "", "ab", "a", "ba", "", "abc",
+                              "abd", "bb", "b", "c", "aa", "cab", "ca"])));
+        // Enough units with a shared prefix to partition more than once.
+        let many: Vec<Vec<u8>> = (0..1000)
+            .map(|i| format!("prefix/{}/{}", i % 7, (i * 7919) % 1000)
+                     .into_bytes())
+            .collect();
+        assert!(sort_prop(many));
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test --release -- --ignored`
+    /// logs the timings.
+    #[test]
+    #[ignore]
+    fn sort_bench() {
+        let _ = env_logger::init();
+        let prefix = "http://www.example.com/a/rather/long/shared/prefix/";
+        let owned: Vec<Vec<u8>> = (0..1000000u64)
+            .map(|i| format!("{}{}", prefix, (i * 2654435761) % 1000000007)
+                     .into_bytes())
+            .collect();
+        let keys: Vec<Key> = owned.iter().map(|k| Key::new(k)).collect();
+
+        let mut a = keys.clone();
+        let start = std::time::Instant::now();
+        a.sort();
+        let std_time = start.elapsed();
+
+        let mut b = keys.clone();
+        let start = std::time::Instant::now();
+        sort(&mut b);
+        let mkqs_time = start.elapsed();
+
+        assert!(a == b);
+        info!("slice::sort: {:?}, multi-key quicksort: {:?}",
+              std_time, mkqs_time);
+    }
+}
@@ -0,0 +1,191 @@
+//! The part of `std::io` that the readers and writers use. Without std the
+//! only source is a byte slice and the only sink a vector, so an error is
+//! either running out of input or a `MarisaError` about what was read.
+
+use core::cmp;
+use core::fmt;
+use core::result;
+use alloc::vec::Vec;
+use error::MarisaError;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    InvalidData,
+    InvalidInput,
+    UnexpectedEof,
+    WriteZero,
+    Other,
+}
+
+#[derive(Debug)]
+pub enum Payload {
+    Error(MarisaError),
+    Message(&'static str),
+}
+
+impl From<MarisaError> for Payload {
+    fn from(e: MarisaError) -> Payload {
+        Payload::Error(e)
+    }
+}
+
+impl From<&'static str> for Payload {
+    fn from(message: &'static str) -> Payload {
+        Payload::Message(message)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind_: ErrorKind,
+    payload_: Payload,
+}
+
+impl Error {
+    pub fn new<P: Into<Payload>>(kind: ErrorKind, payload: P) -> Error {
+        Error { kind_: kind, payload_: payload.into() }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind_
+    }
+
+    /// The `MarisaError` the error was made from, if it was.
+    pub fn into_marisa_error(self) -> Option<MarisaError> {
+        match self.payload_ {
+            Payload::Error(e) => Some(e),
+            Payload::Message(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.payload_ {
+            Payload::Error(ref e) => write!(f, "{}", e),
+            Payload::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub trait Read {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let mut chunk = [0; 64];
+        let mut total = 0;
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(total),
+                n => { buf.extend_from_slice(&chunk[..n]); total += n; },
+            }
+        }
+    }
+
+    fn by_ref(&mut self) -> &mut Self where Self: Sized {
+        self
+    }
+
+    fn take(self, limit: u64) -> Take<Self> where Self: Sized {
+        Take { inner_: self, limit_: limit }
+    }
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Error::new(ErrorKind::UnexpectedEof,
+                                           "truncated input")),
+                n => { let rest = buf; buf = &mut rest[n..]; },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads at most `limit` bytes of `inner`.
+pub struct Take<R> {
+    inner_: R,
+    limit_: u64,
+}
+
+impl<R: Read> Read for Take<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let max = cmp::min(buf.len() as u64, self.limit_) as usize;
+        let n = self.inner_.read(&mut buf[..max])?;
+        self.limit_ -= n as u64;
+        Ok(n)
+    }
+}
+
+pub trait Write {
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    fn flush(&mut self) -> Result<()>;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Error::new(ErrorKind::WriteZero,
+                                           "failed to write whole buffer")),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Read for &'a [u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = cmp::min(buf.len(), self.len());
+        let (head, tail) = self.split_at(n);
+        buf[..n].copy_from_slice(head);
+        *self = tail;
+        Ok(n)
+    }
+}
+
+impl<'a, R: Read + ?Sized> Read for &'a mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for &'a mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Throws away what is written to it.
+pub struct Sink;
+
+pub fn sink() -> Sink {
+    Sink
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+//! What the crate uses of std, from `core` and `alloc`, for builds without
+//! the `std` feature. Mounted as the crate's `std`, so that `use std;` and
+//! `std::` paths elsewhere mean the same with and without it.
+
+pub use core::*;
+
+pub use alloc::{borrow, fmt, slice, str, vec};
+
+pub mod collections {
+    pub use alloc::collections::*;
+}
+
+pub mod prelude {
+    pub mod v1 {
+        pub use alloc::borrow::ToOwned;
+        pub use alloc::boxed::Box;
+        pub use alloc::string::{String, ToString};
+        pub use alloc::vec::Vec;
+    }
+}
+
+pub mod io;
+
+// Tests still run on a host, with the real thing underneath.
+#[cfg(test)]
+pub use host_std::{alloc, cell, panic, sync, thread, time};
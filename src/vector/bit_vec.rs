@@ -1,31 +1,60 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 use std;
+use std::io;
 use std::mem;
 use base::WORD_SIZE;
-use super::intrinsic::Ctz;
+use error::MarisaError;
+use super::intrinsic::{Ctz, prefetch, select_bit_in_word};
+use super::io::SerializableVec;
+use super::pod_vec::PodVec;
 use super::rank_index::RankIndex;
-use super::util::vec_resize;
+use super::util::{invalid_data, map_len, read_bytes, read_len,
+                  read_libmarisa_vec, read_u32, read_u64, slice_total_size,
+                  u64_from_le, vec_resize, write_libmarisa_vec, write_u32,
+                  write_u64};
+
+/// Number of u64 words the bits take up when serialized.
+fn io_words(size: usize) -> usize {
+    size / 64 + if size % 64 != 0 { 1 } else { 0 }
+}
 
+#[cfg(test)]
+thread_local! {
+    /// Number of select0 calls made on this thread, so tests can tell how
+    /// much louds traversal a search did.
+    pub static SELECT0_CALLS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+/// Number of entries in a rank index or select table covering `n` bits, or
+/// `n` 0s or 1s: one per 512 and one past the end.
+fn index_len(n: usize) -> usize {
+    n / 512 + if n % 512 != 0 { 1 } else { 0 } + 1
+}
+
+/// Bits, and their rank index and select tables once built. Each part is
+/// owned, or borrowed from a mapped buffer and copied on the first write.
 #[derive(Clone, Debug)]
-pub struct BitVec {
-    units_: Vec<usize>,
+pub struct BitVec<'a> {
+    units_: PodVec<'a, usize>,
     size_: usize,
     num_1s_: usize,
-    ranks_: Vec<RankIndex>,
-    select0s_: Vec<u32>,
-    select1s_: Vec<u32>,
+    ranks_: PodVec<'a, RankIndex>,
+    select0s_: PodVec<'a, u32>,
+    select1s_: PodVec<'a, u32>,
 }
 
-impl PartialEq for BitVec {
-    fn eq(&self, other: &BitVec) -> bool {
+impl<'a, 'b> PartialEq<BitVec<'b>> for BitVec<'a> {
+    fn eq(&self, other: &BitVec<'b>) -> bool {
         self.size_ == other.size_
         && self.num_1s_ == other.num_1s_
         && self.units_ == other.units_
     }
 }
-impl Eq for BitVec {}
+impl<'a> Eq for BitVec<'a> {}
 
-impl BitVec {
-    pub fn new() -> BitVec {
+impl<'a> BitVec<'a> {
+    pub fn new() -> BitVec<'a> {
         BitVec { 
             units_: Default::default(),
             size_: 0,
@@ -35,21 +64,10 @@ impl BitVec {
             select1s_: Default::default()
         }
     }
-    pub fn from_words<'a, T>(x: T, bits: usize) -> BitVec
-      where T: IntoIterator<Item=&'a usize> {
+    /// An empty vector with room for `bits` bits.
+    pub fn with_capacity(bits: usize) -> BitVec<'a> {
         let mut bv = BitVec::new();
-        bv.units_ = x.into_iter().map(|x| *x).collect();
-        if (bits + WORD_SIZE - 1) / WORD_SIZE != bv.units_.len() { panic!() }
-        bv.size_ = bits;
-        let part = bv.size_ % WORD_SIZE;
-        if 0 != part {
-            let mask = (1 << part) - 1;
-            let last = bv.units_[bv.size_ / WORD_SIZE];
-            if last & mask != last { panic!() }
-        }
-        for word in &bv.units_ {
-            bv.num_1s_ += word.count_ones() as usize;
-        }
+        bv.units_.to_mut().reserve(io_words(bits) * (64 / WORD_SIZE));
         bv
     }
 
@@ -72,6 +90,23 @@ impl BitVec {
         (self.units_[i / WORD_SIZE] & (1usize << (i % WORD_SIZE))) != 0
     }
 
+    /// Hints that `at(i)` and `rank1(i)` will be called soon.
+    pub fn prefetch_rank(&self, i: usize) {
+        if let Some(rank) = self.ranks_.get(i / 512) {
+            prefetch(rank);
+        }
+        if let Some(unit) = self.units_.get(i / WORD_SIZE) {
+            prefetch(unit);
+        }
+    }
+
+    /// Hints that `select0(i)` will be called soon.
+    pub fn prefetch_select0(&self, i: usize) {
+        if let Some(select) = self.select0s_.get(i / 512) {
+            prefetch(select);
+        }
+    }
+
     pub fn is_select0_enabled(&self) -> bool {
         !self.select0s_.is_empty()
     }
@@ -84,17 +119,20 @@ impl BitVec {
         !self.ranks_.is_empty()
     }
 
-    pub fn build(&mut self, enables_select0: bool, enables_select1: bool) {
+    /// Builds the rank index, and the select tables asked for. Each select
+    /// table samples the position of every 512th 0 or 1, and costs 4 bytes
+    /// per 512 of them. There is no fallback without a table: `select0` and
+    /// `select1` panic unless it was built, so ask for what will be used.
+    pub fn build_index(&mut self, enables_select0: bool,
+                       enables_select1: bool) {
 
         let mut old = BitVec::new();
         mem::swap(self, &mut old);
         let old = old;
 
-        let ranks_size = (old.len() / 512)
-                       + (if old.len() % 512 != 0 { 1 } else { 0 })
-                       + 1;
+        let ranks_size = index_len(old.len());
 
-        vec_resize(&mut self.ranks_, ranks_size);
+        vec_resize(self.ranks_.to_mut(), ranks_size);
 
         let mut num_0s: usize = 0;
         let mut num_1s: usize = 0;
@@ -134,20 +172,22 @@ impl BitVec {
         if old.len() % 512 != 0 {
             let rank_id = (old.len() - 1) / 512;
             let nu = num_1s as u32 - self.ranks_[rank_id].abs();
-            match_fallthrough!(
-                ((old.len() - 1) / 64) % 8,
-            {
-                0 => { self.ranks_[rank_id].set_rel1(nu); },
-                1 => { self.ranks_[rank_id].set_rel2(nu); },
-                2 => { self.ranks_[rank_id].set_rel3(nu); },
-                3 => { self.ranks_[rank_id].set_rel4(nu); },
-                4 => { self.ranks_[rank_id].set_rel5(nu); },
-                5 => { self.ranks_[rank_id].set_rel6(nu); },
-                6 => { self.ranks_[rank_id].set_rel7(nu);
-                       break;
-                     },
-                _ => { panic!(); }
-            });
+            let rank = &mut self.ranks_[rank_id];
+            // Each word after the last one in the block starts with all
+            // `nu` ones before it. If the last word is the rel7 word
+            // itself, there is nothing left to fill in.
+            for word in ((old.len() - 1) / 64) % 8 + 1..8 {
+                match word {
+                    1 => rank.set_rel1(nu),
+                    2 => rank.set_rel2(nu),
+                    3 => rank.set_rel3(nu),
+                    4 => rank.set_rel4(nu),
+                    5 => rank.set_rel5(nu),
+                    6 => rank.set_rel6(nu),
+                    7 => rank.set_rel7(nu),
+                    _ => unreachable!(),
+                }
+            }
         }
 
         self.size_ = old.len();
@@ -168,11 +208,34 @@ impl BitVec {
         self.units_.shrink_to_fit();
     }
 
-    pub fn disable_select0(&mut self) {
-        self.select0s_.clear();
-    }
-    pub fn disable_select1(&mut self) {
-        self.select1s_.clear();
+    /// Checks the count of 1s, and the rank index and select tables if
+    /// built, against the bits, by building them again. Linear in the
+    /// length.
+    pub fn verify(&self) -> Result<(), &'static str> {
+        if self.units_.len() * WORD_SIZE < self.size_ {
+            return Err("bit vector length");
+        }
+        let num_1s = (0..self.size_).filter(|&i| self.at(i)).count();
+        if num_1s != self.num_1s_ {
+            return Err("bit vector number of 1s");
+        }
+        if !self.is_rank_enabled() {
+            if self.is_select0_enabled() || self.is_select1_enabled() {
+                return Err("bit vector index");
+            }
+            return Ok(());
+        }
+        let mut fresh = self.clone();
+        fresh.build_index(self.is_select0_enabled(),
+                          self.is_select1_enabled());
+        let same_ranks = fresh.ranks_.len() == self.ranks_.len()
+            && fresh.ranks_.iter().zip(self.ranks_.iter())
+                                  .all(|(a, b)| a.words() == b.words());
+        if !same_ranks || fresh.select0s_ != self.select0s_
+           || fresh.select1s_ != self.select1s_ {
+            return Err("bit vector index");
+        }
+        Ok(())
     }
 
     pub fn push(&mut self, bit: bool) {
@@ -180,7 +243,7 @@ impl BitVec {
 
         if self.size_ == WORD_SIZE * self.units_.len() {
             let newSize = self.units_.len() + (64 / WORD_SIZE);
-            vec_resize(&mut self.units_, newSize);
+            vec_resize(self.units_.to_mut(), newSize);
         }
         if bit {
             self.units_[self.size_ / WORD_SIZE] |=
@@ -190,10 +253,91 @@ impl BitVec {
         self.size_ += 1;
     }
 
+    /// Appends `count` copies of `bit`, a word at a time.
+    pub fn push_run(&mut self, bit: bool, mut count: usize) {
+        let word = if bit { !0u64 } else { 0 };
+        while count >= 64 {
+            self.append_bits(word, 64);
+            count -= 64;
+        }
+        self.append_bits(word, count);
+    }
+
+    /// Appends the bits of `iter` in order, a word at a time.
+    pub fn extend_from_bool_iter<I: Iterator<Item=bool>>(&mut self, iter: I) {
+        let mut word: u64 = 0;
+        let mut n: usize = 0;
+        for bit in iter {
+            word |= (bit as u64) << n;
+            n += 1;
+            if n == 64 {
+                self.append_bits(word, 64);
+                word = 0;
+                n = 0;
+            }
+        }
+        self.append_bits(word, n);
+    }
+
+    /// Appends the first `bit_len` bits of `words`, least significant bit
+    /// of `words[0]` first. Bits past `bit_len` are ignored.
+    pub fn extend_from_words(&mut self, words: &[u64], bit_len: usize) {
+        assert!(bit_len <= words.len() * 64, "MARISA_BOUND_ERROR");
+        let full = bit_len / 64;
+        for &word in &words[..full] {
+            self.append_bits(word, 64);
+        }
+        if bit_len % 64 != 0 {
+            self.append_bits(words[full], bit_len % 64);
+        }
+    }
+
+    /// Appends the low `n` bits of `bits`, `n <= 64`, filling at most two
+    /// units per call.
+    fn append_bits(&mut self, mut bits: u64, mut n: usize) {
+        assert!(n <= 64);
+        assert!(self.size_ + n <= std::u32::MAX as usize);
+        if n < 64 {
+            bits &= (1u64 << n) - 1;
+        }
+        self.num_1s_ += bits.count_ones() as usize;
+        while n > 0 {
+            if self.size_ == WORD_SIZE * self.units_.len() {
+                let new_size = self.units_.len() + (64 / WORD_SIZE);
+                vec_resize(self.units_.to_mut(), new_size);
+            }
+            let offset = self.size_ % WORD_SIZE;
+            let take = std::cmp::min(WORD_SIZE - offset, n);
+            self.units_[self.size_ / WORD_SIZE] |= (bits as usize) << offset;
+            self.size_ += take;
+            bits = if take < 64 { bits >> take } else { 0 };
+            n -= take;
+        }
+    }
+
     pub fn clear(&mut self) {
         *self = BitVec::new();
     }
 
+    /// The position of the first 1 at or after `i`, found a word at a time,
+    /// or None if there is none.
+    pub fn next_1(&self, i: usize) -> Option<usize> {
+        if i >= self.size_ {
+            return None;
+        }
+        let mut unit = i / WORD_SIZE;
+        let mut word = self.units_[unit] & (!0usize << (i % WORD_SIZE));
+        while word == 0 {
+            unit += 1;
+            if unit >= self.units_.len() {
+                return None;
+            }
+            word = self.units_[unit];
+        }
+        let pos = unit * WORD_SIZE + word.ctz() as usize;
+        if pos < self.size_ { Some(pos) } else { None }
+    }
+
     pub fn rank0(&self, i: usize) -> usize {
         assert!(self.is_rank_enabled(),
                 "rank0 was called, but ranks are not enabled");
@@ -228,7 +372,10 @@ impl BitVec {
 
     #[cfg(target_pointer_width = "64")]
     fn rank1_offset_rest(&self, i: usize) -> usize {
-        assert!(i / 64 < self.units_.len());
+        // `i` may be the length, at the start of a unit that doesn't exist.
+        if i % 64 == 0 {
+            return 0;
+        }
         (self.units_[i / 64] & (((1u64.wrapping_shl((i % 64) as u32) as u64) - 1)) as usize)
             .count_ones() as usize
     }
@@ -238,15 +385,20 @@ impl BitVec {
         if ((i / 32) & 1) == 1 {
           rest += (self.units_[(i / 32) - 1]).count_ones() as usize;
         }
-        rest += (self.units_[i / 32] & ((1 << (i % 32)) - 1)).count_ones()
-                as usize;
+        if i % 32 != 0 {
+            rest += (self.units_[i / 32] & ((1 << (i % 32)) - 1)).count_ones()
+                    as usize;
+        }
         rest
     }
 
-    #[cfg(target_pointer_width = "64")]
+    /// Position of the `i`th 0. Panics unless `i < num_0s()` and the select0
+    /// table was built.
     pub fn select0(&self, mut i: usize) -> usize {
+        #[cfg(test)]
+        SELECT0_CALLS.with(|n| n.set(n.get() + 1));
         assert!(self.is_select0_enabled(),
-                "select0 was called, but select0 is not enabled");
+                "select0 was called, but build_index did not enable it");
         assert!(i < self.num_0s(), "MARISA_BOUND_ERROR");
 
         let select_id: usize = i / 512;
@@ -305,108 +457,16 @@ impl BitVec {
             unit_id += 7;
             i -= 448 - (rank.rel7() as usize);
         }
-        return self.select_bit(i, unit_id.wrapping_mul(64),
-                               !self.units_[unit_id]);
+        return unit_id * 64
+               + select_bit_in_word(!self.word(unit_id), i as u32) as usize;
     }
 
-    #[cfg(target_pointer_width = "32")]
-    pub fn select0(&self, mut i: usize) -> usize {
-        assert!(self.is_select0_enabled(),
-                "select0 was called, but select0 is not enabled");
-        assert!(i < self.num_0s(), "MARISA_BOUND_ERROR");
-    
-        let select_id: usize = i / 512;
-        assert!((select_id + 1) < self.select0s_.len(), "MARISA_BOUND_ERROR");
-        if (i % 512) == 0 {
-            return self.select0s_[select_id];
-        }
-        let mut begin: usize = self.select0s_[select_id] / 512;
-        let mut end: usize = (self.select0s_[select_id + 1] + 511) / 512;
-        if begin + 10 >= end {
-            while i >= ((begin + 1) * 512) - self.ranks_[begin + 1].abs() {
-                begin += 1;
-            }
-        } else {
-            while begin + 1 < end {
-                let middle: usize = (begin + end) / 2;
-                if i < (middle * 512) - self.ranks_[middle].abs() {
-                    end = middle;
-                } else {
-                    begin = middle;
-                }
-            }
-        }
-        let rank_id: usize = begin;
-        i -= (rank_id * 512) - self.ranks_[rank_id].abs();
-    
-        let rank: &RankIndex = self.ranks_[rank_id];
-        let mut unit_id: usize = rank_id * 16;
-        if i < (256 - rank.rel4()) {
-            if i < (128 - rank.rel2()) {
-                if i >= (64 - rank.rel1()) {
-                  unit_id += 2;
-                  i -= 64 - rank.rel1();
-                }
-            } else if i < (192 - rank.rel3()) {
-                unit_id += 4;
-                i -= 128 - rank.rel2();
-            } else {
-                unit_id += 6;
-                i -= 192 - rank.rel3();
-            }
-        } else if i < (384 - rank.rel6()) {
-            if i < (320 - rank.rel5()) {
-                unit_id += 8;
-                i -= 256 - rank.rel4();
-            } else {
-                unit_id += 10;
-                i -= 320 - rank.rel5();
-            }
-        } else if i < (448 - rank.rel7()) {
-            unit_id += 12;
-            i -= 384 - rank.rel6();
-        } else {
-            unit_id += 14;
-            i -= 448 - rank.rel7();
-        }
-
-//    #ifdef MARISA_USE_SSE2
-//        return select_bit(
-//            i, unit_id * 32, ~units_[unit_id], ~units_[unit_id + 1]);
-//    #else  // MARISA_USE_SSE2
-        let mut unit: u32 = !self.units_[unit_id];
-        let count = PopCount::new(unit);
-        if i >= count.lo32() {
-            unit_id += 1;
-            i -= count.lo32();
-            unit = !self.units_[unit_id];
-            count = PopCount::new(unit);
-        }
-
-        let mut bit_id: usize = unit_id * 32;
-        if i < count.lo16() {
-            if i >= count.lo8() {
-                bit_id += 8;
-                unit >>= 8;
-                i -= count.lo8();
-            }
-        } else if i < count.lo24() {
-            bit_id += 16;
-            unit >>= 16;
-            i -= count.lo16();
-        } else {
-            bit_id += 24;
-            unit >>= 24;
-            i -= count.lo24();
-        }
-        return bit_id + SELECT_TABLE[i][unit & 0xFF];
-//#endif  // MARISA_USE_SSE2
-    }
 
-    #[cfg(target_pointer_width = "64")]
+    /// Position of the `i`th 1. Panics unless `i < num_1s()` and the select1
+    /// table was built.
     pub fn select1(&self, mut i: usize) -> usize {
         assert!(self.is_select1_enabled(),
-                "select1 was called, but select1 is not enabled");
+                "select1 was called, but build_index did not enable it");
         assert!(i < self.num_1s(), "MARISA_BOUND_ERROR");
 
         let select_id: usize = i / 512;
@@ -465,161 +525,19 @@ impl BitVec {
             unit_id += 7;
             i -= rank.rel7() as usize;
         }
-        return self.select_bit(i, unit_id * 64, self.units_[unit_id]);
+        return unit_id * 64
+               + select_bit_in_word(self.word(unit_id), i as u32) as usize;
     }
 
-    #[cfg(target_pointer_width = "32")]
-    pub fn select1(&self, mut i: usize) -> usize {
-        assert!(self.is_select1_enabled(),
-                "select1 was called, but select1 is not enabled");
-        assert!(i < num_1s(), "MARISA_BOUND_ERROR");
-    
-        let select_id: usize = i / 512;
-        assert!((select_id + 1) < self.select1s_.len(), "MARISA_BOUND_ERROR");
-        if (i % 512) == 0 {
-            return self.select1s_[select_id];
-        }
-        let mut begin: usize = self.select1s_[select_id] / 512;
-        let mut end: usize = (self.select1s_[select_id + 1] + 511) / 512;
-        if begin + 10 >= end {
-            while i >= ranks_[begin + 1].abs() {
-                begin += 1;
-            }
-        } else {
-            while (begin + 1 < end) {
-                let middle: usize = (begin + end) / 2;
-                if i < ranks_[middle].abs() {
-                    end = middle;
-                } else {
-                    begin = middle;
-                }
-            }
-        }
-        let rank_id: usize = begin;
-        i -= self.ranks_[rank_id].abs();
-
-        //const RankIndex &rank = ranks_[rank_id];
-        let rank = self.ranks_[rank_id];
-        let unit_id: usize = rank_id * 16;
-        if i < rank.rel4() {
-            if i < rank.rel2() {
-                if i >= rank.rel1() {
-                    unit_id += 2;
-                    i -= rank.rel1();
-                }
-            } else if i < rank.rel3() {
-                unit_id += 4;
-                i -= rank.rel2();
-            } else {
-                unit_id += 6;
-                i -= rank.rel3();
-            }
-        } else if i < rank.rel6() {
-            if i < rank.rel5() {
-                unit_id += 8;
-                i -= rank.rel4();
-            } else {
-                unit_id += 10;
-                i -= rank.rel5();
-            }
-        } else if i < rank.rel7() {
-            unit_id += 12;
-            i -= rank.rel6();
-        } else {
-            unit_id += 14;
-            i -= rank.rel7();
-        }
-
-//#ifdef MARISA_USE_SSE2
-//        return select_bit(i, unit_id * 32, units_[unit_id], units_[unit_id + 1]);
-//#else  // MARISA_USE_SSE2
-        let mut unit: usize = units_[unit_id];
-        let count = PopCount::new(unit);
-        if i >= count.lo32() {
-            unit_id += 1;
-            i -= count.lo32();
-            unit = units_[unit_id];
-            count = PopCount(unit);
-        }
-
-        let bit_id: usize = unit_id * 32;
-        if i < count.lo16() {
-            if i >= count.lo8() {
-                bit_id += 8;
-                unit >>= 8;
-                i -= count.lo8();
-            }
-        } else if i < count.lo24() {
-            bit_id += 16;
-            unit >>= 16;
-            i -= count.lo16();
-        } else {
-            bit_id += 24;
-            unit >>= 24;
-            i -= count.lo24();
-        }
-        return bit_id + SELECT_TABLE[i][unit & 0xFF];
-//#endif // MARISA_USE_SSE2
-    }
 
+    /// The `i`th group of 64 bits, which is two units on 32-bit platforms.
     #[cfg(target_pointer_width = "64")]
-    fn select_bit(&self, mut i: usize, mut bit_id: usize, mut unit: usize)
-                  -> usize {
-        let MASK_55: usize = 0x5555555555555555usize;
-        let MASK_33: usize = 0x3333333333333333usize;
-        let MASK_0F: usize = 0x0F0F0F0F0F0F0F0Fusize;
-        let MASK_01: usize = 0x0101010101010101usize;
-        let MASK_80: usize = 0x8080808080808080usize;
-
-        let mut counts: usize;
-        {
-//#if defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
-//        __m128i lower_nibbles = _mm_cvtsi64_si128(unit & 0x0F0F0F0F0F0F0F0FULL);
-//        __m128i upper_nibbles = _mm_cvtsi64_si128(unit & 0xF0F0F0F0F0F0F0F0ULL);
-//        upper_nibbles = _mm_srli_epi32(upper_nibbles, 4);
-//    
-//        __m128i lower_counts =
-//            _mm_set_epi8(4, 3, 3, 2, 3, 2, 2, 1, 3, 2, 2, 1, 2, 1, 1, 0);
-//        lower_counts = _mm_shuffle_epi8(lower_counts, lower_nibbles);
-//        __m128i upper_counts =
-//            _mm_set_epi8(4, 3, 3, 2, 3, 2, 2, 1, 3, 2, 2, 1, 2, 1, 1, 0);
-//        upper_counts = _mm_shuffle_epi8(upper_counts, upper_nibbles);
-//    
-//        counts = _mm_cvtsi128_si64(_mm_add_epi8(lower_counts, upper_counts));
-//#else  // defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
-            counts = unit - (unit.wrapping_shr(1) & MASK_55);
-            counts = (counts & MASK_33) + (counts.wrapping_shr(2) & MASK_33);
-            counts = (counts + (counts.wrapping_shr(4))) & MASK_0F;
-//#endif  // defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
-            counts = counts.wrapping_mul(MASK_01);
-        }
-
-//#if defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
-//        UInt8 skip;
-//        {
-//            __m128i x = _mm_cvtsi64_si128((i + 1) * MASK_01);
-//            __m128i y = _mm_cvtsi64_si128(counts);
-//            x = _mm_cmpgt_epi8(x, y);
-//            skip = (UInt8)PopCount::count(_mm_cvtsi128_si64(x));
-//        }
-//#else  // defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
-        let x: usize = (counts | MASK_80) - (i + 1).wrapping_mul(MASK_01);
-//#  ifdef _MSC_VER
-//      unsigned long skip;
-//      ::_BitScanForward64(&skip, (x & MASK_80) >> 7);
-//      --skip;
-//#  else  // _MSC_VER
-        // ctz: count trailing zeros (aka tzcnt, bsf)
-        let skip: u32 =  //::__builtin_ctzll((x & MASK_80) >> 7);
-            (x & MASK_80).wrapping_shr(7).ctz();
-//#  endif  // _MSC_VER
-//#endif  // defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
-    
-        bit_id += skip as usize;
-        unit = unit.wrapping_shr(skip);
-        i = i.wrapping_sub(counts.wrapping_shl(8).wrapping_shr(skip) & 0xFF);
-
-        return bit_id + (SELECT_TABLE[i][unit & 0xFF] as usize);
+    fn word(&self, i: usize) -> u64 {
+        self.units_[i] as u64
+    }
+    #[cfg(target_pointer_width = "32")]
+    fn word(&self, i: usize) -> u64 {
+        (self.units_[2 * i] as u64) | ((self.units_[2 * i + 1] as u64) << 32)
     }
 
 /*
@@ -739,227 +657,243 @@ std::size_t select_bit(std::size_t i, std::size_t bit_id,
 
 
 
-/*
-    fn total_size(&self) -> usize {
-        self.units_.total_size()
-        + self.ranks_.total_size()
-        + self.select0s_.total_size()
-        + self.select1s_.total_size();
+    /// What `total_size` would be for `len` bits of which `num_1s` are set,
+    /// pushed one at a time and then, if `index` is given, built with
+    /// `build_index(index.0, index.1)`.
+    pub fn total_size_for(len: usize, num_1s: usize,
+                          index: Option<(bool, bool)>) -> usize {
+        let units = io_words(len) * 8;
+        match index {
+            None => units,
+            Some((enables_select0, enables_select1)) => {
+                let ranks = index_len(len) * mem::size_of::<RankIndex>();
+                let mut size = units + ranks;
+                if enables_select0 {
+                    size += index_len(len - num_1s) * mem::size_of::<u32>();
+                }
+                if enables_select1 {
+                    size += index_len(num_1s) * mem::size_of::<u32>();
+                }
+                size
+            }
+        }
     }
-    fn io_size(&self) -> usize {
-        self.units_.io_size()
-        + (mem::size_of::<u32>() * 2)
-        + self.ranks_.io_size()
-        + self.select0s_.io_size()
-        + self.select1s_.io_size();
+
+    pub fn total_size(&self) -> usize {
+        slice_total_size(&self.units_)
+        + slice_total_size(&self.ranks_)
+        + slice_total_size(&self.select0s_)
+        + slice_total_size(&self.select1s_)
     }
-*/
 
+    /// Whether the bits and index are all borrowed from a mapped buffer.
+    pub fn is_mapped(&self) -> bool {
+        self.units_.is_mapped() && self.ranks_.is_mapped()
+        && self.select0s_.is_mapped() && self.select1s_.is_mapped()
+    }
 
-/*
-  void map_(Mapper &mapper) {
-    units_.map(mapper);
-    {
-      u32 temp_size;
-      mapper.map(&temp_size);
-      size_ = temp_size;
-    }
-    {
-      u32 temp_num_1s;
-      mapper.map(&temp_num_1s);
-      MARISA_THROW_IF(temp_num_1s > size_, MARISA_FORMAT_ERROR);
-      num_1s_ = temp_num_1s;
-    }
-    ranks_.map(mapper);
-    select0s_.map(mapper);
-    select1s_.map(mapper);
-  }
+    /// Writes the vector as libmarisa does: the words, the length and the
+    /// number of 1s as u32s, then the rank index and the select tables.
+    pub fn write_libmarisa<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_libmarisa_vec(w, &self.io_bytes())?;
+        write_u32(w, self.size_ as u32)?;
+        write_u32(w, self.num_1s_ as u32)?;
+        self.write_index(w)
+    }
 
-  void read_(Reader &reader) {
-    units_.read(reader);
-    {
-      u32 temp_size;
-      reader.read(&temp_size);
-      size_ = temp_size;
-    }
-    {
-      u32 temp_num_1s;
-      reader.read(&temp_num_1s);
-      MARISA_THROW_IF(temp_num_1s > size_, MARISA_FORMAT_ERROR);
-      num_1s_ = temp_num_1s;
-    }
-    ranks_.read(reader);
-    select0s_.read(reader);
-    select1s_.read(reader);
-  }
+    /// Reads a vector written by libmarisa. Its rank index and select
+    /// tables are skipped, so `build_index` again as after `read_v2`.
+    pub fn read_libmarisa<R: io::Read>(r: &mut R) -> io::Result<BitVec<'a>> {
+        let bytes = read_libmarisa_vec(r, 4, "bit vector")?;
+        let size = read_u32(r)? as usize;
+        let num_1s = read_u32(r)? as usize;
+        if bytes.len() != io_words(size) * 8 {
+            return Err(invalid_data("bit vector length"));
+        }
+        let bv = BitVec::from_io_words(size, &bytes).map_err(invalid_data)?;
+        if bv.num_1s_ != num_1s {
+            return Err(invalid_data("bit vector number of 1s"));
+        }
+        read_libmarisa_vec(r, 12, "rank index")?;
+        read_libmarisa_vec(r, 4, "select0 table")?;
+        read_libmarisa_vec(r, 4, "select1 table")?;
+        Ok(bv)
+    }
 
-  void write_(Writer &writer) const {
-    units_.write(writer);
-    writer.write((u32)size_);
-    writer.write((u32)num_1s_);
-    ranks_.write(writer);
-    select0s_.write(writer);
-    select1s_.write(writer);
-  }
-*/
+    /// Reads a vector as format version 2 wrote it: the length, then the
+    /// bits in little-endian u64 words, with no index. `build_index` again
+    /// after.
+    pub fn read_v2<R: io::Read>(r: &mut R) -> io::Result<BitVec<'a>> {
+        let size = read_len(r)?;
+        if size > std::u32::MAX as usize {
+            return Err(invalid_data("bit vector length"));
+        }
+        let bytes = read_bytes(r, io_words(size) * 8)?;
+        BitVec::from_io_words(size, &bytes).map_err(invalid_data)
+    }
+
+    fn write_index<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.ranks_.write_to(w)?;
+        self.select0s_.write_to(w)?;
+        self.select1s_.write_to(w)
+    }
+
+    /// The units as little-endian u64 words.
+    fn io_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(io_words(self.size_) * 8);
+        for &unit in self.units_.iter() {
+            for i in 0..(WORD_SIZE / 8) {
+                bytes.push((unit >> (8 * i)) as u8);
+            }
+        }
+        bytes.resize(io_words(self.size_) * 8, 0);
+        bytes
+    }
+
+    /// Units are laid out as `push` leaves them, so the result compares
+    /// equal to the BitVec that was written.
+    fn from_io_words(size: usize, bytes: &[u8])
+      -> Result<BitVec<'a>, &'static str> {
+        let mut bv = BitVec::new();
+        bv.units_ = bytes.chunks(WORD_SIZE / 8)
+                         .map(|chunk| u64_from_le(chunk) as usize)
+                         .collect::<Vec<_>>().into();
+        bv.size_ = size;
+        bv.num_1s_ = bv.units_.iter().map(|x| x.count_ones() as usize).sum();
+        bv.check_lengths()?;
+        Ok(bv)
+    }
 
+    /// Checks, in constant time, that the parts of a vector read or mapped
+    /// fit together: the words hold the bits, with the rest of the last one
+    /// clear, and the index and tables, where there are any, are as long
+    /// as `build_index` makes them. Whether they hold the right counts is
+    /// for `verify` to check.
+    fn check_lengths(&self) -> Result<(), &'static str> {
+        let size = self.size_;
+        if size > std::u32::MAX as usize
+           || self.units_.len() * (WORD_SIZE / 8) != io_words(size) * 8 {
+            return Err("bit vector length");
+        }
+        if self.num_1s_ > size {
+            return Err("bit vector number of 1s");
+        }
+        if size % WORD_SIZE != 0 || size < WORD_SIZE * self.units_.len() {
+            let used = &self.units_[size / WORD_SIZE..];
+            let mask = (1usize << (size % WORD_SIZE)) - 1;
+            if used[0] & !mask != 0 || used[1..].iter().any(|&x| x != 0) {
+                return Err("bit vector padding");
+            }
+        }
+        let tables = [(&self.select0s_, size - self.num_1s_),
+                      (&self.select1s_, self.num_1s_)];
+        if (!self.ranks_.is_empty() && self.ranks_.len() != index_len(size))
+           || tables.iter().any(|&(table, n)| {
+                  !table.is_empty() && (self.ranks_.is_empty()
+                                        || table.len() != index_len(n))
+              }) {
+            return Err("bit vector index");
+        }
+        Ok(())
+    }
 }
 
-const SELECT_TABLE: [[u8; 256]; 8] =
-[ [ 7, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 6, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 7, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 6, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
-  ]
-, [ 7, 7, 7, 1, 7, 2, 2, 1, 7, 3, 3, 1, 3, 2, 2, 1
-  , 7, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 7, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
-  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 7, 6, 6, 1, 6, 2, 2, 1, 6, 3, 3, 1, 3, 2, 2, 1
-  , 6, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 6, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
-  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 7, 7, 7, 1, 7, 2, 2, 1, 7, 3, 3, 1, 3, 2, 2, 1
-  , 7, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 7, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
-  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 7, 6, 6, 1, 6, 2, 2, 1, 6, 3, 3, 1, 3, 2, 2, 1
-  , 6, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  , 6, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
-  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 2, 7, 7, 7, 3, 7, 3, 3, 2
-  , 7, 7, 7, 4, 7, 4, 4, 2, 7, 4, 4, 3, 4, 3, 3, 2
-  , 7, 7, 7, 5, 7, 5, 5, 2, 7, 5, 5, 3, 5, 3, 3, 2
-  , 7, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
-  , 7, 7, 7, 6, 7, 6, 6, 2, 7, 6, 6, 3, 6, 3, 3, 2
-  , 7, 6, 6, 4, 6, 4, 4, 2, 6, 4, 4, 3, 4, 3, 3, 2
-  , 7, 6, 6, 5, 6, 5, 5, 2, 6, 5, 5, 3, 5, 3, 3, 2
-  , 6, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
-  , 7, 7, 7, 7, 7, 7, 7, 2, 7, 7, 7, 3, 7, 3, 3, 2
-  , 7, 7, 7, 4, 7, 4, 4, 2, 7, 4, 4, 3, 4, 3, 3, 2
-  , 7, 7, 7, 5, 7, 5, 5, 2, 7, 5, 5, 3, 5, 3, 3, 2
-  , 7, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
-  , 7, 7, 7, 6, 7, 6, 6, 2, 7, 6, 6, 3, 6, 3, 3, 2
-  , 7, 6, 6, 4, 6, 4, 4, 2, 6, 4, 4, 3, 4, 3, 3, 2
-  , 7, 6, 6, 5, 6, 5, 5, 2, 6, 5, 5, 3, 5, 3, 3, 2
-  , 6, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 3
-  , 7, 7, 7, 7, 7, 7, 7, 4, 7, 7, 7, 4, 7, 4, 4, 3
-  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 3
-  , 7, 7, 7, 5, 7, 5, 5, 4, 7, 5, 5, 4, 5, 4, 4, 3
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 3
-  , 7, 7, 7, 6, 7, 6, 6, 4, 7, 6, 6, 4, 6, 4, 4, 3
-  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 3
-  , 7, 6, 6, 5, 6, 5, 5, 4, 6, 5, 5, 4, 5, 4, 4, 3
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 3
-  , 7, 7, 7, 7, 7, 7, 7, 4, 7, 7, 7, 4, 7, 4, 4, 3
-  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 3
-  , 7, 7, 7, 5, 7, 5, 5, 4, 7, 5, 5, 4, 5, 4, 4, 3
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 3
-  , 7, 7, 7, 6, 7, 6, 6, 4, 7, 6, 6, 4, 6, 4, 4, 3
-  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 3
-  , 7, 6, 6, 5, 6, 5, 5, 4, 6, 5, 5, 4, 5, 4, 4, 3
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 4
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
-  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 4
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 4
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
-  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 4
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 4
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
-  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 4
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 4
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
-  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 4
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
-  ]
-, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
-  ]
-];
+/// The bits in little-endian u64 words, the length and the number of 1s as
+/// u64s, then the rank index and the two select tables, empty if not
+/// built. The words, index and tables are each a vector as `PodVec` lays
+/// one out, so mapping an aligned buffer borrows them all, and a mapped
+/// vector answers queries without `build_index`. `read_from` checks the
+/// index against the bits; `map_at` only checks the lengths.
+impl<'a> SerializableVec<'a> for BitVec<'a> {
+    fn io_size(&self) -> usize {
+        8 + io_words(self.size_) * 8 + 16 + self.ranks_.io_size()
+        + self.select0s_.io_size() + self.select1s_.io_size()
+    }
+
+    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_libmarisa_vec(w, &self.io_bytes())?;
+        write_u64(w, self.size_ as u64)?;
+        write_u64(w, self.num_1s_ as u64)?;
+        self.write_index(w)
+    }
+
+    fn read_from<R: io::Read>(r: &mut R) -> io::Result<BitVec<'a>> {
+        let units = PodVec::read_from(r)?;
+        let size = read_u64(r)?;
+        let num_1s = read_u64(r)?;
+        if size > std::u32::MAX as u64 || num_1s > size {
+            return Err(invalid_data("bit vector length"));
+        }
+        let bv = BitVec {
+            units_: units,
+            size_: size as usize,
+            num_1s_: num_1s as usize,
+            ranks_: PodVec::read_from(r)?,
+            select0s_: PodVec::read_from(r)?,
+            select1s_: PodVec::read_from(r)?,
+        };
+        bv.check_lengths().and_then(|_| bv.verify()).map_err(invalid_data)?;
+        Ok(bv)
+    }
+
+    fn map_at(buf: &'a [u8], pos: &mut usize)
+      -> Result<BitVec<'a>, MarisaError> {
+        let units = PodVec::map_at(buf, pos)?;
+        let size = map_len(buf, pos, "bit vector length")?;
+        let num_1s = map_len(buf, pos, "bit vector number of 1s")?;
+        let bv = BitVec {
+            units_: units,
+            size_: size,
+            num_1s_: num_1s,
+            ranks_: PodVec::map_at(buf, pos)?,
+            select0s_: PodVec::map_at(buf, pos)?,
+            select1s_: PodVec::map_at(buf, pos)?,
+        };
+        bv.check_lengths()
+          .map_err(|what| MarisaError::FormatError { what: what })?;
+        Ok(bv)
+    }
+}
 
 #[cfg(test)]
 mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
     use base::WORD_SIZE;
     use env_logger;
     use quickcheck as qc;
+    use rand;
     use std;
     use super::BitVec;
+    use aligned_bytes::AlignedBytes;
+    use vector::io::SerializableVec;
+
+    fn bit_vec_from_words<'b, T>(x: T, bits: usize) -> BitVec<'static>
+      where T: IntoIterator<Item=&'b usize> {
+        let mut bv = BitVec::new();
+        bv.units_ = x.into_iter().map(|x| *x).collect::<Vec<_>>().into();
+        if (bits + WORD_SIZE - 1) / WORD_SIZE != bv.units_.len() { panic!() }
+        bv.size_ = bits;
+        let part = bv.size_ % WORD_SIZE;
+        if 0 != part {
+            let mask = (1 << part) - 1;
+            let last = bv.units_[bv.size_ / WORD_SIZE];
+            if last & mask != last { panic!() }
+        }
+        for word in bv.units_.iter() {
+            bv.num_1s_ += word.count_ones() as usize;
+        }
+        bv
+    }
 
     fn build_same(old: &BitVec, new: &mut BitVec) {
         if old.is_rank_enabled() {
-            new.build(old.is_select0_enabled(), old.is_select1_enabled());
+            new.build_index(old.is_select0_enabled(), old.is_select1_enabled());
         }
     }
 
-    impl qc::Arbitrary for BitVec {
-        fn arbitrary<G: qc::Gen>(g: &mut G) -> BitVec {
+    impl qc::Arbitrary for BitVec<'static> {
+        fn arbitrary<G: qc::Gen>(g: &mut G) -> BitVec<'static> {
             let mut v = BitVec::new();
             let vec_size = { let s = g.size(); g.gen_range(0, s) };
             for _ in 0..vec_size {
@@ -967,10 +901,10 @@ mod test {
             }
             match g.gen_range(0, 5) {
                 0 => {},
-                1 => { v.build(false, false); }
-                2 => { v.build(true, false); }
-                3 => { v.build(false, true); }
-                4 => { v.build(true, true); }
+                1 => { v.build_index(false, false); }
+                2 => { v.build_index(true, false); }
+                3 => { v.build_index(false, true); }
+                4 => { v.build_index(true, true); }
                 _ => panic!()
             }
             v
@@ -988,8 +922,8 @@ mod test {
                     let mask = (1 << split) - 1;
                     let l = val & mask;
                     let r = val >> split;
-                    let mut lv = BitVec::from_words(std::iter::once(&l), split);
-                    let mut rv = BitVec::from_words(std::iter::once(&r),
+                    let mut lv = bit_vec_from_words(std::iter::once(&l), split);
+                    let mut rv = bit_vec_from_words(std::iter::once(&r),
                                                     self.size_ - split);
                     build_same(self, &mut lv);
                     build_same(self, &mut rv);
@@ -1001,9 +935,9 @@ mod test {
                     let len = self.units_.len();
                     let split = (len + 1) / 2;
                     let iter2 = iter.clone();
-                    let mut lv = BitVec::from_words(
+                    let mut lv = bit_vec_from_words(
                         iter.take(split), split * WORD_SIZE);
-                    let mut rv = BitVec::from_words(
+                    let mut rv = bit_vec_from_words(
                         iter2.skip(split), (c - split) * WORD_SIZE + p);
                     build_same(self, &mut lv);
                     build_same(self, &mut rv);
@@ -1020,7 +954,7 @@ mod test {
                     *x = *x / 2;
                 }
                 if found_non_zero {
-                    let mut new = BitVec::from_words(units.iter(), self.size_);
+                    let mut new = bit_vec_from_words(units.iter(), self.size_);
                     build_same(self, &mut new);
                     v.push(new);
                 }
@@ -1034,12 +968,12 @@ mod test {
                 (true, s0, s1) if s0 || s1 => {
                     if s0 {
                         let mut cpy = self.clone();
-                        cpy.build(false, s1);
+                        cpy.build_index(false, s1);
                         v.push(cpy);
                     }
                     if s1 {
                         let mut cpy = self.clone();
-                        cpy.build(s0, false);
+                        cpy.build_index(s0, false);
                         v.push(cpy);
                     }
                 },
@@ -1060,10 +994,10 @@ mod test {
     fn can_build() {
         let _ = env_logger::init();
         fn prop(mut bv: BitVec) -> bool {
-            bv.build(true, true);
+            bv.build_index(true, true);
             true
         }
-        qc::quickcheck(prop as fn(BitVec) -> bool);
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
     }
 
     fn naive_rank0(bv: &BitVec, i: usize) -> usize {
@@ -1137,7 +1071,7 @@ mod test {
             let n1 = bv.num_1s();
             nn0 == n0 && nn1 == n1
         }
-        qc::quickcheck(prop as fn(BitVec) -> bool);
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
     }
 
     #[test]
@@ -1147,17 +1081,161 @@ mod test {
             let bv2 = bv.clone();
             bv == bv2
         }
-        qc::quickcheck(prop as fn(BitVec) -> bool);
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
     }
 
     #[test]
     fn from_words() {
         let _ = env_logger::init();
         fn prop(bv: BitVec) -> bool {
-            let bv2 = BitVec::from_words(&bv.units_, bv.size_);
+            let bv2 = bit_vec_from_words(bv.units_.iter(), bv.size_);
             bv == bv2
         }
-        qc::quickcheck(prop as fn(BitVec) -> bool);
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Append {
+        Push(bool),
+        Run(bool, usize),
+        Bools(Vec<bool>),
+        Words(Vec<u64>, usize),
+    }
+
+    impl qc::Arbitrary for Append {
+        fn arbitrary<G: qc::Gen>(g: &mut G) -> Append {
+            match g.gen_range(0, 4) {
+                0 => Append::Push(g.gen()),
+                1 => Append::Run(g.gen(), g.gen_range(0, 200)),
+                2 => Append::Bools(qc::Arbitrary::arbitrary(g)),
+                _ => {
+                    let words: Vec<u64> = qc::Arbitrary::arbitrary(g);
+                    let len = g.gen_range(0, words.len() * 64 + 1);
+                    Append::Words(words, len)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bulk_appends_match_push() {
+        let _ = env_logger::init();
+        fn prop(appends: Vec<Append>) -> bool {
+            let mut bulk = BitVec::with_capacity(100);
+            let mut naive = BitVec::new();
+            for a in &appends {
+                match a {
+                    &Append::Push(bit) => {
+                        bulk.push(bit);
+                        naive.push(bit);
+                    }
+                    &Append::Run(bit, count) => {
+                        bulk.push_run(bit, count);
+                        for _ in 0..count {
+                            naive.push(bit);
+                        }
+                    }
+                    &Append::Bools(ref bits) => {
+                        bulk.extend_from_bool_iter(bits.iter().cloned());
+                        for &bit in bits {
+                            naive.push(bit);
+                        }
+                    }
+                    &Append::Words(ref words, len) => {
+                        bulk.extend_from_words(words, len);
+                        for i in 0..len {
+                            naive.push((words[i / 64] >> (i % 64)) & 1 == 1);
+                        }
+                    }
+                }
+            }
+            bulk == naive && bulk.units_.len() == naive.units_.len()
+        }
+        qc::quickcheck(prop as fn(Vec<Append>) -> bool);
+    }
+
+    /// Not run by default: `RUST_LOG=info cargo test --release -- --ignored`
+    /// logs the timings.
+    #[test]
+    #[ignore]
+    fn bulk_appends_bench() {
+        let _ = env_logger::init();
+        let runs: Vec<usize> = (0..1000000u64)
+            .map(|i| ((i * 2654435761) % 61) as usize).collect();
+
+        let start = std::time::Instant::now();
+        let mut naive = BitVec::new();
+        for &run in &runs {
+            for _ in 0..run {
+                naive.push(false);
+            }
+            naive.push(true);
+        }
+        let push_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut bulk = BitVec::new();
+        for &run in &runs {
+            bulk.push_run(false, run);
+            bulk.push(true);
+        }
+        let run_time = start.elapsed();
+
+        assert!(bulk == naive);
+        info!("push: {:?}, push_run: {:?}", push_time, run_time);
+    }
+
+    /// Checks every rank and select against one linear scan.
+    fn index_agrees(bv: &BitVec) -> bool {
+        let mut zeros = Vec::new();
+        let mut ones = Vec::new();
+        for i in 0..bv.len() {
+            if bv.at(i) { ones.push(i); } else { zeros.push(i); }
+        }
+        (0..bv.len() + 1).all(|i| {
+            let r1 = ones.iter().take_while(|&&x| x < i).count();
+            bv.rank1(i) == r1 && bv.rank0(i) == i - r1
+        })
+        && (!bv.is_select0_enabled()
+            || zeros.iter().enumerate().all(|(i, &x)| bv.select0(i) == x))
+        && (!bv.is_select1_enabled()
+            || ones.iter().enumerate().all(|(i, &x)| bv.select1(i) == x))
+    }
+
+    #[test]
+    fn index_many_blocks() {
+        let _ = env_logger::init();
+        let mut x: u64 = 1;
+        for &len in &[0, 1, 63, 64, 65, 511, 512, 513, 1000, 4095, 4096, 4097,
+                      20000] {
+            // Mostly 0s, even, and mostly 1s, so that both select tables
+            // have sparse and dense samples.
+            for &per_mille in &[3, 500, 997] {
+                let mut bv = BitVec::new();
+                for _ in 0..len {
+                    x = x.wrapping_mul(6364136223846793005)
+                         .wrapping_add(1442695040888963407);
+                    bv.push((x >> 33) % 1000 < per_mille);
+                }
+                bv.build_index(true, true);
+                assert!(index_agrees(&bv), "len {} density {}", len,
+                        per_mille);
+            }
+        }
+    }
+
+    #[test]
+    fn index_qc() {
+        let _ = env_logger::init();
+        fn prop(mut bv: BitVec) -> bool {
+            bv.build_index(true, true);
+            index_agrees(&bv)
+        }
+        // Long enough to cover several rank blocks.
+        qc::QuickCheck::new()
+            .gen(qc::StdGen::new(rand::thread_rng(), 3000))
+            .tests(30)
+            .quickcheck(prop as fn(BitVec<'static>) -> bool);
     }
 
     fn rank_prop(bv: BitVec, i: usize) -> qc::TestResult {
@@ -1173,7 +1251,20 @@ mod test {
     #[test]
     fn test_rank_qc() {
         let _ = env_logger::init();
-        qc::quickcheck(rank_prop as fn(BitVec, usize) -> qc::TestResult);
+        qc::quickcheck(rank_prop
+                       as fn(BitVec<'static>, usize) -> qc::TestResult);
+    }
+
+    fn next_1_prop(bv: BitVec, i: usize) -> bool {
+        let i = i % (bv.len() + 2);
+        bv.next_1(i) == (i..bv.len()).find(|&j| bv.at(j))
+    }
+
+    #[test]
+    fn next_1_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(next_1_prop
+                       as fn(BitVec<'static>, usize) -> bool);
     }
 
     #[test]
@@ -1183,20 +1274,20 @@ mod test {
         bv.push(false);
         bv.push(false);
         bv.push(true);
-        bv.build(true, false);
+        bv.build_index(true, false);
         assert_eq!(1, naive_select0(&bv, 0));
         assert_eq!(2, naive_select0(&bv, 1));
 
         let mut bv = BitVec::new();
         bv.push(false);
         bv.push(false);
-        bv.build(true, false);
+        bv.build_index(true, false);
         assert_eq!(0, naive_select0(&bv, 0));
         assert_eq!(1, naive_select0(&bv, 1));
 
         let arr: [usize; 1] = [ 29151 ];
-        let mut bv = BitVec::from_words(arr.iter(), 16);
-        bv.build(true, true);
+        let mut bv = bit_vec_from_words(arr.iter(), 16);
+        bv.build_index(true, true);
         assert_eq!(0, naive_select1(&bv, 0));
         assert_eq!(1, naive_select1(&bv, 1));
         assert_eq!(2, naive_select1(&bv, 2));
@@ -1221,7 +1312,7 @@ mod test {
         bv.push(true);
         bv.push(false);
         bv.push(true);
-        bv.build(false, true);
+        bv.build_index(false, true);
         assert_eq!(1, naive_select1(&bv, 0));
         assert_eq!(3, naive_select1(&bv, 1));
     }
@@ -1239,16 +1330,16 @@ mod test {
     fn test_select0_qc() {
         let _ = env_logger::init();
         qc::quickcheck(test_select0_qc_prop
-                       as fn(BitVec, usize) -> qc::TestResult);
+                       as fn(BitVec<'static>, usize) -> qc::TestResult);
     }
 
     #[test]
     fn test_select0_manual() {
         let _ = env_logger::init();
-        fn build_from_words<'a, T>(x: T, bits: usize) -> BitVec
+        fn build_from_words<'a, T>(x: T, bits: usize) -> BitVec<'static>
           where T: IntoIterator<Item=&'a usize> {
-            let mut bv = BitVec::from_words(x, bits);
-            bv.build(true, true);
+            let mut bv = bit_vec_from_words(x, bits);
+            bv.build_index(true, true);
             bv
         }
         let arr = [2195usize];
@@ -1271,7 +1362,8 @@ mod test {
             let s = bv.select1(i);
             qc::TestResult::from_bool(ns == s)
         }
-        qc::quickcheck(prop as fn(BitVec, usize) -> qc::TestResult);
+        qc::quickcheck(prop
+                       as fn(BitVec<'static>, usize) -> qc::TestResult);
     }
 
     fn test_bit_vector_prop(mut bv: BitVec) -> qc::TestResult {
@@ -1359,7 +1451,148 @@ mod test {
     #[test]
     fn test_bit_vector() {
         let _ = env_logger::init();
-        qc::quickcheck(test_bit_vector_prop as fn(BitVec) -> qc::TestResult);
+        qc::quickcheck(test_bit_vector_prop
+                       as fn(BitVec<'static>) -> qc::TestResult);
+    }
+
+    #[test]
+    fn test_bit_vector_last_word_of_block() {
+        let _ = env_logger::init();
+        // Lengths whose last bit falls in the eighth word of a rank block.
+        for &len in &[449, 500, 511, 961, 1023] {
+            let mut bv = BitVec::new();
+            for i in 0..len {
+                bv.push(i % 3 == 0);
+            }
+            bv.build_index(true, true);
+            assert!(!test_bit_vector_prop(bv).is_failure());
+        }
+    }
+
+    #[test]
+    fn io_round_trip() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec) -> bool {
+            let mut bytes = Vec::new();
+            bv.write_to(&mut bytes).unwrap();
+            let read = BitVec::read_from(&mut &bytes[..]).unwrap();
+            let buf = AlignedBytes::from_slice(&bytes);
+            let mut used = 0;
+            let mapped = BitVec::map_at(&buf, &mut used).unwrap();
+            read == bv && mapped == bv && used == bytes.len()
+            && bytes.len() == bv.io_size() && read.num_1s() == bv.num_1s()
+            && mapped.is_rank_enabled() == bv.is_rank_enabled()
+            && mapped.is_select0_enabled() == bv.is_select0_enabled()
+            && mapped.is_select1_enabled() == bv.is_select1_enabled()
+            && (!bv.is_rank_enabled() || index_agrees(&mapped))
+            && (1..bytes.len()).all(|len| {
+                BitVec::read_from(&mut &bytes[..len]).is_err()
+                && BitVec::map_at(&bytes[..len], &mut 0).is_err()
+            })
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn verify_catches_a_stale_index() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec) -> bool {
+            if bv.verify().is_err() {
+                return false;
+            }
+            let mut miscounted = bv.clone();
+            miscounted.num_1s_ += 1;
+            let mut bad_rank = bv.clone();
+            if let Some(last) = bad_rank.ranks_.last_mut() {
+                let abs = last.abs();
+                last.set_abs(abs + 1);
+            }
+            let mut bad_select = bv.clone();
+            if let Some(first) = bad_select.select1s_.first_mut() {
+                *first += 1;
+            }
+            miscounted.verify() == Err("bit vector number of 1s")
+            && (!bv.is_rank_enabled()
+                || bad_rank.verify() == Err("bit vector index"))
+            && (!bv.is_select1_enabled()
+                || bad_select.verify() == Err("bit vector index"))
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn io_rejects_set_padding() {
+        let _ = env_logger::init();
+        let mut bv = BitVec::new();
+        for _ in 0..3 {
+            bv.push(true);
+        }
+        let mut bytes = Vec::new();
+        bv.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 56);
+        bytes[8] = 0x0F;
+        assert!(BitVec::read_from(&mut &bytes[..]).is_err());
+        assert!(BitVec::map_at(&bytes, &mut 0).is_err());
+    }
+
+    #[test]
+    fn io_rejects_bad_index_lengths() {
+        let _ = env_logger::init();
+        let mut bv = BitVec::new();
+        for i in 0..1000 {
+            bv.push(i % 3 == 0);
+        }
+        bv.build_index(true, true);
+        let mut bytes = Vec::new();
+        bv.write_to(&mut bytes).unwrap();
+        // The words, the length and the number of 1s take 8 + 128 + 16
+        // bytes, and the rank index is 12 bytes for each of 3 entries.
+        assert_eq!(&bytes[152..160], &[36, 0, 0, 0, 0, 0, 0, 0]);
+        let mut short = bytes[..152].to_vec();
+        short.extend(&[24, 0, 0, 0, 0, 0, 0, 0]);
+        short.extend(&bytes[160..160 + 24]);
+        short.extend(&bytes[160 + 40..]);
+        assert!(BitVec::read_from(&mut &short[..]).is_err());
+        assert!(BitVec::map_at(&short, &mut 0).is_err());
+        // Select tables without a rank index.
+        let mut no_ranks = bytes[..152].to_vec();
+        no_ranks.extend(&[0u8; 8]);
+        no_ranks.extend(&bytes[160 + 40..]);
+        assert!(BitVec::read_from(&mut &no_ranks[..]).is_err());
+        assert!(BitVec::map_at(&no_ranks, &mut 0).is_err());
+    }
+
+    #[test]
+    fn reads_version_2() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec) -> bool {
+            // The length, then the words, which are as the current format
+            // writes them after their byte count.
+            let mut bytes = Vec::new();
+            bv.write_to(&mut bytes).unwrap();
+            let num_bytes = 8 * super::io_words(bv.len());
+            let mut v2: Vec<u8> = (0..8).map(|i| (bv.len() >> (8 * i)) as u8)
+                                        .collect();
+            v2.extend(&bytes[8..8 + num_bytes]);
+            let read = BitVec::read_v2(&mut &v2[..]).unwrap();
+            read == bv && !read.is_rank_enabled()
+            && BitVec::read_v2(&mut &v2[..v2.len() - 1]).is_err()
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
     }
-}
 
+    #[test]
+    fn total_size_for_agrees() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec) -> bool {
+            let index = if bv.is_rank_enabled() {
+                Some((bv.is_select0_enabled(), bv.is_select1_enabled()))
+            } else {
+                None
+            };
+            bv.total_size()
+            == BitVec::total_size_for(bv.len(), bv.num_1s(), index)
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
+    }
+}
@@ -1,34 +1,53 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 use std;
+use std::io;
 use base::WORD_SIZE;
-use super::util::vec_resize;
+use error::MarisaError;
+use super::io::SerializableVec;
+use super::pod_vec::PodVec;
+use super::util::{invalid_data, map_len, read_bytes, read_len,
+                  read_libmarisa_vec, read_u32, u64_from_le, vec_resize,
+                  write_libmarisa_vec, write_u32, write_u64};
+
+/// Number of u64 words `len` values of `value_size` bits take up, in
+/// memory and when serialized. Even 0-bit values get a word. Readers check
+/// that `value_size * len` fits in a usize first; rounding up cannot
+/// overflow after that.
+fn io_words(len: usize, value_size: usize) -> usize {
+    if len == 0 {
+        0
+    } else if value_size == 0 {
+        1
+    } else {
+        let bits = value_size * len;
+        bits / 64 + if bits % 64 != 0 { 1 } else { 0 }
+    }
+}
+
+/// Number of units `len` values of `value_size` bits take up, a whole
+/// number of u64 words whatever the word size.
+fn num_units(len: usize, value_size: usize) -> usize {
+    io_words(len, value_size) * (64 / WORD_SIZE)
+}
 
-#[derive(Clone, Debug)]
-pub struct FlatVec {
-    units_: Vec<usize>,
+/// Packed values, owned or borrowed from a mapped buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlatVec<'a> {
+    units_: PodVec<'a, usize>,
     value_size_: usize,
     mask_: u32,
     len_: usize,
 }
 
-impl FlatVec {
-    pub fn new() -> FlatVec {
-        FlatVec { units_: Vec::new(), value_size_: 0, mask_: 0, len_: 0, }
-    }
-
-    // I imagine there's a better way to do this? I guess implementing
-    // FromIterator would be a start
-
-    pub fn from_values<'a, T, Q>(x: T) -> FlatVec
-      where T: Clone + IntoIterator<Item=&'a u32, IntoIter=Q>,
-            Q: ExactSizeIterator<Item=&'a u32> {
-        let mut fv = FlatVec::new();
-        fv.build(x);
-        fv
+impl<'a> FlatVec<'a> {
+    pub fn new() -> FlatVec<'a> {
+        FlatVec { units_: PodVec::new(), value_size_: 0, mask_: 0, len_: 0, }
     }
 
-    pub fn build<'a, T, Q>(&mut self, values: T)
-      where T: Clone + IntoIterator<Item=&'a u32, IntoIter=Q>,
-            Q: ExactSizeIterator<Item=&'a u32> {
+    pub fn build<'b, T, Q>(&mut self, values: T)
+      where T: Clone + IntoIterator<Item=&'b u32, IntoIter=Q>,
+            Q: ExactSizeIterator<Item=&'b u32> {
         self.clear();
 
         let values_len = values.clone().into_iter().len();
@@ -41,15 +60,8 @@ impl FlatVec {
             max_value >>= 1;
         }
 
-        let mut num_units: usize = if values_len == 0 { 0 }
-                                                 else { 64 / WORD_SIZE };
-        if value_size != 0 {
-            num_units = ((value_size as usize * values_len + WORD_SIZE - 1)
-                         / WORD_SIZE) as usize;
-            num_units += num_units % (64 / WORD_SIZE);
-        }
-
-        vec_resize(&mut self.units_, num_units);
+        let num_units = num_units(values_len, value_size);
+        vec_resize(self.units_.to_mut(), num_units);
         if num_units > 0 {
             *self.units_.last_mut().unwrap() = 0;
         }
@@ -65,21 +77,100 @@ impl FlatVec {
         }
     }
 
-/*
-  void map(Mapper &mapper) {
-    FlatVec temp;
-    temp.map_(mapper);
-    swap(temp);
-  }
-  void read(Reader &reader) {
-    FlatVec temp;
-    temp.read_(reader);
-    swap(temp);
-  }
-  void write(Writer &writer) const {
-    write_(writer);
-  }
-*/
+    /// Writes the vector as libmarisa does: the words, the value size and
+    /// the mask as u32s, then the length as a u64.
+    pub fn write_libmarisa<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_libmarisa_vec(w, &self.io_bytes())?;
+        write_u32(w, self.value_size_ as u32)?;
+        write_u32(w, self.mask_)?;
+        write_u64(w, self.len_ as u64)
+    }
+
+    pub fn read_libmarisa<R: io::Read>(r: &mut R)
+      -> io::Result<FlatVec<'a>> {
+        let bytes = read_libmarisa_vec(r, 4, "flat vector")?;
+        let value_size = read_u32(r)? as usize;
+        let mask = read_u32(r)?;
+        let len = read_len(r)?;
+        if value_size > 32 {
+            return Err(invalid_data("flat vector value size"));
+        }
+        if len.checked_mul(value_size).is_none()
+           || bytes.len() != io_words(len, value_size) * 8 {
+            return Err(invalid_data("flat vector length"));
+        }
+        let fv = FlatVec::from_io_words(len, value_size, &bytes)
+                 .map_err(invalid_data)?;
+        if fv.mask_ != mask {
+            return Err(invalid_data("flat vector mask"));
+        }
+        Ok(fv)
+    }
+
+    /// Reads a vector as format version 2 wrote it: the length and the
+    /// value size, then the packed values in little-endian u64 words.
+    pub fn read_v2<R: io::Read>(r: &mut R) -> io::Result<FlatVec<'a>> {
+        let len = read_len(r)?;
+        let value_size = read_len(r)?;
+        if value_size > 32 {
+            return Err(invalid_data("flat vector value size"));
+        }
+        let num_words = len.checked_mul(value_size)
+            .map(|_| io_words(len, value_size))
+            .ok_or(invalid_data("flat vector length"))?;
+        let bytes = read_bytes(r, num_words * 8)?;
+        FlatVec::from_io_words(len, value_size, &bytes).map_err(invalid_data)
+    }
+
+    /// Units are laid out as `build` leaves them, so the result compares
+    /// equal to the FlatVec that was written.
+    fn from_io_words(len: usize, value_size: usize, bytes: &[u8])
+      -> Result<FlatVec<'a>, &'static str> {
+        let units: Vec<usize> = bytes.chunks(WORD_SIZE / 8)
+            .map(|chunk| u64_from_le(chunk) as usize).collect();
+        FlatVec::from_units(len, value_size, units.into())
+    }
+
+    /// Checks, in constant time, that `units` hold `len` values of
+    /// `value_size` bits and nothing after them.
+    fn from_units(len: usize, value_size: usize, units: PodVec<'a, usize>)
+      -> Result<FlatVec<'a>, &'static str> {
+        if value_size > 32 {
+            return Err("flat vector value size");
+        }
+        let used = match len.checked_mul(value_size) {
+            Some(used) => used,
+            None => return Err("flat vector length"),
+        };
+        if units.len() * (WORD_SIZE / 8) != io_words(len, value_size) * 8 {
+            return Err("flat vector length");
+        }
+        let (unit_id, offset) = (used / WORD_SIZE, used % WORD_SIZE);
+        let partial = offset != 0 && units[unit_id] >> offset != 0;
+        let full_units = (used + WORD_SIZE - 1) / WORD_SIZE;
+        if partial || units[full_units..].iter().any(|&x| x != 0) {
+            return Err("flat vector padding");
+        }
+        let mut fv = FlatVec::new();
+        fv.units_ = units;
+        fv.value_size_ = value_size;
+        if value_size != 0 {
+            fv.mask_ = std::u32::MAX.wrapping_shr(32 - value_size as u32);
+        }
+        fv.len_ = len;
+        Ok(fv)
+    }
+
+    /// The units as little-endian u64 words.
+    fn io_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.units_.len() * WORD_SIZE / 8);
+        for &unit in self.units_.iter() {
+            for i in 0..(WORD_SIZE / 8) {
+                bytes.push((unit >> (8 * i)) as u8);
+            }
+        }
+        bytes
+    }
 
     pub fn at(&self, i: usize) -> u32 {
         assert!(i < self.len(), "MARISA_BOUND_ERROR");
@@ -100,94 +191,33 @@ impl FlatVec {
     pub fn value_size(&self) -> usize {
         self.value_size_
     }
-    fn mask(&self) -> u32 {
-        self.mask_
-    }
     pub fn is_empty(&self) -> bool {
         self.units_.len() == 0
     }
     pub fn len(&self) -> usize {
         self.len_
     }
-    fn storage_len(&self) -> usize {
-        self.units_.len()
-    }
 
-    // FIXME: use From/Into
-    fn to_vec(&self) -> Vec<u32> {
-        let mut vals = Vec::new();
-        for i in 0..self.len() { vals.push(self.at(i)); }
-        vals
+    pub fn total_size(&self) -> usize {
+        num_units(self.len_, self.value_size_) * std::mem::size_of::<usize>()
     }
 
-/*
-    fn total_size() -> usize {
-      return units_.total_size();
-    }
-*/
-/*
-    fn io_size() -> usize {
-      units_.io_size() + (sizeof(u32) * 2) + sizeof(u64)
+    /// Whether the values are borrowed from a mapped buffer.
+    pub fn is_mapped(&self) -> bool {
+        self.units_.is_mapped()
     }
-*/
 
+    /// What `total_size` would be for `len` values, the largest of which is
+    /// `max_value`.
+    pub fn total_size_for(len: usize, max_value: u32) -> usize {
+        let value_size = 32 - max_value.leading_zeros() as usize;
+        num_units(len, value_size) * std::mem::size_of::<usize>()
+    }
     fn clear(&mut self) {
         // FIXME: Should keep allocation around instead, no?
         *self = FlatVec::new();
     }
 
-
-/*
-  void map_(Mapper &mapper) {
-    units_.map(mapper);
-    {
-      u32 temp_value_size;
-      mapper.map(&temp_value_size);
-      MARISA_THROW_IF(temp_value_size > 32, MARISA_FORMAT_ERROR);
-      value_size_ = temp_value_size;
-    }
-    {
-      u32 temp_mask;
-      mapper.map(&temp_mask);
-      mask_ = temp_mask;
-    }
-    {
-      u64 temp_size;
-      mapper.map(&temp_size);
-      MARISA_THROW_IF(temp_size > MARISA_SIZE_MAX, MARISA_SIZE_ERROR);
-      len_ = (usize)temp_size;
-    }
-  }
-
-  void read_(Reader &reader) {
-    units_.read(reader);
-    {
-      u32 temp_value_size;
-      reader.read(&temp_value_size);
-      MARISA_THROW_IF(temp_value_size > 32, MARISA_FORMAT_ERROR);
-      value_size_ = temp_value_size;
-    }
-    {
-      u32 temp_mask;
-      reader.read(&temp_mask);
-      mask_ = temp_mask;
-    }
-    {
-      u64 temp_size;
-      reader.read(&temp_size);
-      MARISA_THROW_IF(temp_size > MARISA_SIZE_MAX, MARISA_SIZE_ERROR);
-      len_ = (usize)temp_size;
-    }
-  }
-
-  void write_(Writer &writer) const {
-    units_.write(writer);
-    writer.write((u32)value_size_);
-    writer.write((u32)mask_);
-    writer.write((u64)len_);
-  }
-*/
-
     fn set(&mut self, i: usize, value: u32) {
         assert!(i < self.len(), "MARISA_BOUND_ERROR");
         assert!(value <= self.mask_, "MARISA_RANGE_ERROR");
@@ -213,16 +243,62 @@ impl FlatVec {
     }
 }
 
+/// The packed values in little-endian u64 words, as a vector laid out as
+/// `PodVec` lays one out, then the value size and the length as u64s.
+/// Mapping an aligned buffer borrows the words.
+impl<'a> SerializableVec<'a> for FlatVec<'a> {
+    fn io_size(&self) -> usize {
+        8 + io_words(self.len_, self.value_size_) * 8 + 16
+    }
+
+    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_libmarisa_vec(w, &self.io_bytes())?;
+        write_u64(w, self.value_size_ as u64)?;
+        write_u64(w, self.len_ as u64)
+    }
+
+    fn read_from<R: io::Read>(r: &mut R) -> io::Result<FlatVec<'a>> {
+        let units = PodVec::read_from(r)?;
+        let value_size = read_len(r)?;
+        let len = read_len(r)?;
+        FlatVec::from_units(len, value_size, units).map_err(invalid_data)
+    }
+
+    fn map_at(buf: &'a [u8], pos: &mut usize)
+      -> Result<FlatVec<'a>, MarisaError> {
+        let units = PodVec::map_at(buf, pos)?;
+        let value_size = map_len(buf, pos, "flat vector value size")?;
+        let len = map_len(buf, pos, "flat vector length")?;
+        FlatVec::from_units(len, value_size, units)
+            .map_err(|what| MarisaError::FormatError { what: what })
+    }
+}
+
 #[cfg(test)]
 mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
     use std;
 //    use base::WORD_SIZE;
     use super::FlatVec;
+    use aligned_bytes::AlignedBytes;
+    use vector::io::SerializableVec;
+    use alloc_counter::bytes_allocated;
     use quickcheck as qc;
     use env_logger;
 
-    impl qc::Arbitrary for FlatVec {
-        fn arbitrary<G: qc::Gen>(g: &mut G) -> FlatVec {
+    fn from_values(values: &[u32]) -> FlatVec<'static> {
+        let mut fv = FlatVec::new();
+        fv.build(values);
+        fv
+    }
+
+    fn to_vec(fv: &FlatVec) -> Vec<u32> {
+        (0..fv.len()).map(|i| fv.at(i)).collect()
+    }
+
+    impl qc::Arbitrary for FlatVec<'static> {
+        fn arbitrary<G: qc::Gen>(g: &mut G) -> FlatVec<'static> {
             let values: Vec<u32> = qc::Arbitrary::arbitrary(g);
             let mut v = FlatVec::new();
             v.build(&values);
@@ -231,10 +307,10 @@ mod test {
         fn shrink(&self) -> Box<Iterator<Item=Self>> {
             if self.len() > 1 {
                 let mut v = Vec::new();
-                let vals = self.to_vec();
+                let vals = to_vec(self);
                 let split = self.len() / 2;
-                v.push(FlatVec::from_values(vals.iter().take(split)));
-                v.push(FlatVec::from_values(vals.iter().skip(split)));
+                v.push(from_values(&vals[..split]));
+                v.push(from_values(&vals[split..]));
                 Box::new(v.into_iter())
             } else {
                 qc::empty_shrinker()
@@ -246,7 +322,7 @@ mod test {
     fn test_flat_vec_qc() {
         let _ = env_logger::init();
         fn prop(mut fv: FlatVec) -> bool {
-            let v = fv.to_vec();
+            let v = to_vec(&fv);
             for (idx, &val) in v.iter().enumerate() {
                 if fv.at(idx) != val { return false; }
             }
@@ -256,7 +332,120 @@ mod test {
             }
             true
         }
-        qc::quickcheck(prop as fn(FlatVec) -> bool);
+        qc::quickcheck(prop as fn(FlatVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn total_size_for_agrees() {
+        let _ = env_logger::init();
+        fn prop(values: Vec<u32>) -> bool {
+            let fv = from_values(&values);
+            let max = values.iter().cloned().max().unwrap_or(0);
+            fv.total_size() == FlatVec::total_size_for(values.len(), max)
+        }
+        qc::quickcheck(prop as fn(Vec<u32>) -> bool);
+    }
+
+    fn round_trips(fv: &FlatVec) -> bool {
+        let mut bytes = Vec::new();
+        fv.write_to(&mut bytes).unwrap();
+        let read = FlatVec::read_from(&mut &bytes[..]).unwrap();
+        let buf = AlignedBytes::from_slice(&bytes);
+        let mut used = 0;
+        let mapped = FlatVec::map_at(&buf, &mut used).unwrap();
+        read == *fv && mapped == *fv && used == bytes.len()
+        && bytes.len() == fv.io_size()
+        && (1..bytes.len()).all(|len| {
+            FlatVec::read_from(&mut &bytes[..len]).is_err()
+            && FlatVec::map_at(&bytes[..len], &mut 0).is_err()
+        })
+    }
+
+    #[test]
+    fn io_round_trip() {
+        let _ = env_logger::init();
+        fn prop(fv: FlatVec) -> bool {
+            round_trips(&fv)
+        }
+        qc::quickcheck(prop as fn(FlatVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn every_value_size() {
+        let _ = env_logger::init();
+        let mut x: u64 = 1;
+        for value_size in 1..33 {
+            let max = std::u32::MAX >> (32 - value_size);
+            // Enough values that some straddle every offset into a u64.
+            let mut values: Vec<u32> = (0..200).map(|_| {
+                x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (x >> 32) as u32 & max
+            }).collect();
+            values[0] = max;
+            values.push(max);
+            let fv = from_values(&values);
+            assert_eq!(fv.value_size(), value_size);
+            assert_eq!(to_vec(&fv), values);
+            assert!(round_trips(&fv), "value size {}", value_size);
+        }
+    }
+
+    #[test]
+    fn io_rejects_set_padding() {
+        let _ = env_logger::init();
+        let fv = from_values(&[5, 1, 7]);
+        let mut bytes = Vec::new();
+        fv.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 32);
+        bytes[9] = 0x80;
+        assert!(FlatVec::read_from(&mut &bytes[..]).is_err());
+        assert!(FlatVec::map_at(&bytes, &mut 0).is_err());
+        bytes[9] = 0;
+        bytes[16] = 33;
+        assert!(FlatVec::map_at(&bytes, &mut 0).is_err());
+    }
+
+    #[test]
+    fn reads_version_2() {
+        let _ = env_logger::init();
+        fn prop(fv: FlatVec) -> bool {
+            // The length and value size, then the words, which are as the
+            // current format writes them after their byte count.
+            let mut bytes = Vec::new();
+            fv.write_to(&mut bytes).unwrap();
+            let num_bytes = 8 * super::io_words(fv.len(), fv.value_size());
+            let le = |x: usize| (0..8).map(move |i| (x >> (8 * i)) as u8);
+            let mut v2: Vec<u8> = le(fv.len()).chain(le(fv.value_size()))
+                                              .collect();
+            v2.extend(&bytes[8..8 + num_bytes]);
+            FlatVec::read_v2(&mut &v2[..]).unwrap() == fv
+            && (0..v2.len()).all(|len| {
+                FlatVec::read_v2(&mut &v2[..len]).is_err()
+            })
+        }
+        qc::quickcheck(prop as fn(FlatVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn io_rejects_huge_lengths_without_allocating() {
+        let _ = env_logger::init();
+        let le = |x: u64| (0..8).map(move |i| (x >> (8 * i)) as u8);
+        let max = std::usize::MAX as u64;
+        // Multi-GiB, just short of overflowing the bit count, and past it.
+        for &(len, value_size) in &[(5u64 << 30, 32u64), (max / 32, 32),
+                                    (max / 32 + 1, 32), (max, 1)] {
+            let mut v2: Vec<u8> = le(len).chain(le(value_size)).collect();
+            v2.extend(&[0u8; 64][..]);
+            // No words, then the value size and the length.
+            let bytes: Vec<u8> = le(0).chain(le(value_size)).chain(le(len))
+                                      .collect();
+            let allocated = bytes_allocated(|| {
+                assert!(FlatVec::read_v2(&mut &v2[..]).is_err());
+                assert!(FlatVec::read_from(&mut &bytes[..]).is_err());
+                assert!(FlatVec::map_at(&bytes, &mut 0).is_err());
+            });
+            assert!(allocated < 1 << 20, "{} bytes", allocated);
+        }
     }
 
     // From marisa-trie/tests/vector-test.cc
@@ -267,7 +456,7 @@ mod test {
         let mut vec = FlatVec::new();
 
         assert!(vec.value_size() == 0);
-        assert!(vec.mask() == 0);
+        assert!(vec.mask_ == 0);
         assert!(vec.len() == 0);
         assert!(vec.is_empty());
         //assert!(vec.total_size() == 0);
@@ -278,7 +467,7 @@ mod test {
         vec.build(&values);
 
         assert!(vec.value_size() == 0);
-        assert!(vec.mask() == 0);
+        assert!(vec.mask_ == 0);
         assert!(vec.len() == 0);
         assert!(vec.is_empty());
         //assert!(vec.total_size() == 0);
@@ -288,7 +477,7 @@ mod test {
         vec.build(&values);
 
         assert!(vec.value_size() == 0);
-        assert!(vec.mask() == 0);
+        assert!(vec.mask_ == 0);
         assert!(vec.len() == 1);
         assert!(!vec.is_empty());
         //assert!(vec.total_size() == 8);
@@ -299,7 +488,7 @@ mod test {
         vec.build(&values);
 
         assert!(vec.value_size() == 8);
-        assert!(vec.mask() == 0xFF);
+        assert!(vec.mask_ == 0xFF);
         assert!(vec.len() == 2);
         assert!(vec.at(0) == 0);
         assert!(vec.at(1) == 255);
@@ -308,7 +497,7 @@ mod test {
         vec.build(&values);
 
         assert!(vec.value_size() == 17);
-        assert!(vec.mask() == 0x1FFFF);
+        assert!(vec.mask_ == 0x1FFFF);
         assert!(vec.len() == 3);
         assert!(vec.at(0) == 0);
         assert!(vec.at(1) == 255);
@@ -324,7 +513,7 @@ mod test {
         vec.clear();
     
         assert!(vec.value_size() == 0);
-        assert!(vec.mask() == 0);
+        assert!(vec.mask_ == 0);
         assert!(vec.size() == 0);
     
         {
@@ -333,7 +522,7 @@ mod test {
           vec.map(mapper);
     
           assert!(vec.value_size() == 17);
-          assert!(vec.mask() == 0x1FFFF);
+          assert!(vec.mask_ == 0x1FFFF);
           assert!(vec.size() == 3);
           assert!(vec[0] == 0);
           assert!(vec[1] == 255);
@@ -349,7 +538,7 @@ mod test {
         }
     
         assert!(vec.value_size() == 17);
-        assert!(vec.mask() == 0x1FFFF);
+        assert!(vec.mask_ == 0x1FFFF);
         assert!(vec.size() == 3);
         assert!(vec[0] == 0);
         assert!(vec[1] == 255);
@@ -1,30 +1,268 @@
+//! Bit tricks used by rank and select. Each is backed by the instruction
+//! the compiler picks for the platform, with the portable versions kept in
+//! the tests to check against. `prefetch`, a hint rather than a trick,
+//! lives here too.
 
-// FIXME: For now, these are portable versions of intrinsic functions we need.
-// They should be replaced by the fastest available intrinsics on each supported
-// platform, and the fastest known portable version on other platforms.
-
-/// Count trailing zeros
+/// Count trailing zeros. Returns the bit width for 0.
 pub trait Ctz {
     fn ctz(self) -> u32;
 }
 
-impl Ctz for u8 {
-    fn ctz(self) -> u32 {
-        (self as u32).ctz()
+macro_rules! impl_ctz {
+    ($($t:ty),*) => { $(
+        impl Ctz for $t {
+            #[inline]
+            fn ctz(self) -> u32 {
+                self.trailing_zeros()
+            }
+        }
+    )* }
+}
+
+impl_ctz!(u8, u16, u32, u64, usize);
+
+/// Count set bits.
+pub trait Popcnt {
+    fn popcnt(self) -> u32;
+}
+
+macro_rules! impl_popcnt {
+    ($($t:ty),*) => { $(
+        impl Popcnt for $t {
+            #[inline]
+            fn popcnt(self) -> u32 {
+                self.count_ones()
+            }
+        }
+    )* }
+}
+
+impl_popcnt!(u8, u16, u32, u64, usize);
+
+/// Hints that `x` will be read soon, so that fetching it into the cache can
+/// overlap other work. A no-op where there is no prefetch instruction.
+#[inline]
+pub fn prefetch<T>(x: &T) {
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse"))]
+    unsafe {
+        use std::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_T0>(x as *const T as *const i8);
     }
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "sse")))]
+    let _ = x;
 }
 
-impl Ctz for u16 {
-    fn ctz(self) -> u32 {
-        (self as u32).ctz()
+const MASK_55: u64 = 0x5555555555555555;
+const MASK_33: u64 = 0x3333333333333333;
+const MASK_0F: u64 = 0x0F0F0F0F0F0F0F0F;
+const MASK_01: u64 = 0x0101010101010101;
+const MASK_80: u64 = 0x8080808080808080;
+
+/// Position of the `k`th set bit of `word`, counting from 0 at the least
+/// significant end. `k` must be less than `word.popcnt()`.
+pub fn select_bit_in_word(word: u64, k: u32) -> u32 {
+    debug_assert!(k < word.popcnt(), "MARISA_BOUND_ERROR");
+    let i = k as u64;
+    let mut counts: u64;
+    {
+//#if defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
+//        __m128i lower_nibbles = _mm_cvtsi64_si128(unit & 0x0F0F0F0F0F0F0F0FULL);
+//        __m128i upper_nibbles = _mm_cvtsi64_si128(unit & 0xF0F0F0F0F0F0F0F0ULL);
+//        upper_nibbles = _mm_srli_epi32(upper_nibbles, 4);
+//    
+//        __m128i lower_counts =
+//            _mm_set_epi8(4, 3, 3, 2, 3, 2, 2, 1, 3, 2, 2, 1, 2, 1, 1, 0);
+//        lower_counts = _mm_shuffle_epi8(lower_counts, lower_nibbles);
+//        __m128i upper_counts =
+//            _mm_set_epi8(4, 3, 3, 2, 3, 2, 2, 1, 3, 2, 2, 1, 2, 1, 1, 0);
+//        upper_counts = _mm_shuffle_epi8(upper_counts, upper_nibbles);
+//    
+//        counts = _mm_cvtsi128_si64(_mm_add_epi8(lower_counts, upper_counts));
+//#else  // defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
+        counts = word - (word.wrapping_shr(1) & MASK_55);
+        counts = (counts & MASK_33) + (counts.wrapping_shr(2) & MASK_33);
+        counts = (counts + (counts.wrapping_shr(4))) & MASK_0F;
+//#endif  // defined(MARISA_X64) && defined(MARISA_USE_SSSE3)
+        counts = counts.wrapping_mul(MASK_01);
     }
+
+//#if defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
+//        UInt8 skip;
+//        {
+//            __m128i x = _mm_cvtsi64_si128((i + 1) * MASK_01);
+//            __m128i y = _mm_cvtsi64_si128(counts);
+//            x = _mm_cmpgt_epi8(x, y);
+//            skip = (UInt8)PopCount::count(_mm_cvtsi128_si64(x));
+//        }
+//#else  // defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
+    let x: u64 = (counts | MASK_80) - (i + 1).wrapping_mul(MASK_01);
+//#  ifdef _MSC_VER
+//      unsigned long skip;
+//      ::_BitScanForward64(&skip, (x & MASK_80) >> 7);
+//      --skip;
+//#  else  // _MSC_VER
+    // ctz: count trailing zeros (aka tzcnt, bsf)
+    let skip: u32 =  //::__builtin_ctzll((x & MASK_80) >> 7);
+        (x & MASK_80).wrapping_shr(7).ctz();
+//#  endif  // _MSC_VER
+//#endif  // defined(MARISA_X64) && defined(MARISA_USE_POPCNT)
+
+    let unit = word.wrapping_shr(skip);
+    let i = i.wrapping_sub(counts.wrapping_shl(8).wrapping_shr(skip) & 0xFF);
+
+    skip + SELECT_TABLE[i as usize][(unit & 0xFF) as usize] as u32
 }
 
-impl Ctz for u32 {
-    fn ctz(self) -> u32 {
-        let v = self;
+const SELECT_TABLE: [[u8; 256]; 8] =
+[ [ 7, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 6, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 7, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 6, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 5, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  , 4, 0, 1, 0, 2, 0, 1, 0, 3, 0, 1, 0, 2, 0, 1, 0
+  ]
+, [ 7, 7, 7, 1, 7, 2, 2, 1, 7, 3, 3, 1, 3, 2, 2, 1
+  , 7, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 7, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
+  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 7, 6, 6, 1, 6, 2, 2, 1, 6, 3, 3, 1, 3, 2, 2, 1
+  , 6, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 6, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
+  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 7, 7, 7, 1, 7, 2, 2, 1, 7, 3, 3, 1, 3, 2, 2, 1
+  , 7, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 7, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
+  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 7, 6, 6, 1, 6, 2, 2, 1, 6, 3, 3, 1, 3, 2, 2, 1
+  , 6, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  , 6, 5, 5, 1, 5, 2, 2, 1, 5, 3, 3, 1, 3, 2, 2, 1
+  , 5, 4, 4, 1, 4, 2, 2, 1, 4, 3, 3, 1, 3, 2, 2, 1
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 2, 7, 7, 7, 3, 7, 3, 3, 2
+  , 7, 7, 7, 4, 7, 4, 4, 2, 7, 4, 4, 3, 4, 3, 3, 2
+  , 7, 7, 7, 5, 7, 5, 5, 2, 7, 5, 5, 3, 5, 3, 3, 2
+  , 7, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
+  , 7, 7, 7, 6, 7, 6, 6, 2, 7, 6, 6, 3, 6, 3, 3, 2
+  , 7, 6, 6, 4, 6, 4, 4, 2, 6, 4, 4, 3, 4, 3, 3, 2
+  , 7, 6, 6, 5, 6, 5, 5, 2, 6, 5, 5, 3, 5, 3, 3, 2
+  , 6, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
+  , 7, 7, 7, 7, 7, 7, 7, 2, 7, 7, 7, 3, 7, 3, 3, 2
+  , 7, 7, 7, 4, 7, 4, 4, 2, 7, 4, 4, 3, 4, 3, 3, 2
+  , 7, 7, 7, 5, 7, 5, 5, 2, 7, 5, 5, 3, 5, 3, 3, 2
+  , 7, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
+  , 7, 7, 7, 6, 7, 6, 6, 2, 7, 6, 6, 3, 6, 3, 3, 2
+  , 7, 6, 6, 4, 6, 4, 4, 2, 6, 4, 4, 3, 4, 3, 3, 2
+  , 7, 6, 6, 5, 6, 5, 5, 2, 6, 5, 5, 3, 5, 3, 3, 2
+  , 6, 5, 5, 4, 5, 4, 4, 2, 5, 4, 4, 3, 4, 3, 3, 2
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 3
+  , 7, 7, 7, 7, 7, 7, 7, 4, 7, 7, 7, 4, 7, 4, 4, 3
+  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 3
+  , 7, 7, 7, 5, 7, 5, 5, 4, 7, 5, 5, 4, 5, 4, 4, 3
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 3
+  , 7, 7, 7, 6, 7, 6, 6, 4, 7, 6, 6, 4, 6, 4, 4, 3
+  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 3
+  , 7, 6, 6, 5, 6, 5, 5, 4, 6, 5, 5, 4, 5, 4, 4, 3
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 3
+  , 7, 7, 7, 7, 7, 7, 7, 4, 7, 7, 7, 4, 7, 4, 4, 3
+  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 3
+  , 7, 7, 7, 5, 7, 5, 5, 4, 7, 5, 5, 4, 5, 4, 4, 3
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 3
+  , 7, 7, 7, 6, 7, 6, 6, 4, 7, 6, 6, 4, 6, 4, 4, 3
+  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 3
+  , 7, 6, 6, 5, 6, 5, 5, 4, 6, 5, 5, 4, 5, 4, 4, 3
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 4
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
+  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 4
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 4
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
+  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 4
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 4
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
+  , 7, 7, 7, 7, 7, 7, 7, 5, 7, 7, 7, 5, 7, 5, 5, 4
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 4
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
+  , 7, 7, 7, 6, 7, 6, 6, 5, 7, 6, 6, 5, 6, 5, 5, 4
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 5
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 6, 7, 7, 7, 6, 7, 6, 6, 5
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 6
+  ]
+, [ 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  , 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7
+  ]
+];
+
+#[cfg(test)]
+mod test {
+    use env_logger;
+    use quickcheck as qc;
+    use super::{Ctz, Popcnt, select_bit_in_word};
+
+    // The portable fallbacks this module used to be made of.
+
+    fn portable_ctz32(v: u32) -> u32 {
         let mut c: u32 = 32;
-        let v = v & ((-(v as i32)) as u32);
+        let v = v & v.wrapping_neg();
         if 0 != v { c -= 1; }
         if 0 != (v & 0x0000FFFF) { c -= 16; }
         if 0 != (v & 0x00FF00FF) { c -= 8; }
@@ -33,13 +271,10 @@ impl Ctz for u32 {
         if 0 != (v & 0x55555555) { c -= 1; }
         c
     }
-}
 
-impl Ctz for u64 {
-    fn ctz(self) -> u32 {
-        let v = self;
+    fn portable_ctz64(v: u64) -> u32 {
         let mut c: u32 = 64;
-        let v = v & ((-(v as i64)) as u64);
+        let v = v & v.wrapping_neg();
         if 0 != v { c -= 1; }
         if 0 != (v & 0x00000000FFFFFFFF) { c -= 32; }
         if 0 != (v & 0x0000FFFF0000FFFF) { c -= 16; }
@@ -49,44 +284,61 @@ impl Ctz for u64 {
         if 0 != (v & 0x5555555555555555) { c -= 1; }
         c
     }
-}
 
-#[cfg(target_pointer_width = "32")]
-impl Ctz for usize {
-    fn ctz(self) -> u32 {
-        (self as u32).ctz()
+    fn portable_popcnt64(mut x: u64) -> u32 {
+        x = (x & 0x5555555555555555) + ((x >> 1) & 0x5555555555555555);
+        x = (x & 0x3333333333333333) + ((x >> 2) & 0x3333333333333333);
+        x = (x & 0x0F0F0F0F0F0F0F0F) + ((x >> 4) & 0x0F0F0F0F0F0F0F0F);
+        (x.wrapping_mul(0x0101010101010101) >> 56) as u32
     }
-}
 
-#[cfg(target_pointer_width = "64")]
-impl Ctz for usize {
-    fn ctz(self) -> u32 {
-        (self as u64).ctz()
+    /// Clears the lowest set bit `k` times.
+    fn portable_select(mut word: u64, k: u32) -> u32 {
+        for _ in 0..k {
+            word &= word - 1;
+        }
+        portable_ctz64(word)
     }
-}
 
+    fn agree(x: u64) -> bool {
+        let (b, h, w) = (x as u8, x as u16, x as u32);
+        let narrow_ok = |v: u32, ctz: u32, bits: u32| {
+            ctz == if v == 0 { bits } else { portable_ctz32(v) }
+        };
+        x.ctz() == portable_ctz64(x)
+        && (x as usize).ctz() == portable_ctz64(x as usize as u64)
+        && w.ctz() == portable_ctz32(w)
+        && narrow_ok(h as u32, h.ctz(), 16)
+        && narrow_ok(b as u32, b.ctz(), 8)
+        && x.popcnt() == portable_popcnt64(x)
+        && (x as usize).popcnt() == portable_popcnt64(x as usize as u64)
+        && w.popcnt() == portable_popcnt64(w as u64)
+        && h.popcnt() == portable_popcnt64(h as u64)
+        && b.popcnt() == portable_popcnt64(b as u64)
+        && (0..x.popcnt()).all(|k| {
+            select_bit_in_word(x, k) == portable_select(x, k)
+        })
+    }
 
-#[cfg(test)]
-mod test {
-    use std;
-    use quickcheck as qc;
-    use env_logger;
-    use super::Ctz;
+    #[test]
+    fn intrinsics_agree_qc() {
+        let _ = env_logger::init();
+        qc::quickcheck(agree as fn(u64) -> bool);
+    }
 
     #[test]
-    fn test_ctz_usize() {
+    fn intrinsics_agree_manual() {
         let _ = env_logger::init();
-        fn prop(i: usize) -> bool {
-            let z = i.ctz() as usize;
-            let is_full = z == std::mem::size_of::<usize>() * 8;
-            let lo_mask = if is_full { !0 } else { ((1 << z) - 1) };
-            let hi_mask = !lo_mask;
-            let next_bit_ok = if is_full { true } else { ((1 << z) & i) != 0 };
-            next_bit_ok
-            && (lo_mask & i == 0)
-            && (hi_mask == 0 || (hi_mask & i != 0))
+        assert!(agree(0));
+        assert!(agree(!0));
+        assert_eq!(0u64.ctz(), 64);
+        assert_eq!(0u8.ctz(), 8);
+        assert_eq!((!0u64).popcnt(), 64);
+        for i in 0..64 {
+            assert!(agree(1 << i));
+            assert!(agree(!(1 << i)));
+            assert!(agree(!0 << i));
+            assert_eq!(select_bit_in_word(!0, i), i);
         }
-        qc::quickcheck(prop as fn(usize) -> bool);
     }
 }
-
@@ -0,0 +1,158 @@
+//! How vectors are laid out in the native format, in the spirit of the
+//! `map_`, `read_` and `write_` members of marisa's vectors. A vector of
+//! values is a `PodVec`: a little-endian u64 size in bytes, the values,
+//! and zeros up to a multiple of 8 bytes. Parts built from vectors, like
+//! bit vectors with their index or the tail, are their vectors and u64
+//! scalars one after another, so every part keeps 8-byte alignment.
+//!
+//! Mapping an aligned buffer borrows every vector in it; a vector that is
+//! misaligned for its values does not map.
+
+use std::io;
+use error::MarisaError;
+
+pub trait SerializableVec<'a>: Sized {
+    /// The number of bytes `write_to` produces.
+    fn io_size(&self) -> usize;
+
+    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()>;
+
+    fn read_from<R: io::Read>(r: &mut R) -> io::Result<Self>;
+
+    /// Reads what `write_to` wrote from `buf` at `pos`, and moves `pos` past
+    /// it. Whatever can be is borrowed from `buf` rather than copied.
+    fn map_at(buf: &'a [u8], pos: &mut usize) -> Result<Self, MarisaError>;
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use std::borrow::Cow;
+    use env_logger;
+    use quickcheck as qc;
+    use aligned_bytes::AlignedBytes;
+    use vector::bit_vec::BitVec;
+    use vector::flat_vec::FlatVec;
+    use vector::pod_vec::PodVec;
+    use super::SerializableVec;
+
+    /// Bytes in front of the vector, so that mapping starts mid-buffer.
+    const JUNK: usize = 8;
+
+    fn written<'a, T: SerializableVec<'a>>(v: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        v.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// `buf` is `JUNK` bytes and then `v` as written. What is read and what
+    /// is mapped must each write the same bytes again, and truncated input
+    /// must fail both ways.
+    fn same_bytes<'a, T: SerializableVec<'a>>(v: &T, buf: &'a [u8]) -> bool {
+        let bytes = &buf[JUNK..];
+        let read = T::read_from(&mut &bytes[..]).unwrap();
+        let mut pos = JUNK;
+        let mapped = T::map_at(buf, &mut pos).unwrap();
+        v.io_size() == bytes.len() && pos == buf.len()
+        && written(&read) == bytes && written(&mapped) == bytes
+        && (JUNK..buf.len()).all(|len| {
+            let mut pos = JUNK;
+            T::read_from(&mut &buf[JUNK..len]).is_err()
+            && T::map_at(&buf[..len], &mut pos).is_err()
+        })
+    }
+
+    fn with_junk(bytes: Vec<u8>) -> AlignedBytes {
+        let mut buf = vec![0xA5; JUNK];
+        buf.extend(bytes);
+        AlignedBytes::from_slice(&buf)
+    }
+
+    #[test]
+    fn bit_vec_same_bytes() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec<'static>) -> bool {
+            same_bytes(&bv, &with_junk(written(&bv)))
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn flat_vec_same_bytes() {
+        let _ = env_logger::init();
+        fn prop(fv: FlatVec<'static>) -> bool {
+            same_bytes(&fv, &with_junk(written(&fv)))
+        }
+        qc::quickcheck(prop as fn(FlatVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn byte_vec_same_bytes() {
+        let _ = env_logger::init();
+        fn prop(bytes: Vec<u8>) -> bool {
+            let v = PodVec::from(bytes);
+            let buf = with_junk(written(&v));
+            if !same_bytes(&v, &buf) {
+                return false;
+            }
+            // Bytes need no alignment, so mapping borrows.
+            let mut pos = JUNK;
+            let mapped = PodVec::<u8>::map_at(&buf, &mut pos).unwrap();
+            mapped.is_mapped()
+            && &mapped[..] == &buf[JUNK + 8..JUNK + 8 + v.len()]
+        }
+        qc::quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn u32_vec_same_bytes() {
+        let _ = env_logger::init();
+        fn prop(xs: Vec<u32>) -> bool {
+            let v = PodVec::from(xs);
+            let buf = with_junk(written(&v));
+            same_bytes(&v, &buf)
+            && PodVec::<u32>::read_from(&mut &buf[JUNK..]).unwrap() == v
+        }
+        qc::quickcheck(prop as fn(Vec<u32>) -> bool);
+    }
+
+    #[test]
+    fn aligned_vectors_are_borrowed() {
+        let _ = env_logger::init();
+        fn prop(bv: BitVec<'static>, fv: FlatVec<'static>) -> bool {
+            let mut bytes = written(&bv);
+            bytes.extend(written(&fv));
+            let buf = AlignedBytes::from_slice(&bytes);
+            let mut pos = 0;
+            let mapped_bv = BitVec::map_at(&buf, &mut pos).unwrap();
+            let mapped_fv = FlatVec::map_at(&buf, &mut pos).unwrap();
+            let little = cfg!(target_endian = "little");
+            pos == buf.len() && mapped_bv == bv && mapped_fv == fv
+            && mapped_bv.is_mapped() == little
+            && mapped_fv.is_mapped() == little
+        }
+        qc::quickcheck(prop as fn(BitVec<'static>, FlatVec<'static>) -> bool);
+    }
+
+    #[test]
+    fn layout() {
+        let v = PodVec::<u32>::from(vec![1, 0x01020304]);
+        assert_eq!(written(&v), [8, 0, 0, 0, 0, 0, 0, 0,
+                                 1, 0, 0, 0, 4, 3, 2, 1]);
+        let v = PodVec(Cow::Borrowed(&b"ab"[..]));
+        assert_eq!(written(&v), [2, 0, 0, 0, 0, 0, 0, 0,
+                                 b'a', b'b', 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn huge_lengths_fail() {
+        let _ = env_logger::init();
+        let mut bytes = vec![0xF8; 8];
+        bytes.extend(b"abcdefgh");
+        assert!(PodVec::<u32>::read_from(&mut &bytes[..]).is_err());
+        assert!(PodVec::<u32>::map_at(&bytes, &mut 0).is_err());
+        assert!(PodVec::<u8>::read_from(&mut &bytes[..]).is_err());
+        assert!(PodVec::<u8>::map_at(&bytes, &mut 0).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 pub mod bit_vec;
 pub mod flat_vec;
+pub mod intrinsic;
+pub mod io;
 pub mod packed_vec;
+pub mod pod_vec;
 
-mod intrinsic;
 mod pop_count;
 mod rank_index;
-mod util;
+pub mod util;
 
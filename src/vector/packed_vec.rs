@@ -1,3 +1,5 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
 use base::WORD_SIZE;
 use std;
 use std::default::Default;
@@ -0,0 +1,278 @@
+//! Vectors that are either owned or borrowed straight from a mapped buffer,
+//! like marisa's Vector, whose `map_` points into the mapping and whose
+//! `read_` copies.
+//!
+//! A vector is written as its size in bytes, a little-endian u64, then its
+//! values, then zeros up to a multiple of 8 bytes, as libmarisa writes one.
+//! Every part of a dictionary is a multiple of 8 bytes, so in a buffer
+//! that starts 8-byte aligned every vector does too. Mapping borrows the
+//! values where the platform is little-endian and copies them where not;
+//! values that are not aligned for their type are an error.
+
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std;
+use std::borrow::Cow;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use error::MarisaError;
+use super::io::SerializableVec;
+use super::util::{invalid_data, map_bytes, map_len, read_bytes, read_len,
+                  u64_from_le, write_u64};
+
+/// Types whose values are laid out in memory as they are serialized, on a
+/// little-endian platform, and for which any bytes are a valid value, so a
+/// mapped buffer can be borrowed as a slice of them.
+///
+/// Unsafe to implement: `size_of::<Self>()` must be the serialized size,
+/// with no padding, and `write_le` must write the bytes of the value as it
+/// is in memory on a little-endian platform.
+pub unsafe trait Pod: Copy + std::fmt::Debug {
+    /// Decodes a value from its `size_of::<Self>()` serialized bytes.
+    fn from_le(bytes: &[u8]) -> Self;
+
+    fn write_le<W: io::Write>(&self, w: &mut W) -> io::Result<()>;
+}
+
+macro_rules! pod_int {
+    ($($t:ty),*) => {$(
+        unsafe impl Pod for $t {
+            fn from_le(bytes: &[u8]) -> $t {
+                u64_from_le(bytes) as $t
+            }
+
+            fn write_le<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+                let mut bytes = [0u8; 8];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = ((*self as u64) >> (8 * i)) as u8;
+                }
+                w.write_all(&bytes[..mem::size_of::<$t>()])
+            }
+        }
+    )*}
+}
+
+pod_int!(u8, u32, u64, usize);
+
+unsafe impl Pod for f32 {
+    fn from_le(bytes: &[u8]) -> f32 {
+        f32::from_bits(<u32 as Pod>::from_le(bytes))
+    }
+
+    fn write_le<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        Pod::write_le(&self.to_bits(), w)
+    }
+}
+
+/// Borrows `bytes` as values of `T` if they can be, or decodes a copy.
+/// `bytes.len()` is a multiple of `size_of::<T>()`, and `bytes` must be
+/// aligned for `T` either way, as they are in a buffer the format allows.
+pub fn cast_or_copy<'a, T: Pod>(bytes: &'a [u8])
+  -> Result<Cow<'a, [T]>, MarisaError> {
+    let size = mem::size_of::<T>();
+    debug_assert!(bytes.len() % size == 0);
+    if bytes.as_ptr() as usize % mem::align_of::<T>() != 0 {
+        return Err(MarisaError::FormatError { what: "alignment" });
+    }
+    if cfg!(target_endian = "little") {
+        // The bytes are aligned for T, lie inside one allocation for as
+        // long as 'a, and hold len / size values whose layout, by `Pod`,
+        // is the serialized one.
+        Ok(Cow::Borrowed(unsafe {
+            std::slice::from_raw_parts(bytes.as_ptr() as *const T,
+                                       bytes.len() / size)
+        }))
+    } else {
+        Ok(Cow::Owned(bytes.chunks(size).map(T::from_le).collect()))
+    }
+}
+
+/// The zeros after `len` bytes of values.
+fn padding_len(len: usize) -> usize {
+    (8 - len % 8) % 8
+}
+
+#[derive(Clone, Debug)]
+pub struct PodVec<'a, T: 'a + Pod>(pub Cow<'a, [T]>);
+
+impl<'a, T: Pod> PodVec<'a, T> {
+    pub fn new() -> PodVec<'a, T> {
+        PodVec(Cow::Owned(Vec::new()))
+    }
+
+    /// The values, copied first if they were borrowed.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        self.0.to_mut()
+    }
+
+    /// Whether the values are borrowed from a mapped buffer.
+    pub fn is_mapped(&self) -> bool {
+        match self.0 {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        }
+    }
+
+    pub fn push(&mut self, x: T) {
+        self.to_mut().push(x)
+    }
+
+    /// Does nothing to a borrowed vector, which has no spare capacity.
+    pub fn shrink_to_fit(&mut self) {
+        if let Cow::Owned(ref mut v) = self.0 {
+            v.shrink_to_fit();
+        }
+    }
+
+    fn byte_len(&self) -> usize {
+        self.0.len() * mem::size_of::<T>()
+    }
+}
+
+impl<'a, T: Pod> Default for PodVec<'a, T> {
+    fn default() -> PodVec<'a, T> {
+        PodVec::new()
+    }
+}
+
+impl<'a, T: Pod> From<Vec<T>> for PodVec<'a, T> {
+    fn from(v: Vec<T>) -> PodVec<'a, T> {
+        PodVec(Cow::Owned(v))
+    }
+}
+
+impl<'a, T: Pod> Deref for PodVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+/// Copies a borrowed vector on the first write, as `Cow` does.
+impl<'a, T: Pod> DerefMut for PodVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.to_mut()
+    }
+}
+
+impl<'a, 'b, T: Pod + PartialEq> PartialEq<PodVec<'b, T>> for PodVec<'a, T> {
+    fn eq(&self, other: &PodVec<'b, T>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<'a, T: Pod + Eq> Eq for PodVec<'a, T> {}
+
+impl<'a, T: Pod> SerializableVec<'a> for PodVec<'a, T> {
+    fn io_size(&self) -> usize {
+        8 + self.byte_len() + padding_len(self.byte_len())
+    }
+
+    fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u64(w, self.byte_len() as u64)?;
+        for x in self.iter() {
+            x.write_le(w)?;
+        }
+        w.write_all(&[0u8; 8][..padding_len(self.byte_len())])
+    }
+
+    fn read_from<R: io::Read>(r: &mut R) -> io::Result<PodVec<'a, T>> {
+        let len = read_len(r)?;
+        if len % mem::size_of::<T>() != 0 {
+            return Err(invalid_data("vector length"));
+        }
+        let bytes = read_bytes(r, len)?;
+        if read_bytes(r, padding_len(len))?.iter().any(|&b| b != 0) {
+            return Err(invalid_data("vector padding"));
+        }
+        Ok(PodVec(Cow::Owned(bytes.chunks(mem::size_of::<T>())
+                                  .map(T::from_le).collect())))
+    }
+
+    fn map_at(buf: &'a [u8], pos: &mut usize)
+      -> Result<PodVec<'a, T>, MarisaError> {
+        let len = map_len(buf, pos, "vector length")?;
+        if len % mem::size_of::<T>() != 0 {
+            return Err(MarisaError::FormatError { what: "vector length" });
+        }
+        let bytes = map_bytes(buf, pos, len, "vector")?;
+        let padding = map_bytes(buf, pos, padding_len(len), "vector")?;
+        if padding.iter().any(|&b| b != 0) {
+            return Err(MarisaError::FormatError { what: "vector padding" });
+        }
+        Ok(PodVec(cast_or_copy(bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use env_logger;
+    use quickcheck as qc;
+    use aligned_bytes::AlignedBytes;
+    use error::MarisaError;
+    use vector::io::SerializableVec;
+    use super::PodVec;
+
+    #[test]
+    fn maps_aligned_and_rejects_misaligned() {
+        let _ = env_logger::init();
+        fn prop(xs: Vec<u64>, shift: usize) -> bool {
+            let v: PodVec<u64> = PodVec::from(xs.clone());
+            let mut bytes = Vec::new();
+            v.write_to(&mut bytes).unwrap();
+            let shift = shift % 8;
+            let mut buf = AlignedBytes::zeroed(shift + bytes.len());
+            buf[shift..].copy_from_slice(&bytes);
+            let mut pos = shift;
+            let mapped = PodVec::<u64>::map_at(&buf, &mut pos);
+            if shift != 0 {
+                return mapped.unwrap_err()
+                       == MarisaError::FormatError { what: "alignment" };
+            }
+            let mapped = mapped.unwrap();
+            mapped[..] == xs[..] && pos == buf.len()
+            && mapped.is_mapped() == cfg!(target_endian = "little")
+        }
+        qc::quickcheck(prop as fn(Vec<u64>, usize) -> bool);
+    }
+
+    #[test]
+    fn writes_copy_borrowed_values() {
+        let _ = env_logger::init();
+        let mut bytes = Vec::new();
+        PodVec::<u32>::from(vec![1, 2, 3]).write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 12 + 4);
+        let buf = AlignedBytes::from_slice(&bytes);
+        let mapped = PodVec::<u32>::map_at(&buf, &mut 0).unwrap();
+        let mut v = mapped.clone();
+        v[1] = 7;
+        assert!(!v.is_mapped());
+        assert_eq!(&v[..], &[1, 7, 3]);
+        assert_eq!(&mapped[..], &[1, 2, 3]);
+        assert!(mapped != v);
+    }
+
+    #[test]
+    fn rejects_bad_lengths_and_padding() {
+        let _ = env_logger::init();
+        let mut bytes = vec![6, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(&[1, 0, 0, 0, 2, 0, 0xFF, 0]);
+        // Not a whole number of u32s.
+        assert!(PodVec::<u32>::read_from(&mut &bytes[..]).is_err());
+        assert!(PodVec::<u32>::map_at(&bytes, &mut 0).is_err());
+        // Six bytes, but the padding is not zero.
+        assert!(PodVec::<u8>::read_from(&mut &bytes[..]).is_err());
+        assert!(PodVec::<u8>::map_at(&bytes, &mut 0).is_err());
+        bytes[14] = 0;
+        let read = PodVec::<u8>::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(&read[..], &[1, 0, 0, 0, 2, 0]);
+        assert_eq!(PodVec::<u8>::map_at(&bytes, &mut 0).unwrap(), read);
+        // Cut short.
+        assert!(PodVec::<u8>::read_from(&mut &bytes[..15]).is_err());
+        assert!(PodVec::<u8>::map_at(&bytes[..15], &mut 0).is_err());
+    }
+}
@@ -1,4 +1,11 @@
+use std::io;
+use super::pod_vec::Pod;
+use super::util::{u64_from_le, write_u32};
+
+/// Laid out as three u32s, the words libmarisa writes, so an index can be
+/// mapped in place.
 #[derive(Clone, Copy, Debug)]
+#[repr(C)]
 pub struct RankIndex {
     abs_: u32,
     rel_lo_: u32,
@@ -52,6 +59,11 @@ impl RankIndex {
                      | ((value & 0x1FF).wrapping_shl(18));
     }
 
+    /// The three words as libmarisa lays them out.
+    pub fn words(&self) -> [u32; 3] {
+        [self.abs_, self.rel_lo_, self.rel_hi_]
+    }
+
     pub fn abs(&self) -> u32 {
         self.abs_
     }
@@ -78,6 +90,20 @@ impl RankIndex {
     }
 }
 
+unsafe impl Pod for RankIndex {
+    fn from_le(bytes: &[u8]) -> RankIndex {
+        let word = |i: usize| u64_from_le(&bytes[4 * i..4 * i + 4]) as u32;
+        RankIndex { abs_: word(0), rel_lo_: word(1), rel_hi_: word(2) }
+    }
+
+    fn write_le<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        for &word in &self.words() {
+            write_u32(w, word)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::RankIndex;
@@ -1,4 +1,11 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::v1::*;
+use std::io;
+use std::io::Read;
 use std::iter;
+use std::mem;
+use base::checked_usize;
+use error::MarisaError;
 
 /// Resize vector, default-initializing any extra elements.
 pub fn vec_resize<T: Default + Clone>(v: &mut Vec<T>, new_len: usize) {
@@ -11,12 +18,119 @@ pub fn vec_resize<T: Default + Clone>(v: &mut Vec<T>, new_len: usize) {
     }
 }
 
+/// Size in bytes of the elements of a slice, like marisa's
+/// Vector::total_size.
+pub fn slice_total_size<T>(v: &[T]) -> usize {
+    mem::size_of::<T>() * v.len()
+}
+
+/// Serialized lengths and words are little-endian u64, whatever the
+/// platform's word size.
+pub fn write_u64<W: io::Write>(w: &mut W, x: u64) -> io::Result<()> {
+    let mut bytes = [0u8; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (x >> (8 * i)) as u8;
+    }
+    w.write_all(&bytes)
+}
+
+pub fn u64_from_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |x, &b| (x << 8) | b as u64)
+}
+
+pub fn read_u64<R: io::Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64_from_le(&bytes))
+}
+
+pub fn write_u32<W: io::Write>(w: &mut W, x: u32) -> io::Result<()> {
+    w.write_all(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8])
+}
+
+pub fn read_u32<R: io::Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u64_from_le(&bytes) as u32)
+}
+
+/// Reads a length, failing with a SizeError if it does not fit in a usize.
+pub fn read_len<R: io::Read>(r: &mut R) -> io::Result<usize> {
+    checked_usize(read_u64(r)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads exactly `len` bytes. The buffer grows with what is actually read,
+/// so a bogus length in a truncated file fails without allocating it all.
+pub fn read_bytes<R: io::Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                  "truncated input"));
+    }
+    Ok(buf)
+}
+
+/// Writes `bytes` as libmarisa writes a vector: its size in bytes as a
+/// little-endian u64, the bytes, then zeros up to a multiple of 8 bytes.
+pub fn write_libmarisa_vec<W: io::Write>(w: &mut W, bytes: &[u8])
+  -> io::Result<()> {
+    write_u64(w, bytes.len() as u64)?;
+    w.write_all(bytes)?;
+    w.write_all(&[0u8; 8][..(8 - bytes.len() % 8) % 8])
+}
+
+/// Reads a vector written by libmarisa, of elements `elem_size` bytes
+/// each, and skips its padding.
+pub fn read_libmarisa_vec<R: io::Read>(r: &mut R, elem_size: usize,
+                                       what: &'static str)
+  -> io::Result<Vec<u8>> {
+    let len = read_len(r)?;
+    if len % elem_size != 0 {
+        return Err(invalid_data(what));
+    }
+    let bytes = read_bytes(r, len)?;
+    read_bytes(r, (8 - len % 8) % 8)?;
+    Ok(bytes)
+}
+
+/// An `InvalidData` error carrying a FormatError naming `what`.
+pub fn invalid_data(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   MarisaError::FormatError { what: what })
+}
+
+/// Takes `len` bytes of `buf` at `*pos`, advancing `*pos` past them.
+pub fn map_bytes<'a>(buf: &'a [u8], pos: &mut usize, len: usize,
+                     what: &'static str)
+  -> Result<&'a [u8], MarisaError> {
+    if buf.len() < *pos || buf.len() - *pos < len {
+        return Err(MarisaError::FormatError { what: what });
+    }
+    let out = &buf[*pos..*pos + len];
+    *pos += len;
+    Ok(out)
+}
+
+pub fn map_len(buf: &[u8], pos: &mut usize, what: &'static str)
+  -> Result<usize, MarisaError> {
+    checked_usize(u64_from_le(map_bytes(buf, pos, 8, what)?))
+}
+
 #[cfg(test)]
 mod test {
-    use super::vec_resize;
+    #[cfg(not(feature = "std"))]
+    use std::prelude::v1::*;
+    use super::{map_len, read_bytes, read_len, read_libmarisa_vec,
+                vec_resize};
+    use alloc_counter::bytes_allocated;
+    use std::io;
     use std::iter;
     use quickcheck::quickcheck;
 
+    const FIVE_GIB: u64 = 5 << 30;
+
     #[test]
     fn test_resize() {
         fn prop<T: Clone + Default + Eq>(mut v: Vec<T>) -> bool {
@@ -42,5 +156,34 @@ mod test {
 
         quickcheck(prop::<u32> as fn(Vec<u32>) -> bool)
     }
-}
 
+    #[test]
+    fn huge_lengths_fail_without_allocating() {
+        let mut input = Vec::new();
+        for &len in &[FIVE_GIB, u64::max_value()] {
+            input.clear();
+            input.extend((0..8).map(|i| (len >> (8 * i)) as u8));
+            input.extend(b"short");
+            let mut pos = 0;
+            match map_len(&input, &mut pos, "len") {
+                Ok(n) => assert_eq!(n as u64, len),
+                Err(e) => assert_eq!(e.to_string(), format!(
+                    "MARISA_SIZE_ERROR: dictionary for this platform is \
+                     too large ({})", len)),
+            }
+            let bytes = bytes_allocated(|| {
+                let mut r = &input[..];
+                match read_len(&mut r) {
+                    Ok(n) => {
+                        let e = read_bytes(&mut r, n).unwrap_err();
+                        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                    }
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                }
+                let mut r = &input[..];
+                assert!(read_libmarisa_vec(&mut r, 1, "vec").is_err());
+            });
+            assert!(bytes < 1 << 20, "{} bytes", bytes);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! What the tests of the query tools share: a dictionary built from
+//! tests/data/keys_clean.txt, and a way to run a tool on some input.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use mars_trie::{Config, LoudsTrie};
+
+pub const KEYS: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/keys_clean.txt");
+
+/// A file that is removed when this goes out of scope.
+pub struct TempFile(pub PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The keys of tests/data/keys_clean.txt, built with the default config
+/// and saved under a name no other test uses.
+pub fn fixture_dictionary(name: &str) -> TempFile {
+    let path = env::temp_dir().join(format!("mars-trie-{}-{}.dic",
+                                            std::process::id(), name));
+    let keys = fs::read(KEYS).unwrap();
+    let trie = LoudsTrie::build_from(keys.split(|&b| b == b'\n')
+                                         .filter(|key| !key.is_empty()),
+                                     &Config::new()).unwrap();
+    trie.save(&path).unwrap();
+    TempFile(path)
+}
+
+/// Runs `bin` with `args`, `stdin` piped in, and checks that it succeeds.
+pub fn run(bin: &str, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(bin).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().expect("could not run the tool");
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{} {:?} failed: {}", bin, args,
+            String::from_utf8_lossy(&output.stderr));
+    output
+}
@@ -0,0 +1,81 @@
+//! Runs the marisa-benchmark tool on a thousand keys and checks the shape of
+//! the table it prints; the numbers themselves depend on the machine.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+// Only the helpers for running tools are used here.
+#[allow(dead_code)]
+mod common;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use common::{run, TempFile};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-benchmark");
+
+/// A thousand distinct keys, one per line.
+fn words() -> TempFile {
+    let path = env::temp_dir().join(format!("mars-trie-{}-benchmark.txt",
+                                            std::process::id()));
+    let mut text = String::new();
+    let mut x = 12345u64;
+    for i in 0..1000 {
+        x = x.wrapping_mul(6364136223846793005)
+             .wrapping_add(1442695040888963407);
+        text.push_str(&format!("{:x}-{}\n", x >> 40, i));
+    }
+    fs::write(&path, text).unwrap();
+    TempFile(path)
+}
+
+/// The rows of the table, each split into its cells.
+fn table_rows(stdout: &[u8]) -> Vec<Vec<String>> {
+    let text = String::from_utf8(stdout.to_vec()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let rules: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].starts_with("------+")).collect();
+    // Above the header, below it and below the rows.
+    assert_eq!(rules.len(), 3, "{}", text);
+    assert_eq!(rules[1] - rules[0], 4, "{}", text);
+    lines[rules[1] + 1..rules[2]].iter().map(|line| {
+        line.split_whitespace().map(|cell| cell.to_owned()).collect()
+    }).collect()
+}
+
+#[test]
+fn prints_a_table() {
+    let words = words();
+    let words = words.0.to_str().unwrap();
+    let output = run(BIN, &[words], b"");
+    let rows = table_rows(&output.stdout);
+    assert_eq!(rows.len(), 5);
+    for (i, row) in rows.iter().enumerate() {
+        assert_eq!(row.len(), 7, "{:?}", row);
+        assert_eq!(row[0], (i + 1).to_string());
+        assert!(row[1].parse::<usize>().unwrap() > 0);
+        for cell in &row[2..] {
+            assert!(cell == "-" || cell.parse::<f64>().is_ok(), "{:?}", row);
+        }
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Number of keys: 1000\n"), "{}", stdout);
+
+    let output = run(BIN, &["-s", "-p", "-N", "2", "-n", "3",
+                            "--config=tail=binary,order=label",
+                            "--queries", words, words], b"");
+    let rows = table_rows(&output.stdout);
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|row| row[6] == "-"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[ns]"));
+    assert!(stdout.contains("TAIL mode: Binary mode\n"));
+    assert!(stdout.contains("Node order: Ascending label order\n"));
+    assert!(stdout.contains("Lookups that hit: 1000 of 1000\n"));
+
+    let status = Command::new(BIN).args(&["--config", "tail=neither", words])
+        .output().unwrap().status;
+    assert!(!status.success());
+}
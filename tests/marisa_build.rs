@@ -0,0 +1,164 @@
+//! Runs the marisa-build tool and reads what it writes with the library.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use mars_trie::{LoudsTrie, NodeOrder, TailMode};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-build");
+const INPUT: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/build_input.txt");
+const KEYS: &'static [&'static str] =
+    &["apple", "applet", "banana", "band", "cherry", "bandana"];
+
+fn run(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(BIN).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().expect("could not run marisa-build");
+    // The tool can exit before reading all of it.
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn check_keys(trie: &LoudsTrie) {
+    assert_eq!(trie.len(), KEYS.len());
+    for key in KEYS {
+        assert!(trie.lookup(key.as_bytes()).is_some(), "missing {}", key);
+    }
+    assert_eq!(trie.lookup(b""), None);
+    assert_eq!(trie.lookup(b"apple\t3"), None);
+}
+
+#[test]
+fn builds_a_file() {
+    let path = env::temp_dir().join(format!("mars-trie-{}-build.dic",
+                                            std::process::id()));
+    let output = run(&[INPUT, "-o", path.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("#keys: 6"), "{}", stderr);
+    let trie = LoudsTrie::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    check_keys(&trie);
+}
+
+#[test]
+fn builds_from_stdin_with_options() {
+    let input = fs::read(INPUT).unwrap();
+    let output = run(&["-n", "1", "--binary-tail", "-l", "--cache-level=5"],
+                     &input);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    let trie = LoudsTrie::read_from(&mut &output.stdout[..]).unwrap();
+    check_keys(&trie);
+    let config = trie.config();
+    assert_eq!(config.num_tries().get(), 1);
+    assert_eq!(config.tail_mode(), TailMode::Binary);
+    assert_eq!(config.node_order(), NodeOrder::Label);
+
+    let output = run(&[], b"\xEF\xBB\xBFone\r\ntwo\r\n");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("stripped 2 trailing carriage returns, 1 byte \
+                             order mark"), "{}", stderr);
+    let trie = LoudsTrie::read_from(&mut &output.stdout[..]).unwrap();
+    assert!(trie.lookup(b"one").is_some() && trie.lookup(b"two").is_some());
+}
+
+#[test]
+fn rejects_bad_input_and_options() {
+    let output = run(&[], b"good\t1\nbad\tx1\n");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("stdin:2: invalid weight: \"x1\""), "{}", stderr);
+
+    let output = run(&[], b"negative\t-1\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stdin:1:"));
+
+    for args in &[&["-n", "0"][..], &["-n", "many"], &["-c", "6"], &["-x"],
+                  &["no such file"]] {
+        let output = run(args, b"key\n");
+        assert!(!output.status.success(), "{:?} succeeded", args);
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn estimates_without_building() {
+    let output = run(&["-e", INPUT], b"");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("#keys read: 7"), "{}", stderr);
+    assert!(stderr.contains("estimated total size: "), "{}", stderr);
+    assert!(!stderr.contains("#nodes"), "{}", stderr);
+}
+
+#[test]
+fn prints_info() {
+    let output = run(&["--info", "-n", "2", INPUT], b"");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("trie 1: "), "{}", stderr);
+    assert!(stderr.contains("suggested #tries: "), "{}", stderr);
+    check_keys(&LoudsTrie::read_from(&mut &output.stdout[..]).unwrap());
+}
+
+#[test]
+fn keeps_carriage_returns_if_asked() {
+    let output = run(&["-r"], b"one\r\ntwo\n");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("kept 1 trailing carriage return\n"), "{}",
+            stderr);
+    let trie = LoudsTrie::read_from(&mut &output.stdout[..]).unwrap();
+    assert!(trie.lookup(b"one\r").is_some() && trie.lookup(b"one").is_none());
+
+    // The last of -r and -R wins.
+    let output = run(&["-r", "--strip-cr"], b"one\r\n");
+    assert!(output.status.success());
+    let trie = LoudsTrie::read_from(&mut &output.stdout[..]).unwrap();
+    assert!(trie.lookup(b"one").is_some());
+}
+
+#[test]
+fn builds_with_a_config_string() {
+    let output = run(&["-C", "tries=1,tail=binary,order=label", "-n", "2",
+                       INPUT], b"");
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    let trie = LoudsTrie::read_from(&mut &output.stdout[..]).unwrap();
+    check_keys(&trie);
+    let config = trie.config();
+    assert_eq!(config.num_tries().get(), 2);
+    assert_eq!(config.tail_mode(), TailMode::Binary);
+    assert_eq!(config.node_order(), NodeOrder::Label);
+
+    let output = run(&["--config=tail=sideways", INPUT], b"");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("option -C/--config: "), "{}", stderr);
+}
+
+#[test]
+fn names_the_line_of_a_bad_key() {
+    let path = env::temp_dir().join(format!("mars-trie-{}-bad-key.txt",
+                                            std::process::id()));
+    fs::write(&path, b"first\n\nsecond\t-2\n").unwrap();
+    let output = run(&[INPUT, path.to_str().unwrap()], b"");
+    fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("{}:3: ", path.display())), "{}",
+            stderr);
+}
@@ -0,0 +1,46 @@
+//! Pipes queries through the marisa-common-prefix-search tool and compares
+//! what it prints with tests/data/common_prefix_expected.txt.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+mod common;
+
+use std::fs;
+use common::{fixture_dictionary, run};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-common-prefix-search");
+const QUERIES: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/common_prefix_queries.txt");
+const EXPECTED: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/common_prefix_expected.txt");
+
+#[test]
+fn matches_golden_output() {
+    let dic = fixture_dictionary("common-prefix-search");
+    let dic = dic.0.to_str().unwrap();
+    let queries = fs::read(QUERIES).unwrap();
+    let expected = fs::read(EXPECTED).unwrap();
+    let output = run(BIN, &["-r", dic], &queries);
+    assert!(output.stdout == expected, "got:\n{}",
+            String::from_utf8_lossy(&output.stdout));
+    // No query has more than 10 prefixes, so no limit changes nothing.
+    assert!(run(BIN, &["-r", "-n", "0", dic], &queries).stdout == expected);
+    if cfg!(feature = "memmap2") {
+        assert!(run(BIN, &["-m", dic], &queries).stdout == expected);
+    }
+
+    // The counts stay, but each query shows at most one key.
+    let output = run(BIN, &["-r", "--max-num-results=1", dic], &queries);
+    let capped = String::from_utf8_lossy(&output.stdout);
+    let all = String::from_utf8_lossy(&expected);
+    let counts = |text: &str| -> Vec<String> {
+        text.lines().filter(|line| line.ends_with("found"))
+            .map(|line| line.to_owned()).collect()
+    };
+    assert_eq!(counts(&capped), counts(&all));
+    assert_eq!(capped.lines().count(), counts(&all).len() + 7);
+}
@@ -0,0 +1,98 @@
+//! Dumps dictionaries with the marisa-dump tool and checks what comes out
+//! against the keys they were built from.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use mars_trie::{Config, LoudsTrie};
+use common::{fixture_dictionary, run, KEYS, TempFile};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-dump");
+
+fn sorted_lines(text: &[u8]) -> Vec<Vec<u8>> {
+    let mut lines: Vec<Vec<u8>> = text.split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty()).map(|line| line.to_vec()).collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn dumps_the_wordlist() {
+    let dic = fixture_dictionary("dump");
+    let dic = dic.0.to_str().unwrap();
+    let wordlist = sorted_lines(&fs::read(KEYS).unwrap());
+    let output = run(BIN, &["-r", dic], b"");
+    assert_eq!(sorted_lines(&output.stdout), wordlist);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("#keys: 20"), "{}", stderr);
+    if cfg!(feature = "memmap2") {
+        assert!(run(BIN, &["-m", dic], b"").stdout == output.stdout);
+    }
+    // The dictionary can come on stdin, too.
+    let bytes = fs::read(dic).unwrap();
+    assert!(run(BIN, &[], &bytes).stdout == output.stdout);
+
+    let output = run(BIN, &["-r", "--delimiter", ",", dic], b"");
+    let commas = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(commas.matches(',').count(), wordlist.len());
+
+    let trie = LoudsTrie::load(dic).unwrap();
+    let output = run(BIN, &["-r", "-t", dic], b"");
+    let lines = sorted_lines(&output.stdout);
+    assert_eq!(lines.len(), wordlist.len());
+    for line in &lines {
+        let tab = line.iter().position(|&b| b == b'\t').unwrap();
+        let id: usize = String::from_utf8_lossy(&line[..tab]).parse()
+                        .unwrap();
+        assert_eq!(trie.lookup(&line[tab + 1..]), Some(id));
+    }
+
+    let output = run(BIN, &["-r", "--stats", dic], b"");
+    let stats = String::from_utf8(output.stdout).unwrap();
+    assert!(stats.starts_with("keys: 20\n"), "{}", stats);
+    assert_eq!(stats, trie.stats().to_string());
+
+    let output = run(BIN, &["-r", "--dot", "5", dic], b"");
+    let mut dot = Vec::new();
+    trie.dump_dot(&mut dot, Some(5)).unwrap();
+    assert!(output.stdout == dot);
+}
+
+#[test]
+fn escapes_keys() {
+    let path = env::temp_dir().join(format!("mars-trie-{}-dump-escape.dic",
+                                            std::process::id()));
+    let _dic = TempFile(path.clone());
+    let keys: Vec<&[u8]> = vec![b"two\nlines", b"back\\slash", b"\xFF\x00",
+                                b"plain"];
+    LoudsTrie::build_from(keys, &Config::new()).unwrap()
+        .save(&path).unwrap();
+    let output = run(BIN, &["-r", "--escape", path.to_str().unwrap()], b"");
+    let lines: Vec<&str> = std::str::from_utf8(&output.stdout).unwrap()
+                           .lines().collect();
+    let mut sorted = lines.clone();
+    sorted.sort();
+    assert_eq!(sorted, vec!["\\xFF\\x00", "back\\x5Cslash", "plain",
+                            "two\\x0Alines"]);
+}
+
+#[test]
+fn reports_garbage_on_stdin() {
+    let mut child = Command::new(BIN)
+        .stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    // Small enough for the pipe to take whole, even if the tool gives up.
+    child.stdin.take().unwrap().write_all(b"not a dictionary").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(22));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: failed to read a dictionary from \
+                             standard input: "), "got: {}", stderr);
+}
@@ -0,0 +1,52 @@
+//! Pipes queries through the marisa-lookup tool and compares what it
+//! prints with tests/data/lookup_expected.txt.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+mod common;
+
+use std::fs;
+use std::process::{Command, Stdio};
+use common::{fixture_dictionary, run};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-lookup");
+const QUERIES: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/lookup_queries.txt");
+const EXPECTED: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/lookup_expected.txt");
+
+#[test]
+fn matches_golden_output() {
+    let dic = fixture_dictionary("lookup");
+    let dic = dic.0.to_str().unwrap();
+    let queries = fs::read(QUERIES).unwrap();
+    let expected = fs::read(EXPECTED).unwrap();
+    let output = run(BIN, &["-r", dic], &queries);
+    assert!(output.stdout == expected, "got:\n{}",
+            String::from_utf8_lossy(&output.stdout));
+    if cfg!(feature = "memmap2") {
+        assert!(run(BIN, &["-m", dic], &queries).stdout == expected);
+        assert!(run(BIN, &[dic], &queries).stdout == expected);
+    }
+    assert!(run(BIN, &["--read", dic], b"").stdout.is_empty());
+}
+
+#[test]
+fn reports_a_missing_dictionary() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/missing.dic");
+    let mut modes = vec![("-r", "load", 21)];
+    if cfg!(feature = "memmap2") {
+        modes.push(("-m", "mmap", 20));
+    }
+    for (flag, verb, code) in modes {
+        let output = Command::new(BIN).args(&[flag, path])
+            .stdin(Stdio::null()).output().unwrap();
+        assert_eq!(output.status.code(), Some(code));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let expected = format!("error: failed to {} a dictionary file: {}: ",
+                               verb, path);
+        assert!(stderr.starts_with(&expected), "got: {}", stderr);
+    }
+}
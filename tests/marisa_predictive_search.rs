@@ -0,0 +1,88 @@
+//! Pipes prefixes through the marisa-predictive-search tool and compares
+//! what it prints with tests/data/predictive_expected.txt. The prefixes
+//! include one that is a key, one that matches nothing, and the empty one.
+
+#![cfg(feature = "std")]
+
+extern crate mars_trie;
+
+mod common;
+
+use std::fs;
+use common::{fixture_dictionary, run};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-predictive-search");
+const QUERIES: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/predictive_queries.txt");
+const EXPECTED: &'static str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/predictive_expected.txt");
+
+/// The results for each prefix, as (id, key) lines, checked against the
+/// count before them.
+fn blocks(output: &[u8]) -> Vec<Vec<(usize, Vec<u8>)>> {
+    let mut lines = output.split(|&b| b == b'\n');
+    let mut out = Vec::new();
+    while let Some(count) = lines.next() {
+        if count.is_empty() {
+            break;
+        }
+        let count: usize = String::from_utf8_lossy(count).parse().unwrap();
+        let block: Vec<(usize, Vec<u8>)> =
+            lines.by_ref().take(count).map(|line| {
+                let tab = line.iter().position(|&b| b == b'\t').unwrap();
+                (String::from_utf8_lossy(&line[..tab]).parse().unwrap(),
+                 line[tab + 1..].to_vec())
+            }).collect();
+        assert_eq!(block.len(), count);
+        out.push(block);
+    }
+    out
+}
+
+#[test]
+fn matches_golden_output() {
+    let dic = fixture_dictionary("predictive-search");
+    let dic = dic.0.to_str().unwrap();
+    let queries = fs::read(QUERIES).unwrap();
+    let expected = fs::read(EXPECTED).unwrap();
+    let output = run(BIN, &["-r", dic], &queries);
+    assert!(output.stdout == expected, "got:\n{}",
+            String::from_utf8_lossy(&output.stdout));
+    assert!(run(BIN, &["-r", "-d", dic], &queries).stdout == expected);
+    if cfg!(feature = "memmap2") {
+        assert!(run(BIN, &["-m", dic], &queries).stdout == expected);
+    }
+}
+
+#[test]
+fn limits_and_orders() {
+    let dic = fixture_dictionary("predictive-search-orders");
+    let dic = dic.0.to_str().unwrap();
+    let queries = fs::read(QUERIES).unwrap();
+    let all = blocks(&run(BIN, &["-r", "-n", "0", dic], &queries).stdout);
+    // The empty prefix finds every key.
+    assert_eq!(all[2].len(), 20);
+
+    for &n in &[1, 2, 10] {
+        let arg = n.to_string();
+        let capped = blocks(&run(BIN, &["-r", "-n", &arg, dic],
+                                 &queries).stdout);
+        assert_eq!(capped.len(), all.len());
+        for (capped, all) in capped.iter().zip(&all) {
+            assert_eq!(&capped[..], &all[..std::cmp::min(n, all.len())]);
+        }
+    }
+
+    let id_order = blocks(&run(BIN, &["-r", "-i", "-n", "0", dic],
+                               &queries).stdout);
+    for (id_order, all) in id_order.iter().zip(&all) {
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(id_order, &sorted);
+    }
+    let first = blocks(&run(BIN, &["-r", "-i", "-n", "3", dic],
+                            &queries).stdout);
+    for (first, id_order) in first.iter().zip(&id_order) {
+        assert_eq!(&first[..], &id_order[..std::cmp::min(3, id_order.len())]);
+    }
+}
@@ -0,0 +1,59 @@
+//! Writes test-vector bundles with the marisa-testvec tool and checks them
+//! with its --verify mode.
+
+#![cfg(feature = "std")]
+
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+const BIN: &'static str = env!("CARGO_BIN_EXE_marisa-testvec");
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().expect("could not run marisa-testvec")
+}
+
+#[test]
+fn writes_and_verifies_a_bundle() {
+    let path = env::temp_dir().join(format!("mars-trie-{}-testvec.tsv",
+                                            std::process::id()));
+    let path = path.to_str().unwrap();
+    let output = run(&["-s", "7", "-k", "40", "-o", path]);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("#keys: 40"));
+    let bundle = fs::read_to_string(path).unwrap();
+    assert!(bundle.starts_with("marisa-testvec\t1\nseed\t7\n"));
+    assert_eq!(bundle.lines().filter(|l| l.starts_with("key\t")).count(), 40);
+
+    // The same seed gives the same bundle, and it checks out.
+    assert!(run(&["--seed=7", "--num-keys=40"]).stdout == bundle.as_bytes());
+    let output = run(&["--verify", path]);
+    assert!(output.status.success(), "{}",
+            String::from_utf8_lossy(&output.stderr));
+
+    let bad = bundle.replacen("\nsha256\t", "\nsha256\t0", 1);
+    fs::write(path, bad).unwrap();
+    let output = run(&["-v", path]);
+    fs::remove_file(path).unwrap();
+    assert_eq!(output.status.code(), Some(40));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sha256: 0"), "{}", stderr);
+    assert!(stderr.contains("1 differences"), "{}", stderr);
+}
+
+#[test]
+fn takes_a_config() {
+    let output = run(&["-k", "10", "-C", "tries=2,tail=binary,order=label"]);
+    assert!(output.status.success());
+    let bundle = String::from_utf8(output.stdout).unwrap();
+    assert!(bundle.contains("\n# tries=2,cache=normal,tail=binary,\
+                             order=label"), "{}", bundle);
+
+    for args in &[&["-C", "tail=sideways"][..], &["-k", "0"], &["-s", "x"],
+                  &["-v", "no such file"], &["operand"]] {
+        let output = run(args);
+        assert!(!output.status.success(), "{:?} succeeded", args);
+        assert!(output.stdout.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+//! The default feature set must not pull quickcheck, or the rand stack it
+//! brings, into downstream builds.
+//!
+//! This reads Cargo.toml itself rather than asking cargo to resolve the
+//! graph, which would need the git dependencies fetched. The manifest is
+//! simple enough: dependency sections, and features as one-line arrays.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+struct Manifest {
+    /// Normal dependencies, and whether each is optional.
+    deps: HashMap<String, bool>,
+    features: HashMap<String, Vec<String>>,
+}
+
+fn unquote(s: &str) -> String {
+    s.trim().trim_matches('"').to_owned()
+}
+
+fn parse(text: &str) -> Manifest {
+    let mut manifest = Manifest { deps: HashMap::new(),
+                                  features: HashMap::new() };
+    let mut section = String::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = line.trim_matches(|c| c == '[' || c == ']').to_owned();
+            if section.starts_with("dependencies.") {
+                let name = section["dependencies.".len()..].to_owned();
+                manifest.deps.insert(name, false);
+            }
+            continue;
+        }
+        let mut kv = line.splitn(2, '=');
+        let key = kv.next().unwrap().trim();
+        let value = kv.next().unwrap_or("").trim();
+        if section == "dependencies" {
+            manifest.deps.insert(key.to_owned(),
+                                 value.replace(' ', "")
+                                      .contains("optional=true"));
+        } else if section.starts_with("dependencies.") {
+            if key == "optional" && value == "true" {
+                let name = section["dependencies.".len()..].to_owned();
+                manifest.deps.insert(name, true);
+            }
+        } else if section == "features" {
+            let items = value.trim_matches(|c| c == '[' || c == ']')
+                .split(',').map(unquote).filter(|s| !s.is_empty())
+                .collect();
+            manifest.features.insert(key.to_owned(), items);
+        }
+    }
+    manifest
+}
+
+/// The dependencies the default features build, optional or not.
+fn default_deps(manifest: &Manifest) -> HashSet<String> {
+    let mut enabled: HashSet<String> = manifest.deps.iter()
+        .filter(|&(_, &optional)| !optional)
+        .map(|(name, _)| name.clone()).collect();
+    let mut seen = HashSet::new();
+    let mut todo = vec!["default".to_owned()];
+    while let Some(item) = todo.pop() {
+        if !seen.insert(item.clone()) {
+            continue;
+        }
+        let name = item.split('/').next().unwrap().to_owned();
+        if let Some(items) = manifest.features.get(&name) {
+            todo.extend(items.iter().cloned());
+        }
+        if manifest.deps.contains_key(&name) {
+            enabled.insert(name);
+        }
+    }
+    enabled
+}
+
+#[test]
+fn default_features_have_no_quickcheck() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let text = fs::read_to_string(path).expect("could not read Cargo.toml");
+    let manifest = parse(&text);
+    assert_eq!(manifest.deps.get("quickcheck"), Some(&true),
+               "quickcheck should be an optional dependency");
+    let enabled = default_deps(&manifest);
+    assert!(enabled.contains("log"), "{:?}", enabled);
+    for name in &["quickcheck", "rand"] {
+        assert!(!enabled.contains(*name),
+                "{} is built by default: {:?}", name, enabled);
+    }
+}
+
+#[test]
+fn features_turn_on_optional_dependencies() {
+    let manifest = parse("[dependencies]\n\
+                          a = \"1\"\n\
+                          b = { version = \"1\", optional = true }\n\
+                          [dependencies.c]\n\
+                          version = \"1\"\n\
+                          optional = true\n\
+                          [features]\n\
+                          default = [\"x\"]\n\
+                          x = [\"c/std\"]\n\
+                          [dev-dependencies]\n\
+                          d = \"1\"\n");
+    let enabled = default_deps(&manifest);
+    let mut names: Vec<&str> = enabled.iter().map(|s| &s[..]).collect();
+    names.sort();
+    assert_eq!(names, vec!["a", "c"]);
+}
@@ -0,0 +1,39 @@
+//! Without the `std` feature the library must build for a target that has
+//! no std at all. Not run by default, since it needs that target; CI runs
+//! it with
+//!
+//!     rustup target add thumbv7em-none-eabihf
+//!     cargo test --test no_std_build -- --ignored
+
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &'static str = "thumbv7em-none-eabihf";
+
+fn target_installed() -> bool {
+    let output = match Command::new("rustc").args(&["--print", "sysroot"])
+                                            .output() {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+    let sysroot = String::from_utf8_lossy(&output.stdout);
+    Path::new(sysroot.trim()).join("lib/rustlib").join(TARGET).exists()
+}
+
+#[test]
+#[ignore]
+fn builds_for_a_target_without_std() {
+    assert!(target_installed(), "the {} target is not installed; run \
+                                 rustup target add {}", TARGET, TARGET);
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    // Apart from the main target directory, so as not to wait on its lock.
+    let target_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/no_std");
+    let output = Command::new(env!("CARGO"))
+        .args(&["build", "--lib", "--manifest-path", manifest,
+                "--no-default-features", "--target", TARGET,
+                "--target-dir", target_dir])
+        .output()
+        .expect("could not run cargo build");
+    assert!(output.status.success(), "cargo build failed: {}",
+            String::from_utf8_lossy(&output.stderr));
+}
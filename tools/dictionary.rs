@@ -0,0 +1,79 @@
+//! The dictionary a tool works on, named on the command line, and either
+//! mapped (-m) or read into memory (-r). Mapping needs the memmap2
+//! feature; without it the tools read, and -m is an error.
+
+use std::ffi::OsString;
+use std::process;
+#[cfg(feature = "memmap2")]
+use mars_trie::MappedTrie;
+use mars_trie::{LoudsTrie, LoudsTrieView};
+
+/// Whether a dictionary is mapped unless the command line says otherwise.
+pub const MMAP_BY_DEFAULT: bool = cfg!(feature = "memmap2");
+
+pub enum Dictionary {
+    #[cfg(feature = "memmap2")]
+    Mapped(MappedTrie),
+    Read(LoudsTrie),
+}
+
+impl Dictionary {
+    pub fn trie<'a>(&'a self) -> &'a LoudsTrieView<'a> {
+        match *self {
+            #[cfg(feature = "memmap2")]
+            Dictionary::Mapped(ref trie) => trie.trie(),
+            Dictionary::Read(ref trie) => trie,
+        }
+    }
+}
+
+#[cfg(feature = "memmap2")]
+fn map(path: &OsString) -> Dictionary {
+    match MappedTrie::open(path) {
+        Ok(trie) => Dictionary::Mapped(trie),
+        Err(e) => {
+            eprintln!("error: failed to mmap a dictionary file: {}: {}",
+                      path.to_string_lossy(), e);
+            process::exit(20)
+        },
+    }
+}
+
+#[cfg(not(feature = "memmap2"))]
+fn map(_: &OsString) -> Dictionary {
+    eprintln!("error: this build cannot mmap dictionaries; use -r, or \
+               build with the memmap2 feature");
+    process::exit(20)
+}
+
+/// Opens the dictionary at `path`, or exits with upstream's codes and
+/// messages: 20 if it cannot be mapped and 21 if it cannot be read.
+pub fn open_path(path: &OsString, mmap: bool) -> Dictionary {
+    if mmap {
+        return map(path);
+    }
+    match LoudsTrie::load(path) {
+        Ok(trie) => Dictionary::Read(trie),
+        Err(e) => {
+            eprintln!("error: failed to load a dictionary file: {}: {}",
+                      path.to_string_lossy(), e);
+            process::exit(21)
+        },
+    }
+}
+
+/// Opens the one dictionary `operands` name, as `open_path` does. Exits
+/// with 10 if there is no dictionary and 11 if there is more than one.
+pub fn open(operands: &[OsString], mmap: bool) -> Dictionary {
+    match operands.len() {
+        0 => {
+            eprintln!("error: dictionary is not specified");
+            process::exit(10)
+        },
+        1 => open_path(&operands[0], mmap),
+        _ => {
+            eprintln!("error: more than one dictionaries are specified");
+            process::exit(11)
+        },
+    }
+}
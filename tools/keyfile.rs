@@ -0,0 +1,120 @@
+//! Reading the keys a dictionary is built from, one per line, from the
+//! files named on the command line or from stdin.
+//!
+//! A line can end in a TAB and a weight, "key\t2.5", as in upstream's
+//! tools; a key without one weighs 1. The weight is whatever follows the
+//! last TAB, so a key that itself holds a TAB needs one. Empty lines are
+//! skipped, and a "\r" before each "\n", unless the tool is asked to keep
+//! them, and a byte order mark at the start of each file are stripped,
+//! with a count on stderr. A weight that does not parse, or is negative,
+//! is an error naming the file and line.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::process;
+use std::str;
+use mars_trie::{Keyset, MarisaError};
+use mars_trie::keyio::{KeyDisplay, LineArtifacts, normalize_line, strip_bom,
+                       UTF8_BOM};
+
+fn die(code: i32, message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(code)
+}
+
+/// `line` split into its key and weight, or the text that should have
+/// been a weight.
+fn split_weight(line: &[u8]) -> Result<(&[u8], f32), &[u8]> {
+    let tab = match line.iter().rposition(|&b| b == b'\t') {
+        Some(tab) => tab,
+        None => return Ok((line, 1.0)),
+    };
+    let field = &line[tab + 1..];
+    match str::from_utf8(field).ok().and_then(|f| f.parse::<f32>().ok()) {
+        Some(weight) => Ok((&line[..tab], weight)),
+        None => Err(field),
+    }
+}
+
+/// Where the keys `read_key_files` pushed came from, by their index in
+/// the keyset, so that an error naming a key can name its line.
+pub struct KeySources {
+    names_: Vec<String>,
+    /// The file, as an index into `names_`, and the line of each key.
+    lines_: Vec<(usize, usize)>,
+}
+
+impl KeySources {
+    /// `e`, led by the file and line of the key it names, if any.
+    pub fn describe(&self, e: &MarisaError) -> String {
+        if let &MarisaError::InvalidKey { index, .. } = e {
+            if let Some(&(file, line)) = self.lines_.get(index as usize) {
+                return format!("{}:{}: {}", self.names_[file], line, e);
+            }
+        }
+        e.to_string()
+    }
+}
+
+fn read_keys<R: BufRead>(r: R, keyset: &mut Keyset, sources: &mut KeySources,
+                         strip_cr: bool, stripped: &mut LineArtifacts) {
+    let file = sources.names_.len() - 1;
+    let name = sources.names_[file].clone();
+    for (i, line) in r.split(b'\n').enumerate() {
+        let line = line.unwrap_or_else(|e| {
+            die(11, format!("failed to read {}: {}", name, e))
+        });
+        let mut line = &line[..];
+        if i == 0 && line.starts_with(UTF8_BOM) {
+            line = strip_bom(line);
+            stripped.boms += 1;
+        }
+        if strip_cr && line.ends_with(b"\r") {
+            line = normalize_line(line);
+            stripped.crs += 1;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (key, weight) = split_weight(line).unwrap_or_else(|field| {
+            die(12, format!("{}:{}: invalid weight: \"{}\"", name, i + 1,
+                            KeyDisplay::new(field)))
+        });
+        sources.lines_.push((file, i + 1));
+        if let Err(e) = keyset.try_push_weighted(key, weight) {
+            die(12, sources.describe(&e));
+        }
+    }
+}
+
+/// The keys of the files `paths` name, or of stdin if there are none, and
+/// where each came from. A "\r" ending a line is kept unless `strip_cr`.
+/// Exits with 10 if a file cannot be opened, 11 if it cannot be read, and
+/// 12 for a bad line.
+pub fn read_key_files(paths: &[OsString], strip_cr: bool)
+  -> (Keyset, KeySources) {
+    let mut keyset = Keyset::new();
+    let mut sources = KeySources { names_: Vec::new(), lines_: Vec::new() };
+    let mut stripped = LineArtifacts::default();
+    if paths.is_empty() {
+        let stdin = io::stdin();
+        sources.names_.push("stdin".to_owned());
+        read_keys(stdin.lock(), &mut keyset, &mut sources, strip_cr,
+                  &mut stripped);
+    }
+    for path in paths {
+        let name = path.to_string_lossy().into_owned();
+        let file = File::open(path).unwrap_or_else(|e| {
+            die(10, format!("failed to open {}: {}", name, e))
+        });
+        sources.names_.push(name);
+        read_keys(io::BufReader::new(file), &mut keyset, &mut sources,
+                  strip_cr, &mut stripped);
+    }
+    if !stripped.is_empty() {
+        eprintln!("stripped {}", stripped);
+    }
+    (keyset, sources)
+}
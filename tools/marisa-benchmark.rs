@@ -1,442 +1,359 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
+//! Builds a dictionary of the keys, read as `keyfile` says, for each number
+//! of tries in a range, and prints a table of its size and of how fast it
+//! builds and answers each kind of query, as upstream's marisa-benchmark
+//! does. Times are wall clock times.
+//!
+//! The queries are the keys themselves unless --queries names a file of
+//! them, one per line. Searches for the keys are checked against the ids
+//! the build gave them; searches for other queries can't be, and only the
+//! number of lookups that hit is printed. Searches that restore keys go
+//! through one `SearchState`, so that the times are of the dictionary
+//! rather than of the allocator.
 
-#include <cstdlib>
-#include <cstring>
-#include <ctime>
-#include <fstream>
-#include <iostream>
-#include <string>
-#include <vector>
+extern crate mars_trie;
 
-#include <marisa.h>
+mod cmdopt;
+mod keyfile;
 
-#include "cmdopt.h"
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::process;
+use std::time::Instant;
+use mars_trie::{CacheLevel, Config, Keyset, LoudsTrie, MAX_NUM_TRIES,
+                MIN_NUM_TRIES, NodeOrder, NumTries, SearchState, TailMode};
+use mars_trie::keyio;
+use cmdopt::Opt;
+use keyfile::KeySources;
 
-namespace {
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'N', long: "min-num-tries", has_arg: true },
+    Opt { short: 'n', long: "max-num-tries", has_arg: true },
+    Opt { short: 't', long: "text-tail", has_arg: false },
+    Opt { short: 'b', long: "binary-tail", has_arg: false },
+    Opt { short: 'w', long: "weight-order", has_arg: false },
+    Opt { short: 'l', long: "label-order", has_arg: false },
+    Opt { short: 'c', long: "cache-level", has_arg: true },
+    Opt { short: 'C', long: "config", has_arg: true },
+    Opt { short: 'q', long: "queries", has_arg: true },
+    Opt { short: 'P', long: "predict-on", has_arg: false },
+    Opt { short: 'p', long: "predict-off", has_arg: false },
+    Opt { short: 'S', long: "print-speed", has_arg: false },
+    Opt { short: 's', long: "print-time", has_arg: false },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
 
-int param_min_num_tries = 1;
-int param_max_num_tries = 5;
-marisa::TailMode param_tail_mode = MARISA_DEFAULT_TAIL;
-marisa::NodeOrder param_node_order = MARISA_DEFAULT_ORDER;
-marisa::CacheLevel param_cache_level = MARISA_DEFAULT_CACHE;
-bool param_with_predict = true;
-bool param_print_speed = true;
+const RULE: &'static str =
+    "------+----------+--------+--------+--------+--------+--------";
 
-class Clock {
- public:
-  Clock() : cl_(std::clock()) {}
-
-  void reset() {
-    cl_ = std::clock();
-  }
-
-  double elasped() const {
-    std::clock_t cur = std::clock();
-    return 1.0 * (cur - cl_) / CLOCKS_PER_SEC;
-  }
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... [FILE]...\n\n\
+             Options:\n\
+             \x20 -N, --min-num-tries=[N]  limit the number of tries \
+             [{}, {}] (default: 1)\n\
+             \x20 -n, --max-num-tries=[N]  limit the number of tries \
+             [{}, {}] (default: 5)\n\
+             \x20 -t, --text-tail      build a dictionary with text TAIL \
+             (default)\n\
+             \x20 -b, --binary-tail    build a dictionary with binary TAIL\n\
+             \x20 -w, --weight-order   arrange siblings in weight order \
+             (default)\n\
+             \x20 -l, --label-order    arrange siblings in label order\n\
+             \x20 -c, --cache-level=[N]    specify the cache size \
+             [1, 5] (default: 3)\n\
+             \x20 -C, --config=[S]     set the cache, tail and order as in \
+             \"cache=small,tail=binary\"\n\
+             \x20 -q, --queries=[FILE] search for the lines of FILE \
+             (default: the keys)\n\
+             \x20 -P, --predict-on     include predictive search (default)\n\
+             \x20 -p, --predict-off    skip predictive search\n\
+             \x20 -S, --print-speed    print speed [1000 keys/s] (default)\n\
+             \x20 -s, --print-time     print time [ns/key]\n\
+             \x20 -h, --help           print this help\n\n",
+            cmd, MIN_NUM_TRIES, MAX_NUM_TRIES, MIN_NUM_TRIES, MAX_NUM_TRIES);
+}
 
- private:
-  std::clock_t cl_;
-};
+struct Params {
+    min_num_tries: u32,
+    max_num_tries: u32,
+    config: Config,
+    with_predict: bool,
+    print_speed: bool,
+}
 
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... [FILE]...\n\n"
-      "Options:\n"
-      "  -N, --min-num-tries=[N]  limit the number of tries"
-      " [" << MARISA_MIN_NUM_TRIES << ", " << MARISA_MAX_NUM_TRIES
-      << "] (default: 1)\n"
-      "  -n, --max-num-tries=[N]  limit the number of tries"
-      " [" << MARISA_MIN_NUM_TRIES << ", " << MARISA_MAX_NUM_TRIES
-      << "] (default: 10)\n"
-      "  -t, --text-tail      build a dictionary with text TAIL (default)\n"
-      "  -b, --binary-tail    build a dictionary with binary TAIL\n"
-      "  -w, --weight-order   arrange siblings in weight order (default)\n"
-      "  -l, --label-order    arrange siblings in label order\n"
-      "  -c, --cache-level=[N]    specify the cache size"
-      " [1, 5] (default: 3)\n"
-      "  -P, --with-predict       include predictive search (default)\n"
-      "  -p, --without-predict    skip predictive search\n"
-      "  -S, --print-speed    print speed [1000 keys/s] (default)\n"
-      "  -s, --print-time     print time [ns/key]\n"
-      "  -h, --help           print this help\n"
-      << std::endl;
+fn die(code: i32, message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(code)
 }
 
-void print_config() {
-  std::cout << "Number of tries: " << param_min_num_tries
-      << " - " << param_max_num_tries << std::endl;
+fn number(value: &Option<OsString>) -> Option<u32> {
+    value.as_ref().and_then(|v| v.to_str()).and_then(|v| v.parse().ok())
+}
 
-  std::cout << "TAIL mode: ";
-  switch (param_tail_mode) {
-    case MARISA_TEXT_TAIL: {
-      std::cout << "Text mode" << std::endl;
-      break;
+fn num_tries(c: char, value: &Option<OsString>, code: i32) -> u32 {
+    match number(value) {
+        Some(n) if n >= MIN_NUM_TRIES && n <= MAX_NUM_TRIES => n,
+        _ => die(code, format!("option `-{}' with an invalid argument: {}",
+                               c, value.as_ref().unwrap().to_string_lossy())),
     }
-    case MARISA_BINARY_TAIL: {
-      std::cout << "Binary mode" << std::endl;
-      break;
-    }
-  }
+}
 
-  std::cout << "Node order: ";
-  switch (param_node_order) {
-    case MARISA_LABEL_ORDER: {
-      std::cout << "Ascending label order" << std::endl;
-      break;
-    }
-    case MARISA_WEIGHT_ORDER: {
-      std::cout << "Descending weight order" << std::endl;
-      break;
-    }
-  }
+fn print_config(params: &Params) {
+    println!("Number of tries: {} - {}", params.min_num_tries,
+             params.max_num_tries);
+    println!("TAIL mode: {}", match params.config.tail_mode() {
+        TailMode::Text => "Text mode",
+        TailMode::Binary => "Binary mode",
+        TailMode::Auto => "Auto mode",
+    });
+    println!("Node order: {}", match params.config.node_order() {
+        NodeOrder::Label => "Ascending label order",
+        NodeOrder::Weight => "Descending weight order",
+    });
+    println!("Cache level: {}", match params.config.cache_level() {
+        CacheLevel::Huge => "Huge cache",
+        CacheLevel::Large => "Large cache",
+        CacheLevel::Normal => "Normal cache",
+        CacheLevel::Small => "Small cache",
+        CacheLevel::Tiny => "Tiny cache",
+    });
+}
 
-  std::cout << "Cache level: ";
-  switch (param_cache_level) {
-    case MARISA_HUGE_CACHE: {
-      std::cout << "Huge cache" << std::endl;
-      break;
-    }
-    case MARISA_LARGE_CACHE: {
-      std::cout << "Large cache" << std::endl;
-      break;
-    }
-    case MARISA_NORMAL_CACHE: {
-      std::cout << "Normal cache" << std::endl;
-      break;
-    }
-    case MARISA_SMALL_CACHE: {
-      std::cout << "Small cache" << std::endl;
-      break;
-    }
-    case MARISA_TINY_CACHE: {
-      std::cout << "Tiny cache" << std::endl;
-      break;
-    }
-  }
+fn seconds_since(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
 }
 
-void print_time_info(std::size_t num_keys, double elasped) {
-  if (param_print_speed) {
-    if (elasped == 0.0) {
-      std::printf(" %8s", "-");
-    } else {
-      std::printf(" %8.2f", num_keys / elasped / 1000.0);
-    }
-  } else {
-    if ((elasped == 0.0) || (num_keys == 0)) {
-      std::printf(" %8s", "-");
+/// Prints the speed of, or the time per key of, `num_keys` queries that
+/// took `elapsed` seconds, or "-" for a step that was skipped.
+fn print_time_info(params: &Params, num_keys: usize, elapsed: f64) {
+    if elapsed == 0.0 || num_keys == 0 {
+        print!(" {:>8}", "-");
+    } else if params.print_speed {
+        print!(" {:8.2}", num_keys as f64 / elapsed / 1000.0);
     } else {
-      std::printf(" %8.1f", 1000000000.0 * elasped / num_keys);
+        print!(" {:8.1}", 1e9 * elapsed / num_keys as f64);
     }
-  }
 }
 
-void read_keys(std::istream &input, marisa::Keyset *keyset,
-    std::vector<float> *weights) {
-  std::string line;
-  while (std::getline(input, line)) {
-    const std::string::size_type delim_pos = line.find_last_of('\t');
-    float weight = 1.0F;
-    if (delim_pos != line.npos) {
-      char *end_of_value;
-      weight = (float)std::strtod(&line[delim_pos + 1], &end_of_value);
-      if (*end_of_value == '\0') {
-        line.resize(delim_pos);
-      }
-    }
-    keyset->push_back(line.c_str(), line.length());
-    weights->push_back(weight);
-  }
+fn failed(what: &str) -> ! {
+    println!();
+    die(40, format!("{} failed", what))
 }
 
-int read_keys(const char * const *args, std::size_t num_args,
-    marisa::Keyset *keyset, std::vector<float> *weights) {
-  if (num_args == 0) {
-    read_keys(std::cin, keyset, weights);
-  }
-  for (std::size_t i = 0; i < num_args; ++i) {
-    std::ifstream input_file(args[i], std::ios::binary);
-    if (!input_file) {
-      std::cerr << "error: failed to open: " << args[i] << std::endl;
-      return 10;
+fn benchmark_lookup(params: &Params, trie: &LoudsTrie, keyset: &Keyset,
+                    queries: Option<&Keyset>) -> usize {
+    let start = Instant::now();
+    let mut num_hits = 0;
+    match queries {
+        None => for i in 0..keyset.len() {
+            if trie.lookup(keyset.key(i)) != keyset.id(i) {
+                failed("lookup");
+            }
+            num_hits += 1;
+        },
+        Some(queries) => for i in 0..queries.len() {
+            if trie.lookup(queries.key(i)).is_some() {
+                num_hits += 1;
+            }
+        },
     }
-    read_keys(input_file, keyset, weights);
-  }
-  std::cout << "Number of keys: " << keyset->size() << std::endl;
-  std::cout << "Total length: " << keyset->total_length() << std::endl;
-  return 0;
-}
-
-void benchmark_build(marisa::Keyset &keyset,
-    const std::vector<float> &weights, int num_tries, marisa::Trie *trie) {
-  for (std::size_t i = 0; i < keyset.size(); ++i) {
-    keyset[i].set_weight(weights[i]);
-  }
-  Clock cl;
-  trie->build(keyset, num_tries | param_tail_mode | param_node_order |
-      param_cache_level);
-  std::printf(" %10lu", (unsigned long)trie->io_size());
-  print_time_info(keyset.size(), cl.elasped());
+    let num_queries = queries.unwrap_or(keyset).len();
+    print_time_info(params, num_queries, seconds_since(start));
+    num_hits
 }
 
-void benchmark_lookup(const marisa::Trie &trie,
-    const marisa::Keyset &keyset) {
-  Clock cl;
-  marisa::Agent agent;
-  for (std::size_t i = 0; i < keyset.size(); ++i) {
-    agent.set_query(keyset[i].ptr(), keyset[i].length());
-    if (!trie.lookup(agent) || (agent.key().id() != keyset[i].id())) {
-      std::cerr << "error: lookup() failed" << std::endl;
-      return;
+fn benchmark_reverse_lookup(params: &Params, trie: &LoudsTrie,
+                            keyset: &Keyset, state: &mut SearchState) {
+    let start = Instant::now();
+    for i in 0..keyset.len() {
+        let id = keyset.id(i).unwrap();
+        match trie.id_lookup_with(state, id) {
+            Ok(key) if key == keyset.key(i) => {},
+            _ => failed("reverse_lookup"),
+        }
     }
-  }
-  print_time_info(keyset.size(), cl.elasped());
+    print_time_info(params, keyset.len(), seconds_since(start));
 }
 
-void benchmark_reverse_lookup(const marisa::Trie &trie,
-    const marisa::Keyset &keyset) {
-  Clock cl;
-  marisa::Agent agent;
-  for (std::size_t i = 0; i < keyset.size(); ++i) {
-    agent.set_query(keyset[i].id());
-    trie.reverse_lookup(agent);
-    if ((agent.key().id() != keyset[i].id()) ||
-        (agent.key().length() != keyset[i].length()) ||
-        (std::memcmp(agent.key().ptr(), keyset[i].ptr(),
-            agent.key().length()) != 0)) {
-      std::cerr << "error: reverse_lookup() failed" << std::endl;
-      return;
+fn benchmark_common_prefix_search(params: &Params, trie: &LoudsTrie,
+                                  keyset: &Keyset,
+                                  queries: Option<&Keyset>) {
+    let start = Instant::now();
+    match queries {
+        None => for i in 0..keyset.len() {
+            let key = keyset.key(i);
+            match trie.common_prefix_search(key).last() {
+                Some((id, len)) if Some(id) == keyset.id(i)
+                                   && len == key.len() => {},
+                _ => failed("common_prefix_search"),
+            }
+        },
+        Some(queries) => for i in 0..queries.len() {
+            for _ in trie.common_prefix_search(queries.key(i)) {}
+        },
     }
-  }
-  print_time_info(keyset.size(), cl.elasped());
+    let num_queries = queries.unwrap_or(keyset).len();
+    print_time_info(params, num_queries, seconds_since(start));
 }
 
-void benchmark_common_prefix_search(const marisa::Trie &trie,
-    const marisa::Keyset &keyset) {
-  Clock cl;
-  marisa::Agent agent;
-  for (std::size_t i = 0; i < keyset.size(); ++i) {
-    agent.set_query(keyset[i].ptr(), keyset[i].length());
-    while (trie.common_prefix_search(agent)) {
-      if (agent.key().id() > keyset[i].id()) {
-        std::cerr << "error: common_prefix_search() failed" << std::endl;
+fn benchmark_predictive_search(params: &Params, trie: &LoudsTrie,
+                               keyset: &Keyset, queries: Option<&Keyset>,
+                               state: &mut SearchState) {
+    let num_queries = queries.unwrap_or(keyset).len();
+    if !params.with_predict {
+        print_time_info(params, num_queries, 0.0);
         return;
-      }
     }
-    if (agent.key().id() != keyset[i].id()) {
-      std::cerr << "error: common_prefix_search() failed" << std::endl;
-      return;
+    let start = Instant::now();
+    match queries {
+        // The key comes first, and the keys below it, which are deeper,
+        // all have larger ids.
+        None => for i in 0..keyset.len() {
+            let id = keyset.id(i).unwrap();
+            let mut cursor = trie.predictive_search_with(state, keyset.key(i));
+            match cursor.next() {
+                Some((first, _)) if first == id => {},
+                _ => failed("predictive_search"),
+            }
+            while let Some((next, _)) = cursor.next() {
+                if next <= id {
+                    failed("predictive_search");
+                }
+            }
+        },
+        Some(queries) => for i in 0..queries.len() {
+            let mut cursor = trie.predictive_search_with(state,
+                                                         queries.key(i));
+            while let Some(_) = cursor.next() {}
+        },
     }
-  }
-  print_time_info(keyset.size(), cl.elasped());
+    print_time_info(params, num_queries, seconds_since(start));
 }
 
-void benchmark_predictive_search(const marisa::Trie &trie,
-    const marisa::Keyset &keyset) {
-  if (!param_with_predict) {
-    print_time_info(keyset.size(), 0.0);
-    return;
-  }
-
-  Clock cl;
-  marisa::Agent agent;
-  for (std::size_t i = 0; i < keyset.size(); ++i) {
-    agent.set_query(keyset[i].ptr(), keyset[i].length());
-    if (!trie.predictive_search(agent) ||
-        (agent.key().id() != keyset[i].id())) {
-      std::cerr << "error: predictive_search() failed" << std::endl;
-      return;
+/// Prints the row for `num_tries`, and returns the number of lookups that
+/// hit.
+fn benchmark(params: &Params, keyset: &mut Keyset, sources: &KeySources,
+             queries: Option<&Keyset>, num_tries: u32) -> usize {
+    print!("{:6}", num_tries);
+    let config = params.config.with_num_tries(NumTries::new(num_tries));
+    let start = Instant::now();
+    let trie = LoudsTrie::build(keyset, &config).unwrap_or_else(|e| {
+        println!();
+        die(20, format!("failed to build a dictionary: {}",
+                        sources.describe(&e)))
+    });
+    let elapsed = seconds_since(start);
+    print!(" {:10}", trie.io_size());
+    print_time_info(params, keyset.len(), elapsed);
+    let mut num_hits = 0;
+    if !trie.is_empty() {
+        let mut state = SearchState::new();
+        num_hits = benchmark_lookup(params, &trie, keyset, queries);
+        benchmark_reverse_lookup(params, &trie, keyset, &mut state);
+        benchmark_common_prefix_search(params, &trie, keyset, queries);
+        benchmark_predictive_search(params, &trie, keyset, queries,
+                                    &mut state);
     }
-    while (trie.predictive_search(agent)) {
-      if (agent.key().id() <= keyset[i].id()) {
-        std::cerr << "error: predictive_search() failed" << std::endl;
-        return;
-      }
-    }
-  }
-  print_time_info(keyset.size(), cl.elasped());
-}
-
-void benchmark(marisa::Keyset &keyset, const std::vector<float> &weights,
-    int num_tries) {
-  std::printf("%6d", num_tries);
-  marisa::Trie trie;
-  benchmark_build(keyset, weights, num_tries, &trie);
-  if (!trie.empty()) {
-    benchmark_lookup(trie, keyset);
-    benchmark_reverse_lookup(trie, keyset);
-    benchmark_common_prefix_search(trie, keyset);
-    benchmark_predictive_search(trie, keyset);
-  }
-  std::printf("\n");
+    println!();
+    num_hits
 }
 
-int benchmark(const char * const *args, std::size_t num_args) try {
-  marisa::Keyset keyset;
-  std::vector<float> weights;
-  const int ret = read_keys(args, num_args, &keyset, &weights);
-  if (ret != 0) {
-    return ret;
-  }
-  std::printf("------+----------+--------+--------+"
-      "--------+--------+--------\n");
-  std::printf("%6s %10s %8s %8s %8s %8s %8s\n",
-      "#tries", "size", "build", "lookup", "reverse", "prefix", "predict");
-  std::printf("%6s %10s %8s %8s %8s %8s %8s\n",
-      "", "", "", "", "lookup", "search", "search");
-  if (param_print_speed) {
-    std::printf("%6s %10s %8s %8s %8s %8s %8s\n",
-        "", "[bytes]",
-        "[K/s]", "[K/s]", "[K/s]", "[K/s]", "[K/s]");
-  } else {
-    std::printf("%6s %10s %8s %8s %8s %8s %8s\n",
-        "", "[bytes]", "[ns]", "[ns]", "[ns]", "[ns]", "[ns]");
-  }
-  std::printf("------+----------+--------+--------+"
-      "--------+--------+--------\n");
-  for (int i = param_min_num_tries; i <= param_max_num_tries; ++i) {
-    benchmark(keyset, weights, i);
-  }
-  std::printf("------+----------+--------+--------+"
-      "--------+--------+--------\n");
-  return 0;
-} catch (const marisa::Exception &ex) {
-  std::cerr << ex.what() << std::endl;
-  return -1;
-}
-
-}  // namespace
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-benchmark".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+    let mut params = Params {
+        min_num_tries: 1,
+        max_num_tries: 5,
+        config: Config::new(),
+        with_predict: true,
+        print_speed: true,
+    };
+    let mut queries_path = None;
+    for (c, value) in parsed.options {
+        match c {
+            'N' => params.min_num_tries = num_tries(c, &value, 1),
+            'n' => params.max_num_tries = num_tries(c, &value, 2),
+            't' => params.config.set_tail_mode(TailMode::Text),
+            'b' => params.config.set_tail_mode(TailMode::Binary),
+            'w' => params.config.set_node_order(NodeOrder::Weight),
+            'l' => params.config.set_node_order(NodeOrder::Label),
+            'c' => params.config.set_cache_level(match number(&value) {
+                Some(1) => CacheLevel::Tiny,
+                Some(2) => CacheLevel::Small,
+                Some(3) => CacheLevel::Normal,
+                Some(4) => CacheLevel::Large,
+                Some(5) => CacheLevel::Huge,
+                _ => die(3, format!("option `-c' with an invalid argument: \
+                                     {}", value.unwrap().to_string_lossy())),
+            }),
+            // The range of tries is the table's rows, so a number of
+            // tries given here is left out.
+            'C' => {
+                let value = value.unwrap();
+                let config: Config = value.to_string_lossy().parse()
+                                          .unwrap_or_else(|e| {
+                    die(3, format!("option `--config': {}", e))
+                });
+                params.config.set_cache_level(config.cache_level());
+                params.config.set_tail_mode(config.tail_mode());
+                params.config.set_node_order(config.node_order());
+            },
+            'q' => queries_path = value,
+            'P' => params.with_predict = true,
+            'p' => params.with_predict = false,
+            'S' => params.print_speed = true,
+            's' => params.print_speed = false,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
+        }
+    }
+    print_config(&params);
 
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
+    let (mut keyset, sources) = keyfile::read_key_files(&parsed.operands,
+                                                        true);
+    println!("Number of keys: {}", keyset.len());
+    println!("Total length: {}", keyset.total_length());
+    let queries = queries_path.map(|path| {
+        let name = path.to_string_lossy().into_owned();
+        let file = File::open(&path).unwrap_or_else(|e| {
+            die(10, format!("failed to open {}: {}", name, e))
+        });
+        let mut queries = Keyset::new();
+        keyio::read_keys(io::BufReader::new(file), &mut queries, true)
+            .unwrap_or_else(|e| {
+                die(11, format!("failed to read {}: {}", name, e))
+            });
+        println!("Number of queries: {}", queries.len());
+        queries
+    });
 
-  ::cmdopt_option long_options[] = {
-    { "min-num-tries", 1, NULL, 'N' },
-    { "max-num-tries", 1, NULL, 'n' },
-    { "text-tail", 0, NULL, 't' },
-    { "binary-tail", 0, NULL, 'b' },
-    { "weight-order", 0, NULL, 'w' },
-    { "label-order", 0, NULL, 'l' },
-    { "cache-level", 1, NULL, 'c' },
-    { "predict-on", 0, NULL, 'P' },
-    { "predict-off", 0, NULL, 'p' },
-    { "print-speed", 0, NULL, 'S' },
-    { "print-time", 0, NULL, 's' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "N:n:tbwlc:PpSsh", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'N': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value <= 0) ||
-            (value > MARISA_MAX_NUM_TRIES)) {
-          std::cerr << "error: option `-n' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
-          return 1;
-        }
-        param_min_num_tries = (int)value;
-        break;
-      }
-      case 'n': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value <= 0) ||
-            (value > MARISA_MAX_NUM_TRIES)) {
-          std::cerr << "error: option `-n' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
-          return 2;
-        }
-        param_max_num_tries = (int)value;
-        break;
-      }
-      case 't': {
-        param_tail_mode = MARISA_TEXT_TAIL;
-        break;
-      }
-      case 'b': {
-        param_tail_mode = MARISA_BINARY_TAIL;
-        break;
-      }
-      case 'w': {
-        param_node_order = MARISA_WEIGHT_ORDER;
-        break;
-      }
-      case 'l': {
-        param_node_order = MARISA_LABEL_ORDER;
-        break;
-      }
-      case 'c': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value < 1) || (value > 5)) {
-          std::cerr << "error: option `-c' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
-          return 3;
-        } else if (value == 1) {
-          param_cache_level = MARISA_TINY_CACHE;
-        } else if (value == 2) {
-          param_cache_level = MARISA_SMALL_CACHE;
-        } else if (value == 3) {
-          param_cache_level = MARISA_NORMAL_CACHE;
-        } else if (value == 4) {
-          param_cache_level = MARISA_LARGE_CACHE;
-        } else if (value == 5) {
-          param_cache_level = MARISA_HUGE_CACHE;
-        }
-        break;
-      }
-      case 'P': {
-        param_with_predict = true;
-        break;
-      }
-      case 'p': {
-        param_with_predict = false;
-        break;
-      }
-      case 'S': {
-        param_print_speed = true;
-        break;
-      }
-      case 's': {
-        param_print_speed = false;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
+    println!("{}", RULE);
+    println!("{:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8}",
+             "#tries", "size", "build", "lookup", "reverse", "prefix",
+             "predict");
+    println!("{:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8}",
+             "", "", "", "", "lookup", "search", "search");
+    let unit = if params.print_speed { "[K/s]" } else { "[ns]" };
+    println!("{:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8}",
+             "", "[bytes]", unit, unit, unit, unit, unit);
+    println!("{}", RULE);
+    let mut num_hits = 0;
+    for num_tries in params.min_num_tries..params.max_num_tries + 1 {
+        num_hits = benchmark(&params, &mut keyset, &sources, queries.as_ref(),
+                             num_tries);
+    }
+    println!("{}", RULE);
+    if let Some(ref queries) = queries {
+        println!("Lookups that hit: {} of {}", num_hits, queries.len());
     }
-  }
-  print_config();
-  return benchmark(cmdopt.argv + cmdopt.optind, cmdopt.argc - cmdopt.optind);
 }
@@ -1,230 +1,191 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
-
-#ifdef _WIN32
- #include <fcntl.h>
- #include <io.h>
- #include <stdio.h>
-#endif  // _WIN32
-
-#include <cstdlib>
-#include <fstream>
-#include <iostream>
-#include <string>
-
-#include <marisa.h>
-
-#include "cmdopt.h"
-
-namespace {
-
-int param_num_tries = MARISA_DEFAULT_NUM_TRIES;
-marisa::TailMode param_tail_mode = MARISA_DEFAULT_TAIL;
-marisa::NodeOrder param_node_order = MARISA_DEFAULT_ORDER;
-marisa::CacheLevel param_cache_level = MARISA_DEFAULT_CACHE;
-const char *output_filename = NULL;
-
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... [FILE]...\n\n"
-      "Options:\n"
-      "  -n, --num-tries=[N]  limit the number of tries"
-      " [" << MARISA_MIN_NUM_TRIES << ", " << MARISA_MAX_NUM_TRIES
-      << "] (default: 3)\n"
-      "  -t, --text-tail      build a dictionary with text TAIL (default)\n"
-      "  -b, --binary-tail    build a dictionary with binary TAIL\n"
-      "  -w, --weight-order   arrange siblings in weight order (default)\n"
-      "  -l, --label-order    arrange siblings in label order\n"
-      "  -c, --cache-level=[N]    specify the cache size"
-      " [1, 5] (default: 3)\n"
-      "  -o, --output=[FILE]  write tries to FILE (default: stdout)\n"
-      "  -h, --help           print this help\n"
-      << std::endl;
+//! Builds a dictionary from keys, one per line, read from the files named
+//! on the command line or from stdin, and writes it to stdout or to the
+//! file given with -o.
+//!
+//! Keys are read as `keyfile` says: a line can end in a TAB and a weight,
+//! empty lines are skipped, and a bad weight stops the build with the
+//! file and line on stderr. A key that appears more than once is stored
+//! once, with the sum of its weights.
+//!
+//! Besides upstream's options, --config sets the build as a config string
+//! does, --estimate-only prints the estimated size instead of building,
+//! --info adds the edges of each trie and a suggested number of tries to
+//! the report, and --keep-cr keeps the "\r" of CRLF line endings, which
+//! are otherwise stripped.
+
+extern crate mars_trie;
+
+mod cmdopt;
+mod keyfile;
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::process;
+use mars_trie::{CacheLevel, Config, LoudsTrie, MAX_NUM_TRIES,
+                MIN_NUM_TRIES, NodeOrder, NumTries, suggest_num_tries,
+                TailMode};
+use cmdopt::Opt;
+
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'n', long: "num-tries", has_arg: true },
+    Opt { short: 't', long: "text-tail", has_arg: false },
+    Opt { short: 'b', long: "binary-tail", has_arg: false },
+    Opt { short: 'w', long: "weight-order", has_arg: false },
+    Opt { short: 'l', long: "label-order", has_arg: false },
+    Opt { short: 'c', long: "cache-level", has_arg: true },
+    Opt { short: 'C', long: "config", has_arg: true },
+    Opt { short: 'R', long: "strip-cr", has_arg: false },
+    Opt { short: 'r', long: "keep-cr", has_arg: false },
+    Opt { short: 'e', long: "estimate-only", has_arg: false },
+    Opt { short: 'i', long: "info", has_arg: false },
+    Opt { short: 'o', long: "output", has_arg: true },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
+
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... [FILE]...\n\n\
+             Options:\n\
+             \x20 -n, --num-tries=[N]  limit the number of tries \
+             [{}, {}] (default: 3)\n\
+             \x20 -t, --text-tail      build a dictionary with text TAIL \
+             (default)\n\
+             \x20 -b, --binary-tail    build a dictionary with binary TAIL\n\
+             \x20 -w, --weight-order   arrange siblings in weight order \
+             (default)\n\
+             \x20 -l, --label-order    arrange siblings in label order\n\
+             \x20 -c, --cache-level=[N]    specify the cache size \
+             [1, 5] (default: 3)\n\
+             \x20 -C, --config=[S]     build as config S says, as in \
+             \"tries=2,tail=binary\"\n\
+             \x20 -R, --strip-cr       strip a \\r ending a line \
+             (default)\n\
+             \x20 -r, --keep-cr        keep a \\r ending a line in the key\n\
+             \x20 -e, --estimate-only  print the estimated size, and \
+             build nothing\n\
+             \x20 -i, --info           print the edges of each trie and \
+             a number of tries\n\
+             \x20 -o, --output=[FILE]  write tries to FILE \
+             (default: stdout)\n\
+             \x20 -h, --help           print this help\n",
+            cmd, MIN_NUM_TRIES, MAX_NUM_TRIES);
 }
 
-void read_keys(std::istream &input, marisa::Keyset *keyset) {
-  std::string line;
-  while (std::getline(input, line)) {
-    const std::string::size_type delim_pos = line.find_last_of('\t');
-    float weight = 1.0F;
-    if (delim_pos != line.npos) {
-      char *end_of_value;
-      weight = (float)std::strtod(&line[delim_pos + 1], &end_of_value);
-      if (*end_of_value == '\0') {
-        line.resize(delim_pos);
-      }
-    }
-    keyset->push_back(line.c_str(), line.length(), weight);
-  }
+fn die(code: i32, message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(code)
 }
 
-int build(const char * const *args, std::size_t num_args) {
-  marisa::Keyset keyset;
-  if (num_args == 0) try {
-    read_keys(std::cin, &keyset);
-  } catch (const marisa::Exception &ex) {
-    std::cerr << ex.what() << ": failed to read keys" << std::endl;
-    return 10;
-  }
+fn number(value: &Option<OsString>) -> Option<u32> {
+    value.as_ref().and_then(|v| v.to_str()).and_then(|v| v.parse().ok())
+}
 
-  for (std::size_t i = 0; i < num_args; ++i) try {
-    std::ifstream input_file(args[i], std::ios::binary);
-    if (!input_file) {
-      std::cerr << "error: failed to open: " << args[i] << std::endl;
-      return 11;
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-build".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+
+    let mut config = Config::new();
+    let mut strip_cr = true;
+    let mut estimate_only = false;
+    let mut info = false;
+    let mut output = None;
+    for (c, value) in parsed.options {
+        match c {
+            'n' => match number(&value) {
+                Some(n) if n >= MIN_NUM_TRIES && n <= MAX_NUM_TRIES => {
+                    config.set_num_tries(NumTries::new(n));
+                },
+                _ => die(1, format!("option -n/--num-tries: the number \
+                                     of tries must be in [{}, {}]",
+                                    MIN_NUM_TRIES, MAX_NUM_TRIES)),
+            },
+            't' => config.set_tail_mode(TailMode::Text),
+            'b' => config.set_tail_mode(TailMode::Binary),
+            'w' => config.set_node_order(NodeOrder::Weight),
+            'l' => config.set_node_order(NodeOrder::Label),
+            'c' => config.set_cache_level(match number(&value) {
+                Some(1) => CacheLevel::Tiny,
+                Some(2) => CacheLevel::Small,
+                Some(3) => CacheLevel::Normal,
+                Some(4) => CacheLevel::Large,
+                Some(5) => CacheLevel::Huge,
+                _ => die(2, "option -c/--cache-level: the cache level must \
+                             be in [1, 5]".to_owned()),
+            }),
+            // All of it, so that options after it can change it.
+            'C' => {
+                let value = value.unwrap();
+                config = value.to_string_lossy().parse().unwrap_or_else(|e| {
+                    die(3, format!("option -C/--config: {}", e))
+                });
+            },
+            'R' => strip_cr = true,
+            'r' => strip_cr = false,
+            'e' => estimate_only = true,
+            'i' => info = true,
+            'o' => output = value,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
+        }
     }
-    read_keys(input_file, &keyset);
-  } catch (const marisa::Exception &ex) {
-    std::cerr << ex.what() << ": failed to read keys" << std::endl;
-    return 12;
-  }
-
-  marisa::Trie trie;
-  try {
-    trie.build(keyset, param_num_tries | param_tail_mode | param_node_order |
-        param_cache_level);
-  } catch (const marisa::Exception &ex) {
-    std::cerr << ex.what() << ": failed to build a dictionary" << std::endl;
-    return 20;
-  }
 
-  std::cerr << "#keys: " << trie.num_keys() << std::endl;
-  std::cerr << "#nodes: " << trie.num_nodes() << std::endl;
-  std::cerr << "size: " << trie.io_size() << std::endl;
-
-  if (output_filename != NULL) {
-    try {
-      trie.save(output_filename);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to write a dictionary to file: "
-          << output_filename << std::endl;
-      return 30;
-    }
-  } else {
-#ifdef _WIN32
-    const int stdout_fileno = ::_fileno(stdout);
-    if (stdout_fileno < 0) {
-      std::cerr << "error: failed to get the file descriptor of "
-          "standard output" << std::endl;
-      return 31;
-    }
-    if (::_setmode(stdout_fileno, _O_BINARY) == -1) {
-      std::cerr << "error: failed to set binary mode" << std::endl;
-      return 32;
+    let (mut keyset, sources) =
+        keyfile::read_key_files(&parsed.operands, strip_cr);
+    let kept = keyset.validate().crs;
+    if kept != 0 {
+        eprintln!("kept {} trailing carriage return{}", kept,
+                  if kept == 1 { "" } else { "s" });
     }
-#endif  // _WIN32
-    try {
-      std::cout << trie;
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what()
-          << ": failed to write a dictionary to standard output" << std::endl;
-      return 33;
-    }
-  }
-  return 0;
-}
 
-}  // namespace
-
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
+    if estimate_only {
+        let estimate = LoudsTrie::estimate_size(&keyset, &config);
+        // Before a key read twice is merged.
+        eprintln!("#keys read: {}", keyset.len());
+        eprintln!("estimated total size: {} [{}, {}]", estimate.expected(),
+                  estimate.low(), estimate.high());
+        return;
+    }
 
-  ::cmdopt_option long_options[] = {
-    { "max-num-tries", 1, NULL, 'n' },
-    { "text-tail", 0, NULL, 't' },
-    { "binary-tail", 0, NULL, 'b' },
-    { "weight-order", 0, NULL, 'w' },
-    { "label-order", 0, NULL, 'l' },
-    { "cache-level", 1, NULL, 'c' },
-    { "output", 1, NULL, 'o' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "n:tbwlc:o:h", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'n': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value <= 0) ||
-            (value > MARISA_MAX_NUM_TRIES)) {
-          std::cerr << "error: option `-n' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
-          return 1;
-        }
-        param_num_tries = (int)value;
-        break;
-      }
-      case 't': {
-        param_tail_mode = MARISA_TEXT_TAIL;
-        break;
-      }
-      case 'b': {
-        param_tail_mode = MARISA_BINARY_TAIL;
-        break;
-      }
-      case 'w': {
-        param_node_order = MARISA_WEIGHT_ORDER;
-        break;
-      }
-      case 'l': {
-        param_node_order = MARISA_LABEL_ORDER;
-        break;
-      }
-      case 'c': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value < 1) || (value > 5)) {
-          std::cerr << "error: option `-c' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
-          return 2;
-        } else if (value == 1) {
-          param_cache_level = MARISA_TINY_CACHE;
-        } else if (value == 2) {
-          param_cache_level = MARISA_SMALL_CACHE;
-        } else if (value == 3) {
-          param_cache_level = MARISA_NORMAL_CACHE;
-        } else if (value == 4) {
-          param_cache_level = MARISA_LARGE_CACHE;
-        } else if (value == 5) {
-          param_cache_level = MARISA_HUGE_CACHE;
+    let trie = LoudsTrie::build(&mut keyset, &config).unwrap_or_else(|e| {
+        die(20, format!("failed to build a dictionary: {}",
+                        sources.describe(&e)))
+    });
+    let stats = trie.stats();
+    eprintln!("#keys: {}", stats.num_keys);
+    eprintln!("#nodes: {}", stats.num_nodes);
+    eprintln!("size: {}", stats.io_size());
+    if info {
+        let levels = trie.level_stats();
+        for (i, level) in levels.iter().enumerate() {
+            eprintln!("trie {}: {} nodes, {} link edges, {} byte edges \
+                       ({:.1}% links)", i + 1, level.num_nodes(),
+                      level.num_link_edges(), level.num_byte_edges(),
+                      100.0 * level.link_share());
         }
-        break;
-      }
-      case 'o': {
-        output_filename = cmdopt.optarg;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
+        eprintln!("suggested #tries: {}", suggest_num_tries(&levels).get());
+    }
+
+    match output {
+        Some(path) => trie.save(&path).unwrap_or_else(|e| {
+            die(30, format!("failed to write a dictionary to {}: {}",
+                            path.to_string_lossy(), e))
+        }),
+        None => {
+            let stdout = io::stdout();
+            let mut w = stdout.lock();
+            trie.write_to(&mut w).and_then(|()| w.flush()).unwrap_or_else(|e| {
+                die(33, format!("failed to write a dictionary to stdout: {}",
+                                e))
+            });
+        },
     }
-  }
-  return build(cmdopt.argv + cmdopt.optind, cmdopt.argc - cmdopt.optind);
 }
@@ -1,167 +1,112 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
+//! Finds the keys that are prefixes of each line of stdin, as upstream's
+//! marisa-common-prefix-search does: "N found", or "not found", then a
+//! line "id\tkey\tquery" for each of the first -n keys, shortest first.
+//! Lines are bytes, UTF-8 or not, without their "\n" or "\r\n".
 
-#include <cstdlib>
-#include <iostream>
-#include <string>
+extern crate mars_trie;
 
-#include <marisa.h>
+mod cmdopt;
+mod dictionary;
 
-#include "cmdopt.h"
+use std::env;
+use std::io;
+use std::io::{BufRead, Write};
+use std::process;
+use mars_trie::LoudsTrieView;
+use mars_trie::keyio::normalize_line;
+use cmdopt::Opt;
 
-namespace {
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'n', long: "max-num-results", has_arg: true },
+    Opt { short: 'm', long: "mmap-dictionary", has_arg: false },
+    Opt { short: 'r', long: "read-dictionary", has_arg: false },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
 
-std::size_t max_num_results = 10;
-bool mmap_flag = true;
-
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... DIC\n\n"
-      "Options:\n"
-      "  -n, --max-num-results=[N]  limit the number of results to N"
-      " (default: 10)\n"
-      "                             0: no limit\n"
-      "  -m, --mmap-dictionary  use memory-mapped I/O to load a dictionary"
-      " (default)\n"
-      "  -r, --read-dictionary  read an entire dictionary into memory\n"
-      "  -h, --help             print this help\n"
-      << std::endl;
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... DIC\n\n\
+             Options:\n\
+             \x20 -n, --max-num-results=[N]  limit the number of results \
+             to N (default: 10)\n\
+             \x20                            0: no limit\n\
+             \x20 -m, --mmap-dictionary  use memory-mapped I/O to load a \
+             dictionary{}\n\
+             \x20 -r, --read-dictionary  read an entire dictionary into \
+             memory{}\n\
+             \x20 -h, --help             print this help\n\n",
+            cmd,
+            if dictionary::MMAP_BY_DEFAULT { " (default)" } else { "" },
+            if dictionary::MMAP_BY_DEFAULT { "" } else { " (default)" });
 }
 
-int common_prefix_search(const char * const *args, std::size_t num_args) {
-  if (num_args == 0) {
-    std::cerr << "error: dictionary is not specified" << std::endl;
-    return 10;
-  } else if (num_args > 1) {
-    std::cerr << "error: more than one dictionaries are specified"
-        << std::endl;
-    return 11;
-  }
-
-  marisa::Trie trie;
-  if (mmap_flag) {
-    try {
-      trie.mmap(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to mmap a dictionary file: "
-          << args[0] << std::endl;
-      return 20;
-    }
-  } else {
-    try {
-      trie.load(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to load a dictionary file: "
-          << args[0] << std::endl;
-      return 21;
-    }
-  }
-
-  marisa::Agent agent;
-  marisa::Keyset keyset;
-  std::string str;
-  while (std::getline(std::cin, str)) {
-    try {
-      agent.set_query(str.c_str(), str.length());
-      while (trie.common_prefix_search(agent)) {
-        keyset.push_back(agent.key());
-      }
-      if (keyset.empty()) {
-        std::cout << "not found" << std::endl;
-      } else {
-        std::cout << keyset.size() << " found" << std::endl;
-        const std::size_t end = std::min(max_num_results, keyset.size());
-        for (std::size_t i = 0; i < end; ++i) {
-          std::cout << keyset[i].id() << '\t';
-          std::cout.write(keyset[i].ptr(), keyset[i].length()) << '\t';
-          std::cout << str << '\n';
+fn search<R: BufRead, W: Write>(trie: &LoudsTrieView, max_num_results: usize,
+                                mut r: R, mut w: W) -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut found = Vec::new();
+    loop {
+        line.clear();
+        if r.read_until(b'\n', &mut line)? == 0 {
+            return w.flush();
+        }
+        let query = normalize_line(&line);
+        found.clear();
+        found.extend(trie.common_prefix_search(query));
+        if found.is_empty() {
+            writeln!(w, "not found")?;
+            continue;
+        }
+        writeln!(w, "{} found", found.len())?;
+        for &(id, len) in found.iter().take(max_num_results) {
+            write!(w, "{}\t", id)?;
+            w.write_all(&query[..len])?;
+            w.write_all(b"\t")?;
+            w.write_all(query)?;
+            w.write_all(b"\n")?;
         }
-      }
-      keyset.reset();
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": common_prefix_search() failed: "
-          << str << std::endl;
-      return 30;
-    }
-
-    if (!std::cout) {
-      std::cerr << "error: failed to write results to standard output"
-          << std::endl;
-      return 31;
     }
-  }
-
-  return 0;
 }
 
-}  // namespace
-
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
-
-  ::cmdopt_option long_options[] = {
-    { "max-num-results", 1, NULL, 'n' },
-    { "mmap-dictionary", 0, NULL, 'm' },
-    { "read-dictionary", 0, NULL, 'r' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "n:mrh", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'n': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value < 0)) {
-          std::cerr << "error: option `-n' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-common-prefix-search".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+    let mut max_num_results = 10;
+    let mut mmap = dictionary::MMAP_BY_DEFAULT;
+    for (c, value) in parsed.options {
+        match c {
+            'n' => {
+                let value = value.unwrap();
+                max_num_results = match value.to_str()
+                                             .and_then(|v| v.parse().ok()) {
+                    Some(0) => usize::max_value(),
+                    Some(n) => n,
+                    None => {
+                        eprintln!("error: option `-n' with an invalid \
+                                   argument: {}", value.to_string_lossy());
+                        process::exit(1)
+                    },
+                };
+            },
+            'm' => mmap = true,
+            'r' => mmap = false,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
         }
-        if ((value == 0) || ((unsigned long long)value > MARISA_SIZE_MAX)) {
-          max_num_results = MARISA_SIZE_MAX;
-        } else {
-          max_num_results = (std::size_t)value;
-        }
-        break;
-      }
-      case 'm': {
-        mmap_flag = true;
-        break;
-      }
-      case 'r': {
-        mmap_flag = false;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
     }
-  }
-  return common_prefix_search(cmdopt.argv + cmdopt.optind,
-      cmdopt.argc - cmdopt.optind);
+    let dic = dictionary::open(&parsed.operands, mmap);
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let w = io::BufWriter::new(stdout.lock());
+    if let Err(e) = search(dic.trie(), max_num_results, stdin.lock(), w) {
+        eprintln!("error: common_prefix_search failed: {}", e);
+        process::exit(30);
+    }
 }
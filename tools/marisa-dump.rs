@@ -1,175 +1,187 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
+//! Writes every key of each dictionary named on the command line, or of
+//! the one on stdin, in the order `LoudsTrie::iter` gives them, each
+//! followed by the delimiter. Keys are streamed, one at a time.
+//!
+//! A key can hold the delimiter, or bytes a terminal mangles; with
+//! --escape, bytes other than printable ASCII, and the backslash, are
+//! written as \xNN, so each key reads back unambiguously. --stats and
+//! --dot write what `LoudsTrie::stats` and `LoudsTrie::dump_dot` give
+//! instead of the keys.
 
-#ifdef _WIN32
- #include <fcntl.h>
- #include <io.h>
- #include <stdio.h>
-#endif  // _WIN32
+extern crate mars_trie;
 
-#include <cstdlib>
-#include <iostream>
-#include <string>
+mod cmdopt;
+// Any number of dictionaries, so `open_path` rather than `open`.
+#[allow(dead_code)]
+mod dictionary;
 
-#include <marisa.h>
+use std::env;
+use std::io;
+use std::io::Write;
+use std::process;
+use mars_trie::{LoudsTrie, LoudsTrieView};
+use cmdopt::Opt;
 
-#include "cmdopt.h"
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'd', long: "delimiter", has_arg: true },
+    Opt { short: 't', long: "with-id", has_arg: false },
+    Opt { short: 'e', long: "escape", has_arg: false },
+    Opt { short: 's', long: "stats", has_arg: false },
+    Opt { short: 'g', long: "dot", has_arg: true },
+    Opt { short: 'm', long: "mmap-dictionary", has_arg: false },
+    Opt { short: 'r', long: "read-dictionary", has_arg: false },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
 
-namespace {
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... DIC...\n\n\
+             Options:\n\
+             \x20 -d, --delimiter=[S]    specify the delimiter \
+             (default: \"\\n\")\n\
+             \x20 -t, --with-id          write each key's id and a TAB \
+             before it\n\
+             \x20 -e, --escape           write bytes other than printable \
+             ASCII as \\xNN\n\
+             \x20 -s, --stats            write statistics instead of keys\n\
+             \x20 -g, --dot=[N]          write the first N nodes as a \
+             Graphviz graph\n\
+             \x20                        instead of keys\n\
+             \x20 -m, --mmap-dictionary  use memory-mapped I/O to load a \
+             dictionary{}\n\
+             \x20 -r, --read-dictionary  read an entire dictionary into \
+             memory{}\n\
+             \x20 -h, --help             print this help\n\n",
+            cmd,
+            if dictionary::MMAP_BY_DEFAULT { " (default)" } else { "" },
+            if dictionary::MMAP_BY_DEFAULT { "" } else { " (default)" });
+}
 
-const char *delimiter = "\n";
-bool mmap_flag = true;
+enum Output {
+    Keys,
+    Stats,
+    Dot(usize),
+}
 
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... DIC...\n\n"
-      "Options:\n"
-      "  -d, --delimiter=[S]    specify the delimier (default: \"\\n\")\n"
-      "  -m, --mmap-dictionary  use memory-mapped I/O to load a dictionary"
-      " (default)\n"
-      "  -r, --read-dictionary  read an entire dictionary into memory\n"
-      "  -h, --help             print this help\n"
-      << std::endl;
+struct Params {
+    delimiter: Vec<u8>,
+    with_id: bool,
+    escape: bool,
+    output: Output,
 }
 
-int dump(const marisa::Trie &trie) {
-  std::size_t num_keys = 0;
-  marisa::Agent agent;
-  agent.set_query("");
-  try {
-    while (trie.predictive_search(agent)) {
-      std::cout.write(agent.key().ptr(), agent.key().length()) << delimiter;
-      if (!std::cout) {
-        std::cerr << "error: failed to write results to standard output"
-            << std::endl;
-        return 20;
-      }
-      ++num_keys;
+fn write_escaped<W: Write>(w: &mut W, key: &[u8]) -> io::Result<()> {
+    for &b in key {
+        if b >= 0x20 && b < 0x7F && b != b'\\' {
+            w.write_all(&[b])?;
+        } else {
+            write!(w, "\\x{:02X}", b)?;
+        }
     }
-  } catch (const marisa::Exception &ex) {
-    std::cerr << ex.what() << ": predictive_search() failed" << std::endl;
-    return 21;
-  }
-  std::cerr << "#keys: " << num_keys << std::endl;
-  return 0;
+    Ok(())
 }
 
-int dump(const char *filename) {
-  marisa::Trie trie;
-  if (filename != NULL) {
-    std::cerr << "input: " << filename << std::endl;
-    if (mmap_flag) {
-      try {
-        trie.mmap(filename);
-      } catch (const marisa::Exception &ex) {
-        std::cerr << ex.what() << ": failed to mmap a dictionary file: "
-            << filename << std::endl;
-        return 10;
-      }
-    } else {
-      try {
-        trie.load(filename);
-      } catch (const marisa::Exception &ex) {
-        std::cerr << ex.what() << ": failed to load a dictionary file: "
-            << filename << std::endl;
-        return 11;
-      }
-    }
-  } else {
-    std::cerr << "input: <stdin>" << std::endl;
-#ifdef _WIN32
-    const int stdin_fileno = ::_fileno(stdin);
-    if (stdin_fileno < 0) {
-      std::cerr << "error: failed to get the file descriptor of "
-          "standard input" << std::endl;
-      return 20;
-    }
-    if (::_setmode(stdin_fileno, _O_BINARY) == -1) {
-      std::cerr << "error: failed to set binary mode" << std::endl;
-      return 21;
-    }
-#endif  // _WIN32
-    try {
-      std::cin >> trie;
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what()
-          << ": failed to read a dictionary from standard input" << std::endl;
-      return 22;
+/// Writes the keys of `trie`, and returns how many there were.
+fn dump<W: Write>(trie: &LoudsTrieView, params: &Params, w: &mut W)
+  -> io::Result<usize> {
+    let mut num_keys = 0;
+    for (id, key) in trie.iter() {
+        if params.with_id {
+            write!(w, "{}\t", id)?;
+        }
+        if params.escape {
+            write_escaped(w, &key)?;
+        } else {
+            w.write_all(&key)?;
+        }
+        w.write_all(&params.delimiter)?;
+        num_keys += 1;
     }
-  }
-  return dump(trie);
+    Ok(num_keys)
 }
 
-int dump(const char * const *args, std::size_t num_args) {
-  if (num_args == 0) {
-    return dump(NULL);
-  }
-  for (std::size_t i = 0; i < num_args; ++i) {
-    const int result = dump(args[i]);
-    if (result != 0) {
-      return result;
+fn write<W: Write>(trie: &LoudsTrieView, params: &Params, w: &mut W)
+  -> io::Result<()> {
+    match params.output {
+        Output::Keys => {
+            let num_keys = dump(trie, params, w)?;
+            w.flush()?;
+            eprintln!("#keys: {}", num_keys);
+        },
+        Output::Stats => write!(w, "{}", trie.stats())?,
+        Output::Dot(max_nodes) => trie.dump_dot(w, Some(max_nodes))?,
     }
-  }
-  return 0;
+    w.flush()
 }
 
-}  // namespace
+fn write_or_die<W: Write>(trie: &LoudsTrieView, params: &Params, w: &mut W) {
+    if let Err(e) = write(trie, params, w) {
+        eprintln!("error: failed to write results to standard output: {}",
+                  e);
+        process::exit(30)
+    }
+}
 
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-dump".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+    let mut params = Params {
+        delimiter: b"\n".to_vec(),
+        with_id: false,
+        escape: false,
+        output: Output::Keys,
+    };
+    let mut mmap = dictionary::MMAP_BY_DEFAULT;
+    for (c, value) in parsed.options {
+        match c {
+            'd' => {
+                params.delimiter = value.unwrap().to_string_lossy()
+                                        .into_owned().into_bytes();
+            },
+            't' => params.with_id = true,
+            'e' => params.escape = true,
+            's' => params.output = Output::Stats,
+            'g' => {
+                let value = value.unwrap();
+                match value.to_str().and_then(|v| v.parse().ok()) {
+                    Some(n) => params.output = Output::Dot(n),
+                    None => {
+                        eprintln!("error: option `--dot' with an invalid \
+                                   argument: {}", value.to_string_lossy());
+                        process::exit(1)
+                    },
+                }
+            },
+            'm' => mmap = true,
+            'r' => mmap = false,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
+        }
+    }
 
-  ::cmdopt_option long_options[] = {
-    { "delimiter", 1, NULL, 'd' },
-    { "mmap-dictionary", 0, NULL, 'm' },
-    { "read-dictionary", 0, NULL, 'r' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "d:mrh", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'd': {
-        delimiter = cmdopt.optarg;
-        break;
-      }
-      case 'm': {
-        mmap_flag = true;
-        break;
-      }
-      case 'r': {
-        mmap_flag = false;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
+    let stdout = io::stdout();
+    let mut w = io::BufWriter::new(stdout.lock());
+    if parsed.operands.is_empty() {
+        eprintln!("input: <stdin>");
+        let stdin = io::stdin();
+        let trie = LoudsTrie::read_from(&mut stdin.lock())
+                   .unwrap_or_else(|e| {
+            eprintln!("error: failed to read a dictionary from standard \
+                       input: {}", e);
+            process::exit(22)
+        });
+        write_or_die(&trie, &params, &mut w);
+    }
+    for path in &parsed.operands {
+        eprintln!("input: {}", path.to_string_lossy());
+        let dic = dictionary::open_path(path, mmap);
+        write_or_die(dic.trie(), &params, &mut w);
     }
-  }
-  return dump(cmdopt.argv + cmdopt.optind, cmdopt.argc - cmdopt.optind);
 }
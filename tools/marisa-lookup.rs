@@ -1,134 +1,86 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
+//! Looks up each line of stdin in a dictionary and prints "id\tkey" for a
+//! key it holds and "-1\tkey" for one it doesn't, as upstream's
+//! marisa-lookup does. Lines are bytes, UTF-8 or not, without their "\n"
+//! or "\r\n". Lines are read and answered one at a time, so stdin can be
+//! as long as it likes.
 
-#include <iostream>
-#include <string>
+extern crate mars_trie;
 
-#include <marisa.h>
+mod cmdopt;
+mod dictionary;
 
-#include "cmdopt.h"
+use std::env;
+use std::io;
+use std::io::{BufRead, Write};
+use std::process;
+use mars_trie::LoudsTrieView;
+use mars_trie::keyio::normalize_line;
+use cmdopt::Opt;
 
-namespace {
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'm', long: "mmap-dictionary", has_arg: false },
+    Opt { short: 'r', long: "read-dictionary", has_arg: false },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
 
-bool mmap_flag = true;
-
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... DIC\n\n"
-      "Options:\n"
-      "  -m, --mmap-dictionary  use memory-mapped I/O to load a dictionary"
-      " (default)\n"
-      "  -r, --read-dictionary  read an entire dictionary into memory\n"
-      "  -h, --help             print this help\n"
-      << std::endl;
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... DIC\n\n\
+             Options:\n\
+             \x20 -m, --mmap-dictionary  use memory-mapped I/O to load a \
+             dictionary{}\n\
+             \x20 -r, --read-dictionary  read an entire dictionary into \
+             memory{}\n\
+             \x20 -h, --help             print this help\n\n",
+            cmd,
+            if dictionary::MMAP_BY_DEFAULT { " (default)" } else { "" },
+            if dictionary::MMAP_BY_DEFAULT { "" } else { " (default)" });
 }
 
-int lookup(const char * const *args, std::size_t num_args) {
-  if (num_args == 0) {
-    std::cerr << "error: dictionary is not specified" << std::endl;
-    return 10;
-  } else if (num_args > 1) {
-    std::cerr << "error: more than one dictionaries are specified"
-        << std::endl;
-    return 11;
-  }
-
-  marisa::Trie trie;
-  if (mmap_flag) {
-    try {
-      trie.mmap(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to mmap a dictionary file: "
-          << args[0] << std::endl;
-      return 20;
-    }
-  } else {
-    try {
-      trie.load(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to load a dictionary file: "
-          << args[0] << std::endl;
-      return 21;
-    }
-  }
-
-  marisa::Agent agent;
-  std::string str;
-  while (std::getline(std::cin, str)) {
-    try {
-      agent.set_query(str.c_str(), str.length());
-      if (trie.lookup(agent)) {
-        std::cout << agent.key().id() << '\t' << str << '\n';
-      } else {
-        std::cout << "-1\t" << str << '\n';
-      }
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": lookup() failed: " << str << std::endl;
-      return 30;
+fn lookup<R: BufRead, W: Write>(trie: &LoudsTrieView, mut r: R, mut w: W)
+  -> io::Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if r.read_until(b'\n', &mut line)? == 0 {
+            return w.flush();
+        }
+        let key = normalize_line(&line);
+        match trie.lookup(key) {
+            Some(id) => write!(w, "{}\t", id)?,
+            None => w.write_all(b"-1\t")?,
+        }
+        w.write_all(key)?;
+        w.write_all(b"\n")?;
     }
-
-    if (!std::cout) {
-      std::cerr << "error: failed to write results to standard output"
-          << std::endl;
-      return 30;
-    }
-  }
-
-  return 0;
 }
 
-}  // namespace
-
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-lookup".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+    let mut mmap = dictionary::MMAP_BY_DEFAULT;
+    for (c, _) in parsed.options {
+        match c {
+            'm' => mmap = true,
+            'r' => mmap = false,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
+        }
+    }
+    let dic = dictionary::open(&parsed.operands, mmap);
 
-  ::cmdopt_option long_options[] = {
-    { "mmap-dictionary", 0, NULL, 'm' },
-    { "read-dictionary", 0, NULL, 'r' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "mrh", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'm': {
-        mmap_flag = true;
-        break;
-      }
-      case 'r': {
-        mmap_flag = false;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let w = io::BufWriter::new(stdout.lock());
+    if let Err(e) = lookup(dic.trie(), stdin.lock(), w) {
+        eprintln!("error: failed to look up keys: {}", e);
+        process::exit(30);
     }
-  }
-  return lookup(cmdopt.argv + cmdopt.optind, cmdopt.argc - cmdopt.optind);
 }
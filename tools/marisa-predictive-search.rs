@@ -1,167 +1,153 @@
-// Copyright (c) 2010-2013, Susumu Yata
-// All rights reserved.
-//
-// Redistribution and use in source and binary forms, with or without
-// modification, are permitted provided that the following conditions are met:
-//
-// - Redistributions of source code must retain the above copyright notice, this
-//   list of conditions and the following disclaimer.
-// - Redistributions in binary form must reproduce the above copyright notice,
-//   this list of conditions and the following disclaimer in the documentation
-//   and/or other materials provided with the distribution.
-//
-// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
-// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
-// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
-// ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
-// LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
-// CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
-// SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
-// INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
-// CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
-// ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
-// POSSIBILITY OF SUCH DAMAGE.
+//! Finds the keys that start with each line of stdin: a line with how many
+//! are shown, then "id\tkey" for each. Lines are bytes, UTF-8 or not,
+//! without their "\n" or "\r\n".
+//!
+//! Keys come depth first by default, as `predictive_search` gives them
+//! and upstream's marisa-predictive-search prints them. The search is
+//! lazy, so with -n it stops after N keys however many more there are,
+//! and the count is of the keys shown. With -i they come in id order
+//! instead, which means visiting every key under the prefix, though only
+//! the first N are kept.
 
-#include <cstdlib>
-#include <iostream>
-#include <string>
+extern crate mars_trie;
 
-#include <marisa.h>
+mod cmdopt;
+mod dictionary;
 
-#include "cmdopt.h"
+use std::env;
+use std::io;
+use std::io::{BufRead, Write};
+use std::process;
+use mars_trie::{LongKeys, LoudsTrieView};
+use mars_trie::keyio::normalize_line;
+use cmdopt::Opt;
 
-namespace {
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 'n', long: "max-num-results", has_arg: true },
+    Opt { short: 'd', long: "depth-first", has_arg: false },
+    Opt { short: 'i', long: "id-order", has_arg: false },
+    Opt { short: 'm', long: "mmap-dictionary", has_arg: false },
+    Opt { short: 'r', long: "read-dictionary", has_arg: false },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
 
-std::size_t max_num_results = 10;
-bool mmap_flag = true;
+/// The longest key restored in place in id order; longer ones are looked
+/// up by id.
+const KEY_BUF_LEN: usize = 4096;
 
-void print_help(const char *cmd) {
-  std::cerr << "Usage: " << cmd << " [OPTION]... DIC\n\n"
-      "Options:\n"
-      "  -n, --max-num-results=[N]  limit the number of outputs to N"
-      " (default: 10)\n"
-      "                             0: no limit\n"
-      "  -m, --mmap-dictionary  use memory-mapped I/O to load a dictionary"
-      " (default)\n"
-      "  -r, --read-dictionary  read an entire dictionary into memory\n"
-      "  -h, --help             print this help\n"
-      << std::endl;
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]... DIC\n\n\
+             Options:\n\
+             \x20 -n, --max-num-results=[N]  limit the number of outputs \
+             to N (default: 10)\n\
+             \x20                            0: no limit\n\
+             \x20 -d, --depth-first      print keys depth first (default)\n\
+             \x20 -i, --id-order         print keys in id order\n\
+             \x20 -m, --mmap-dictionary  use memory-mapped I/O to load a \
+             dictionary{}\n\
+             \x20 -r, --read-dictionary  read an entire dictionary into \
+             memory{}\n\
+             \x20 -h, --help             print this help\n\n",
+            cmd,
+            if dictionary::MMAP_BY_DEFAULT { " (default)" } else { "" },
+            if dictionary::MMAP_BY_DEFAULT { "" } else { " (default)" });
 }
 
-int predictive_search(const char * const *args, std::size_t num_args) {
-  if (num_args == 0) {
-    std::cerr << "error: dictionary is not specified" << std::endl;
-    return 10;
-  } else if (num_args > 1) {
-    std::cerr << "error: more than one dictionaries are specified"
-        << std::endl;
-    return 11;
-  }
-
-  marisa::Trie trie;
-  if (mmap_flag) {
-    try {
-      trie.mmap(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to mmap a dictionary file: "
-          << args[0] << std::endl;
-      return 20;
-    }
-  } else {
-    try {
-      trie.load(args[0]);
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": failed to load a dictionary file: "
-          << args[0] << std::endl;
-      return 21;
-    }
-  }
-
-  marisa::Agent agent;
-  marisa::Keyset keyset;
-  std::string str;
-  while (std::getline(std::cin, str)) {
-    try {
-      agent.set_query(str.c_str(), str.length());
-      while (trie.predictive_search(agent)) {
-        keyset.push_back(agent.key());
-      }
-      if (keyset.empty()) {
-        std::cout << "not found" << std::endl;
-      } else {
-        std::cout << keyset.size() << " found" << std::endl;
-        const std::size_t end = std::min(max_num_results, keyset.size());
-        for (std::size_t i = 0; i < end; ++i) {
-          std::cout << keyset[i].id() << '\t';
-          std::cout.write(keyset[i].ptr(), keyset[i].length()) << '\t';
-          std::cout << str << '\n';
+/// Up to `max_num_results` keys starting with `prefix`, in id order.
+fn id_order(trie: &LoudsTrieView, prefix: &[u8], max_num_results: usize,
+            found: &mut Vec<(usize, Vec<u8>)>) -> io::Result<()> {
+    let mut buf = vec![0; KEY_BUF_LEN];
+    let mut long_keys = Vec::new();
+    trie.for_each_key_into(prefix, &mut buf, LongKeys::Truncate,
+                           |id, key, truncated| {
+        if found.len() < max_num_results {
+            if truncated {
+                long_keys.push(found.len());
+            }
+            found.push((id, key.to_vec()));
         }
-      }
-      keyset.reset();
-    } catch (const marisa::Exception &ex) {
-      std::cerr << ex.what() << ": predictive_search() failed: "
-          << str << std::endl;
-      return 30;
+    });
+    for i in long_keys {
+        let (id, ref mut key) = found[i];
+        trie.id_lookup_into_vec(id, key).map_err(|e| {
+            io::Error::new(io::ErrorKind::Other, e.to_string())
+        })?;
     }
-
-    if (!std::cout) {
-      std::cerr << "error: failed to write results to standard output"
-          << std::endl;
-      return 31;
-    }
-  }
-
-  return 0;
+    Ok(())
 }
 
-}  // namespace
-
-int main(int argc, char *argv[]) {
-  std::ios::sync_with_stdio(false);
-
-  ::cmdopt_option long_options[] = {
-    { "max-num-results", 1, NULL, 'n' },
-    { "mmap-dictionary", 0, NULL, 'm' },
-    { "read-dictionary", 0, NULL, 'r' },
-    { "help", 0, NULL, 'h' },
-    { NULL, 0, NULL, 0 }
-  };
-  ::cmdopt_t cmdopt;
-  ::cmdopt_init(&cmdopt, argc, argv, "n:mrh", long_options);
-  int label;
-  while ((label = ::cmdopt_get(&cmdopt)) != -1) {
-    switch (label) {
-      case 'n': {
-        char *end_of_value;
-        const long value = std::strtol(cmdopt.optarg, &end_of_value, 10);
-        if ((*end_of_value != '\0') || (value < 0)) {
-          std::cerr << "error: option `-n' with an invalid argument: "
-              << cmdopt.optarg << std::endl;
+fn search<R: BufRead, W: Write>(trie: &LoudsTrieView, max_num_results: usize,
+                                depth_first: bool, mut r: R, mut w: W)
+  -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut found = Vec::new();
+    loop {
+        line.clear();
+        if r.read_until(b'\n', &mut line)? == 0 {
+            return w.flush();
         }
-        if ((value == 0) || ((unsigned long long)value > MARISA_SIZE_MAX)) {
-          max_num_results = MARISA_SIZE_MAX;
+        let prefix = normalize_line(&line);
+        found.clear();
+        if depth_first {
+            found.extend(trie.predictive_search(prefix)
+                             .take(max_num_results));
         } else {
-          max_num_results = (std::size_t)value;
+            id_order(trie, prefix, max_num_results, &mut found)?;
+        }
+        writeln!(w, "{}", found.len())?;
+        for &(id, ref key) in &found {
+            write!(w, "{}\t", id)?;
+            w.write_all(key)?;
+            w.write_all(b"\n")?;
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-predictive-search".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+    let mut max_num_results = 10;
+    let mut depth_first = true;
+    let mut mmap = dictionary::MMAP_BY_DEFAULT;
+    for (c, value) in parsed.options {
+        match c {
+            'n' => {
+                let value = value.unwrap();
+                max_num_results = match value.to_str()
+                                             .and_then(|v| v.parse().ok()) {
+                    Some(0) => usize::max_value(),
+                    Some(n) => n,
+                    None => {
+                        eprintln!("error: option `-n' with an invalid \
+                                   argument: {}", value.to_string_lossy());
+                        process::exit(1)
+                    },
+                };
+            },
+            'd' => depth_first = true,
+            'i' => depth_first = false,
+            'm' => mmap = true,
+            'r' => mmap = false,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
         }
-        break;
-      }
-      case 'm': {
-        mmap_flag = true;
-        break;
-      }
-      case 'r': {
-        mmap_flag = false;
-        break;
-      }
-      case 'h': {
-        print_help(argv[0]);
-        return 0;
-      }
-      default: {
-        return 1;
-      }
     }
-  }
-  return predictive_search(cmdopt.argv + cmdopt.optind,
-      cmdopt.argc - cmdopt.optind);
+    let dic = dictionary::open(&parsed.operands, mmap);
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let w = io::BufWriter::new(stdout.lock());
+    if let Err(e) = search(dic.trie(), max_num_results, depth_first,
+                           stdin.lock(), w) {
+        eprintln!("error: predictive_search failed: {}", e);
+        process::exit(30);
+    }
 }
@@ -0,0 +1,474 @@
+//! Writes test vectors for ports and bindings of this crate: from a seed,
+//! a bundle of keys, the config they are built with, the SHA-256 of the
+//! dictionary `LoudsTrie::write_to` writes, the id of each key, and what
+//! common-prefix and predictive searches find for queries derived from
+//! the keys. With --verify it checks a bundle against this build instead.
+//!
+//! A bundle is text, one record per line, fields split by TABs, and keys
+//! and queries in hex so that any byte can appear in them:
+//!
+//!     marisa-testvec  1
+//!     seed            SEED
+//!     config          FLAGS (as Config::flags gives them, in hex)
+//!     sha256          HASH
+//!     key             KEY  WEIGHT  ID
+//!     query           QUERY  PREFIX_IDS  PREDICTIVE_IDS
+//!
+//! with a key line for each key and a query line for each query. Prefix
+//! ids are of the keys that start `QUERY`, shortest first; predictive ids
+//! are of the keys that `QUERY` starts, in ascending order, as the order
+//! of a predictive search depends on the node order. Lists are split by
+//! commas. Lines starting with "#" are comments.
+//!
+//! Queries are every prefix of every key, and each key with one byte
+//! changed and with one byte added. The seed picks the keys, their
+//! weights and, unless -C gives one, the config.
+
+extern crate mars_trie;
+
+mod cmdopt;
+mod sha256;
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Write};
+use std::process;
+use mars_trie::{Config, Keyset, LoudsTrie, MarisaError, NodeOrder, NumTries,
+                TailMode};
+use cmdopt::Opt;
+use sha256::Sha256;
+
+const OPTIONS: &'static [Opt] = &[
+    Opt { short: 's', long: "seed", has_arg: true },
+    Opt { short: 'k', long: "num-keys", has_arg: true },
+    Opt { short: 'C', long: "config", has_arg: true },
+    Opt { short: 'o', long: "output", has_arg: true },
+    Opt { short: 'v', long: "verify", has_arg: true },
+    Opt { short: 'h', long: "help", has_arg: false },
+];
+
+/// The first line of a bundle, and the version of its layout.
+const MAGIC: &'static str = "marisa-testvec\t1";
+
+fn print_help(cmd: &str) {
+    eprint!("Usage: {} [OPTION]...\n\n\
+             Options:\n\
+             \x20 -s, --seed=[N]       generate keys from seed N \
+             (default: 0)\n\
+             \x20 -k, --num-keys=[N]   generate N keys (default: 100)\n\
+             \x20 -C, --config=[S]     build as config S says, rather than \
+             as the seed does\n\
+             \x20 -o, --output=[FILE]  write the bundle to FILE \
+             (default: stdout)\n\
+             \x20 -v, --verify=[FILE]  check the bundle in FILE against \
+             this build\n\
+             \x20 -h, --help           print this help\n",
+            cmd);
+}
+
+fn die(code: i32, message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(code)
+}
+
+/// SplitMix64, which any seed, 0 included, starts well.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Mostly a few letters, so that keys share prefixes, and sometimes
+    /// any byte at all.
+    fn byte(&mut self) -> u8 {
+        if self.below(8) == 0 {
+            self.next() as u8
+        } else {
+            b"abcd"[self.below(4) as usize]
+        }
+    }
+}
+
+/// What a bundle holds.
+#[derive(Debug, PartialEq)]
+struct Bundle {
+    seed_: u64,
+    config_: Config,
+    sha256_: String,
+    /// Each key, its weight and its id.
+    keys_: Vec<(Vec<u8>, f32, usize)>,
+    /// Each query, and the ids of its common-prefix and predictive
+    /// results.
+    queries_: Vec<(Vec<u8>, Vec<usize>, Vec<usize>)>,
+}
+
+/// The keys and weights the seed picks, and the queries derived from
+/// them.
+fn generate(seed: u64, num_keys: usize)
+  -> (Vec<(Vec<u8>, f32)>, Vec<Vec<u8>>) {
+    let mut rng = Rng(seed);
+    let mut keys: Vec<(Vec<u8>, f32)> = Vec::with_capacity(num_keys);
+    while keys.len() < num_keys {
+        let len = 1 + rng.below(8) as usize;
+        let key: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+        if keys.iter().all(|&(ref k, _)| *k != key) {
+            keys.push((key, 1.0 + rng.below(4) as f32));
+        }
+    }
+    let mut queries = Vec::new();
+    for &(ref key, _) in &keys {
+        for len in 0..key.len() + 1 {
+            queries.push(key[..len].to_vec());
+        }
+        let mut changed = key.clone();
+        let i = rng.below(key.len() as u64) as usize;
+        changed[i] = changed[i].wrapping_add(1 + rng.below(255) as u8);
+        queries.push(changed);
+        let mut longer = key.clone();
+        longer.push(rng.byte());
+        queries.push(longer);
+    }
+    queries.sort();
+    queries.dedup();
+    (keys, queries)
+}
+
+/// The config the seed picks, of up to 4 tries.
+fn seed_config(seed: u64) -> Config {
+    let mut rng = Rng(!seed);
+    let mut config = Config::new();
+    config.set_num_tries(NumTries::new(1 + rng.below(4) as u32));
+    config.set_tail_mode(if rng.below(2) == 0 { TailMode::Text }
+                         else { TailMode::Binary });
+    config.set_node_order(if rng.below(2) == 0 { NodeOrder::Weight }
+                          else { NodeOrder::Label });
+    config
+}
+
+/// The bundle this build gives for `keys` and `queries`.
+fn expect(seed: u64, config: &Config, keys: &[(Vec<u8>, f32)],
+          queries: Vec<Vec<u8>>) -> Result<Bundle, MarisaError> {
+    let mut keyset = Keyset::new();
+    for &(ref key, weight) in keys {
+        keyset.try_push_weighted(key, weight)?;
+    }
+    let trie = LoudsTrie::build(&mut keyset, config)?;
+    let mut bytes = Vec::new();
+    trie.write_to(&mut bytes).unwrap();
+    let mut hasher = Sha256::new();
+    hasher.update(&bytes);
+    let keys = keys.iter().map(|&(ref key, weight)| {
+        (key.clone(), weight, trie.lookup(key).unwrap())
+    }).collect();
+    let queries = queries.into_iter().map(|query| {
+        let prefixes = trie.common_prefix_search(&query)
+                           .map(|(id, _)| id).collect();
+        let mut predictions: Vec<usize> = trie.predictive_search(&query)
+                                              .map(|(id, _)| id).collect();
+        predictions.sort();
+        (query, prefixes, predictions)
+    }).collect();
+    Ok(Bundle {
+        seed_: seed,
+        config_: trie.config(),
+        sha256_: sha256::to_hex(&hasher.finish()),
+        keys_: keys,
+        queries_: queries,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn ids(ids: &[usize]) -> String {
+    ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn write_bundle<W: Write>(bundle: &Bundle, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", MAGIC)?;
+    writeln!(w, "seed\t{}", bundle.seed_)?;
+    writeln!(w, "# {}", bundle.config_)?;
+    writeln!(w, "config\t0x{:08x}", bundle.config_.flags())?;
+    writeln!(w, "sha256\t{}", bundle.sha256_)?;
+    for &(ref key, weight, id) in &bundle.keys_ {
+        writeln!(w, "key\t{}\t{}\t{}", hex(key), weight, id)?;
+    }
+    for &(ref query, ref prefixes, ref predictions) in &bundle.queries_ {
+        writeln!(w, "query\t{}\t{}\t{}", hex(query), ids(prefixes),
+                 ids(predictions))?;
+    }
+    w.flush()
+}
+
+fn parse_hex(field: &str) -> Option<Vec<u8>> {
+    if field.len() % 2 != 0 || !field.is_ascii() {
+        return None;
+    }
+    (0..field.len() / 2).map(|i| {
+        u8::from_str_radix(&field[2 * i..2 * i + 2], 16).ok()
+    }).collect()
+}
+
+fn parse_ids(field: &str) -> Option<Vec<usize>> {
+    if field.is_empty() {
+        return Some(Vec::new());
+    }
+    field.split(',').map(|id| id.parse().ok()).collect()
+}
+
+/// A bundle `write_bundle` wrote, or the number of the first line that
+/// could not be read.
+fn read_bundle<R: BufRead>(r: R) -> Result<Bundle, String> {
+    let mut bundle = Bundle {
+        seed_: 0,
+        config_: Config::new(),
+        sha256_: String::new(),
+        keys_: Vec::new(),
+        queries_: Vec::new(),
+    };
+    let mut lines = r.lines();
+    match lines.next() {
+        Some(Ok(ref line)) if line == MAGIC => {},
+        _ => return Err("not a bundle".to_owned()),
+    }
+    for (i, line) in lines.enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.starts_with("#") {
+            continue;
+        }
+        let bad = || format!("line {}: bad record", i + 2);
+        let fields: Vec<&str> = line.split('\t').collect();
+        match (fields[0], fields.len()) {
+            ("seed", 2) => {
+                bundle.seed_ = fields[1].parse().map_err(|_| bad())?;
+            },
+            ("config", 2) => {
+                bundle.config_ = fields[1].parse().map_err(|_| bad())?;
+            },
+            ("sha256", 2) => bundle.sha256_ = fields[1].to_owned(),
+            ("key", 4) => {
+                let key = parse_hex(fields[1]).ok_or_else(&bad)?;
+                let weight = fields[2].parse().map_err(|_| bad())?;
+                let id = fields[3].parse().map_err(|_| bad())?;
+                bundle.keys_.push((key, weight, id));
+            },
+            ("query", 4) => {
+                let query = parse_hex(fields[1]).ok_or_else(&bad)?;
+                let prefixes = parse_ids(fields[2]).ok_or_else(&bad)?;
+                let predictions = parse_ids(fields[3]).ok_or_else(&bad)?;
+                bundle.queries_.push((query, prefixes, predictions));
+            },
+            _ => return Err(bad()),
+        }
+    }
+    Ok(bundle)
+}
+
+/// Where `found`, from a bundle, and `expected`, from this build, differ.
+fn differences(found: &Bundle, expected: &Bundle) -> Vec<String> {
+    let mut out = Vec::new();
+    if found.config_ != expected.config_ {
+        out.push(format!("config: {} rather than {}", found.config_,
+                         expected.config_));
+    }
+    if found.sha256_ != expected.sha256_ {
+        out.push(format!("sha256: {} rather than {}", found.sha256_,
+                         expected.sha256_));
+    }
+    for (f, e) in found.keys_.iter().zip(&expected.keys_) {
+        if f.2 != e.2 {
+            out.push(format!("key {}: id {} rather than {}", hex(&f.0), f.2,
+                             e.2));
+        }
+    }
+    for (f, e) in found.queries_.iter().zip(&expected.queries_) {
+        if f.1 != e.1 {
+            out.push(format!("query {}: prefixes {} rather than {}",
+                             hex(&f.0), ids(&f.1), ids(&e.1)));
+        }
+        if f.2 != e.2 {
+            out.push(format!("query {}: predictions {} rather than {}",
+                             hex(&f.0), ids(&f.2), ids(&e.2)));
+        }
+    }
+    out
+}
+
+/// Builds the keys of the bundle `r` holds and compares the result with
+/// it.
+fn verify<R: BufRead>(r: R) -> Result<Vec<String>, String> {
+    let found = read_bundle(r)?;
+    let keys: Vec<_> = found.keys_.iter().map(|&(ref key, weight, _)| {
+        (key.clone(), weight)
+    }).collect();
+    let queries = found.queries_.iter().map(|q| q.0.clone()).collect();
+    let expected = expect(found.seed_, &found.config_, &keys, queries)
+        .map_err(|e| format!("failed to build a dictionary: {}", e))?;
+    Ok(differences(&found, &expected))
+}
+
+fn number(value: &Option<OsString>) -> Option<u64> {
+    value.as_ref().and_then(|v| v.to_str()).and_then(|v| v.parse().ok())
+}
+
+fn main() {
+    let mut args = env::args_os();
+    let cmd = args.next().map(|cmd| cmd.to_string_lossy().into_owned())
+                  .unwrap_or("marisa-testvec".to_owned());
+    let parsed = cmdopt::parse(args, OPTIONS).unwrap_or_else(|e| {
+        eprintln!("{}: {}", cmd, e);
+        print_help(&cmd);
+        process::exit(1)
+    });
+
+    let mut seed = 0;
+    let mut num_keys = 100;
+    let mut config = None;
+    let mut output = None;
+    let mut bundle = None;
+    for (c, value) in parsed.options {
+        match c {
+            's' => seed = number(&value).unwrap_or_else(|| {
+                die(1, "option -s/--seed: the seed must be a u64".to_owned())
+            }),
+            'k' => num_keys = match number(&value) {
+                Some(n) if n > 0 => n as usize,
+                _ => die(1, "option -k/--num-keys: the number of keys must \
+                             be positive".to_owned()),
+            },
+            'C' => {
+                let value = value.unwrap();
+                config = Some(value.to_string_lossy().parse().unwrap_or_else(
+                    |e| die(3, format!("option -C/--config: {}", e))));
+            },
+            'o' => output = value,
+            'v' => bundle = value,
+            _ => {
+                print_help(&cmd);
+                return;
+            },
+        }
+    }
+    if !parsed.operands.is_empty() {
+        print_help(&cmd);
+        process::exit(1)
+    }
+
+    if let Some(path) = bundle {
+        let name = path.to_string_lossy().into_owned();
+        let file = File::open(&path).unwrap_or_else(|e| {
+            die(10, format!("failed to open {}: {}", name, e))
+        });
+        let found = verify(io::BufReader::new(file)).unwrap_or_else(|e| {
+            die(12, format!("{}: {}", name, e))
+        });
+        for difference in &found {
+            eprintln!("{}", difference);
+        }
+        if !found.is_empty() {
+            die(40, format!("{}: {} differences", name, found.len()));
+        }
+        eprintln!("{}: ok", name);
+        return;
+    }
+
+    let (keys, queries) = generate(seed, num_keys);
+    let config = config.unwrap_or_else(|| seed_config(seed));
+    let bundle = expect(seed, &config, &keys, queries).unwrap_or_else(|e| {
+        die(20, format!("failed to build a dictionary: {}", e))
+    });
+    eprintln!("#keys: {}", bundle.keys_.len());
+    eprintln!("#queries: {}", bundle.queries_.len());
+    match output {
+        Some(path) => {
+            let name = path.to_string_lossy().into_owned();
+            File::create(&path).and_then(|file| {
+                write_bundle(&bundle, &mut io::BufWriter::new(file))
+            }).unwrap_or_else(|e| {
+                die(30, format!("failed to write a bundle to {}: {}", name, e))
+            });
+        },
+        None => {
+            let stdout = io::stdout();
+            write_bundle(&bundle, &mut stdout.lock()).unwrap_or_else(|e| {
+                die(33, format!("failed to write a bundle to stdout: {}", e))
+            });
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mars_trie::{Config, NodeOrder, TailMode};
+    use super::{expect, generate, read_bundle, seed_config, verify,
+                write_bundle};
+
+    fn bundle_bytes(seed: u64, config: &Config) -> Vec<u8> {
+        let (keys, queries) = generate(seed, 50);
+        let bundle = expect(seed, config, &keys, queries).unwrap();
+        let mut bytes = Vec::new();
+        write_bundle(&bundle, &mut bytes).unwrap();
+        assert_eq!(read_bundle(&bytes[..]).unwrap(), bundle);
+        bytes
+    }
+
+    #[test]
+    fn generates_and_verifies() {
+        for seed in 0..8 {
+            let bytes = bundle_bytes(seed, &seed_config(seed));
+            assert_eq!(verify(&bytes[..]), Ok(Vec::new()), "seed {}", seed);
+            assert!(bytes == bundle_bytes(seed, &seed_config(seed)));
+        }
+        let config = Config::new().with_tail_mode(TailMode::Binary)
+                                  .with_node_order(NodeOrder::Label);
+        let bytes = bundle_bytes(1, &config);
+        assert_eq!(verify(&bytes[..]), Ok(Vec::new()));
+        assert!(bytes != bundle_bytes(2, &config));
+    }
+
+    #[test]
+    fn finds_differences() {
+        let text = String::from_utf8(bundle_bytes(3, &seed_config(3)))
+                       .unwrap();
+        // Another hash, an id swapped between two keys, and a query result
+        // dropped.
+        let mut lines: Vec<String> = text.lines().map(|l| l.to_owned())
+                                                 .collect();
+        let sha = lines.iter().position(|l| l.starts_with("sha256\t"))
+                              .unwrap();
+        lines[sha] = format!("sha256\t{}", "0".repeat(64));
+        let key = lines.iter().position(|l| l.starts_with("key\t")).unwrap();
+        let id = |line: &str| line.rsplit('\t').next().unwrap().to_owned();
+        let (a, b) = (id(&lines[key]), id(&lines[key + 1]));
+        for &(i, ref id) in &[(key, b), (key + 1, a)] {
+            let at = lines[i].rfind('\t').unwrap();
+            lines[i] = format!("{}\t{}", &lines[i][..at], id);
+        }
+        let query = lines.iter().position(|l| l.starts_with("query\t"))
+                                .unwrap();
+        lines[query] = lines[query].rsplitn(2, '\t').nth(1).unwrap()
+                                   .to_owned() + "\t";
+        let bad = lines.join("\n");
+        let found = verify(bad.as_bytes()).unwrap();
+        assert_eq!(found.len(), 4, "{:?}", found);
+        assert!(found[0].starts_with("sha256: 000"));
+        assert!(found[3].contains(": predictions  rather than "));
+
+        assert!(verify(&b"key\t61\t1\t0\n"[..]).is_err());
+        let bad = text.replacen("\nkey\t", "\nkey\tzz", 1);
+        assert_eq!(verify(bad.as_bytes()),
+                   Err("line 6: bad record".to_owned()));
+    }
+}
@@ -0,0 +1,155 @@
+//! SHA-256 (FIPS 180-4), to name a serialized dictionary by its bytes in
+//! marisa-testvec's bundles.
+
+use std;
+use std::fmt::Write;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+    0x1f83d9ab, 0x5be0cd19,
+];
+
+#[derive(Clone)]
+pub struct Sha256 {
+    state_: [u32; 8],
+    /// The bytes of a block not yet full.
+    block_: [u8; 64],
+    len_: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 { state_: H0, block_: [0; 64], len_: 0 }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let used = (self.len_ % 64) as usize;
+            let n = std::cmp::min(64 - used, bytes.len());
+            self.block_[used..used + n].copy_from_slice(&bytes[..n]);
+            self.len_ += n as u64;
+            bytes = &bytes[n..];
+            if used + n == 64 {
+                let block = self.block_;
+                self.compress(&block);
+            }
+        }
+    }
+
+    /// The hash of everything passed to `update`.
+    pub fn finish(mut self) -> [u8; 32] {
+        let num_bits = self.len_.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.len_ % 64 != 56 {
+            self.update(&[0]);
+        }
+        let mut len = [0; 8];
+        for (i, b) in len.iter_mut().enumerate() {
+            *b = (num_bits >> (56 - 8 * i)) as u8;
+        }
+        self.update(&len);
+        let mut hash = [0; 32];
+        for (i, word) in self.state_.iter().enumerate() {
+            for j in 0..4 {
+                hash[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+            }
+        }
+        hash
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[4 * i] as u32) << 24
+                   | (block[4 * i + 1] as u32) << 16
+                   | (block[4 * i + 2] as u32) << 8
+                   | block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18)
+                     ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19)
+                     ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7])
+                            .wrapping_add(s1);
+        }
+        let mut v = self.state_;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11)
+                     ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch)
+                         .wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13)
+                     ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1),
+                 v[4], v[5], v[6]];
+        }
+        for (s, x) in self.state_.iter_mut().zip(v.iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+}
+
+/// `hash` as lowercase hex, as sha256sum prints it.
+pub fn to_hex(hash: &[u8; 32]) -> String {
+    let mut hex = String::with_capacity(64);
+    for b in hash.iter() {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sha256, to_hex};
+
+    fn sha256(bytes: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(bytes);
+        to_hex(&hasher.finish())
+    }
+
+    #[test]
+    fn known_hashes() {
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb924\
+                                 27ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223\
+                                    b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmno\
+                             mnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039\
+                    a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(sha256(&vec![b'a'; 1_000_000]),
+                   "cdc76e5c9914fb9281a1c7e284d73e67\
+                    f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn split_updates() {
+        let bytes: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let whole = sha256(&bytes);
+        for split in &[0, 1, 55, 56, 63, 64, 65, 128, 299] {
+            let mut hasher = Sha256::new();
+            hasher.update(&bytes[..*split]);
+            hasher.update(&bytes[*split..]);
+            assert_eq!(to_hex(&hasher.finish()), whole, "split at {}", split);
+        }
+    }
+}